@@ -6,8 +6,8 @@ use tokio::time::sleep;
 use tracing::{error, info};
 
 use instant_acme::{
-    Account, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt, NewAccount, NewOrder,
-    OrderStatus,
+    Account, AuthorizationStatus, CertStatus, ChallengeType, Identifier, LetsEncrypt, NewAccount,
+    NewOrder, OrderStatus,
 };
 
 #[tokio::main]
@@ -20,11 +20,9 @@ async fn main() -> anyhow::Result<()> {
     // using `Account::from_credentials()`.
 
     let account = Account::create(
-        &NewAccount {
-            contact: &[],
-            terms_of_service_agreed: true,
-            only_return_existing: false,
-        },
+        &NewAccount::builder()
+            .terms_of_service_agreed(true)
+            .build()?,
         LetsEncrypt::Staging.url(),
         None,
     )
@@ -36,9 +34,7 @@ async fn main() -> anyhow::Result<()> {
 
     let identifier = Identifier::Dns(opts.name);
     let mut order = account
-        .new_order(&NewOrder {
-            identifiers: &[identifier],
-        })
+        .new_order(&NewOrder::builder().identifier(identifier).build()?)
         .await
         .unwrap();
 
@@ -61,12 +57,10 @@ async fn main() -> anyhow::Result<()> {
         // pick something else to use here.
 
         let challenge = authz
-            .challenges
-            .iter()
-            .find(|c| c.r#type == ChallengeType::Dns01)
+            .find_challenge(ChallengeType::Dns01)
             .ok_or_else(|| anyhow::anyhow!("no dns01 challenge found"))?;
 
-        let Identifier::Dns(identifier) = &authz.identifier;
+        let identifier = authz.identifier.value();
 
         println!("Please set the following DNS record then press any key:");
         println!(
@@ -134,8 +128,10 @@ async fn main() -> anyhow::Result<()> {
     order.finalize(&csr).await.unwrap();
     let cert_chain_pem = loop {
         match order.certificate().await.unwrap() {
-            Some(cert_chain_pem) => break cert_chain_pem,
-            None => sleep(Duration::from_secs(1)).await,
+            CertStatus::Ready(cert_chain_pem) => break cert_chain_pem,
+            CertStatus::Processing { retry_after } => {
+                sleep(retry_after.unwrap_or(Duration::from_secs(1))).await
+            }
         }
     };
 
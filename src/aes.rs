@@ -0,0 +1,187 @@
+//! AES-256 in CBC mode, for encrypting private keys in PKCS #12 exports
+//!
+//! ring only exposes AES through its AEAD constructions, while PKCS #12 readers like Java's
+//! KeyStore expect keys encrypted with PBES2 and AES-CBC. Only encryption is implemented.
+//! The S-box lookups are not constant-time, which is acceptable for encrypting a key once
+//! into a file, but rules this implementation out for anything an attacker can time.
+
+/// Encrypt `data` with `key` and `iv`, padding it as PKCS #7 requires
+pub(crate) fn aes256_cbc_encrypt(key: &[u8; 32], iv: &[u8; 16], data: &[u8]) -> Vec<u8> {
+    let mut round_keys = expand_key(key);
+    let pad = BLOCK - data.len() % BLOCK;
+    let mut out = Vec::with_capacity(data.len() + pad);
+    out.extend_from_slice(data);
+    out.resize(data.len() + pad, pad as u8);
+
+    let mut previous = *iv;
+    for chunk in out.chunks_mut(BLOCK) {
+        let mut block = [0; BLOCK];
+        for (i, byte) in block.iter_mut().enumerate() {
+            *byte = chunk[i] ^ previous[i];
+        }
+        encrypt_block(&round_keys, &mut block);
+        chunk.copy_from_slice(&block);
+        previous = block;
+    }
+
+    for round_key in round_keys.iter_mut() {
+        round_key.fill(0);
+    }
+    out
+}
+
+/// Encrypt a single `block` in place with the expanded `round_keys`
+fn encrypt_block(round_keys: &[[u8; BLOCK]; ROUNDS + 1], block: &mut [u8; BLOCK]) {
+    add_round_key(block, &round_keys[0]);
+    for (round, round_key) in round_keys.iter().enumerate().skip(1) {
+        for byte in block.iter_mut() {
+            *byte = SBOX[*byte as usize];
+        }
+        shift_rows(block);
+        if round != ROUNDS {
+            mix_columns(block);
+        }
+        add_round_key(block, round_key);
+    }
+}
+
+fn add_round_key(block: &mut [u8; BLOCK], round_key: &[u8; BLOCK]) {
+    for (byte, key) in block.iter_mut().zip(round_key) {
+        *byte ^= key;
+    }
+}
+
+/// Rotate row `r` of the column-major state left by `r` bytes
+fn shift_rows(block: &mut [u8; BLOCK]) {
+    let state = *block;
+    for column in 0..4 {
+        for row in 0..4 {
+            block[column * 4 + row] = state[((column + row) % 4) * 4 + row];
+        }
+    }
+}
+
+fn mix_columns(block: &mut [u8; BLOCK]) {
+    for column in block.chunks_mut(4) {
+        let [a, b, c, d] = [column[0], column[1], column[2], column[3]];
+        let all = a ^ b ^ c ^ d;
+        column[0] ^= all ^ xtime(a ^ b);
+        column[1] ^= all ^ xtime(b ^ c);
+        column[2] ^= all ^ xtime(c ^ d);
+        column[3] ^= all ^ xtime(d ^ a);
+    }
+}
+
+/// Multiply by x in GF(2^8)
+fn xtime(byte: u8) -> u8 {
+    (byte << 1) ^ ((byte >> 7) * 0x1b)
+}
+
+/// The key schedule of FIPS 197 section 5.2, as one round key per round
+fn expand_key(key: &[u8; 32]) -> [[u8; BLOCK]; ROUNDS + 1] {
+    let mut words = [[0u8; 4]; 4 * (ROUNDS + 1)];
+    for (word, chunk) in words.iter_mut().zip(key.chunks(4)) {
+        word.copy_from_slice(chunk);
+    }
+
+    let mut rcon = 1;
+    for i in KEY_WORDS..words.len() {
+        let mut word = words[i - 1];
+        if i % KEY_WORDS == 0 {
+            word.rotate_left(1);
+            word.iter_mut()
+                .for_each(|byte| *byte = SBOX[*byte as usize]);
+            word[0] ^= rcon;
+            rcon = xtime(rcon);
+        } else if i % KEY_WORDS == 4 {
+            word.iter_mut()
+                .for_each(|byte| *byte = SBOX[*byte as usize]);
+        }
+        for (byte, earlier) in word.iter_mut().zip(words[i - KEY_WORDS]) {
+            *byte ^= earlier;
+        }
+        words[i] = word;
+    }
+
+    let mut round_keys = [[0; BLOCK]; ROUNDS + 1];
+    for (round_key, chunk) in round_keys.iter_mut().zip(words.chunks(4)) {
+        for (i, word) in chunk.iter().enumerate() {
+            round_key[i * 4..i * 4 + 4].copy_from_slice(word);
+        }
+    }
+    for word in words.iter_mut() {
+        word.fill(0);
+    }
+    round_keys
+}
+
+const BLOCK: usize = 16;
+const KEY_WORDS: usize = 8;
+const ROUNDS: usize = 14;
+
+#[rustfmt::skip]
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn fips_197_vector() {
+        // FIPS 197 appendix C.3
+        let key = unhex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let mut block = [0; BLOCK];
+        block.copy_from_slice(&unhex("00112233445566778899aabbccddeeff"));
+        encrypt_block(&expand_key(key[..].try_into().unwrap()), &mut block);
+        assert_eq!(block[..], unhex("8ea2b7ca516745bfeafc49904b496089")[..]);
+    }
+
+    #[test]
+    fn cbc_vector_with_padding() {
+        // NIST SP 800-38A F.2.5, followed by a block of padding
+        let key = unhex("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4");
+        let iv = unhex("000102030405060708090a0b0c0d0e0f");
+        let plaintext = unhex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+        let ciphertext = aes256_cbc_encrypt(
+            key[..].try_into().unwrap(),
+            iv[..].try_into().unwrap(),
+            &plaintext,
+        );
+        assert_eq!(ciphertext.len(), 48);
+        assert_eq!(
+            ciphertext[..32],
+            unhex("f58c4c04d6e5f1ba779eabfb5f7bfbd69cfc4e967edb808d679f777bc6702c7d")[..]
+        );
+    }
+
+    #[test]
+    fn short_input_is_padded_to_a_block() {
+        let ciphertext = aes256_cbc_encrypt(&[0; 32], &[0; 16], b"abc");
+        assert_eq!(ciphertext.len(), BLOCK);
+        assert_eq!(aes256_cbc_encrypt(&[0; 32], &[0; 16], b"").len(), BLOCK);
+    }
+}
@@ -0,0 +1,202 @@
+//! Recording what an account did, for compliance evidence
+//!
+//! Set an [`AuditLog`] with [`AccountConfig::audit_log()`](crate::AccountConfig::audit_log())
+//! to have every order created, every authorization that is no longer pending, every
+//! certificate downloaded and every revocation performed through the account recorded as an
+//! [`AuditEntry`]. [`JsonlAuditLog`] appends the entries to a file, one JSON object per line:
+//!
+//! ```text
+//! {"time":"2024-05-01T12:00:00Z","account":"https://ca.example/acct/1","event":"order_created",...}
+//! ```
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{AuthorizationStatus, ChallengeType, Error, Identifier, Problem, RevocationReason};
+
+/// An append-only record of the actions of an account
+///
+/// Entries are recorded right after the server has confirmed the action. If recording
+/// fails, the operation returns the error of the log even though the server performed the
+/// action, so that no action goes unrecorded unnoticed. Authorizations are recorded once per
+/// [`Order`](crate::Order) value, so that an order resumed after a restart may record them
+/// again.
+pub trait AuditLog: Send + Sync + 'static {
+    /// Append `entry` to the log
+    fn record(&self, entry: &AuditEntry<'_>) -> Result<(), Error>;
+}
+
+impl fmt::Debug for dyn AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog").finish_non_exhaustive()
+    }
+}
+
+/// An action recorded in an [`AuditLog`]
+#[derive(Debug, Serialize)]
+pub struct AuditEntry<'a> {
+    /// When the action was confirmed by the server
+    #[serde(with = "time::serde::rfc3339")]
+    pub time: OffsetDateTime,
+    /// The URL of the account that performed the action
+    pub account: &'a str,
+    /// What was done
+    #[serde(flatten)]
+    pub event: AuditEvent<'a>,
+}
+
+/// What an account did, as recorded in an [`AuditEntry`]
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum AuditEvent<'a> {
+    /// An order was created
+    OrderCreated {
+        /// The URL of the order
+        order: &'a str,
+        /// The identifiers of the order
+        identifiers: &'a [Identifier],
+    },
+    /// An authorization of an order is no longer pending
+    AuthorizationCompleted {
+        /// The URL of the order
+        order: &'a str,
+        /// The URL of the authorization
+        authorization: &'a str,
+        /// The identifier of the authorization
+        identifier: &'a Identifier,
+        /// The new status of the authorization
+        status: AuthorizationStatus,
+        /// The type of the challenge that was validated or failed, if any
+        #[serde(skip_serializing_if = "Option::is_none")]
+        challenge: Option<ChallengeType>,
+        /// Why the challenge failed
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<&'a Problem>,
+    },
+    /// A certificate was downloaded for an order
+    CertificateIssued {
+        /// The URL of the order
+        order: &'a str,
+        /// The serial number of the certificate, in hexadecimal
+        serial: &'a str,
+        /// The time after which the certificate is no longer valid
+        #[serde(with = "time::serde::rfc3339")]
+        not_after: OffsetDateTime,
+    },
+    /// A certificate was revoked
+    CertificateRevoked {
+        /// The serial number of the certificate, in hexadecimal
+        serial: &'a str,
+        /// The reason given for the revocation
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<RevocationReason>,
+    },
+}
+
+/// An [`AuditLog`] that appends entries to a file as JSON lines
+///
+/// Every entry is written and synced to disk before the operation that recorded it returns.
+/// Writes block the task that records the entry.
+pub struct JsonlAuditLog {
+    file: Mutex<File>,
+}
+
+impl JsonlAuditLog {
+    /// Append entries to the file at `path`, creating it if it does not exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AuditLog for JsonlAuditLog {
+    fn record(&self, entry: &AuditEntry<'_>) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(&line)?;
+        Ok(file.sync_data()?)
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::mock::testing::{account, csr, RecordingSolver};
+    use crate::mock::MockServer;
+    use crate::{AccountConfig, NewOrder, RetryPolicy};
+
+    #[tokio::test]
+    async fn recorded_actions() {
+        let path = std::env::temp_dir().join(format!("instant-acme-audit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = Arc::new(JsonlAuditLog::open(&path).unwrap());
+        let account = account(MockServer::new(), AccountConfig::new().audit_log(log)).await;
+        let new_order = NewOrder::builder()
+            .identifier(Identifier::Dns("example.com".to_owned()))
+            .build()
+            .unwrap();
+
+        let policy = RetryPolicy::new().delay(Duration::from_millis(1));
+        let mut order = account.new_order(&new_order).await.unwrap();
+        let guard = order
+            .present_challenges(Arc::new(RecordingSolver::default()))
+            .await
+            .unwrap();
+        order.wait_validated(policy).await.unwrap();
+        guard.cleanup().await.unwrap();
+        let chain = order
+            .finalize_and_collect(&csr(&["example.com"]), policy)
+            .await
+            .unwrap();
+        let leaf = chain.der().unwrap().remove(0);
+        let reason = Some(RevocationReason::Superseded);
+        account.revoke_certificate(&leaf, reason).await.unwrap();
+
+        let entries = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+        let events = entries
+            .iter()
+            .map(|entry| entry["event"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                "order_created",
+                "authorization_completed",
+                "certificate_issued",
+                "certificate_revoked",
+            ]
+        );
+
+        let order_url = order.url();
+        for entry in &entries {
+            assert_eq!(entry["account"], account.id());
+        }
+        assert_eq!(entries[0]["order"], order_url);
+        assert_eq!(entries[0]["identifiers"][0]["value"], "example.com");
+        assert_eq!(entries[1]["status"], "valid");
+        assert_eq!(entries[1]["challenge"], "http-01");
+        assert_eq!(entries[2]["order"], order_url);
+        assert_eq!(entries[3]["serial"], entries[2]["serial"]);
+        assert_eq!(entries[3]["reason"], 4);
+    }
+}
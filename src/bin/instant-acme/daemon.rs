@@ -0,0 +1,432 @@
+//! The `daemon` subcommand: keep the certificates in a configuration file renewed
+//!
+//! ```toml
+//! # Both are optional and override --store and --account
+//! store = "/var/lib/instant-acme"
+//! account = "default"
+//! # Optional; refuse to renew with an account of a staging CA
+//! environment = "production"
+//! renew_before_days = 30
+//! check_interval_hours = 12
+//!
+//! [[certificate]]
+//! name = "example.com"
+//! domains = ["example.com", "*.example.com"]
+//! dns = "cloudflare"
+//! dns_token_env = "CLOUDFLARE_API_TOKEN"
+//! hooks = ["systemctl reload nginx"]
+//! webhooks = ["https://hooks.example.com/certificate-renewed"]
+//! ```
+//!
+//! Hooks run through `sh -c` after a certificate has been issued or renewed, with
+//! `ACME_CERT_NAME`, `ACME_CERT_DOMAINS` (space-separated), `ACME_CERT_FULLCHAIN` and
+//! `ACME_CERT_KEY` set. Webhooks receive a JSON `POST` with the event (`issued`, `renewed`
+//! or `failed`), the name and the domains of the certificate, and its expiry or the error.
+//! Unlike the ACME requests, webhooks may also use plain `http://` URLs, for receivers on
+//! the same host or network.
+//!
+//! On `SIGHUP`, the certificates are reloaded from the configuration file: new and changed
+//! certificates are checked right away, and removed ones are no longer renewed. The other
+//! settings only take effect after a restart.
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request};
+use serde::Deserialize;
+use serde_json::json;
+use time::format_description::well_known::Rfc3339;
+
+use instant_acme::store::FileStore;
+use instant_acme::{
+    Environment, Error, Hook, HookFuture, HttpClient, Identifier, RenewalEvent, RenewalHandle,
+    RenewalOutcome, RenewalReport, RenewalService, RenewalSpec,
+};
+
+use crate::{load_account, DnsArgs, DnsProvider};
+
+pub(crate) async fn run(
+    config_path: &Path,
+    store: PathBuf,
+    account: String,
+    once: bool,
+) -> Result<(), Box<dyn StdError>> {
+    let config = load(config_path)?;
+    let store = FileStore::new(config.store.unwrap_or(store));
+    let account = load_account(&store, &config.account.unwrap_or(account)).await?;
+    let store = Arc::new(store);
+
+    let current = certificates(config.certificates, &[])?;
+    let hooks = Arc::new(ConfigHooks {
+        http: webhook_client(),
+        store: store.clone(),
+        certificates: RwLock::new(certificate_hooks(&current)),
+    });
+    let mut service = RenewalService::new(account, store)
+        .renew_before(Duration::from_secs(config.renew_before_days * 24 * 60 * 60))
+        .check_interval(Duration::from_secs(config.check_interval_hours * 60 * 60))
+        .hook(hooks.clone());
+    if let Some(environment) = config.environment {
+        service = service.environment(environment);
+    }
+    for (_, spec) in &current {
+        service = service.certificate(spec.clone());
+    }
+
+    if once {
+        service.run_once().await.into_iter().for_each(print_report);
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    tokio::spawn(reload(
+        config_path.to_owned(),
+        service.handle(),
+        hooks,
+        current,
+    ));
+    service.run(print_report).await;
+    Ok(())
+}
+
+fn load(config_path: &Path) -> Result<Config, Box<dyn StdError>> {
+    let data = std::fs::read_to_string(config_path)?;
+    let document = crate::toml::parse(&data)
+        .map_err(|e| format!("invalid configuration {}: {e}", config_path.display()))?;
+    Ok(serde_json::from_value::<Config>(document)
+        .map_err(|e| format!("invalid configuration {}: {e}", config_path.display()))?)
+}
+
+/// Reload the certificates from the configuration file on every `SIGHUP`
+#[cfg(unix)]
+async fn reload(
+    config_path: PathBuf,
+    handle: RenewalHandle,
+    hooks: Arc<ConfigHooks>,
+    mut current: Vec<(CertificateConfig, RenewalSpec)>,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            eprintln!("failed to listen for SIGHUP: {e}");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        let updated =
+            load(&config_path).and_then(|config| certificates(config.certificates, &current));
+        match updated {
+            Ok(updated) => {
+                *hooks.certificates.write().unwrap() = certificate_hooks(&updated);
+                handle.set_certificates(updated.iter().map(|(_, spec)| spec.clone()).collect());
+                eprintln!("reloaded {}", config_path.display());
+                current = updated;
+            }
+            Err(e) => eprintln!("failed to reload {}: {e}", config_path.display()),
+        }
+    }
+}
+
+/// Build the specs for the certificates in the configuration
+///
+/// Certificates that are configured like one in `previous` keep its spec, so that a reload
+/// does not check them again.
+fn certificates(
+    configs: Vec<CertificateConfig>,
+    previous: &[(CertificateConfig, RenewalSpec)],
+) -> Result<Vec<(CertificateConfig, RenewalSpec)>, Box<dyn StdError>> {
+    let mut certificates = Vec::with_capacity(configs.len());
+    for certificate in configs {
+        if let Some((_, spec)) = previous.iter().find(|(c, _)| c.same_spec(&certificate)) {
+            let spec = spec.clone();
+            certificates.push((certificate, spec));
+            continue;
+        }
+
+        let first = match certificate.domains.first() {
+            Some(domain) => domain.clone(),
+            None => return Err("certificate without domains in configuration".into()),
+        };
+        let name = certificate
+            .name
+            .clone()
+            .unwrap_or_else(|| first.trim_start_matches("*.").to_owned());
+
+        let token = match (&certificate.dns_token, &certificate.dns_token_env) {
+            (Some(token), _) => Some(token.clone()),
+            (None, Some(var)) => Some(std::env::var(var).map_err(|_| format!("{var} is not set"))?),
+            (None, None) => std::env::var("ACME_DNS_TOKEN").ok(),
+        };
+        let dns = DnsArgs {
+            dns: certificate.dns,
+            zone: certificate.zone.clone(),
+            dns_token: token,
+            dns_command: certificate.dns_command.clone(),
+        };
+
+        let identifiers = certificate
+            .domains
+            .iter()
+            .cloned()
+            .map(Identifier::Dns)
+            .collect();
+        let solver = dns.solver(&first)?;
+        let spec = RenewalSpec::new(name, identifiers, solver);
+        certificates.push((certificate, spec));
+    }
+    Ok(certificates)
+}
+
+fn certificate_hooks(
+    certificates: &[(CertificateConfig, RenewalSpec)],
+) -> HashMap<String, CertificateHooks> {
+    certificates
+        .iter()
+        .map(|(certificate, spec)| {
+            let hooks = CertificateHooks {
+                domains: certificate.domains.clone(),
+                commands: certificate.hooks.clone(),
+                webhooks: certificate.webhooks.clone(),
+            };
+            (spec.name.clone(), hooks)
+        })
+        .collect()
+}
+
+fn print_report(report: RenewalReport) {
+    let (event, not_after) = match report.outcome {
+        Ok(RenewalOutcome::Issued { not_after }) => ("issued", not_after),
+        Ok(RenewalOutcome::Renewed { not_after }) => ("renewed", not_after),
+        Ok(RenewalOutcome::Reissued { not_after }) => ("reissued", not_after),
+        Ok(RenewalOutcome::NotDue { not_after }) => ("not due", not_after),
+        Err(e) => {
+            eprintln!("{}: failed: {e}", report.name);
+            return;
+        }
+    };
+    let not_after = not_after
+        .format(&Rfc3339)
+        .unwrap_or_else(|_| not_after.to_string());
+    eprintln!("{}: {event}, expires {not_after}", report.name);
+    for e in report.hook_errors {
+        eprintln!("{}: {e}", report.name);
+    }
+}
+
+/// Runs the hooks and webhooks from the configuration file
+struct ConfigHooks {
+    /// Sends webhooks, which (unlike the ACME client) allows plain HTTP
+    http: Box<dyn HttpClient>,
+    store: Arc<FileStore>,
+    certificates: RwLock<HashMap<String, CertificateHooks>>,
+}
+
+fn webhook_client() -> Box<dyn HttpClient> {
+    Box::new(
+        hyper::Client::builder().build(
+            hyper_rustls::HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_or_http()
+                .enable_http1()
+                .enable_http2()
+                .build(),
+        ),
+    )
+}
+
+/// The actions to take when a certificate is issued, renewed or fails to renew
+#[derive(Clone)]
+struct CertificateHooks {
+    domains: Vec<String>,
+    commands: Vec<String>,
+    webhooks: Vec<String>,
+}
+
+impl Hook for ConfigHooks {
+    fn call<'a>(&'a self, event: &'a RenewalEvent<'a>) -> HookFuture<'a> {
+        Box::pin(async move {
+            let (name, mut body, certificate) = match *event {
+                RenewalEvent::CertificateIssued { name, certificate } => {
+                    (name, json!({ "event": "issued" }), Some(certificate))
+                }
+                RenewalEvent::CertificateRenewed { name, certificate } => {
+                    (name, json!({ "event": "renewed" }), Some(certificate))
+                }
+                RenewalEvent::RenewalFailed { name, error, .. } => (
+                    name,
+                    json!({ "event": "failed", "error": error.to_string() }),
+                    None,
+                ),
+                _ => return Ok(()),
+            };
+            let hooks = match self.certificates.read().unwrap().get(name) {
+                Some(hooks) => hooks.clone(),
+                None => return Ok(()),
+            };
+
+            let mut failed = Vec::new();
+            if let Some(certificate) = certificate {
+                body["not_after"] = json!(certificate
+                    .chain
+                    .not_after()?
+                    .format(&Rfc3339)
+                    .map_err(|e| Error::Hook(e.into()))?);
+
+                let dir = self.store.certificate_dir(name)?;
+                for command in &hooks.commands {
+                    let status = tokio::process::Command::new("sh")
+                        .arg("-c")
+                        .arg(command)
+                        .env("ACME_CERT_NAME", name)
+                        .env("ACME_CERT_DOMAINS", hooks.domains.join(" "))
+                        .env("ACME_CERT_FULLCHAIN", dir.join("fullchain.pem"))
+                        .env("ACME_CERT_KEY", dir.join("privkey.pem"))
+                        .status()
+                        .await;
+                    match status {
+                        Ok(status) if status.success() => {}
+                        Ok(status) => failed.push(format!("`{command}` exited with {status}")),
+                        Err(e) => failed.push(format!("`{command}` failed: {e}")),
+                    }
+                }
+            }
+
+            body["name"] = json!(name);
+            body["domains"] = json!(hooks.domains);
+            for url in &hooks.webhooks {
+                let request = Request::builder()
+                    .method(Method::POST)
+                    .uri(url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()));
+                let result = match request {
+                    Ok(request) => self.http.request(request).await,
+                    Err(e) => {
+                        failed.push(format!("webhook {url}: {e}"));
+                        continue;
+                    }
+                };
+                match result {
+                    Ok(rsp) if rsp.status().is_success() => {}
+                    Ok(rsp) => failed.push(format!("webhook {url} returned {}", rsp.status())),
+                    Err(e) => failed.push(format!("webhook {url}: {e}")),
+                }
+            }
+
+            match failed.is_empty() {
+                true => Ok(()),
+                false => Err(Error::Hook(failed.join("; ").into())),
+            }
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    store: Option<PathBuf>,
+    account: Option<String>,
+    environment: Option<Environment>,
+    #[serde(default = "default_renew_before_days")]
+    renew_before_days: u64,
+    #[serde(default = "default_check_interval_hours")]
+    check_interval_hours: u64,
+    #[serde(default, rename = "certificate")]
+    certificates: Vec<CertificateConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CertificateConfig {
+    name: Option<String>,
+    domains: Vec<String>,
+    dns: DnsProvider,
+    zone: Option<String>,
+    dns_token: Option<String>,
+    dns_token_env: Option<String>,
+    dns_command: Option<String>,
+    #[serde(default)]
+    hooks: Vec<String>,
+    #[serde(default)]
+    webhooks: Vec<String>,
+}
+
+impl CertificateConfig {
+    /// Whether `other` results in the same [`RenewalSpec`], ignoring the hooks
+    fn same_spec(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.domains == other.domains
+            && self.dns == other.dns
+            && self.zone == other.zone
+            && self.dns_token == other.dns_token
+            && self.dns_token_env == other.dns_token_env
+            && self.dns_command == other.dns_command
+    }
+}
+
+fn default_renew_before_days() -> u64 {
+    30
+}
+
+fn default_check_interval_hours() -> u64 {
+    12
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn plain_http_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/renewed", listener.local_addr().unwrap());
+        let receiver = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).unwrap();
+                assert_ne!(n, 0);
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let hooks = ConfigHooks {
+            http: webhook_client(),
+            store: Arc::new(FileStore::new(std::env::temp_dir())),
+            certificates: RwLock::new(HashMap::from([(
+                "example.com".to_owned(),
+                CertificateHooks {
+                    domains: vec!["example.com".to_owned()],
+                    commands: Vec::new(),
+                    webhooks: vec![url],
+                },
+            )])),
+        };
+        let identifiers = [Identifier::Dns("example.com".to_owned())];
+        let event = RenewalEvent::RenewalFailed {
+            name: "example.com",
+            identifiers: &identifiers,
+            error: &Error::Str("rate limited"),
+        };
+        hooks.call(&event).await.unwrap();
+
+        let request = receiver.join().unwrap();
+        assert!(request.starts_with("POST /renewed HTTP/1.1\r\n"));
+        assert!(request.contains(r#""event":"failed""#));
+    }
+}
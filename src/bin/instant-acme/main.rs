@@ -0,0 +1,740 @@
+//! Command-line ACME client built on instant-acme
+//!
+//! Accounts and certificates are kept in a [`FileStore`], so that `renew` and `revoke` can
+//! find what `account create` and `issue` stored earlier:
+//!
+//! ```text
+//! instant-acme account create --server letsencrypt-staging --contact mailto:ops@example.com --agree-tos
+//! ACME_DNS_TOKEN=... instant-acme issue -d example.com -d '*.example.com' --dns cloudflare
+//! ACME_DNS_TOKEN=... instant-acme issue -d example.com --dns cloudflare --keep-until-expiring
+//! ACME_DNS_TOKEN=... instant-acme renew --name example.com --dns cloudflare
+//! ACME_DNS_TOKEN=... instant-acme issue -d example.com --dns cloudflare --dry-run
+//! instant-acme revoke --name example.com --reason superseded
+//! ACME_EXPORT_PASSWORD=... instant-acme export --name example.com --to jks --out /etc/tomcat
+//! instant-acme daemon --config /etc/instant-acme.toml
+//! ```
+
+use std::error::Error as StdError;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rcgen::{Certificate, CertificateParams, DistinguishedName};
+use serde::Deserialize;
+use serde_json::json;
+use time::format_description::well_known::Rfc3339;
+
+use instant_acme::solver::{
+    ChallengeSolver, CloudflareSolver, DesecSolver, DigitalOceanSolver, ExecSolver, HetznerSolver,
+    WebhookSolver,
+};
+use instant_acme::store::{
+    check_coverage, issuing_ca, CertStore, Coverage, ExportFormat, FileStore, StoredCertificate,
+};
+use instant_acme::{
+    Account, AuthorizationStatus, CertificateChain, ChallengeType, Clock, Environment, Error,
+    ExternalAccountKey, Identifier, KnownCa, NewAccount, NewOrder, Order, OrderStatus, RetryPolicy,
+    RevocationReason, SystemClock,
+};
+
+mod daemon;
+mod toml;
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli).await {
+        eprintln!("error: {e}");
+        process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn StdError>> {
+    let store = FileStore::new(&cli.store);
+    match cli.command {
+        Command::Account {
+            command: AccountCommand::Create(args),
+        } => {
+            let account = create_account(&store, &cli.account, args).await?;
+            match cli.format {
+                Format::Pem => println!("{}", account.id()),
+                Format::Json => print_json(&json!({ "name": cli.account, "id": account.id() })),
+            }
+        }
+        Command::Order { domains } => {
+            let account = load_account(&store, &cli.account).await?;
+            let mut order = account.new_order(&new_order(&domains)?).await?;
+            print_order(&mut order, cli.format).await?;
+        }
+        Command::Issue {
+            domains,
+            name,
+            dns,
+            dry_run,
+            keep_until_expiring,
+            days,
+            deployed,
+        } => {
+            let identifiers = identifiers(&domains);
+            let name = name.unwrap_or_else(|| domains[0].trim_start_matches("*.").to_owned());
+            if keep_until_expiring && !dry_run {
+                let renew_before = Duration::from_secs(days * 24 * 60 * 60);
+                let mut coverage =
+                    check_coverage(&store, &name, &identifiers, renew_before, &SystemClock).await?;
+                if let (Coverage::Needed, Some(path)) = (coverage, &deployed) {
+                    let pem = std::fs::read_to_string(path)
+                        .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                    let chain = CertificateChain::from_pem(pem)?;
+                    coverage = Coverage::of(&chain, &identifiers, renew_before, &SystemClock)?;
+                }
+                if let Coverage::AlreadyCovered { not_after } = coverage {
+                    let not_after = not_after.format(&Rfc3339)?;
+                    match cli.format {
+                        Format::Pem => eprintln!(
+                            "{name} is already covered by a certificate that expires {not_after}"
+                        ),
+                        Format::Json => print_json(&json!({
+                            "name": name,
+                            "issued": false,
+                            "not_after": not_after,
+                        })),
+                    }
+                    return Ok(());
+                }
+            }
+
+            let mut account = load_account(&store, &cli.account).await?;
+            if dry_run {
+                account = staging_account(&account).await?;
+            }
+            let certificate = issue(&account, identifiers, dns.solver(&domains[0])?).await?;
+            match dry_run {
+                true => print_dry_run(&name, &certificate, cli.format)?,
+                false => {
+                    store.save_certificate(&name, &certificate).await?;
+                    print_certificate(&name, &certificate, cli.format)?;
+                }
+            }
+        }
+        Command::Renew {
+            name,
+            days,
+            force,
+            dns,
+            dry_run,
+        } => {
+            let stored = store
+                .load_certificate(&name)
+                .await?
+                .ok_or("no certificate with this name in the store")?;
+            let remaining = stored.chain.not_after()? - SystemClock.now_utc();
+            if !force && !dry_run && remaining > time::Duration::days(days) {
+                match cli.format {
+                    Format::Pem => eprintln!("{name} is not due for renewal"),
+                    Format::Json => print_json(&json!({
+                        "name": name,
+                        "renewed": false,
+                        "not_after": stored.chain.not_after()?.format(&Rfc3339)?,
+                    })),
+                }
+                return Ok(());
+            }
+
+            let mut account = load_account(&store, &cli.account).await?;
+            if dry_run {
+                account = staging_account(&account).await?;
+            }
+            let solver = dns.solver(stored.identifiers[0].value())?;
+            let certificate = issue(&account, stored.identifiers.clone(), solver).await?;
+            match dry_run {
+                true => print_dry_run(&name, &certificate, cli.format)?,
+                false => {
+                    store.save_certificate(&name, &certificate).await?;
+                    print_certificate(&name, &certificate, cli.format)?;
+                }
+            }
+        }
+        Command::Revoke { name, reason } => {
+            let stored = store
+                .load_certificate(&name)
+                .await?
+                .ok_or("no certificate with this name in the store")?;
+            let account = load_account(&store, &cli.account).await?;
+            let leaf = stored.chain.der()?.remove(0);
+            account
+                .revoke_certificate(&leaf, reason.map(RevocationReason::from))
+                .await?;
+            match cli.format {
+                Format::Pem => eprintln!("revoked {name}"),
+                Format::Json => print_json(&json!({ "name": name, "revoked": true })),
+            }
+        }
+        Command::Export {
+            name,
+            to,
+            out,
+            password,
+        } => {
+            let stored = store
+                .load_certificate(&name)
+                .await?
+                .ok_or("no certificate with this name in the store")?;
+            let format = ExportFormat::from(to);
+            let password = match (format, password) {
+                (_, Some(password)) => password,
+                (ExportFormat::Pkcs12 | ExportFormat::Jks, None) => {
+                    return Err("PKCS #12 and JKS files need a password".into())
+                }
+                (_, None) => String::new(),
+            };
+
+            let mut paths = Vec::new();
+            for file in stored.export(&name, format, &password)? {
+                paths.push(file.write_to(&out)?.display().to_string());
+            }
+            match cli.format {
+                Format::Pem => {
+                    for path in &paths {
+                        eprintln!("wrote {path}");
+                    }
+                }
+                Format::Json => print_json(&json!({ "name": name, "files": paths })),
+            }
+        }
+        Command::Daemon { config, once } => {
+            daemon::run(&config, cli.store, cli.account, once).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn create_account(
+    store: &FileStore,
+    name: &str,
+    args: CreateAccount,
+) -> Result<Account, Box<dyn StdError>> {
+    if !args.agree_tos {
+        return Err("the CA's terms of service must be accepted with --agree-tos".into());
+    }
+
+    let mut new_account = NewAccount::builder().terms_of_service_agreed(true);
+    for contact in &args.contact {
+        new_account = new_account.contact(contact.clone());
+    }
+
+    let eab = match (args.eab_kid, args.eab_hmac_key) {
+        (Some(kid), Some(key)) => Some(ExternalAccountKey::from_base64(kid, &key)?),
+        (None, None) => None,
+        _ => return Err("--eab-kid and --eab-hmac-key must be used together".into()),
+    };
+
+    let server_url = server_url(&args.server)?;
+    let mut builder = Account::builder();
+    if server_url != args.server {
+        builder = builder.ca_label(args.server.clone());
+    }
+    let account = builder
+        .create(&new_account.build()?, &server_url, eab.as_ref())
+        .await?;
+    store.save_account(name, &account.credentials()).await?;
+    Ok(account)
+}
+
+/// An account with the staging environment of `account`'s CA, for dry runs
+///
+/// Staging accounts are used as they are. For production CAs, a throwaway account is
+/// registered with their staging environment, since staging does not know production
+/// accounts.
+async fn staging_account(account: &Account) -> Result<Account, Box<dyn StdError>> {
+    if account.environment() == Some(Environment::Staging) {
+        return Ok(account.clone());
+    }
+
+    let staging = account
+        .directory_url()
+        .and_then(KnownCa::from_url)
+        .and_then(|ca| ca.staging())
+        .ok_or("dry runs need an account with a CA that has a known staging environment")?;
+    if staging.requires_eab() {
+        return Err(format!(
+            "{staging:?} requires external account binding; create an account for it and pass \
+             it with --account instead"
+        )
+        .into());
+    }
+
+    let new_account = NewAccount::builder()
+        .terms_of_service_agreed(true)
+        .build()?;
+    Ok(Account::create(&new_account, staging.url(), None).await?)
+}
+
+async fn load_account(store: &FileStore, name: &str) -> Result<Account, Box<dyn StdError>> {
+    let credentials = store
+        .load_account(name)
+        .await?
+        .ok_or("no account with this name in the store; run `account create` first")?;
+    Ok(Account::from_credentials(credentials)?)
+}
+
+/// Order a certificate for `identifiers`, solving DNS-01 challenges with `solver`
+async fn issue(
+    account: &Account,
+    identifiers: Vec<Identifier>,
+    solver: Arc<dyn ChallengeSolver>,
+) -> Result<StoredCertificate, Box<dyn StdError>> {
+    let mut order = account
+        .new_order(
+            &NewOrder::builder()
+                .identifiers(identifiers.clone())
+                .build()?,
+        )
+        .await?;
+
+    let guard = order.present_challenges(solver).await?;
+    let validated = order.wait_validated(RetryPolicy::default()).await;
+    let cleaned = guard.cleanup().await;
+    validated?;
+    if let Err(e) = cleaned {
+        eprintln!("warning: failed to clean up challenge responses: {e}");
+    }
+    if order.state().status == OrderStatus::Invalid {
+        return Err(Error::InvalidState {
+            expected: "ready",
+            actual: "invalid".to_owned(),
+        }
+        .into());
+    }
+
+    let names = identifiers
+        .iter()
+        .map(|identifier| identifier.value().to_owned())
+        .collect::<Vec<_>>();
+    let mut params = CertificateParams::new(names);
+    params.distinguished_name = DistinguishedName::new();
+    let key = Certificate::from_params(params)?;
+    let chain = order
+        .finalize_and_collect(&key.serialize_request_der()?, RetryPolicy::default())
+        .await?;
+
+    Ok(StoredCertificate {
+        identifiers,
+        chain,
+        private_key_pem: key.serialize_private_key_pem(),
+        ca: issuing_ca(account),
+    })
+}
+
+async fn print_order(order: &mut Order, format: Format) -> Result<(), Box<dyn StdError>> {
+    if let Format::Json = format {
+        let url = order.url().to_owned();
+        print_json(&json!({ "url": url, "state": order.state() }));
+        return Ok(());
+    }
+
+    println!("order: {}", order.url());
+    println!("status: {:?}", order.state().status);
+    for authz in order.authorizations().await? {
+        if authz.status != AuthorizationStatus::Pending {
+            continue;
+        }
+
+        let name = authz.identifier.value();
+        if let Some(challenge) = authz.find_challenge(ChallengeType::Dns01) {
+            let value = order.key_authorization(challenge).dns_value();
+            let name = name.trim_start_matches("*.");
+            println!("_acme-challenge.{name}. IN TXT \"{value}\"");
+        }
+    }
+    Ok(())
+}
+
+fn print_certificate(
+    name: &str,
+    certificate: &StoredCertificate,
+    format: Format,
+) -> Result<(), Box<dyn StdError>> {
+    match format {
+        Format::Pem => print!("{}", certificate.chain.pem()),
+        Format::Json => print_json(&json!({
+            "name": name,
+            "identifiers": certificate.identifiers,
+            "not_after": certificate.chain.not_after()?.format(&Rfc3339)?,
+            "chain": certificate.chain.pem(),
+        })),
+    }
+    Ok(())
+}
+
+fn print_dry_run(
+    name: &str,
+    certificate: &StoredCertificate,
+    format: Format,
+) -> Result<(), Box<dyn StdError>> {
+    let not_after = certificate.chain.not_after()?.format(&Rfc3339)?;
+    match format {
+        Format::Pem => eprintln!("dry run for {name} succeeded (expires {not_after}, not stored)"),
+        Format::Json => print_json(&json!({
+            "name": name,
+            "dry_run": true,
+            "identifiers": certificate.identifiers,
+            "not_after": not_after,
+        })),
+    }
+    Ok(())
+}
+
+fn print_json(value: &serde_json::Value) {
+    // Serializing a `Value` cannot fail
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+fn new_order(domains: &[String]) -> Result<NewOrder, Error> {
+    NewOrder::builder()
+        .identifiers(identifiers(domains))
+        .build()
+}
+
+fn identifiers(domains: &[String]) -> Vec<Identifier> {
+    domains.iter().cloned().map(Identifier::Dns).collect()
+}
+
+/// Resolve a directory URL or the name of a well-known CA
+fn server_url(server: &str) -> Result<String, Box<dyn StdError>> {
+    if server.starts_with("https://") || server.starts_with("http://") {
+        return Ok(server.to_owned());
+    }
+
+    let ca = match server {
+        "letsencrypt" => KnownCa::LetsEncrypt,
+        "letsencrypt-staging" => KnownCa::LetsEncryptStaging,
+        "zerossl" => KnownCa::ZeroSsl,
+        "google" => KnownCa::GoogleTrustServices,
+        "google-staging" => KnownCa::GoogleTrustServicesStaging,
+        "buypass" => KnownCa::BuypassGo,
+        "buypass-staging" => KnownCa::BuypassGoStaging,
+        "sslcom-rsa" => KnownCa::SslComRsa,
+        "sslcom-ecc" => KnownCa::SslComEcc,
+        "sectigo" => KnownCa::SectigoDv,
+        _ => return Err(format!("unknown CA `{server}`; pass a directory URL instead").into()),
+    };
+    Ok(ca.url().to_owned())
+}
+
+/// Command-line ACME client
+#[derive(Parser)]
+#[command(name = "instant-acme", version)]
+struct Cli {
+    /// Directory for account credentials and certificates
+    #[arg(
+        long,
+        global = true,
+        env = "INSTANT_ACME_STORE",
+        default_value = "instant-acme"
+    )]
+    store: PathBuf,
+    /// Name of the account in the store
+    #[arg(long, global = true, default_value = "default")]
+    account: String,
+    /// Output format
+    #[arg(long, global = true, value_enum, default_value = "pem")]
+    format: Format,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage ACME accounts
+    Account {
+        #[command(subcommand)]
+        command: AccountCommand,
+    },
+    /// Create an order and show the DNS records needed to validate it
+    Order {
+        /// Domain names to order a certificate for
+        #[arg(short, long = "domain", required = true)]
+        domains: Vec<String>,
+    },
+    /// Order a certificate, solving DNS-01 challenges automatically, and store it
+    Issue {
+        /// Domain names to order a certificate for
+        #[arg(short, long = "domain", required = true)]
+        domains: Vec<String>,
+        /// Name of the certificate in the store (defaults to the first domain)
+        #[arg(long)]
+        name: Option<String>,
+        #[command(flatten)]
+        dns: DnsArgs,
+        /// Issue from the CA's staging environment and don't store the certificate
+        #[arg(long)]
+        dry_run: bool,
+        /// Don't issue if the stored or deployed certificate covers the domains and does
+        /// not expire soon
+        #[arg(long)]
+        keep_until_expiring: bool,
+        /// With --keep-until-expiring, issue if the certificate expires within this many days
+        #[arg(long, default_value = "30")]
+        days: u64,
+        /// With --keep-until-expiring, also check the PEM certificate chain at this path
+        #[arg(long, requires = "keep_until_expiring")]
+        deployed: Option<PathBuf>,
+    },
+    /// Issue a stored certificate again if it expires soon
+    Renew {
+        /// Name of the certificate in the store
+        #[arg(long)]
+        name: String,
+        /// Renew if the certificate expires within this many days
+        #[arg(long, default_value = "30")]
+        days: i64,
+        /// Renew even if the certificate does not expire soon
+        #[arg(long)]
+        force: bool,
+        #[command(flatten)]
+        dns: DnsArgs,
+        /// Renew from the CA's staging environment, whether or not the certificate expires
+        /// soon, and don't store the new certificate
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Revoke a stored certificate
+    Revoke {
+        /// Name of the certificate in the store
+        #[arg(long)]
+        name: String,
+        /// Reason for the revocation
+        #[arg(long, value_enum)]
+        reason: Option<Reason>,
+    },
+    /// Write a stored certificate and its key in a format for servers that don't read PEM files
+    Export {
+        /// Name of the certificate in the store
+        #[arg(long)]
+        name: String,
+        /// Format to write the certificate in (the global --format sets the output format)
+        #[arg(long, value_enum)]
+        to: Export,
+        /// Directory to write the files to
+        #[arg(long)]
+        out: PathBuf,
+        /// Password protecting PKCS #12 and JKS files
+        #[arg(long, env = "ACME_EXPORT_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Keep the certificates in a configuration file issued and renewed
+    Daemon {
+        /// Path to the TOML configuration file
+        #[arg(long)]
+        config: PathBuf,
+        /// Check the certificates once and exit
+        #[arg(long)]
+        once: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AccountCommand {
+    /// Create an account and store its credentials
+    Create(CreateAccount),
+}
+
+#[derive(Args)]
+struct CreateAccount {
+    /// Directory URL, or the name of a well-known CA (like `letsencrypt` or `zerossl`)
+    #[arg(long, default_value = "letsencrypt")]
+    server: String,
+    /// Contact URLs, like `mailto:ops@example.com`
+    #[arg(long)]
+    contact: Vec<String>,
+    /// Agree to the CA's terms of service
+    #[arg(long)]
+    agree_tos: bool,
+    /// Key identifier for external account binding
+    #[arg(long)]
+    eab_kid: Option<String>,
+    /// Base64url-encoded HMAC key for external account binding
+    #[arg(long)]
+    eab_hmac_key: Option<String>,
+}
+
+#[derive(Args)]
+struct DnsArgs {
+    /// DNS provider used to solve DNS-01 challenges
+    #[arg(long, value_enum)]
+    dns: DnsProvider,
+    /// DNS zone (or domain, for deSEC and DigitalOcean) holding the records
+    ///
+    /// Defaults to the first domain, without any wildcard prefix.
+    #[arg(long)]
+    zone: Option<String>,
+    /// API token for the DNS provider, or the bearer token for `webhook`
+    #[arg(long, env = "ACME_DNS_TOKEN", hide_env_values = true)]
+    dns_token: Option<String>,
+    /// Program to run for `exec`, or the URL to call for `webhook`
+    #[arg(long)]
+    dns_command: Option<String>,
+}
+
+impl DnsArgs {
+    fn solver(&self, domain: &str) -> Result<Arc<dyn ChallengeSolver>, Box<dyn StdError>> {
+        let zone = self
+            .zone
+            .clone()
+            .unwrap_or_else(|| domain.trim_start_matches("*.").to_owned());
+        let token = || {
+            self.dns_token
+                .clone()
+                .ok_or("--dns-token (or ACME_DNS_TOKEN) is required for this provider")
+        };
+        let command = || {
+            self.dns_command
+                .clone()
+                .ok_or("--dns-command is required for this provider")
+        };
+
+        Ok(match self.dns {
+            DnsProvider::Cloudflare => Arc::new(CloudflareSolver::new(zone, token()?)),
+            DnsProvider::Desec => Arc::new(DesecSolver::new(zone, token()?)),
+            DnsProvider::Digitalocean => Arc::new(DigitalOceanSolver::new(zone, token()?)),
+            DnsProvider::Hetzner => Arc::new(HetznerSolver::new(zone, token()?)),
+            DnsProvider::Exec => Arc::new(ExecSolver::new(command()?)),
+            DnsProvider::Webhook => {
+                let solver = WebhookSolver::new(command()?);
+                match &self.dns_token {
+                    Some(token) => Arc::new(solver.bearer_token(token.clone())),
+                    None => Arc::new(solver),
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Eq, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum DnsProvider {
+    Cloudflare,
+    Desec,
+    Digitalocean,
+    Hetzner,
+    Exec,
+    Webhook,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Pem,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Export {
+    Haproxy,
+    Certbot,
+    Der,
+    Pkcs12,
+    Jks,
+}
+
+impl From<Export> for ExportFormat {
+    fn from(format: Export) -> Self {
+        match format {
+            Export::Haproxy => ExportFormat::CombinedPem,
+            Export::Certbot => ExportFormat::Certbot,
+            Export::Der => ExportFormat::Der,
+            Export::Pkcs12 => ExportFormat::Pkcs12,
+            Export::Jks => ExportFormat::Jks,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Reason {
+    Unspecified,
+    KeyCompromise,
+    Superseded,
+    AffiliationChanged,
+    CessationOfOperation,
+}
+
+impl From<Reason> for RevocationReason {
+    fn from(reason: Reason) -> Self {
+        match reason {
+            Reason::Unspecified => RevocationReason::Unspecified,
+            Reason::KeyCompromise => RevocationReason::KeyCompromise,
+            Reason::Superseded => RevocationReason::Superseded,
+            Reason::AffiliationChanged => RevocationReason::AffiliationChanged,
+            Reason::CessationOfOperation => RevocationReason::CessationOfOperation,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use instant_acme::mock::MockServer;
+    use instant_acme::solver::{SolverChallenge, SolverFuture};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn issue_certificate() {
+        let account = mock_account(MockServer::new()).await;
+        let identifiers = identifiers(&["example.com".to_owned()]);
+        let certificate = issue(&account, identifiers.clone(), Arc::new(NoopSolver))
+            .await
+            .unwrap();
+        assert_eq!(certificate.identifiers, identifiers);
+        assert_eq!(certificate.ca.as_deref(), Some(MockServer::DIRECTORY_URL));
+        assert_eq!(certificate.chain.der().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn invalid_challenges() {
+        let account = mock_account(MockServer::new().with_invalid_challenges()).await;
+        let identifiers = identifiers(&["example.com".to_owned()]);
+        let err = issue(&account, identifiers, Arc::new(NoopSolver))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::InvalidState {
+                    expected: "ready",
+                    ..
+                })
+            ),
+            "unexpected error: {err}"
+        );
+    }
+
+    async fn mock_account(server: MockServer) -> Account {
+        let new_account = NewAccount::builder()
+            .terms_of_service_agreed(true)
+            .build()
+            .unwrap();
+        Account::builder()
+            .http(Box::new(server))
+            .create(&new_account, MockServer::DIRECTORY_URL, None)
+            .await
+            .unwrap()
+    }
+
+    struct NoopSolver;
+
+    impl ChallengeSolver for NoopSolver {
+        fn challenge_type(&self) -> ChallengeType {
+            ChallengeType::Dns01
+        }
+
+        fn present<'a>(&'a self, _: &'a SolverChallenge) -> SolverFuture<'a> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn cleanup<'a>(&'a self, _: &'a SolverChallenge) -> SolverFuture<'a> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+}
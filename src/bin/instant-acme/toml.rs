@@ -0,0 +1,893 @@
+//! A TOML parser for daemon configuration files
+//!
+//! Implements TOML 1.0: comments, tables and arrays of tables, bare, quoted and dotted keys,
+//! inline tables, arrays, basic and literal strings (on one or multiple lines), integers in
+//! decimal, hexadecimal, octal and binary, floats, booleans and date-times. The document is
+//! returned as JSON and deserialized into the configuration types with serde, which keeps
+//! the dependencies of the CLI to those of the library. Date-times are returned as RFC 3339
+//! strings; infinite and NaN floats can't be represented in JSON and are rejected.
+
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Number, Value};
+
+pub(crate) fn parse(input: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    parser
+        .document()
+        .map_err(|e| format!("line {}: {e}", parser.line))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+/// A table of the document being parsed
+struct Table {
+    items: BTreeMap<String, Item>,
+    kind: Kind,
+}
+
+impl Table {
+    fn new(kind: Kind) -> Self {
+        Self {
+            items: BTreeMap::new(),
+            kind,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        let mut map = Map::new();
+        for (key, item) in self.items {
+            let value = match item {
+                Item::Value(value) => value,
+                Item::Table(table) => table.into_value(),
+                Item::Tables(tables) => {
+                    Value::Array(tables.into_iter().map(Table::into_value).collect())
+                }
+            };
+            map.insert(key, value);
+        }
+        Value::Object(map)
+    }
+}
+
+/// How a table was defined, which determines how it may be extended
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// Created as a parent of a table header, so it can still get a header of its own
+    Implicit,
+    /// Defined by a table header (or the root table)
+    Header,
+    /// Created by a dotted key, so it can only be extended by dotted keys
+    Dotted,
+}
+
+enum Item {
+    /// A value, including arrays and inline tables, which can't be extended
+    Value(Value),
+    Table(Table),
+    /// An array of tables, defined by `[[header]]`s
+    Tables(Vec<Table>),
+}
+
+impl Parser {
+    fn document(&mut self) -> Result<Value, String> {
+        let mut root = Table::new(Kind::Header);
+        let mut current = Vec::new();
+        loop {
+            self.skip_blank(true);
+            match self.peek() {
+                None => return Ok(root.into_value()),
+                Some('[') => {
+                    self.pos += 1;
+                    let array = self.eat('[');
+                    self.skip_spaces();
+                    let path = self.key_path()?;
+                    self.skip_spaces();
+                    self.expect(']')?;
+                    if array {
+                        self.expect(']')?;
+                    }
+                    self.end_of_line()?;
+                    define(&mut root, &path, array)?;
+                    current = path;
+                }
+                Some(_) => {
+                    let path = self.key_path()?;
+                    self.skip_spaces();
+                    self.expect('=')?;
+                    self.skip_spaces();
+                    let value = self.value()?;
+                    self.end_of_line()?;
+                    insert(lookup(&mut root, &current), &path, value)?;
+                }
+            }
+        }
+    }
+
+    /// A key, which may be dotted
+    fn key_path(&mut self) -> Result<Vec<String>, String> {
+        let mut path = vec![self.key()?];
+        loop {
+            self.skip_spaces();
+            if !self.eat('.') {
+                return Ok(path);
+            }
+            self.skip_spaces();
+            path.push(self.key()?);
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('"') => {
+                self.pos += 1;
+                self.basic_string()
+            }
+            Some('\'') => {
+                self.pos += 1;
+                self.literal_string()
+            }
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    self.pos += 1;
+                }
+                match self.pos > start {
+                    true => Ok(self.chars[start..self.pos].iter().collect()),
+                    false => Err("expected a key".to_owned()),
+                }
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') if self.lookahead("\"\"\"") => {
+                self.pos += 3;
+                self.multiline_basic_string().map(Value::String)
+            }
+            Some('"') => {
+                self.pos += 1;
+                self.basic_string().map(Value::String)
+            }
+            Some('\'') if self.lookahead("'''") => {
+                self.pos += 3;
+                self.multiline_literal_string().map(Value::String)
+            }
+            Some('\'') => {
+                self.pos += 1;
+                self.literal_string().map(Value::String)
+            }
+            Some('[') => {
+                self.pos += 1;
+                self.array()
+            }
+            Some('{') => {
+                self.pos += 1;
+                self.inline_table()
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphabetic()) {
+                    self.pos += 1;
+                }
+                match self.chars[start..self.pos]
+                    .iter()
+                    .collect::<String>()
+                    .as_str()
+                {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "inf" | "nan" => Err("infinite and NaN floats are not supported".to_owned()),
+                    word => Err(format!("unexpected `{word}`; strings must be quoted")),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '-' || c == '+' => self.number_or_date_time(),
+            _ => Err("expected a value".to_owned()),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank(true);
+            if self.eat(']') {
+                return Ok(Value::Array(items));
+            }
+
+            items.push(self.value()?);
+            self.skip_blank(true);
+            if !self.eat(',') {
+                self.expect(']')?;
+                return Ok(Value::Array(items));
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, String> {
+        let mut table = Table::new(Kind::Header);
+        self.skip_spaces();
+        if self.eat('}') {
+            return Ok(table.into_value());
+        }
+
+        loop {
+            let path = self.key_path()?;
+            self.skip_spaces();
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            insert(&mut table, &path, value)?;
+            self.skip_spaces();
+            match self.eat(',') {
+                true => self.skip_spaces(),
+                false => {
+                    self.expect('}')?;
+                    return Ok(table.into_value());
+                }
+            }
+        }
+    }
+
+    /// A basic string, after the opening quote
+    fn basic_string(&mut self) -> Result<String, String> {
+        let mut out = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(out),
+                Some('\\') => out.push(self.escape()?),
+                Some('\n') | None => return Err("unterminated string".to_owned()),
+                Some(c) => out.push(allowed(c)?),
+            }
+        }
+    }
+
+    /// A multi-line basic string, after the opening quotes
+    fn multiline_basic_string(&mut self) -> Result<String, String> {
+        self.skip_newline();
+        let mut out = String::new();
+        loop {
+            match self.next() {
+                Some('"') => {
+                    if let Some(quotes) = self.closing_quotes('"')? {
+                        out.extend(std::iter::repeat('"').take(quotes));
+                        return Ok(out);
+                    }
+                    out.push('"');
+                }
+                Some('\\') => {
+                    // A line ending backslash trims all whitespace up to the next content
+                    let start = self.pos;
+                    while matches!(self.peek(), Some(' ' | '\t')) {
+                        self.pos += 1;
+                    }
+                    match self.peek() {
+                        Some('\n' | '\r') => {
+                            while matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) {
+                                self.next();
+                            }
+                        }
+                        _ => {
+                            self.pos = start;
+                            out.push(self.escape()?);
+                        }
+                    }
+                }
+                Some('\n') => out.push('\n'),
+                Some('\r') if self.eat('\n') => out.push('\n'),
+                None => return Err("unterminated string".to_owned()),
+                Some(c) => out.push(allowed(c)?),
+            }
+        }
+    }
+
+    /// A literal string, after the opening quote
+    fn literal_string(&mut self) -> Result<String, String> {
+        let mut out = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(out),
+                Some('\n') | None => return Err("unterminated string".to_owned()),
+                Some(c) => out.push(allowed(c)?),
+            }
+        }
+    }
+
+    /// A multi-line literal string, after the opening quotes
+    fn multiline_literal_string(&mut self) -> Result<String, String> {
+        self.skip_newline();
+        let mut out = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => {
+                    if let Some(quotes) = self.closing_quotes('\'')? {
+                        out.extend(std::iter::repeat('\'').take(quotes));
+                        return Ok(out);
+                    }
+                    out.push('\'');
+                }
+                Some('\n') => out.push('\n'),
+                Some('\r') if self.eat('\n') => out.push('\n'),
+                None => return Err("unterminated string".to_owned()),
+                Some(c) => out.push(allowed(c)?),
+            }
+        }
+    }
+
+    /// After a quote in a multi-line string, check whether it ends the string
+    ///
+    /// Returns the number of quotes that belong to the content (up to two quotes may precede
+    /// the closing delimiter), or `None` if the string continues.
+    fn closing_quotes(&mut self, quote: char) -> Result<Option<usize>, String> {
+        let mut count = 1;
+        while self.peek() == Some(quote) {
+            self.pos += 1;
+            count += 1;
+        }
+        match count {
+            1 | 2 => {
+                self.pos -= count - 1;
+                Ok(None)
+            }
+            3..=5 => Ok(Some(count - 3)),
+            _ => Err("too many quotes at the end of a multi-line string".to_owned()),
+        }
+    }
+
+    /// An escape sequence in a basic string, after the backslash
+    fn escape(&mut self) -> Result<char, String> {
+        let digits = match self.next() {
+            Some('b') => return Ok('\u{8}'),
+            Some('t') => return Ok('\t'),
+            Some('n') => return Ok('\n'),
+            Some('f') => return Ok('\u{c}'),
+            Some('r') => return Ok('\r'),
+            Some('"') => return Ok('"'),
+            Some('\\') => return Ok('\\'),
+            Some('u') => 4,
+            Some('U') => 8,
+            _ => return Err("invalid escape sequence".to_owned()),
+        };
+
+        let hex = self
+            .chars
+            .get(self.pos..self.pos + digits)
+            .filter(|hex| hex.iter().all(char::is_ascii_hexdigit))
+            .ok_or("invalid Unicode escape sequence")?
+            .iter()
+            .collect::<String>();
+        self.pos += digits;
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("invalid Unicode scalar value `{hex}`"))
+    }
+
+    fn number_or_date_time(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || "_+-.:".contains(c)) {
+            self.pos += 1;
+        }
+        // A date may be separated from the time with a space
+        if self.pos - start == 10
+            && self.peek() == Some(' ')
+            && matches!(self.chars.get(self.pos + 1), Some(c) if c.is_ascii_digit())
+        {
+            self.pos += 1;
+            while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || "+-.:".contains(c))
+            {
+                self.pos += 1;
+            }
+        }
+
+        let token = self.chars[start..self.pos].iter().collect::<String>();
+        if token.contains(':') || (token.len() >= 10 && token[..10].matches('-').count() == 2) {
+            return date_time(&token)
+                .map(Value::String)
+                .ok_or_else(|| format!("invalid date-time `{token}`"));
+        }
+        if matches!(token.trim_start_matches(['+', '-']), "inf" | "nan") {
+            return Err("infinite and NaN floats are not supported".to_owned());
+        }
+        number(&token).ok_or_else(|| format!("invalid number `{token}`"))
+    }
+
+    /// Skip spaces and comments, and newlines if `newlines` is set
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => self.pos += 1,
+                '\n' if newlines => {
+                    self.pos += 1;
+                    self.line += 1;
+                }
+                '#' => {
+                    while !matches!(self.peek(), Some('\n') | None) {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skip the newline directly after the opening quotes of a multi-line string
+    fn skip_newline(&mut self) {
+        if self.lookahead("\r\n") {
+            self.pos += 1;
+        }
+        if self.peek() == Some('\n') {
+            self.next();
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_blank(false);
+        match self.peek() {
+            None | Some('\n') => Ok(()),
+            Some(c) => Err(format!("unexpected `{c}` at end of line")),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.eat(expected) {
+            true => Ok(()),
+            false => Err(format!("expected `{expected}`")),
+        }
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn lookahead(&self, expected: &str) -> bool {
+        expected
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+}
+
+/// Define the table at `path` with a `[header]`, or a new table in the array of tables at
+/// `path` with a `[[header]]` if `array` is set
+fn define(root: &mut Table, path: &[String], array: bool) -> Result<(), String> {
+    let (last, parents) = path.split_last().ok_or("expected a key")?;
+    let mut table = root;
+    for key in parents {
+        let item = table
+            .items
+            .entry(key.clone())
+            .or_insert_with(|| Item::Table(Table::new(Kind::Implicit)));
+        table = match item {
+            Item::Table(table) => table,
+            Item::Tables(tables) => tables.last_mut().unwrap(),
+            Item::Value(_) => return Err(format!("`{key}` is not a table")),
+        };
+    }
+
+    match (table.items.get_mut(last), array) {
+        (None, false) => {
+            table
+                .items
+                .insert(last.clone(), Item::Table(Table::new(Kind::Header)));
+        }
+        (None, true) => {
+            let tables = vec![Table::new(Kind::Header)];
+            table.items.insert(last.clone(), Item::Tables(tables));
+        }
+        (Some(Item::Table(table)), false) if table.kind == Kind::Implicit => {
+            table.kind = Kind::Header;
+        }
+        (Some(Item::Tables(tables)), true) => tables.push(Table::new(Kind::Header)),
+        (Some(_), _) => return Err(format!("`{}` is defined twice", path.join("."))),
+    }
+    Ok(())
+}
+
+/// The table that the `key = value` pairs after the header for `path` are added to
+fn lookup<'a>(root: &'a mut Table, path: &[String]) -> &'a mut Table {
+    let mut table = root;
+    for key in path {
+        table = match table.items.get_mut(key) {
+            Some(Item::Table(table)) => table,
+            Some(Item::Tables(tables)) => tables.last_mut().unwrap(),
+            _ => unreachable!(),
+        };
+    }
+    table
+}
+
+/// Add the value of a `key = value` pair to `table`, creating the tables of a dotted key
+fn insert(table: &mut Table, path: &[String], value: Value) -> Result<(), String> {
+    let (last, parents) = path.split_last().ok_or("expected a key")?;
+    let mut table = table;
+    for key in parents {
+        let item = table
+            .items
+            .entry(key.clone())
+            .or_insert_with(|| Item::Table(Table::new(Kind::Dotted)));
+        table = match item {
+            Item::Table(table) if table.kind == Kind::Dotted => table,
+            _ => return Err(format!("`{key}` is already defined and can't be extended")),
+        };
+    }
+
+    if table.items.contains_key(last) {
+        return Err(format!("`{}` is defined twice", path.join(".")));
+    }
+    table.items.insert(last.clone(), Item::Value(value));
+    Ok(())
+}
+
+/// Control characters other than tab must be escaped
+fn allowed(c: char) -> Result<char, String> {
+    match c != '\t' && c.is_control() {
+        true => Err(format!(
+            "control character U+{:04X} must be escaped",
+            c as u32
+        )),
+        false => Ok(c),
+    }
+}
+
+fn number(token: &str) -> Option<Value> {
+    for (prefix, radix) in [("0x", 16), ("0o", 8), ("0b", 2)] {
+        if let Some(digits) = token.strip_prefix(prefix) {
+            let digits = without_underscores(digits, radix)?;
+            return i64::from_str_radix(&digits, radix).ok().map(Value::from);
+        }
+    }
+
+    let unsigned = token.strip_prefix(['+', '-']).unwrap_or(token);
+    let (int, rest) = match unsigned.find(['.', 'e', 'E']) {
+        Some(idx) => unsigned.split_at(idx),
+        None => (unsigned, ""),
+    };
+    if int.len() > 1 && int.starts_with('0') {
+        return None;
+    }
+    let int = without_underscores(int, 10)?;
+    let sign = &token[..token.len() - unsigned.len()];
+    if rest.is_empty() {
+        return format!("{sign}{int}").parse::<i64>().ok().map(Value::from);
+    }
+
+    let (fraction, exponent) = match rest.find(['e', 'E']) {
+        Some(idx) => (&rest[..idx], Some(&rest[idx + 1..])),
+        None => (rest, None),
+    };
+    let mut float = format!("{sign}{int}");
+    if let Some(fraction) = fraction.strip_prefix('.') {
+        float.push('.');
+        float.push_str(&without_underscores(fraction, 10)?);
+    } else if !fraction.is_empty() {
+        return None;
+    }
+    if let Some(exponent) = exponent {
+        let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        float.push('e');
+        float.push_str(&exponent[..exponent.len() - digits.len()]);
+        float.push_str(&without_underscores(digits, 10)?);
+    }
+    Number::from_f64(float.parse().ok()?).map(Value::Number)
+}
+
+/// The digits of `digits`, which may only have underscores between digits
+fn without_underscores(digits: &str, radix: u32) -> Option<String> {
+    let valid = !digits.is_empty()
+        && !digits.starts_with('_')
+        && !digits.ends_with('_')
+        && !digits.contains("__")
+        && digits.chars().all(|c| c == '_' || c.is_digit(radix));
+    match valid {
+        true => Some(digits.replace('_', "")),
+        false => None,
+    }
+}
+
+/// Validate an offset or local date-time, date or time, and format it as in RFC 3339
+fn date_time(token: &str) -> Option<String> {
+    let (date, time) = match token.find(['T', 't', ' ']) {
+        Some(idx) => (Some(&token[..idx]), Some(&token[idx + 1..])),
+        None if token.contains(':') => (None, Some(token)),
+        None => (Some(token), None),
+    };
+
+    if let Some(date) = date {
+        let parts = date.split('-').collect::<Vec<_>>();
+        let (year, month, day) = match parts[..] {
+            [year, month, day] if year.len() == 4 && month.len() == 2 && day.len() == 2 => {
+                (year, month, day)
+            }
+            _ => return None,
+        };
+        let (year, month, day) = (decimal(year)?, decimal(month)?, decimal(day)?);
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days = match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return None,
+        };
+        if day == 0 || day > days {
+            return None;
+        }
+    }
+
+    if let Some(time) = time {
+        // Only date-times may have an offset
+        let (local, offset) = match time.find(['Z', 'z', '+', '-']) {
+            Some(_) if date.is_none() => return None,
+            Some(idx) => time.split_at(idx),
+            None => (time, ""),
+        };
+        let (hms, fraction) = match local.split_once('.') {
+            Some((hms, fraction)) => (hms, Some(fraction)),
+            None => (local, None),
+        };
+        match hms.split(':').collect::<Vec<_>>()[..] {
+            [h, m, s] if h.len() == 2 && m.len() == 2 && s.len() == 2 => {
+                if decimal(h)? > 23 || decimal(m)? > 59 || decimal(s)? > 60 {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+        if matches!(fraction, Some(fraction) if !is_digits(fraction)) {
+            return None;
+        }
+        match offset {
+            "" | "Z" | "z" => {}
+            _ => match offset[1..].split(':').collect::<Vec<_>>()[..] {
+                [h, m] if h.len() == 2 && m.len() == 2 => {
+                    if decimal(h)? > 23 || decimal(m)? > 59 {
+                        return None;
+                    }
+                }
+                _ => return None,
+            },
+        }
+    }
+
+    Some(match (date, time) {
+        (Some(date), Some(time)) => format!("{date}T{}", time.to_uppercase()),
+        _ => token.to_owned(),
+    })
+}
+
+/// The value of a component of a date-time, like the year or the minutes
+fn decimal(digits: &str) -> Option<u32> {
+    match is_digits(digits) {
+        true => digits.parse().ok(),
+        false => None,
+    }
+}
+
+fn is_digits(digits: &str) -> bool {
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse;
+
+    #[test]
+    fn daemon_config() {
+        let document = parse(
+            r#"
+# Both are optional and override --store and --account
+store = "/var/lib/instant-acme"
+account = "default"
+renew_before_days = 30
+
+[[certificate]]
+name = "example.com"
+domains = ["example.com", "*.example.com"]  # a comment
+dns = "cloudflare"
+hooks = [
+    "systemctl reload nginx",
+]
+
+[[certificate]]
+domains = ['example.org']
+dns = "exec"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            document,
+            json!({
+                "store": "/var/lib/instant-acme",
+                "account": "default",
+                "renew_before_days": 30,
+                "certificate": [
+                    {
+                        "name": "example.com",
+                        "domains": ["example.com", "*.example.com"],
+                        "dns": "cloudflare",
+                        "hooks": ["systemctl reload nginx"],
+                    },
+                    { "domains": ["example.org"], "dns": "exec" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn tables_and_keys() {
+        let document = parse(
+            r#"
+name.first = "Tom"
+"quoted key" = 1
+'literal.key' = 2
+point = { x = 1, y.z = 2 }
+empty = {}
+
+[a.b]
+c = true
+
+[a]
+d = false
+
+[a.b.e]
+f = 3
+
+[[a.list]]
+g = 4
+[a.list.sub]
+h = 5
+[[a.list]]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            document,
+            json!({
+                "name": { "first": "Tom" },
+                "quoted key": 1,
+                "literal.key": 2,
+                "point": { "x": 1, "y": { "z": 2 } },
+                "empty": {},
+                "a": {
+                    "b": { "c": true, "e": { "f": 3 } },
+                    "d": false,
+                    "list": [{ "g": 4, "sub": { "h": 5 } }, {}],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn strings() {
+        let document = parse(concat!(
+            "basic = \"tab\\tquote\\\" \\u00e9 \\U0001F600\"\n",
+            "literal = 'C:\\Users\\nodejs'\n",
+            "multi = \"\"\"\nline one\nline two \\\n    continued\"\"\"\n",
+            "quotes = \"\"\"\"quoted\"\"\"\"\n",
+            "raw = '''\nfirst\n  second\\n'''\n",
+        ))
+        .unwrap();
+
+        assert_eq!(document["basic"], "tab\tquote\" \u{e9} \u{1F600}");
+        assert_eq!(document["literal"], "C:\\Users\\nodejs");
+        assert_eq!(document["multi"], "line one\nline two continued");
+        assert_eq!(document["quotes"], "\"quoted\"");
+        assert_eq!(document["raw"], "first\n  second\\n");
+    }
+
+    #[test]
+    fn numbers_and_dates() {
+        let document = parse(
+            r#"
+int = +1_000
+negative = -17
+zero = 0
+hex = 0xDEAD_beef
+octal = 0o755
+binary = 0b1101
+float = 6.626e-34
+fraction = -0.01
+exponent = 5e+22
+offset = 1979-05-27T07:32:00.999-07:00
+space = 1979-05-27 07:32:00Z
+local = 1979-05-27T07:32:00
+date = 1979-05-27
+time = 00:32:00.5
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(document["int"], 1000);
+        assert_eq!(document["negative"], -17);
+        assert_eq!(document["zero"], 0);
+        assert_eq!(document["hex"], 0xdead_beef_i64);
+        assert_eq!(document["octal"], 0o755);
+        assert_eq!(document["binary"], 13);
+        assert_eq!(document["float"], 6.626e-34);
+        assert_eq!(document["fraction"], -0.01);
+        assert_eq!(document["exponent"], 5e22);
+        assert_eq!(document["offset"], "1979-05-27T07:32:00.999-07:00");
+        assert_eq!(document["space"], "1979-05-27T07:32:00Z");
+        assert_eq!(document["local"], "1979-05-27T07:32:00");
+        assert_eq!(document["date"], "1979-05-27");
+        assert_eq!(document["time"], "00:32:00.5");
+    }
+
+    #[test]
+    fn invalid_documents() {
+        for (document, error) in [
+            ("a = 1\na = 2", "line 2: `a` is defined twice"),
+            ("[a]\n[a]", "line 2: `a` is defined twice"),
+            ("a = 1\n[a]", "line 2: `a` is defined twice"),
+            ("a.b = 1\n[a]", "line 2: `a` is defined twice"),
+            ("[a]\nb.c = 1\n[a.b]", "line 3: `a.b` is defined twice"),
+            (
+                "[a.b]\n[a]\nb.c = 1",
+                "line 3: `b` is already defined and can't be extended",
+            ),
+            (
+                "a = { b = 1 }\na.c = 2",
+                "line 2: `a` is already defined and can't be extended",
+            ),
+            ("a = [1]\n[[a]]", "line 2: `a` is defined twice"),
+            ("a = { b = 1, }", "line 1: expected a key"),
+            ("a = { b = 1\n}", "line 1: expected `}`"),
+            ("a = \"open", "line 1: unterminated string"),
+            ("a = \"\\x\"", "line 1: invalid escape sequence"),
+            ("a = 012", "line 1: invalid number `012`"),
+            ("a = 1__0", "line 1: invalid number `1__0`"),
+            ("a = 1.", "line 1: invalid number `1.`"),
+            (
+                "a = nan",
+                "line 1: infinite and NaN floats are not supported",
+            ),
+            ("a = 1979-02-29", "line 1: invalid date-time `1979-02-29`"),
+            ("a = 07:32:00Z", "line 1: invalid date-time `07:32:00Z`"),
+            (
+                "a = bare",
+                "line 1: unexpected `bare`; strings must be quoted",
+            ),
+            ("a = 1 b = 2", "line 1: unexpected `b` at end of line"),
+            ("[a] ]", "line 1: unexpected `]` at end of line"),
+        ] {
+            assert_eq!(parse(document).unwrap_err(), error, "{document:?}");
+        }
+    }
+}
@@ -0,0 +1,814 @@
+//! Issuing many certificates with bounded concurrency
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use ring::digest::{digest, SHA256};
+
+use crate::clock::Deadline;
+use crate::coordinator::{Coordinator, LockToken};
+use crate::solver::ChallengeSolver;
+use crate::store::{hex, order_key, CertStore};
+use crate::{
+    Account, AccountConfig, CertificateChain, Environment, Error, Identifier, NewOrder, Order,
+    OrderStatus, RetryPolicy,
+};
+
+/// Issues certificates for many [`IssuanceSpec`]s through a single [`Account`]
+///
+/// Up to [`BulkIssuer::concurrency()`] orders are processed at the same time, all within the
+/// task that polls [`BulkRun::next()`]. Orders share the account's replay nonce pool and are
+/// subject to its client-side rate limits, both of which are set through the
+/// [`AccountConfig`](crate::AccountConfig).
+///
+/// Specs for the same set of identifiers as an earlier spec are not issued again. Specs that
+/// share a domain with an order in progress wait until that order is done, so that their
+/// challenge responses don't interfere.
+///
+/// # Scheduling
+///
+/// Up to [`BulkIssuer::lookahead()`] specs are read ahead from the iterator. Among those,
+/// specs with a higher [`IssuancePriority`] are started first. Within a priority class, the
+/// tenant with the fewest orders in progress goes next, so that one tenant's bulk import
+/// cannot starve the others; ties go to the tenant that was served least recently. Specs
+/// of the same tenant and priority are started in iterator order.
+///
+/// # Multiple replicas
+///
+/// Replicas that issue certificates through the same account can share a [`Coordinator`].
+/// Each order then holds a lock for its set of identifiers, which is extended while the
+/// order is in progress, and specs whose lock is held by another replica fail with
+/// [`Error::LockHeld`] instead of being issued twice. New orders can
+/// also be counted against a limit shared by all replicas with
+/// [`BulkIssuer::shared_order_limit()`].
+///
+/// # Restarts
+///
+/// With a [`BulkIssuer::store()`], every order is kept in the store until its certificate
+/// has been issued, under the [`order_key()`] of its identifiers and a hash of its CSR. A run
+/// that restarts after a crash resumes those orders instead of creating new ones, and
+/// collects certificates the CA issued in the meantime, so that it doesn't count against the
+/// CA's rate limits again.
+pub struct BulkIssuer {
+    account: Account,
+    solver: Arc<dyn ChallengeSolver>,
+    concurrency: usize,
+    lookahead: usize,
+    policy: RetryPolicy,
+    coordinator: Option<Arc<dyn Coordinator>>,
+    order_limit: Option<(u32, Duration)>,
+    environment: Option<Environment>,
+    store: Option<Arc<dyn CertStore>>,
+}
+
+impl BulkIssuer {
+    /// Issue certificates through `account`, completing challenges with `solver`
+    pub fn new(account: Account, solver: Arc<dyn ChallengeSolver>) -> Self {
+        Self {
+            account,
+            solver,
+            concurrency: 8,
+            lookahead: 256,
+            policy: RetryPolicy::default(),
+            coordinator: None,
+            order_limit: None,
+            environment: None,
+            store: None,
+        }
+    }
+
+    /// Set the maximum number of orders in progress at the same time (defaults to 8)
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set how many specs are read ahead for scheduling (defaults to 256)
+    ///
+    /// Priorities and fair sharing only apply among the specs that have been read ahead.
+    pub fn lookahead(mut self, lookahead: usize) -> Self {
+        self.lookahead = lookahead.max(1);
+        self
+    }
+
+    /// Set how orders are polled while waiting for validation and issuance
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Coordinate with other replicas through `coordinator`
+    pub fn coordinator(mut self, coordinator: Arc<dyn Coordinator>) -> Self {
+        self.coordinator = Some(coordinator);
+        self
+    }
+
+    /// Allow at most `max` new orders per `window` across all replicas
+    ///
+    /// Only applies if a [`Coordinator`] is set. Orders wait until the shared limit allows
+    /// them; limits for this replica alone are set in the
+    /// [`AccountConfig`](crate::AccountConfig).
+    pub fn shared_order_limit(mut self, max: u32, window: Duration) -> Self {
+        self.order_limit = Some((max, window));
+        self
+    }
+
+    /// Only issue certificates if the account is registered with a CA of `environment`
+    ///
+    /// All specs then fail with [`Error::EnvironmentMismatch`] instead of being ordered if
+    /// the account belongs to another environment. See [`Account::check_environment()`].
+    pub fn environment(mut self, environment: Environment) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Keep orders in progress in `store`, so that restarted runs resume them
+    ///
+    /// See [Restarts](BulkIssuer#restarts).
+    pub fn store(mut self, store: Arc<dyn CertStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Start issuing certificates for `specs`
+    ///
+    /// Specs are taken from the iterator as capacity becomes available. Call
+    /// [`BulkRun::next()`] to drive the orders and collect their results.
+    pub fn run<I: IntoIterator<Item = IssuanceSpec>>(&self, specs: I) -> BulkRun<'_, I::IntoIter> {
+        BulkRun {
+            issuer: self,
+            specs: specs.into_iter(),
+            queued: Vec::new(),
+            seen: HashSet::new(),
+            tenants: HashMap::new(),
+            started: 0,
+            done: VecDeque::new(),
+            active: Vec::new(),
+        }
+    }
+}
+
+/// The identifiers and CSR for one certificate issued by a [`BulkIssuer`]
+#[derive(Clone, Debug)]
+pub struct IssuanceSpec {
+    /// The identifiers to include in the order
+    pub identifiers: Vec<Identifier>,
+    /// The DER-encoded CSR to finalize the order with
+    pub csr_der: Vec<u8>,
+    /// How urgently the certificate is needed
+    pub priority: IssuancePriority,
+    /// The tenant the certificate is issued for, for fair sharing between tenants
+    pub tenant: Option<String>,
+}
+
+impl IssuanceSpec {
+    /// A spec with [`IssuancePriority::Normal`] priority and no tenant
+    pub fn new(identifiers: Vec<Identifier>, csr_der: Vec<u8>) -> Self {
+        Self {
+            identifiers,
+            csr_der,
+            priority: IssuancePriority::default(),
+            tenant: None,
+        }
+    }
+
+    /// Set the priority
+    pub fn priority(mut self, priority: IssuancePriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set the tenant
+    pub fn tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+}
+
+/// Priority classes for [`IssuanceSpec`]s, from most to least urgent
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum IssuancePriority {
+    /// Renewals of certificates that are about to expire
+    Urgent,
+    /// Certificates for new customers
+    Normal,
+    /// Re-issues that can wait, like key rotations or profile changes
+    Opportunistic,
+}
+
+impl Default for IssuancePriority {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+/// The outcome of one [`IssuanceSpec`]
+#[derive(Debug)]
+pub struct IssuanceResult {
+    /// The identifiers from the spec
+    pub identifiers: Vec<Identifier>,
+    /// The issued certificate chain, or the reason issuance failed
+    pub result: Result<CertificateChain, Error>,
+}
+
+/// Certificates being issued by a [`BulkIssuer`]
+///
+/// Dropping the run cancels the orders in progress; see the
+/// [crate-level documentation](crate#cancellation).
+pub struct BulkRun<'a, I> {
+    issuer: &'a BulkIssuer,
+    specs: I,
+    /// Specs read ahead from the iterator, in iterator order
+    queued: Vec<IssuanceSpec>,
+    /// The identifier sets of all specs read so far
+    seen: HashSet<Vec<String>>,
+    tenants: HashMap<Option<String>, Tenant>,
+    /// The number of orders started so far
+    started: u64,
+    /// Results that are available without waiting
+    done: VecDeque<IssuanceResult>,
+    active: Vec<Active<'a>>,
+}
+
+impl<'a, I: Iterator<Item = IssuanceSpec>> BulkRun<'a, I> {
+    /// Wait for the next order to complete, returning `None` once all specs are done
+    ///
+    /// Results are returned in the order in which the orders complete.
+    pub async fn next(&mut self) -> Option<IssuanceResult> {
+        self.fill();
+        if let Some(result) = self.done.pop_front() {
+            return Some(result);
+        }
+
+        if self.active.is_empty() {
+            return None;
+        }
+
+        let (idx, result) = NextCompleted {
+            active: &mut self.active,
+        }
+        .await;
+        let active = self.active.swap_remove(idx);
+        if let Some(tenant) = self.tenants.get_mut(&active.tenant) {
+            tenant.active -= 1;
+        }
+        Some(result)
+    }
+
+    /// Start orders until the concurrency limit is reached or no specs are left
+    fn fill(&mut self) {
+        self.read_ahead();
+        while self.active.len() < self.issuer.concurrency {
+            let next = self
+                .queued
+                .iter()
+                .enumerate()
+                .filter(|(_, spec)| !self.overlaps(spec))
+                .min_by_key(|(idx, spec)| {
+                    let tenant = self.tenants.get(&spec.tenant);
+                    (
+                        spec.priority,
+                        tenant.map_or(0, |t| t.active),
+                        tenant.map_or(0, |t| t.last_started),
+                        *idx,
+                    )
+                })
+                .map(|(idx, _)| idx);
+
+            let spec = match next {
+                Some(idx) => self.queued.remove(idx),
+                None => return,
+            };
+
+            self.started += 1;
+            let tenant = self.tenants.entry(spec.tenant.clone()).or_default();
+            tenant.active += 1;
+            tenant.last_started = self.started;
+
+            let domains = spec.identifiers.iter().map(domain).collect();
+            let tenant = spec.tenant.clone();
+            let task = issue(self.issuer, spec);
+            self.active.push(Active {
+                domains,
+                tenant,
+                task: Box::pin(task),
+            });
+            self.read_ahead();
+        }
+    }
+
+    /// Read specs from the iterator until the lookahead is full
+    fn read_ahead(&mut self) {
+        while self.queued.len() < self.issuer.lookahead {
+            let spec = match self.specs.next() {
+                Some(spec) => spec,
+                None => return,
+            };
+
+            let mut names = spec
+                .identifiers
+                .iter()
+                .map(Identifier::canonical)
+                .collect::<Vec<_>>();
+            names.sort_unstable();
+            names.dedup();
+
+            match self.seen.contains(&names) {
+                false => {
+                    self.seen.insert(names);
+                    self.queued.push(spec);
+                }
+                true => self.done.push_back(IssuanceResult {
+                    identifiers: spec.identifiers,
+                    result: Err(Error::DuplicateSpec(names)),
+                }),
+            }
+        }
+    }
+
+    /// Whether `spec` shares a domain with an active order
+    fn overlaps(&self, spec: &IssuanceSpec) -> bool {
+        spec.identifiers.iter().any(|identifier| {
+            let domain = domain(identifier);
+            self.active
+                .iter()
+                .any(|active| active.domains.contains(&domain))
+        })
+    }
+}
+
+/// Scheduling state for one tenant
+#[derive(Default)]
+struct Tenant {
+    /// The number of orders in progress
+    active: usize,
+    /// When the tenant's most recent order was started, as a value of [`BulkRun::started`]
+    last_started: u64,
+}
+
+struct Active<'a> {
+    /// The domains in the order, without wildcard prefixes
+    domains: HashSet<String>,
+    tenant: Option<String>,
+    task: Pin<Box<dyn Future<Output = IssuanceResult> + 'a>>,
+}
+
+/// Resolves with the index and result of the first active order to complete
+struct NextCompleted<'f, 'a> {
+    active: &'f mut Vec<Active<'a>>,
+}
+
+impl Future for NextCompleted<'_, '_> {
+    type Output = (usize, IssuanceResult);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        for (idx, active) in self.active.iter_mut().enumerate() {
+            if let Poll::Ready(result) = active.task.as_mut().poll(cx) {
+                return Poll::Ready((idx, result));
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Run a complete issuance for `spec`, holding the coordinator's lock if there is one
+async fn issue(issuer: &BulkIssuer, spec: IssuanceSpec) -> IssuanceResult {
+    let result = match &issuer.coordinator {
+        Some(coordinator) => coordinated(issuer, &**coordinator, &spec).await,
+        None => issue_order(issuer, &spec).await,
+    };
+
+    IssuanceResult {
+        identifiers: spec.identifiers,
+        result,
+    }
+}
+
+async fn coordinated(
+    issuer: &BulkIssuer,
+    coordinator: &dyn Coordinator,
+    spec: &IssuanceSpec,
+) -> Result<CertificateChain, Error> {
+    let mut names = spec
+        .identifiers
+        .iter()
+        .map(Identifier::canonical)
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    let key = format!("order:{}", names.join(","));
+
+    let token = match coordinator.try_lock(&key, LOCK_TTL).await? {
+        Some(token) => token,
+        None => return Err(Error::LockHeld { key }),
+    };
+
+    let result = Deadline {
+        operation: Box::pin(async {
+            if let Some((max, window)) = issuer.order_limit {
+                while let Some(wait) = coordinator.acquire_slot("new-orders", max, window).await? {
+                    issuer.account.inner.client.config.clock.sleep(wait).await;
+                }
+            }
+            issue_order(issuer, spec).await
+        }),
+        expired: Box::pin(keep_locked(issuer, coordinator, &key, &token)),
+    }
+    .await;
+
+    let unlocked = coordinator.unlock(&key, &token).await;
+    let chain = result.ok_or_else(|| Error::LockHeld { key: key.clone() })??;
+    unlocked?;
+    Ok(chain)
+}
+
+/// Extend the lock for `key` periodically, completing once it has been lost
+///
+/// Failures to reach the coordinator are retried until the lock may have expired.
+async fn keep_locked(
+    issuer: &BulkIssuer,
+    coordinator: &dyn Coordinator,
+    key: &str,
+    token: &LockToken,
+) {
+    let clock = &*issuer.account.inner.client.config.clock;
+    let mut expires = clock.now() + LOCK_TTL;
+    loop {
+        clock.sleep(LOCK_TTL / 3).await;
+        let now = clock.now();
+        match coordinator.extend(key, token, LOCK_TTL).await {
+            Ok(true) => expires = now + LOCK_TTL,
+            Ok(false) => return,
+            Err(_) if clock.now() >= expires => return,
+            Err(_) => {}
+        }
+    }
+}
+
+async fn issue_order(issuer: &BulkIssuer, spec: &IssuanceSpec) -> Result<CertificateChain, Error> {
+    if let Some(environment) = issuer.environment {
+        issuer.account.check_environment(environment)?;
+    }
+    let new_order = NewOrder::builder()
+        .identifiers(spec.identifiers.iter().cloned())
+        .build()?;
+    // Orders are only resumed for the same CSR, which their certificate may be issued for
+    let key = format!(
+        "{}-{}",
+        order_key(&spec.identifiers),
+        &hex(digest(&SHA256, &spec.csr_der).as_ref())[..16]
+    );
+    let mut order = match &issuer.store {
+        Some(store) => resume_or_create(&issuer.account, &**store, &key, &new_order, true).await?,
+        None => issuer.account.new_order(&new_order).await?,
+    };
+
+    let guard = order.present_challenges(issuer.solver.clone()).await?;
+    let config = &issuer.account.inner.client.config;
+    let validated = wait_validated(&mut order, config, issuer.policy).await;
+    let cleanup = guard.cleanup().await;
+    validated?;
+    cleanup?;
+
+    let chain = order
+        .finalize_and_collect(&spec.csr_der, issuer.policy)
+        .await?;
+    if let Some(store) = &issuer.store {
+        store.remove_order(&key).await?;
+    }
+    Ok(chain)
+}
+
+/// Resume the order stored as `key` in `store`, or create it from `new_order` and store it
+///
+/// Stored orders that are pending or ready are resumed. Orders that are processing or valid
+/// are only resumed if `issued` is set, since their certificate is for the CSR they were
+/// finalized with. Failed orders and orders the server rejects are replaced with a new one.
+pub(crate) async fn resume_or_create(
+    account: &Account,
+    store: &dyn CertStore,
+    key: &str,
+    new_order: &NewOrder,
+    issued: bool,
+) -> Result<Order, Error> {
+    let now = account.inner.client.config.clock.now_utc();
+    let stored = store.load_order(key).await?;
+    // Unfinished orders that expired in the store are abandoned without asking the server
+    let stored = stored.filter(|order| issued || !matches!(order.expires, Some(e) if e <= now));
+    if let Some(resumable) = stored {
+        match account.resume_order(resumable).await {
+            Ok(mut order) => {
+                let state = order.state();
+                let usable = match state.status {
+                    OrderStatus::Pending | OrderStatus::Ready => !state.is_expired(now),
+                    OrderStatus::Processing | OrderStatus::Valid => issued,
+                    OrderStatus::Invalid => false,
+                };
+                if usable {
+                    return Ok(order);
+                }
+            }
+            // Like for an order that expired or was deleted; anything else is retried later
+            Err(Error::Api(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let order = account.new_order(new_order).await?;
+    store.save_order(key, &order.to_resumable()).await?;
+    Ok(order)
+}
+
+/// Poll `order` according to `policy` until it is no longer pending
+///
+/// Fails with [`Error::OrderExpired`] instead of polling an order that is still pending
+/// after its expiry, or that the server invalidated because it expired.
+pub(crate) async fn wait_validated(
+    order: &mut Order,
+    config: &AccountConfig,
+    policy: RetryPolicy,
+) -> Result<(), Error> {
+    let mut delay = policy.delay;
+    for _ in 0..policy.attempts {
+        let state = order.state();
+        if matches!(state.status, OrderStatus::Pending | OrderStatus::Invalid)
+            && state.is_expired(config.clock.now_utc())
+        {
+            return Err(Error::OrderExpired);
+        } else if state.status != OrderStatus::Pending {
+            return audit_authorizations(order, config).await;
+        }
+
+        config
+            .clock
+            .sleep(policy.jittered(delay, &*config.rng))
+            .await;
+        let retry_after = order.refresh().await?.meta.retry_after;
+        delay = policy.next_delay(delay, retry_after);
+    }
+
+    match order.state().status {
+        OrderStatus::Pending => Err(Error::Timeout("challenge validation")),
+        _ => audit_authorizations(order, config).await,
+    }
+}
+
+/// Retrieve the authorizations of an order that is no longer pending, if they are audited
+///
+/// Polling the order does not retrieve them, so their outcome would not be recorded.
+async fn audit_authorizations(order: &mut Order, config: &AccountConfig) -> Result<(), Error> {
+    if config.audit_log.is_some() {
+        order.authorizations().await?;
+    }
+    Ok(())
+}
+
+/// The domain of `identifier`, without any wildcard prefix
+fn domain(identifier: &Identifier) -> String {
+    let name = identifier.canonical();
+    match name.strip_prefix("*.") {
+        Some(base) => base.to_owned(),
+        None => name,
+    }
+}
+
+/// How long an order may hold its coordinator lock, in case the replica dies
+const LOCK_TTL: Duration = Duration::from_secs(15 * 60);
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::coordinator::{CoordinatorFuture, MemoryCoordinator};
+    use crate::mock::testing::{account, csr, RecordingSolver};
+    use crate::mock::{MockClock, MockServer};
+    use crate::solver::{SolverChallenge, SolverFuture};
+    use crate::ChallengeType;
+
+    fn spec(name: &str) -> IssuanceSpec {
+        IssuanceSpec::new(vec![Identifier::Dns(name.to_owned())], csr(&[name]))
+    }
+
+    #[tokio::test]
+    async fn deduplication() {
+        let clock = MockClock::new();
+        let config = AccountConfig::new().clock(Arc::new(clock.clone()));
+        let account = account(MockServer::new().with_processing_polls(2), config).await;
+        let solver = Arc::new(OverlapSolver::default());
+        let issuer = BulkIssuer::new(account, solver.clone());
+
+        let dns = |name: &str| Identifier::Dns(name.to_owned());
+        let pair = |first: &str, second: &str| {
+            IssuanceSpec::new(vec![dns(first), dns(second)], csr(&[first, second]))
+        };
+        let specs = [
+            spec("a.example.com"),
+            spec("A.Example.com"),
+            pair("b.example.com", "a.example.com"),
+            pair("a.example.com", "B.example.com"),
+            spec("c.example.com"),
+        ];
+        let mut run = issuer.run(specs);
+        let mut results = Vec::new();
+        let ((), ()) = tokio::join!(
+            async {
+                while let Some(result) = run.next().await {
+                    results.push(result);
+                }
+            },
+            async {
+                for _ in 0..120 {
+                    tokio::task::yield_now().await;
+                    clock.advance(Duration::from_secs(60));
+                }
+            }
+        );
+
+        // Duplicates fail with the canonical names of the spec they repeat
+        let mut duplicates = Vec::new();
+        let mut issued = Vec::new();
+        for result in results {
+            let names = result
+                .identifiers
+                .iter()
+                .map(|identifier| identifier.value().to_owned())
+                .collect::<Vec<_>>();
+            match result.result {
+                Ok(_) => issued.push(names),
+                Err(Error::DuplicateSpec(canonical)) => duplicates.push((names, canonical)),
+                Err(error) => panic!("unexpected error for {names:?}: {error:?}"),
+            }
+        }
+        duplicates.sort();
+        assert_eq!(
+            duplicates,
+            [
+                (
+                    vec!["A.Example.com".to_owned()],
+                    vec!["a.example.com".to_owned()]
+                ),
+                (
+                    vec!["a.example.com".to_owned(), "B.example.com".to_owned()],
+                    vec!["a.example.com".to_owned(), "b.example.com".to_owned()]
+                ),
+            ]
+        );
+        assert_eq!(issued.len(), 3);
+
+        // The orders sharing a.example.com ran one after the other
+        assert!(!solver.overlapped.load(Ordering::SeqCst));
+        let mut presented = solver.presented.lock().unwrap().clone();
+        presented.sort();
+        assert_eq!(
+            presented,
+            [
+                "a.example.com",
+                "a.example.com",
+                "b.example.com",
+                "c.example.com"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lock_contention() {
+        let account = account(MockServer::new(), AccountConfig::new()).await;
+        let coordinator = Arc::new(MemoryCoordinator::new());
+        let held = coordinator.try_lock("order:a.example.com", LOCK_TTL).await;
+        assert!(held.unwrap().is_some());
+
+        let solver = Arc::new(RecordingSolver::default());
+        let issuer = BulkIssuer::new(account, solver.clone()).coordinator(coordinator.clone());
+        let mut run = issuer.run([spec("a.example.com"), spec("b.example.com")]);
+        let mut results = HashMap::new();
+        while let Some(result) = run.next().await {
+            results.insert(result.identifiers[0].value().to_owned(), result.result);
+        }
+
+        match &results["a.example.com"] {
+            Err(Error::LockHeld { key }) => assert_eq!(key, "order:a.example.com"),
+            result => panic!("unexpected result: {result:?}"),
+        }
+        assert!(results["b.example.com"].is_ok());
+        assert_eq!(*solver.presented.lock().unwrap(), ["b.example.com"]);
+
+        // The lock of the issued order has been released
+        let lock = coordinator.try_lock("order:b.example.com", LOCK_TTL).await;
+        assert!(lock.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn lock_extension() {
+        for keep in [true, false] {
+            let clock = MockClock::new();
+            let config = AccountConfig::new().clock(Arc::new(clock.clone()));
+            let account = account(MockServer::new().with_processing_polls(4), config).await;
+            let coordinator = Arc::new(Extending {
+                inner: MemoryCoordinator::new(),
+                keep,
+                extended: AtomicU32::new(0),
+            });
+
+            // Each poll takes 10 minutes, so the order takes longer than the lock's TTL
+            let policy = RetryPolicy::new()
+                .delay(Duration::from_secs(600))
+                .backoff(1);
+            let issuer = BulkIssuer::new(account, Arc::new(RecordingSolver::default()))
+                .coordinator(coordinator.clone())
+                .retry_policy(policy);
+            let mut run = issuer.run([spec("a.example.com")]);
+            let (result, ()) = tokio::join!(run.next(), async {
+                for _ in 0..120 {
+                    tokio::task::yield_now().await;
+                    clock.advance(Duration::from_secs(60));
+                }
+            });
+
+            let extended = coordinator.extended.load(Ordering::SeqCst);
+            match (keep, result.unwrap().result) {
+                (true, Ok(_)) => assert!(extended >= 3, "extended {extended} times"),
+                (false, Err(Error::LockHeld { .. })) => assert_eq!(extended, 1),
+                (_, result) => panic!("unexpected result: {result:?}"),
+            }
+        }
+    }
+
+    /// Records whether a domain was presented again before its earlier challenge was cleaned up
+    #[derive(Default)]
+    struct OverlapSolver {
+        active: Mutex<Vec<String>>,
+        presented: Mutex<Vec<String>>,
+        overlapped: AtomicBool,
+    }
+
+    impl ChallengeSolver for OverlapSolver {
+        fn challenge_type(&self) -> ChallengeType {
+            ChallengeType::Http01
+        }
+
+        fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+            let domain = challenge.domain().to_owned();
+            let mut active = self.active.lock().unwrap();
+            if active.contains(&domain) {
+                self.overlapped.store(true, Ordering::SeqCst);
+            }
+            active.push(domain.clone());
+            self.presented.lock().unwrap().push(domain);
+            Box::pin(async { Ok(()) })
+        }
+
+        fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+            let mut active = self.active.lock().unwrap();
+            if let Some(idx) = active.iter().position(|d| d == challenge.domain()) {
+                active.remove(idx);
+            }
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// Counts extensions, and pretends they failed unless `keep` is set
+    struct Extending {
+        inner: MemoryCoordinator,
+        keep: bool,
+        extended: AtomicU32,
+    }
+
+    impl Coordinator for Extending {
+        fn try_lock<'a>(
+            &'a self,
+            key: &'a str,
+            ttl: Duration,
+        ) -> CoordinatorFuture<'a, Option<LockToken>> {
+            self.inner.try_lock(key, ttl)
+        }
+
+        fn extend<'a>(
+            &'a self,
+            _: &'a str,
+            _: &'a LockToken,
+            _: Duration,
+        ) -> CoordinatorFuture<'a, bool> {
+            self.extended.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move { Ok(self.keep) })
+        }
+
+        fn unlock<'a>(&'a self, key: &'a str, token: &'a LockToken) -> CoordinatorFuture<'a, ()> {
+            self.inner.unlock(key, token)
+        }
+
+        fn acquire_slot<'a>(
+            &'a self,
+            key: &'a str,
+            max: u32,
+            window: Duration,
+        ) -> CoordinatorFuture<'a, Option<Duration>> {
+            self.inner.acquire_slot(key, max, window)
+        }
+    }
+}
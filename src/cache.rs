@@ -0,0 +1,280 @@
+//! Remembering valid authorizations across orders
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::store::CertStore;
+use crate::{Authorization, AuthorizationStatus, Clock, Error, Identifier, SystemClock};
+
+/// Valid authorizations of an account, remembered across orders
+///
+/// Servers like Let's Encrypt attach authorizations that are still valid to later orders for
+/// the same identifiers. Set a cache with
+/// [`AccountConfig::authorization_cache()`](crate::AccountConfig::authorization_cache()) to
+/// have [`Order::authorizations()`](crate::Order::authorizations()) and the methods built on
+/// it take those from the cache instead of retrieving them again, so that no challenges are
+/// presented for them. Cached authorizations are returned without challenges.
+///
+/// Authorizations are evicted once they expire within [`AuthorizationCache::min_remaining()`]
+/// of now. Authorizations are tied to an account, so use one cache per account. If the
+/// server stops honoring an authorization before it expires, for example because it was
+/// deactivated, forget it with [`AuthorizationCache::remove()`] or
+/// [`AuthorizationCache::clear()`].
+pub struct AuthorizationCache {
+    entries: Mutex<HashMap<String, CachedAuthorization>>,
+    store: Option<(Arc<dyn CertStore>, String)>,
+    min_remaining: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl AuthorizationCache {
+    /// An empty cache that is kept in memory
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            store: None,
+            min_remaining: Duration::from_secs(60 * 60),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Load the cache stored as `name` in `store`, and save it there after every change
+    ///
+    /// Failures to save the cache are ignored, because they only cost a later order the
+    /// retrieval of its authorizations.
+    pub async fn load(store: Arc<dyn CertStore>, name: impl Into<String>) -> Result<Self, Error> {
+        let name = name.into();
+        let entries = store
+            .load_authorizations(&name)
+            .await?
+            .into_iter()
+            .map(|entry| (entry.url.clone(), entry))
+            .collect();
+        Ok(Self {
+            entries: Mutex::new(entries),
+            store: Some((store, name)),
+            ..Self::new()
+        })
+    }
+
+    /// Evict authorizations that expire within `duration` (defaults to 1 hour)
+    ///
+    /// The margin should cover the time it takes to finalize an order, since the server
+    /// rejects orders whose authorizations expired in the meantime.
+    pub fn min_remaining(mut self, duration: Duration) -> Self {
+        self.min_remaining = duration;
+        self
+    }
+
+    /// Compare expiry times with `clock` (defaults to [`SystemClock`])
+    ///
+    /// Use the clock of the [`AccountConfig`](crate::AccountConfig) the cache is set in.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The cached authorization for `identifier`, if any
+    pub fn get(&self, identifier: &Identifier) -> Option<CachedAuthorization> {
+        self.entries()
+            .values()
+            .find(|entry| entry.identifier == *identifier)
+            .cloned()
+    }
+
+    /// The identifiers with cached authorizations
+    pub fn identifiers(&self) -> Vec<Identifier> {
+        self.entries()
+            .values()
+            .map(|entry| entry.identifier.clone())
+            .collect()
+    }
+
+    /// Forget the authorizations for `identifier`, returning whether there were any
+    pub async fn remove(&self, identifier: &Identifier) -> bool {
+        let removed = {
+            let mut entries = self.entries.lock().unwrap();
+            let len = entries.len();
+            entries.retain(|_, entry| entry.identifier != *identifier);
+            entries.len() != len
+        };
+        if removed {
+            self.save().await;
+        }
+        removed
+    }
+
+    /// Forget all authorizations
+    pub async fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+        self.save().await;
+    }
+
+    /// The cached authorization at `url` as returned by the server, if it is still usable
+    pub(crate) fn authorization(&self, url: &str) -> Option<Authorization> {
+        let entries = self.entries();
+        let entry = entries.get(url)?;
+        Some(Authorization {
+            identifier: entry.identifier.clone(),
+            status: AuthorizationStatus::Valid,
+            expires: Some(entry.expires),
+            challenges: Vec::new(),
+            subdomain_auth_allowed: false,
+            url: entry.url.clone(),
+        })
+    }
+
+    /// Cache `authorizations` if they are valid and forget them otherwise
+    ///
+    /// Returns whether the cache changed.
+    pub(crate) fn update<'a>(
+        &self,
+        authorizations: impl IntoIterator<Item = &'a Authorization>,
+    ) -> bool {
+        let mut entries = self.entries();
+        let mut changed = false;
+        for authz in authorizations {
+            let expires = match (authz.status, authz.expires, authz.url.is_empty()) {
+                (AuthorizationStatus::Valid, Some(expires), false) => expires,
+                _ => {
+                    changed |= entries.remove(&authz.url).is_some();
+                    continue;
+                }
+            };
+
+            // Subdomain authorizations also cover other identifiers, which would need to be
+            // matched differently, so these are always retrieved from the server
+            if authz.subdomain_auth_allowed || !self.usable(expires) {
+                continue;
+            }
+
+            let entry = CachedAuthorization {
+                url: authz.url.clone(),
+                identifier: authz.identifier.clone(),
+                expires,
+            };
+            if entries.get(&authz.url) != Some(&entry) {
+                entries.insert(authz.url.clone(), entry);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Save the cache to its store, if it has one
+    pub(crate) async fn save(&self) {
+        let (store, name) = match &self.store {
+            Some(store) => store,
+            None => return,
+        };
+
+        let entries = self.entries().values().cloned().collect::<Vec<_>>();
+        if let Err(_error) = store.save_authorizations(name, &entries).await {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(error = %_error, "failed to save authorization cache");
+        }
+    }
+
+    /// Lock the entries after evicting those that are no longer usable
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, CachedAuthorization>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| self.usable(entry.expires));
+        entries
+    }
+
+    fn usable(&self, expires: OffsetDateTime) -> bool {
+        expires - self.clock.now_utc() > self.min_remaining
+    }
+}
+
+impl fmt::Debug for AuthorizationCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizationCache")
+            .field("entries", &self.entries.lock().unwrap().len())
+            .field("store", &self.store.as_ref().map(|(_, name)| name))
+            .field("min_remaining", &self.min_remaining)
+            .finish()
+    }
+}
+
+impl Default for AuthorizationCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A valid authorization kept in an [`AuthorizationCache`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CachedAuthorization {
+    /// The URL of the authorization
+    pub url: String,
+    /// The identifier that the account is authorized to represent
+    pub identifier: Identifier,
+    /// The time after which the server will consider the authorization invalid
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires: OffsetDateTime,
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock::testing::{account, RecordingSolver};
+    use crate::mock::{Fault, FaultInjector, MockClock, MockServer};
+    use crate::{AccountConfig, NewOrder, RetryPolicy};
+
+    #[tokio::test]
+    async fn reused_authorizations() {
+        let clock = MockClock::new();
+        let server = MockServer::new()
+            .with_authorization_reuse()
+            .with_clock(Arc::new(clock.clone()));
+        let faults = FaultInjector::new(server);
+        let cache = Arc::new(AuthorizationCache::new().clock(Arc::new(clock.clone())));
+        let config = AccountConfig::new().authorization_cache(cache.clone());
+        let account = account(faults.clone(), config).await;
+        let identifier = Identifier::Dns("example.com".to_owned());
+        let new_order = NewOrder::builder()
+            .identifier(identifier.clone())
+            .build()
+            .unwrap();
+        let solver = Arc::new(RecordingSolver::default());
+        let policy = RetryPolicy::new().delay(Duration::from_millis(1));
+
+        let mut order = account.new_order(&new_order).await.unwrap();
+        let guard = order.present_challenges(solver.clone()).await.unwrap();
+        order.wait_validated(policy).await.unwrap();
+        guard.cleanup().await.unwrap();
+        assert!(cache.get(&identifier).is_none());
+
+        // Authorizations are cached once they are retrieved in the valid state
+        let mut order = account.new_order(&new_order).await.unwrap();
+        let authorizations = order.authorizations().await.unwrap();
+        assert_eq!(authorizations[0].status, AuthorizationStatus::Valid);
+        let cached = cache.get(&identifier).unwrap();
+        assert_eq!(cached.url, order.state().authorizations[0]);
+
+        // Later orders take them from the cache instead of the server
+        faults.inject_for("authz/", Fault::HtmlError(500));
+        let mut order = account.new_order(&new_order).await.unwrap();
+        let authorizations = order.authorizations().await.unwrap();
+        assert_eq!(authorizations[0].status, AuthorizationStatus::Valid);
+        assert!(authorizations[0].challenges.is_empty());
+        let guard = order.present_challenges(solver.clone()).await.unwrap();
+        guard.cleanup().await.unwrap();
+        assert_eq!(solver.presented.lock().unwrap().len(), 1);
+        assert_eq!(faults.pending(), 1);
+
+        // Authorizations that are about to expire are retrieved from the server again
+        let remaining = cached.expires - clock.now_utc();
+        clock.advance(Duration::try_from(remaining).unwrap() - Duration::from_secs(3600));
+        assert!(cache.get(&identifier).is_none());
+        assert!(order.authorizations().await.is_err());
+        assert_eq!(faults.pending(), 0);
+        assert!(cache.identifiers().is_empty());
+    }
+}
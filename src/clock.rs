@@ -0,0 +1,80 @@
+//! Time sources for polling, backoff, rate limiting and timeouts
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use time::OffsetDateTime;
+
+/// The future returned by [`Clock::sleep()`]
+pub type SleepFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+/// A source of time for an [`Account`](crate::Account) and the orders created from it
+///
+/// All waiting done by this crate goes through the clock set in the
+/// [`AccountConfig`](crate::AccountConfig): polling delays, backoff, client-side rate limits,
+/// directory expiry and timeouts. Timestamps from the server and from certificates, like
+/// order and authorization expiry, renewal windows and revocation information, are
+/// compared with [`Clock::now_utc()`]. The default [`SystemClock`] uses Tokio's timer, which
+/// can be controlled with `tokio::time::pause()`, and the system's wall clock; tests can
+/// also substitute [`MockClock`](crate::mock::MockClock) (with the `mock` feature).
+pub trait Clock: Send + Sync + 'static {
+    /// The current time
+    fn now(&self) -> Instant;
+
+    /// The current wall-clock time
+    fn now_utc(&self) -> OffsetDateTime;
+
+    /// Wait for `duration` to pass
+    fn sleep(&self, duration: Duration) -> SleepFuture;
+}
+
+impl fmt::Debug for dyn Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Clock")
+            .field("now", &self.now())
+            .field("now_utc", &self.now_utc())
+            .finish()
+    }
+}
+
+/// A [`Clock`] backed by Tokio's timer and the system's wall clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn now_utc(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+
+    fn sleep(&self, duration: Duration) -> SleepFuture {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Run `operation`, resolving to `None` if `expired` completes first
+pub(crate) struct Deadline<'a, F: Future> {
+    pub(crate) operation: Pin<Box<F>>,
+    pub(crate) expired: Pin<Box<dyn Future<Output = ()> + 'a>>,
+}
+
+impl<F: Future> Future for Deadline<'_, F> {
+    type Output = Option<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(output) = self.operation.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+
+        match self.expired.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
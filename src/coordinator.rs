@@ -0,0 +1,274 @@
+//! Coordination between replicas that issue certificates through the same account
+//!
+//! A [`Coordinator`] provides advisory locks, so that two replicas don't issue a certificate
+//! for the same identifiers at the same time, and shared rate-limit counters, so that
+//! replicas together stay below the CA's limits. [`BulkIssuer`](crate::BulkIssuer) uses a
+//! coordinator if one is configured. [`MemoryCoordinator`] coordinates the tasks of a single
+//! process; [`RedisCoordinator`] (with the `redis` feature) coordinates replicas through a
+//! shared Redis server.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::{Clock, Error, SystemClock};
+
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "redis")]
+pub use redis::RedisCoordinator;
+
+/// The future returned by [`Coordinator`] methods
+pub type CoordinatorFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + 'a>>;
+
+/// Advisory locks and shared rate-limit counters
+pub trait Coordinator: Send + Sync {
+    /// Take the lock for `key` if nobody holds it, for at most `ttl`
+    ///
+    /// Returns `None` if the lock is held by someone else. The lock expires after `ttl` even
+    /// if it is not released, so that a crashed replica cannot hold it forever.
+    fn try_lock<'a>(
+        &'a self,
+        key: &'a str,
+        ttl: Duration,
+    ) -> CoordinatorFuture<'a, Option<LockToken>>;
+
+    /// Extend the lock for `key` to expire `ttl` from now, if it is still held with `token`
+    ///
+    /// Returns `false` if the lock expired or was taken by someone else in the meantime.
+    /// Holders of long-running locks call this periodically, well before `ttl` passes.
+    fn extend<'a>(
+        &'a self,
+        key: &'a str,
+        token: &'a LockToken,
+        ttl: Duration,
+    ) -> CoordinatorFuture<'a, bool>;
+
+    /// Release the lock for `key`, if it is still held with `token`
+    fn unlock<'a>(&'a self, key: &'a str, token: &'a LockToken) -> CoordinatorFuture<'a, ()>;
+
+    /// Count an event against a limit of `max` events per `window`, shared by all replicas
+    ///
+    /// Returns `None` if the event was counted, or how long to wait before trying again if
+    /// the limit has been reached. Events that are over the limit are not counted.
+    fn acquire_slot<'a>(
+        &'a self,
+        key: &'a str,
+        max: u32,
+        window: Duration,
+    ) -> CoordinatorFuture<'a, Option<Duration>>;
+}
+
+/// Proof of holding a lock taken with [`Coordinator::try_lock()`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LockToken(String);
+
+impl LockToken {
+    /// A new random token
+    pub fn generate() -> Result<Self, Error> {
+        let mut bytes = [0; 16];
+        SystemRandom::new().fill(&mut bytes)?;
+        Ok(Self(BASE64_URL_SAFE_NO_PAD.encode(bytes)))
+    }
+
+    /// The token as a string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A [`Coordinator`] that keeps its state in memory
+///
+/// This only coordinates the tasks within a single process, for deployments with a single
+/// replica.
+pub struct MemoryCoordinator {
+    locks: Mutex<HashMap<String, (LockToken, Instant)>>,
+    counters: Mutex<HashMap<String, (u32, Instant)>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl MemoryCoordinator {
+    /// A coordinator without any locks or counters
+    pub fn new() -> Self {
+        Self {
+            locks: Mutex::default(),
+            counters: Mutex::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Expire locks and counters according to `clock` (defaults to [`SystemClock`])
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl Default for MemoryCoordinator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coordinator for MemoryCoordinator {
+    fn try_lock<'a>(
+        &'a self,
+        key: &'a str,
+        ttl: Duration,
+    ) -> CoordinatorFuture<'a, Option<LockToken>> {
+        Box::pin(async move {
+            let now = self.clock.now();
+            let mut locks = self.locks.lock().unwrap();
+            if let Some((_, expires)) = locks.get(key) {
+                if *expires > now {
+                    return Ok(None);
+                }
+            }
+
+            let token = LockToken::generate()?;
+            locks.insert(key.to_owned(), (token.clone(), now + ttl));
+            Ok(Some(token))
+        })
+    }
+
+    fn extend<'a>(
+        &'a self,
+        key: &'a str,
+        token: &'a LockToken,
+        ttl: Duration,
+    ) -> CoordinatorFuture<'a, bool> {
+        Box::pin(async move {
+            let now = self.clock.now();
+            let mut locks = self.locks.lock().unwrap();
+            match locks.get_mut(key) {
+                Some((held, expires)) if held == token && *expires > now => {
+                    *expires = now + ttl;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        })
+    }
+
+    fn unlock<'a>(&'a self, key: &'a str, token: &'a LockToken) -> CoordinatorFuture<'a, ()> {
+        Box::pin(async move {
+            let mut locks = self.locks.lock().unwrap();
+            if locks.get(key).map_or(false, |(held, _)| held == token) {
+                locks.remove(key);
+            }
+            Ok(())
+        })
+    }
+
+    fn acquire_slot<'a>(
+        &'a self,
+        key: &'a str,
+        max: u32,
+        window: Duration,
+    ) -> CoordinatorFuture<'a, Option<Duration>> {
+        Box::pin(async move {
+            let now = self.clock.now();
+            let mut counters = self.counters.lock().unwrap();
+            let (count, reset) = counters.entry(key.to_owned()).or_insert((0, now + window));
+            if *reset <= now {
+                *count = 0;
+                *reset = now + window;
+            }
+
+            if *count >= max {
+                return Ok(Some(*reset - now));
+            }
+
+            *count += 1;
+            Ok(None)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_locks() {
+        let coordinator = MemoryCoordinator::new();
+        let ttl = Duration::from_secs(60);
+        let token = coordinator.try_lock("a", ttl).await.unwrap().unwrap();
+        assert!(coordinator.try_lock("a", ttl).await.unwrap().is_none());
+        assert!(coordinator.try_lock("b", ttl).await.unwrap().is_some());
+
+        let other = LockToken::generate().unwrap();
+        assert!(!coordinator.extend("a", &other, ttl).await.unwrap());
+        assert!(coordinator.extend("a", &token, ttl).await.unwrap());
+        assert!(!coordinator.extend("c", &token, ttl).await.unwrap());
+
+        // Only the holder can release the lock
+        coordinator.unlock("a", &other).await.unwrap();
+        assert!(coordinator.try_lock("a", ttl).await.unwrap().is_none());
+        coordinator.unlock("a", &token).await.unwrap();
+        assert!(!coordinator.extend("a", &token, ttl).await.unwrap());
+        assert!(coordinator.try_lock("a", ttl).await.unwrap().is_some());
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn expired_locks() {
+        let clock = crate::mock::MockClock::new();
+        let coordinator = MemoryCoordinator::new().clock(Arc::new(clock.clone()));
+        let ttl = Duration::from_secs(60);
+        let token = coordinator.try_lock("a", ttl).await.unwrap().unwrap();
+        clock.advance(Duration::from_secs(30));
+        assert!(coordinator.extend("a", &token, ttl).await.unwrap());
+
+        clock.advance(Duration::from_secs(59));
+        assert!(coordinator.try_lock("a", ttl).await.unwrap().is_none());
+        clock.advance(Duration::from_secs(1));
+        assert!(!coordinator.extend("a", &token, ttl).await.unwrap());
+        assert!(coordinator.try_lock("a", ttl).await.unwrap().is_some());
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn slot_windows() {
+        let clock = crate::mock::MockClock::new();
+        let coordinator = MemoryCoordinator::new().clock(Arc::new(clock.clone()));
+        let window = Duration::from_secs(60);
+        assert_eq!(
+            coordinator.acquire_slot("k", 1, window).await.unwrap(),
+            None
+        );
+        clock.advance(Duration::from_secs(20));
+        let wait = coordinator.acquire_slot("k", 1, window).await.unwrap();
+        assert_eq!(wait, Some(Duration::from_secs(40)));
+        clock.advance(Duration::from_secs(40));
+        assert_eq!(
+            coordinator.acquire_slot("k", 1, window).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_slots() {
+        let coordinator = MemoryCoordinator::new();
+        let window = Duration::from_secs(60);
+        assert_eq!(
+            coordinator.acquire_slot("k", 2, window).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            coordinator.acquire_slot("k", 2, window).await.unwrap(),
+            None
+        );
+        let wait = coordinator.acquire_slot("k", 2, window).await.unwrap();
+        assert!(matches!(wait, Some(wait) if wait <= window));
+        assert_eq!(
+            coordinator.acquire_slot("l", 2, window).await.unwrap(),
+            None
+        );
+    }
+}
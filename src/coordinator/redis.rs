@@ -0,0 +1,338 @@
+//! Coordinator backed by a Redis server
+
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use super::{Coordinator, CoordinatorFuture, LockToken};
+use crate::Error;
+
+/// A [`Coordinator`] that keeps its state on a Redis server
+///
+/// Locks are keys set with `NX` and an expiry, and are only released by the holder of the
+/// token. Rate-limit counters are keys that expire at the end of their window. All keys
+/// start with a configurable prefix. The connection is opened on first use and opened again
+/// after errors, and after commands that were cancelled before their reply was read.
+pub struct RedisCoordinator {
+    addr: String,
+    password: Option<String>,
+    prefix: String,
+    conn: Mutex<Option<BufReader<TcpStream>>>,
+}
+
+impl RedisCoordinator {
+    /// Coordinate through the Redis server at `addr` (like `127.0.0.1:6379`)
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            password: None,
+            prefix: "instant-acme:".to_owned(),
+            conn: Mutex::new(None),
+        }
+    }
+
+    /// Authenticate with `password` after connecting
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Set the prefix for all keys (defaults to `instant-acme:`)
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Send a command and read its reply, reconnecting if needed
+    ///
+    /// The connection is taken out of `conn` until the reply has been read, so that it is
+    /// dropped if this future is dropped in between. Otherwise the reply to a cancelled
+    /// command would be read as the reply to the next one.
+    async fn command(&self, args: &[&[u8]]) -> Result<Reply, Error> {
+        let mut conn = self.conn.lock().await;
+        let mut stream = match conn.take() {
+            Some(stream) => stream,
+            None => self.connect().await?,
+        };
+
+        let reply = roundtrip(&mut stream, args).await?;
+        *conn = Some(stream);
+        match reply {
+            Reply::Error(message) => Err(Error::Backend {
+                service: "Redis",
+                reason: message,
+            }),
+            reply => Ok(reply),
+        }
+    }
+
+    async fn connect(&self) -> Result<BufReader<TcpStream>, Error> {
+        let mut stream = BufReader::new(TcpStream::connect(&self.addr).await?);
+        if let Some(password) = &self.password {
+            match roundtrip(&mut stream, &[b"AUTH", password.as_bytes()]).await? {
+                Reply::Value => {}
+                Reply::Error(message) => {
+                    return Err(Error::Backend {
+                        service: "Redis",
+                        reason: message,
+                    })
+                }
+                _ => return Err(Error::Malformed("unexpected Redis reply")),
+            }
+        }
+        Ok(stream)
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{key}", self.prefix)
+    }
+}
+
+impl Coordinator for RedisCoordinator {
+    fn try_lock<'a>(
+        &'a self,
+        key: &'a str,
+        ttl: Duration,
+    ) -> CoordinatorFuture<'a, Option<LockToken>> {
+        Box::pin(async move {
+            let token = LockToken::generate()?;
+            let key = self.key(key);
+            let ttl = millis(ttl);
+            let args: [&[u8]; 6] = [
+                b"SET",
+                key.as_bytes(),
+                token.as_str().as_bytes(),
+                b"NX",
+                b"PX",
+                ttl.as_bytes(),
+            ];
+            match self.command(&args).await? {
+                Reply::Value => Ok(Some(token)),
+                Reply::Nil => Ok(None),
+                _ => Err(Error::Malformed("unexpected Redis reply")),
+            }
+        })
+    }
+
+    fn extend<'a>(
+        &'a self,
+        key: &'a str,
+        token: &'a LockToken,
+        ttl: Duration,
+    ) -> CoordinatorFuture<'a, bool> {
+        Box::pin(async move {
+            let key = self.key(key);
+            let ttl = millis(ttl);
+            let args: [&[u8]; 6] = [
+                b"EVAL",
+                EXTEND.as_bytes(),
+                b"1",
+                key.as_bytes(),
+                token.as_str().as_bytes(),
+                ttl.as_bytes(),
+            ];
+            match self.command(&args).await? {
+                Reply::Integer(extended) => Ok(extended == 1),
+                _ => Err(Error::Malformed("unexpected Redis reply")),
+            }
+        })
+    }
+
+    fn unlock<'a>(&'a self, key: &'a str, token: &'a LockToken) -> CoordinatorFuture<'a, ()> {
+        Box::pin(async move {
+            let key = self.key(key);
+            let args: [&[u8]; 5] = [
+                b"EVAL",
+                UNLOCK.as_bytes(),
+                b"1",
+                key.as_bytes(),
+                token.as_str().as_bytes(),
+            ];
+            match self.command(&args).await? {
+                Reply::Integer(_) => Ok(()),
+                _ => Err(Error::Malformed("unexpected Redis reply")),
+            }
+        })
+    }
+
+    fn acquire_slot<'a>(
+        &'a self,
+        key: &'a str,
+        max: u32,
+        window: Duration,
+    ) -> CoordinatorFuture<'a, Option<Duration>> {
+        Box::pin(async move {
+            let key = self.key(key);
+            let (max, window) = (max.to_string(), millis(window));
+            let args: [&[u8]; 6] = [
+                b"EVAL",
+                ACQUIRE_SLOT.as_bytes(),
+                b"1",
+                key.as_bytes(),
+                max.as_bytes(),
+                window.as_bytes(),
+            ];
+            match self.command(&args).await? {
+                Reply::Integer(wait) if wait < 0 => Ok(None),
+                Reply::Integer(wait) => Ok(Some(Duration::from_millis(wait as u64))),
+                _ => Err(Error::Malformed("unexpected Redis reply")),
+            }
+        })
+    }
+}
+
+/// A reply in the Redis serialization protocol (RESP2), without arrays
+enum Reply {
+    /// A status or bulk string reply, whose contents are not needed
+    Value,
+    Integer(i64),
+    Nil,
+    /// An error reply, with the server's message
+    Error(String),
+}
+
+async fn roundtrip(stream: &mut BufReader<TcpStream>, args: &[&[u8]]) -> Result<Reply, Error> {
+    let mut request = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        request.extend_from_slice(arg);
+        request.extend_from_slice(b"\r\n");
+    }
+    stream.get_mut().write_all(&request).await?;
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let line = line.trim_end_matches("\r\n");
+    let (kind, value) = match line.chars().next() {
+        Some(kind) => (kind, &line[1..]),
+        None => {
+            return Err(Error::Backend {
+                service: "Redis",
+                reason: "connection closed by the server".to_owned(),
+            })
+        }
+    };
+
+    let invalid = || Error::Malformed("Redis reply");
+    match kind {
+        '+' => Ok(Reply::Value),
+        '-' => Ok(Reply::Error(value.to_owned())),
+        ':' => Ok(Reply::Integer(value.parse().map_err(|_| invalid())?)),
+        '$' => {
+            let len = value.parse::<i64>().map_err(|_| invalid())?;
+            if len < 0 {
+                return Ok(Reply::Nil);
+            }
+
+            // Skip the contents and the trailing CRLF
+            let mut data = vec![0; len as usize + 2];
+            stream.read_exact(&mut data).await?;
+            Ok(Reply::Value)
+        }
+        '*' if value == "-1" => Ok(Reply::Nil),
+        _ => Err(invalid()),
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    duration.as_millis().max(1).to_string()
+}
+
+/// Delete the lock key if it still holds the caller's token
+const UNLOCK: &str = "if redis.call('GET', KEYS[1]) == ARGV[1] then \
+    return redis.call('DEL', KEYS[1]) else return 0 end";
+
+/// Reset the expiry of the lock key if it still holds the caller's token
+const EXTEND: &str = "if redis.call('GET', KEYS[1]) == ARGV[1] then \
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2]) else return 0 end";
+
+/// Count an event unless the limit is reached; returns -1 if counted, or the milliseconds
+/// until the window ends
+const ACQUIRE_SLOT: &str = "local n = tonumber(redis.call('GET', KEYS[1]) or '0') \
+    if n >= tonumber(ARGV[1]) then \
+        local ttl = redis.call('PTTL', KEYS[1]) \
+        if ttl < 0 then redis.call('PEXPIRE', KEYS[1], ARGV[2]) ttl = tonumber(ARGV[2]) end \
+        return ttl end \
+    if redis.call('INCR', KEYS[1]) == 1 then redis.call('PEXPIRE', KEYS[1], ARGV[2]) end \
+    return -1";
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Serve one connection per entry of `script`, answering each command on it with the next
+    /// reply after the given delay
+    async fn serve(script: Vec<Vec<(Duration, &'static str)>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            for replies in script {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    for (delay, reply) in replies {
+                        let mut line = String::new();
+                        stream.read_line(&mut line).await.unwrap();
+                        let args = line.trim_end()[1..].parse::<usize>().unwrap();
+                        for _ in 0..2 * args {
+                            stream.read_line(&mut line).await.unwrap();
+                        }
+
+                        tokio::time::sleep(delay).await;
+                        let reply = format!("{reply}\r\n");
+                        stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+                    }
+
+                    let _ = stream.read_to_end(&mut Vec::new()).await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn cancelled_command() {
+        let addr = serve(vec![
+            vec![(Duration::from_millis(200), "+OK")],
+            vec![(Duration::ZERO, "$-1")],
+        ])
+        .await;
+        let coordinator = RedisCoordinator::new(addr);
+        let ttl = Duration::from_secs(60);
+
+        let cancelled =
+            tokio::time::timeout(Duration::from_millis(20), coordinator.try_lock("a", ttl)).await;
+        assert!(cancelled.is_err());
+
+        // The late `+OK` must not be taken as the reply to the next command
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(coordinator.try_lock("a", ttl).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn error_replies() {
+        let addr = serve(vec![vec![
+            (Duration::ZERO, "-ERR unknown command 'EVAL'"),
+            (Duration::ZERO, ":1"),
+        ]])
+        .await;
+        let coordinator = RedisCoordinator::new(addr);
+        let token = LockToken::generate().unwrap();
+
+        match coordinator.unlock("a", &token).await {
+            Err(Error::Backend { service, reason }) => {
+                assert_eq!(service, "Redis");
+                assert_eq!(reason, "ERR unknown command 'EVAL'");
+            }
+            result => panic!("unexpected result: {result:?}"),
+        }
+
+        // The connection is still usable after an error reply
+        let extended = coordinator.extend("a", &token, Duration::from_secs(60));
+        assert!(extended.await.unwrap());
+    }
+}
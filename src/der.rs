@@ -0,0 +1,1303 @@
+//! Minimal DER and PEM helpers for handling private key material and certificates
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use std::num::NonZeroU32;
+
+use ring::{digest, hmac, pbkdf2};
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+use crate::aes;
+use crate::store::hex;
+use crate::{Error, KeyType, SignedCertificateTimestamp, TnEntry};
+
+/// Wrap an RFC 5915 `ECPrivateKey` for P-256 in a PKCS#8 `PrivateKeyInfo`
+pub(crate) fn pkcs8_from_sec1(sec1: &[u8]) -> Vec<u8> {
+    pkcs8(&sequence(&[&oid(OID_EC_PUBLIC_KEY), &oid(OID_P256)]), sec1)
+}
+
+/// Wrap a PKCS#1 `RSAPrivateKey` in a PKCS#8 `PrivateKeyInfo`
+pub(crate) fn pkcs8_from_pkcs1(pkcs1: &[u8]) -> Vec<u8> {
+    pkcs8(
+        &sequence(&[&oid(OID_RSA_ENCRYPTION), &tlv(NULL, &[])]),
+        pkcs1,
+    )
+}
+
+/// Encode the seed of an ML-DSA-44 key in a PKCS#8 `PrivateKeyInfo`, in the seed-only form
+/// of draft-ietf-lamps-dilithium-certificates
+#[cfg(feature = "ml-dsa")]
+pub(crate) fn pkcs8_from_ml_dsa44_seed(seed: &[u8; 32]) -> Vec<u8> {
+    pkcs8(&sequence(&[&oid(OID_ML_DSA_44)]), &tlv(ML_DSA_SEED, seed))
+}
+
+/// Encode an RFC 5915 `ECPrivateKey` from the private scalar and uncompressed public point
+pub(crate) fn sec1(private: &[u8], public: &[u8]) -> Vec<u8> {
+    let mut bits = Vec::with_capacity(public.len() + 1);
+    bits.push(0);
+    bits.extend_from_slice(public);
+    sequence(&[
+        &tlv(INTEGER, &[1]),
+        &tlv(OCTET_STRING, private),
+        &tlv(CONTEXT_1, &tlv(BIT_STRING, &bits)),
+    ])
+}
+
+/// Encode a PKCS#1 `RSAPrivateKey` from its big-endian components
+///
+/// `components` must contain `n`, `e`, `d`, `p`, `q`, `dp`, `dq` and `qi`, in that order.
+pub(crate) fn pkcs1(components: &[Vec<u8>]) -> Vec<u8> {
+    let mut parts = vec![integer(&[0])];
+    parts.extend(components.iter().map(|c| integer(c)));
+    sequence(&parts.iter().map(|p| p.as_slice()).collect::<Vec<_>>())
+}
+
+/// Components of a private key extracted from a PKCS#8 `PrivateKeyInfo`
+pub(crate) enum PrivateKey {
+    /// The private scalar and uncompressed public point of a P-256 key
+    Ec { private: Vec<u8>, public: Vec<u8> },
+    /// The components of an RSA key, in the order documented on [`pkcs1()`]
+    Rsa(Vec<Vec<u8>>),
+    /// The seed of an ML-DSA-44 key
+    #[cfg(feature = "ml-dsa")]
+    MlDsa44([u8; 32]),
+}
+
+impl PrivateKey {
+    /// Parse the key material from a PKCS#8 `PrivateKeyInfo`
+    pub(crate) fn from_pkcs8(pkcs8: &[u8]) -> Result<Self, Error> {
+        let (info, _) = expect(SEQUENCE, pkcs8)?;
+        let (_, info) = expect(INTEGER, info)?;
+        let (algorithm, info) = expect(SEQUENCE, info)?;
+        let (oid, _) = expect(OBJECT_IDENTIFIER, algorithm)?;
+        let (key, _) = expect(OCTET_STRING, info)?;
+        match oid {
+            OID_EC_PUBLIC_KEY => {
+                let (key, _) = expect(SEQUENCE, key)?;
+                let (_, key) = expect(INTEGER, key)?;
+                let (private, mut key) = expect(OCTET_STRING, key)?;
+                if key.first() == Some(&CONTEXT_0) {
+                    key = expect(CONTEXT_0, key)?.1;
+                }
+                let (public, _) = expect(CONTEXT_1, key)?;
+                let (public, _) = expect(BIT_STRING, public)?;
+                Ok(Self::Ec {
+                    private: private.to_vec(),
+                    public: public.get(1..).unwrap_or_default().to_vec(),
+                })
+            }
+            OID_RSA_ENCRYPTION => {
+                let (mut key, _) = expect(SEQUENCE, key)?;
+                let mut components = Vec::with_capacity(8);
+                for i in 0..9 {
+                    let (value, rest) = expect(INTEGER, key)?;
+                    key = rest;
+                    if i > 0 {
+                        let start = value.iter().position(|&b| b != 0).unwrap_or(value.len());
+                        components.push(value[start..].to_vec());
+                    }
+                }
+                Ok(Self::Rsa(components))
+            }
+            #[cfg(feature = "ml-dsa")]
+            OID_ML_DSA_44 => {
+                // The seed alone, or followed by the expanded key, which is derived again
+                let seed = match key.first() {
+                    Some(&ML_DSA_SEED) => expect(ML_DSA_SEED, key)?.0,
+                    Some(&SEQUENCE) => expect(OCTET_STRING, expect(SEQUENCE, key)?.0)?.0,
+                    _ => return Err(Error::Unsupported("ML-DSA private key without seed")),
+                };
+                let mut out = [0; 32];
+                match seed.len() == out.len() {
+                    true => out.copy_from_slice(seed),
+                    false => return Err(Error::Malformed("ML-DSA private key seed")),
+                }
+                Ok(Self::MlDsa44(out))
+            }
+            _ => Err(Error::Unsupported("private key algorithm")),
+        }
+    }
+}
+
+/// The public key of a certificate signing request
+pub(crate) enum CsrKey<'a> {
+    /// An uncompressed P-256 point, signed with ECDSA and SHA-256
+    P256(&'a [u8]),
+    /// An uncompressed P-384 point, signed with ECDSA and SHA-384
+    P384(&'a [u8]),
+    /// An Ed25519 public key
+    Ed25519(&'a [u8]),
+}
+
+/// Build a PKCS#10 certificate signing request with an empty subject
+///
+/// The DNS `names` are requested as subject alternative names, as ACME CAs expect. Each of
+/// the `attributes` is added as an attribute OID with an `OCTET STRING` value. With
+/// `must_staple`, the `status_request` TLS feature (RFC 7633) is requested as well. `sign`
+/// signs the encoded `CertificationRequestInfo` with the private key, returning an
+/// ASN.1-encoded ECDSA signature or a raw Ed25519 signature.
+pub(crate) fn csr(
+    names: &[&str],
+    key: CsrKey<'_>,
+    attributes: &[(&[u8], &[u8])],
+    must_staple: bool,
+    sign: impl FnOnce(&[u8]) -> Result<Vec<u8>, Error>,
+) -> Result<Vec<u8>, Error> {
+    let names = names
+        .iter()
+        .map(|name| tlv(DNS_NAME, name.as_bytes()))
+        .collect::<Vec<_>>()
+        .concat();
+    let mut extensions = sequence(&[
+        &oid(OID_SUBJECT_ALT_NAME),
+        &tlv(OCTET_STRING, &tlv(SEQUENCE, &names)),
+    ]);
+    if must_staple {
+        extensions.extend(sequence(&[
+            &oid(OID_TLS_FEATURE),
+            &tlv(
+                OCTET_STRING,
+                &tlv(SEQUENCE, &tlv(INTEGER, &[TLS_STATUS_REQUEST])),
+            ),
+        ]));
+    }
+    let mut attributes = attributes
+        .iter()
+        .map(|(id, value)| sequence(&[&oid(id), &tlv(SET, &tlv(OCTET_STRING, value))]))
+        .collect::<Vec<_>>();
+    attributes.push(sequence(&[
+        &oid(OID_EXTENSION_REQUEST),
+        &tlv(SET, &tlv(SEQUENCE, &extensions)),
+    ]));
+
+    let (algorithm, public_key, signature_algorithm) = match key {
+        CsrKey::P256(public_key) => (
+            sequence(&[&oid(OID_EC_PUBLIC_KEY), &oid(OID_P256)]),
+            public_key,
+            OID_ECDSA_WITH_SHA256,
+        ),
+        CsrKey::P384(public_key) => (
+            sequence(&[&oid(OID_EC_PUBLIC_KEY), &oid(OID_P384)]),
+            public_key,
+            OID_ECDSA_WITH_SHA384,
+        ),
+        CsrKey::Ed25519(public_key) => (sequence(&[&oid(OID_ED25519)]), public_key, OID_ED25519),
+    };
+
+    let info = sequence(&[
+        &tlv(INTEGER, &[0]),
+        &tlv(SEQUENCE, &[]),
+        &sequence(&[&algorithm, &tlv(BIT_STRING, &[&[0], public_key].concat())]),
+        &tlv(CONTEXT_0, &attributes.concat()),
+    ]);
+
+    let signature = sign(&info)?;
+    Ok(sequence(&[
+        &info,
+        &sequence(&[&oid(signature_algorithm)]),
+        &tlv(BIT_STRING, &[&[0], &signature[..]].concat()),
+    ]))
+}
+
+/// Encode a TNAuthList (RFC 8226 section 9), which uses explicit tags
+pub(crate) fn tn_auth_list(entries: &[TnEntry]) -> Vec<u8> {
+    let entries = entries
+        .iter()
+        .map(|entry| match entry {
+            TnEntry::ServiceProviderCode(spc) => tlv(CONTEXT_0, &tlv(IA5_STRING, spc.as_bytes())),
+            TnEntry::Range { start, count } => tlv(
+                CONTEXT_1,
+                &sequence(&[
+                    &tlv(IA5_STRING, start.as_bytes()),
+                    &integer(&count.to_be_bytes()),
+                ]),
+            ),
+            TnEntry::Number(number) => tlv(CONTEXT_2, &tlv(IA5_STRING, number.as_bytes())),
+        })
+        .collect::<Vec<_>>();
+    tlv(SEQUENCE, &entries.concat())
+}
+
+/// Encode a PKCS #12 archive with the PKCS #8 key `pkcs8` and the certificate `chain`
+///
+/// The key and the end-entity certificate are named `alias`. The key is in a shrouded key
+/// bag, encrypted with PBES2 (RFC 8018) using PBKDF2-HMAC-SHA256 over `password` and
+/// `key_salt`, and AES-256-CBC with `iv`, like OpenSSL 3 does by default; Java's KeyStore
+/// ignores unencrypted keys. The certificates are not encrypted. The archive is protected by
+/// an HMAC-SHA256 over its contents, keyed from `password` and `salt` with the PKCS #12 key
+/// derivation (RFC 7292 appendix B).
+pub(crate) fn pkcs12(
+    pkcs8: &[u8],
+    chain: &[Vec<u8>],
+    alias: &str,
+    password: &str,
+    salt: &[u8],
+    key_salt: &[u8],
+    iv: &[u8; 16],
+) -> Vec<u8> {
+    let key_id = digest::digest(&digest::SHA256, pkcs8);
+    let attributes = tlv(
+        SET,
+        &[
+            sequence(&[
+                &oid(OID_LOCAL_KEY_ID),
+                &tlv(SET, &tlv(OCTET_STRING, key_id.as_ref())),
+            ]),
+            sequence(&[
+                &oid(OID_FRIENDLY_NAME),
+                &tlv(SET, &tlv(BMP_STRING, &utf16_be(alias))),
+            ]),
+        ]
+        .concat(),
+    );
+
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PKCS12_ITERATIONS).unwrap(),
+        key_salt,
+        password.as_bytes(),
+        &mut key,
+    );
+    let encrypted = aes::aes256_cbc_encrypt(&key, iv, pkcs8);
+    key.fill(0);
+    let pbkdf2_params = sequence(&[
+        &tlv(OCTET_STRING, key_salt),
+        &integer(&PKCS12_ITERATIONS.to_be_bytes()),
+        &sequence(&[&oid(OID_HMAC_WITH_SHA256), &tlv(NULL, &[])]),
+    ]);
+    let algorithm = sequence(&[
+        &oid(OID_PBES2),
+        &sequence(&[
+            &sequence(&[&oid(OID_PBKDF2), &pbkdf2_params]),
+            &sequence(&[&oid(OID_AES256_CBC), &tlv(OCTET_STRING, iv)]),
+        ]),
+    ]);
+    let shrouded = sequence(&[&algorithm, &tlv(OCTET_STRING, &encrypted)]);
+
+    let mut bags = vec![sequence(&[
+        &oid(OID_PKCS8_SHROUDED_KEY_BAG),
+        &tlv(CONTEXT_0, &shrouded),
+        &attributes,
+    ])];
+    for (i, certificate) in chain.iter().enumerate() {
+        let value = sequence(&[
+            &oid(OID_X509_CERTIFICATE),
+            &tlv(CONTEXT_0, &tlv(OCTET_STRING, certificate)),
+        ]);
+        let attributes = match i {
+            0 => &attributes[..],
+            _ => &[],
+        };
+        bags.push(sequence(&[
+            &oid(OID_CERT_BAG),
+            &tlv(CONTEXT_0, &value),
+            attributes,
+        ]));
+    }
+
+    let data =
+        |content: &[u8]| sequence(&[&oid(OID_DATA), &tlv(CONTEXT_0, &tlv(OCTET_STRING, content))]);
+    let authenticated_safe = sequence(&[&data(&tlv(SEQUENCE, &bags.concat()))]);
+
+    // The password is a NUL-terminated BMPString, and a 32-byte key takes a single round
+    let mut password = utf16_be(password);
+    password.extend_from_slice(&[0, 0]);
+    let repeat = |input: &[u8]| match input.is_empty() {
+        true => Vec::new(),
+        false => {
+            let len = (input.len() + 63) / 64 * 64;
+            input.iter().copied().cycle().take(len).collect::<Vec<_>>()
+        }
+    };
+    let mut key = [&[PKCS12_MAC_ID; 64][..], &repeat(salt), &repeat(&password)].concat();
+    for _ in 0..PKCS12_ITERATIONS {
+        key = digest::digest(&digest::SHA256, &key).as_ref().to_vec();
+    }
+    let mac = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, &key),
+        &authenticated_safe,
+    );
+
+    sequence(&[
+        &integer(&[3]),
+        &data(&authenticated_safe),
+        &sequence(&[
+            &sequence(&[
+                &sequence(&[&oid(OID_SHA256), &tlv(NULL, &[])]),
+                &tlv(OCTET_STRING, mac.as_ref()),
+            ]),
+            &tlv(OCTET_STRING, salt),
+            &integer(&PKCS12_ITERATIONS.to_be_bytes()),
+        ]),
+    ])
+}
+
+/// Encode a Java KeyStore with the PKCS #8 key `pkcs8` and the certificate `chain` as `alias`
+///
+/// The key is protected with `password` using the JKS key protection algorithm, and the
+/// keystore with the same password. `timestamp` is the creation time of the entry, in
+/// milliseconds since the Unix epoch.
+pub(crate) fn jks(
+    pkcs8: &[u8],
+    chain: &[Vec<u8>],
+    alias: &str,
+    password: &str,
+    salt: &[u8],
+    timestamp: u64,
+) -> Vec<u8> {
+    let password = utf16_be(password);
+    let sha1 = |parts: &[&[u8]]| {
+        let mut context = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+        for part in parts {
+            context.update(part);
+        }
+        context.finish()
+    };
+
+    let mut protected = salt.to_vec();
+    let mut stream = sha1(&[&password, salt]);
+    for (i, b) in pkcs8.iter().enumerate() {
+        let offset = i % stream.as_ref().len();
+        if i > 0 && offset == 0 {
+            stream = sha1(&[&password, stream.as_ref()]);
+        }
+        protected.push(b ^ stream.as_ref()[offset]);
+    }
+    protected.extend_from_slice(sha1(&[&password, pkcs8]).as_ref());
+    let key = sequence(&[
+        &sequence(&[&oid(OID_JKS_KEY_PROTECTOR), &tlv(NULL, &[])]),
+        &tlv(OCTET_STRING, &protected),
+    ]);
+
+    let utf = |out: &mut Vec<u8>, value: &str| {
+        out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        out.extend_from_slice(value.as_bytes());
+    };
+    let mut out = Vec::new();
+    out.extend_from_slice(&JKS_MAGIC.to_be_bytes());
+    out.extend_from_slice(&2u32.to_be_bytes());
+    out.extend_from_slice(&1u32.to_be_bytes());
+    out.extend_from_slice(&1u32.to_be_bytes());
+    utf(&mut out, alias);
+    out.extend_from_slice(&timestamp.to_be_bytes());
+    out.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    out.extend_from_slice(&key);
+    out.extend_from_slice(&(chain.len() as u32).to_be_bytes());
+    for certificate in chain {
+        utf(&mut out, "X.509");
+        out.extend_from_slice(&(certificate.len() as u32).to_be_bytes());
+        out.extend_from_slice(certificate);
+    }
+
+    let integrity = sha1(&[&password, b"Mighty Aphrodite", &out]);
+    out.extend_from_slice(integrity.as_ref());
+    out
+}
+
+fn utf16_be(value: &str) -> Vec<u8> {
+    value.encode_utf16().flat_map(u16::to_be_bytes).collect()
+}
+
+/// Convert a DER-encoded ECDSA signature to the fixed encoding of `r` and `s` with `len`
+/// bytes each, as used by JWS
+pub(crate) fn ecdsa_signature_fixed(signature: &[u8], len: usize) -> Result<Vec<u8>, Error> {
+    const INVALID: Error = Error::Malformed("ECDSA signature");
+    let (signature, rest) = expect(SEQUENCE, signature)?;
+    let (r, signature) = expect(INTEGER, signature)?;
+    let (s, signature) = expect(INTEGER, signature)?;
+    if !rest.is_empty() || !signature.is_empty() {
+        return Err(INVALID);
+    }
+
+    let mut out = Vec::with_capacity(2 * len);
+    for value in [r, s] {
+        let start = value.iter().position(|&b| b != 0).unwrap_or(value.len());
+        let value = &value[start..];
+        if value.len() > len {
+            return Err(INVALID);
+        }
+        out.resize(out.len() + len - value.len(), 0);
+        out.extend_from_slice(value);
+    }
+    Ok(out)
+}
+
+/// Extract the `notBefore` and `notAfter` times from a DER-encoded X.509 certificate
+pub(crate) fn validity(certificate: &[u8]) -> Result<(OffsetDateTime, OffsetDateTime), Error> {
+    let (_, tbs) = tbs_certificate(certificate)?;
+    let (_, tbs) = expect(SEQUENCE, tbs)?;
+    let (_, tbs) = expect(SEQUENCE, tbs)?;
+    let (validity, _) = expect(SEQUENCE, tbs)?;
+    let (not_before, validity) = time(validity)?;
+    let (not_after, _) = time(validity)?;
+    Ok((not_before, not_after))
+}
+
+/// The type and size in bits of the subject public key of a DER-encoded X.509 certificate
+///
+/// The type is `None` for keys of other types, whose size is then reported as 0.
+pub(crate) fn public_key(certificate: &[u8]) -> Result<(Option<KeyType>, usize), Error> {
+    let (_, tbs) = tbs_certificate(certificate)?;
+    let (_, tbs) = expect(SEQUENCE, tbs)?;
+    let (_, tbs) = expect(SEQUENCE, tbs)?;
+    let (_, tbs) = expect(SEQUENCE, tbs)?;
+    let (_, tbs) = expect(SEQUENCE, tbs)?;
+    subject_public_key(tbs)
+}
+
+/// The type and size in bits of the public key of a DER-encoded PKCS #10 certificate signing
+/// request
+pub(crate) fn csr_public_key(csr: &[u8]) -> Result<(Option<KeyType>, usize), Error> {
+    let (csr, _) = expect(SEQUENCE, csr)?;
+    let (info, _) = expect(SEQUENCE, csr)?;
+    let (_, info) = expect(INTEGER, info)?;
+    let (_, info) = expect(SEQUENCE, info)?;
+    subject_public_key(info)
+}
+
+/// The type and size in bits of a `SubjectPublicKeyInfo`
+fn subject_public_key(input: &[u8]) -> Result<(Option<KeyType>, usize), Error> {
+    let (spki, _) = expect(SEQUENCE, input)?;
+    let (algorithm, spki) = expect(SEQUENCE, spki)?;
+    let (key_type, parameters) = expect(OBJECT_IDENTIFIER, algorithm)?;
+    Ok(match key_type {
+        OID_RSA_ENCRYPTION => {
+            let (key, _) = expect(BIT_STRING, spki)?;
+            let (key, _) = expect(SEQUENCE, key.get(1..).unwrap_or_default())?;
+            let (modulus, _) = expect(INTEGER, key)?;
+            let start = modulus
+                .iter()
+                .position(|&b| b != 0)
+                .unwrap_or(modulus.len());
+            let bits = match modulus[start..].first() {
+                Some(first) => (modulus.len() - start) * 8 - first.leading_zeros() as usize,
+                None => 0,
+            };
+            (Some(KeyType::Rsa), bits)
+        }
+        OID_EC_PUBLIC_KEY => match expect(OBJECT_IDENTIFIER, parameters)?.0 {
+            OID_P256 => (Some(KeyType::EcdsaP256), 256),
+            OID_P384 => (Some(KeyType::EcdsaP384), 384),
+            _ => (None, 0),
+        },
+        OID_ED25519 => (Some(KeyType::Ed25519), 256),
+        _ => (None, 0),
+    })
+}
+
+/// Extract the DNS names from the subject alternative name extension of a DER-encoded X.509
+/// certificate
+pub(crate) fn dns_names(certificate: &[u8]) -> Result<Vec<String>, Error> {
+    match certificate_extensions(certificate)? {
+        Some(extensions) => extension_dns_names(extensions),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The `Extensions` sequence of a DER-encoded X.509 certificate, if it has one
+pub(crate) fn certificate_extensions(certificate: &[u8]) -> Result<Option<&[u8]>, Error> {
+    let (_, mut tbs) = tbs_certificate(certificate)?;
+    // Skip the signature algorithm, issuer, validity and subject
+    for _ in 0..4 {
+        tbs = expect(SEQUENCE, tbs)?.1;
+    }
+    let (_, mut tbs) = expect(SEQUENCE, tbs)?;
+    // Skip the issuer and subject unique identifiers
+    for tag in [0x81, 0x82] {
+        if tbs.first() == Some(&tag) {
+            tbs = expect(tag, tbs)?.1;
+        }
+    }
+    match tbs.first() == Some(&CONTEXT_3) {
+        true => Ok(Some(expect(CONTEXT_3, tbs)?.0)),
+        false => Ok(None),
+    }
+}
+
+/// The value of the extension with the given `id` in a DER-encoded `Extensions` sequence
+pub(crate) fn find_extension<'a>(
+    extensions: &'a [u8],
+    id: &[u8],
+) -> Result<Option<&'a [u8]>, Error> {
+    let (mut extensions, _) = expect(SEQUENCE, extensions)?;
+    while !extensions.is_empty() {
+        let (extension, rest) = expect(SEQUENCE, extensions)?;
+        extensions = rest;
+        let (extension_id, mut extension) = expect(OBJECT_IDENTIFIER, extension)?;
+        if extension_id != id {
+            continue;
+        }
+        if extension.first() == Some(&BOOLEAN) {
+            extension = expect(BOOLEAN, extension)?.1;
+        }
+        return Ok(Some(expect(OCTET_STRING, extension)?.0));
+    }
+    Ok(None)
+}
+
+/// Extract the DNS names from the subject alternative name extension of a DER-encoded
+/// PKCS #10 certificate signing request
+pub(crate) fn csr_dns_names(csr: &[u8]) -> Result<Vec<String>, Error> {
+    let (csr, _) = expect(SEQUENCE, csr)?;
+    let (info, _) = expect(SEQUENCE, csr)?;
+    let (_, info) = expect(INTEGER, info)?;
+    let (_, info) = expect(SEQUENCE, info)?;
+    let (_, info) = expect(SEQUENCE, info)?;
+    let (mut attributes, _) = expect(CONTEXT_0, info)?;
+    while !attributes.is_empty() {
+        let (attribute, rest) = expect(SEQUENCE, attributes)?;
+        attributes = rest;
+        let (id, values) = expect(OBJECT_IDENTIFIER, attribute)?;
+        if id == OID_EXTENSION_REQUEST {
+            let (extensions, _) = expect(SET, values)?;
+            return extension_dns_names(extensions);
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// Extract the DNS names from the subject alternative name extension in a DER-encoded
+/// `Extensions` sequence
+fn extension_dns_names(extensions: &[u8]) -> Result<Vec<String>, Error> {
+    let value = match find_extension(extensions, OID_SUBJECT_ALT_NAME)? {
+        Some(value) => value,
+        None => return Ok(Vec::new()),
+    };
+
+    let (mut general_names, _) = expect(SEQUENCE, value)?;
+    let mut names = Vec::new();
+    while let Some(&tag) = general_names.first() {
+        let (name, rest) = expect(tag, general_names)?;
+        general_names = rest;
+        if tag == DNS_NAME {
+            let name = std::str::from_utf8(name)
+                .map_err(|_| Error::Malformed("DNS name in certificate"))?;
+            names.push(name.to_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// The signed certificate timestamps embedded in a DER-encoded X.509 certificate
+/// (RFC 6962 section 3.3)
+pub(crate) fn scts(certificate: &[u8]) -> Result<Vec<SignedCertificateTimestamp>, Error> {
+    const INVALID: Error = Error::Malformed("signed certificate timestamp list");
+    let extensions = match certificate_extensions(certificate)? {
+        Some(extensions) => extensions,
+        None => return Ok(Vec::new()),
+    };
+    let value = match find_extension(extensions, OID_SCT_LIST)? {
+        Some(value) => value,
+        None => return Ok(Vec::new()),
+    };
+
+    // The list is TLS-encoded inside an OCTET STRING, with 16-bit length prefixes
+    let (list, _) = expect(OCTET_STRING, value)?;
+    let (mut list, rest) = tls_vector(list).ok_or(INVALID)?;
+    if !rest.is_empty() {
+        return Err(INVALID);
+    }
+
+    let mut scts = Vec::new();
+    while !list.is_empty() {
+        let (sct, rest) = tls_vector(list).ok_or(INVALID)?;
+        list = rest;
+        let (&version, sct) = sct.split_first().ok_or(INVALID)?;
+        if sct.len() < 40 {
+            return Err(INVALID);
+        }
+        let (log_id, sct) = sct.split_at(32);
+        let (timestamp, sct) = sct.split_at(8);
+        let timestamp = u64::from_be_bytes(timestamp.try_into().unwrap());
+        let (extensions, sct) = tls_vector(sct).ok_or(INVALID)?;
+        let (algorithms, sct) = match sct.len() < 2 {
+            true => return Err(INVALID),
+            false => sct.split_at(2),
+        };
+        let (signature, _) = tls_vector(sct).ok_or(INVALID)?;
+
+        scts.push(SignedCertificateTimestamp {
+            version,
+            log_id: log_id.try_into().unwrap(),
+            timestamp: OffsetDateTime::from_unix_timestamp_nanos(timestamp as i128 * 1_000_000)
+                .map_err(|_| INVALID)?,
+            extensions: extensions.to_vec(),
+            hash_algorithm: algorithms[0],
+            signature_algorithm: algorithms[1],
+            signature: signature.to_vec(),
+        });
+    }
+    Ok(scts)
+}
+
+/// Split a TLS vector with a 16-bit length prefix off `input`
+fn tls_vector(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u16::from_be_bytes([*input.first()?, *input.get(1)?]) as usize;
+    match input.len() >= len + 2 {
+        true => Some((&input[2..len + 2], &input[len + 2..])),
+        false => None,
+    }
+}
+
+/// The serial number of a DER-encoded X.509 certificate, in lowercase hexadecimal
+///
+/// Leading zero bytes of the encoded integer are skipped.
+pub(crate) fn serial_number(certificate: &[u8]) -> Result<String, Error> {
+    let (serial, _) = tbs_certificate(certificate)?;
+    let start = serial
+        .iter()
+        .position(|&b| b != 0)
+        .unwrap_or(serial.len().saturating_sub(1));
+    Ok(hex(&serial[start..]))
+}
+
+/// The serial number and the fields after it in the `tbsCertificate` of a DER-encoded X.509
+/// certificate
+fn tbs_certificate(certificate: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let (certificate, _) = expect(SEQUENCE, certificate)?;
+    let (mut tbs, _) = expect(SEQUENCE, certificate)?;
+    if tbs.first() == Some(&CONTEXT_0) {
+        tbs = expect(CONTEXT_0, tbs)?.1;
+    }
+    expect(INTEGER, tbs)
+}
+
+/// Read a `UTCTime` or `GeneralizedTime` value in the `YYMMDDHHMMSSZ` or `YYYYMMDDHHMMSSZ`
+/// form required by RFC 5280
+///
+/// OCSP responders may add fractional seconds to `GeneralizedTime` values, which are accepted
+/// and truncated.
+pub(crate) fn time(input: &[u8]) -> Result<(OffsetDateTime, &[u8]), Error> {
+    const INVALID: Error = Error::Malformed("certificate validity time");
+    let year_len = match input.first() {
+        Some(&UTC_TIME) => 2,
+        Some(&GENERALIZED_TIME) => 4,
+        _ => return Err(INVALID),
+    };
+
+    let (value, rest) = expect(input[0], input)?;
+    let value = match value.split_last() {
+        Some((b'Z', value)) => value,
+        _ => return Err(INVALID),
+    };
+    // GeneralizedTime may have fractional seconds, which are truncated
+    let (digits, fraction) = match value.iter().position(|&b| b == b'.') {
+        Some(dot) if year_len == 4 => (&value[..dot], &value[dot + 1..]),
+        _ => (value, &b"0"[..]),
+    };
+    if digits.len() != year_len + 10
+        || fraction.is_empty()
+        || !digits.iter().chain(fraction).all(u8::is_ascii_digit)
+    {
+        return Err(INVALID);
+    }
+
+    let number = |start: usize, len: usize| {
+        digits[start..start + len]
+            .iter()
+            .fold(0, |acc, &b| acc * 10 + (b - b'0') as i32)
+    };
+    let year = match (year_len, number(0, year_len)) {
+        // Two-digit years from 50 are in the 20th century (RFC 5280 section 4.1.2.5.1)
+        (2, year @ 50..=99) => 1900 + year,
+        (2, year) => 2000 + year,
+        (_, year) => year,
+    };
+
+    let field = |i: usize| number(year_len + i * 2, 2) as u8;
+    let month = Month::try_from(field(0)).map_err(|_| INVALID)?;
+    let date = Date::from_calendar_date(year, month, field(1)).map_err(|_| INVALID)?;
+    let time = Time::from_hms(field(2), field(3), field(4)).map_err(|_| INVALID)?;
+    Ok((PrimitiveDateTime::new(date, time).assume_utc(), rest))
+}
+
+/// Read a DER value with the given tag, returning its contents and the remaining input
+pub(crate) fn expect(tag: u8, input: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    const MALFORMED: Error = Error::Malformed("DER data");
+    match input {
+        [actual, ..] if *actual == tag => {}
+        _ => return Err(MALFORMED),
+    }
+
+    let (len, header) = match input.get(1) {
+        Some(&len) if len < 0x80 => (len as usize, 2),
+        Some(&len) if (0x81..=0x84).contains(&len) => {
+            let size = (len & 0x7f) as usize;
+            let bytes = input.get(2..2 + size).ok_or(MALFORMED)?;
+            let len = bytes.iter().fold(0, |acc, &b| (acc << 8) | b as usize);
+            (len, 2 + size)
+        }
+        _ => return Err(MALFORMED),
+    };
+
+    let end = header.checked_add(len).ok_or(MALFORMED)?;
+    let value = input.get(header..end).ok_or(MALFORMED)?;
+    Ok((value, &input[end..]))
+}
+
+fn pkcs8(algorithm: &[u8], private_key: &[u8]) -> Vec<u8> {
+    sequence(&[
+        &tlv(INTEGER, &[0]),
+        algorithm,
+        &tlv(OCTET_STRING, private_key),
+    ])
+}
+
+pub(crate) fn sequence(contents: &[&[u8]]) -> Vec<u8> {
+    tlv(SEQUENCE, &contents.concat())
+}
+
+pub(crate) fn oid(encoded: &[u8]) -> Vec<u8> {
+    tlv(OBJECT_IDENTIFIER, encoded)
+}
+
+/// Encode an unsigned big-endian integer
+fn integer(value: &[u8]) -> Vec<u8> {
+    let start = value.iter().position(|&b| b != 0).unwrap_or(value.len());
+    let value = &value[start..];
+    match value.first() {
+        None => tlv(INTEGER, &[0]),
+        Some(&b) if b & 0x80 != 0 => tlv(INTEGER, &[&[0], value].concat()),
+        Some(_) => tlv(INTEGER, value),
+    }
+}
+
+pub(crate) fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(value.len() + 6);
+    out.push(tag);
+    match value.len() {
+        len @ 0..=0x7f => out.push(len as u8),
+        len => {
+            let bytes = len.to_be_bytes();
+            let start = bytes
+                .iter()
+                .position(|&b| b != 0)
+                .unwrap_or(bytes.len() - 1);
+            out.push(0x80 | (bytes.len() - start) as u8);
+            out.extend_from_slice(&bytes[start..]);
+        }
+    }
+    out.extend_from_slice(value);
+    out
+}
+
+/// Encode `der` as a PEM block with the given label
+pub(crate) fn pem_encode(label: &str, der: &[u8]) -> String {
+    let encoded = BASE64_STANDARD.encode(der);
+    let mut out = format!("-----BEGIN {label}-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        // Base64 output is always ASCII
+        out.push_str(std::str::from_utf8(line).unwrap());
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {label}-----\n"));
+    out
+}
+
+/// Decode the first PEM block in `input`, returning its label and contents
+pub(crate) fn pem_decode(input: &[u8]) -> Result<(String, Vec<u8>), Error> {
+    pem_decode_all(input)?
+        .into_iter()
+        .next()
+        .ok_or(Error::Str("no PEM block found"))
+}
+
+/// Decode all PEM blocks in `input`, returning their labels and contents
+pub(crate) fn pem_decode_all(input: &[u8]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let mut rest =
+        std::str::from_utf8(input).map_err(|_| Error::Malformed("PEM data is not UTF-8"))?;
+    let mut blocks = Vec::new();
+    while let Some((_, block)) = rest.split_once("-----BEGIN ") {
+        let (label, block) = block
+            .split_once("-----")
+            .ok_or(Error::Malformed("PEM header"))?;
+        let (body, remainder) = block
+            .split_once(&format!("-----END {label}-----"))
+            .ok_or(Error::Malformed("PEM block without footer"))?;
+
+        let body = body
+            .chars()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect::<String>();
+        blocks.push((label.to_owned(), BASE64_STANDARD.decode(body)?));
+        rest = remainder;
+    }
+
+    Ok(blocks)
+}
+
+/// Checks the block boundaries of PEM data that arrives in chunks
+#[derive(Default)]
+pub(crate) struct PemScanner {
+    /// The incomplete line at the end of the data seen so far
+    line: Vec<u8>,
+    /// The label of the block being read, if any
+    label: Option<String>,
+    blocks: usize,
+    /// The base64 body of the first block
+    first: String,
+}
+
+impl PemScanner {
+    pub(crate) fn feed(&mut self, mut chunk: &[u8]) -> Result<(), Error> {
+        while let Some(end) = chunk.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&chunk[..end]);
+            self.end_line()?;
+            chunk = &chunk[end + 1..];
+        }
+
+        self.line.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    /// Check that the data ended after a complete block, returning the number of blocks
+    pub(crate) fn finish(&mut self) -> Result<usize, Error> {
+        self.end_line()?;
+        match (&self.label, self.blocks) {
+            (Some(_), _) => Err(Error::Malformed("PEM block without footer")),
+            (None, 0) => Err(Error::Str("no PEM block found")),
+            (None, blocks) => Ok(blocks),
+        }
+    }
+
+    fn end_line(&mut self) -> Result<(), Error> {
+        let line = std::mem::take(&mut self.line);
+        let result = self.check_line(&line);
+        self.line = line;
+        self.line.clear();
+        result
+    }
+
+    fn check_line(&mut self, line: &[u8]) -> Result<(), Error> {
+        let line = std::str::from_utf8(line)
+            .map_err(|_| Error::Malformed("PEM data is not UTF-8"))?
+            .trim();
+        let boundary = |prefix: &str| {
+            line.strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix("-----"))
+        };
+
+        if let Some(label) = boundary("-----BEGIN ") {
+            if self.label.is_some() {
+                return Err(Error::Malformed("PEM block without footer"));
+            }
+            self.label = Some(label.to_owned());
+        } else if let Some(label) = boundary("-----END ") {
+            if self.label.as_deref() != Some(label) {
+                return Err(Error::Malformed("PEM footer"));
+            }
+            self.label = None;
+            self.blocks += 1;
+        } else if self.label.is_some()
+            && !line
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='))
+        {
+            return Err(Error::Malformed("PEM body"));
+        } else if self.label.is_some() && self.blocks == 0 {
+            self.first.push_str(line);
+        }
+
+        Ok(())
+    }
+
+    /// The decoded contents of the first block, once it is complete
+    pub(crate) fn first_block(&self) -> Result<Vec<u8>, Error> {
+        match self.blocks {
+            0 => Err(Error::Str("no PEM block found")),
+            _ => Ok(BASE64_STANDARD.decode(&self.first)?),
+        }
+    }
+}
+
+const BOOLEAN: u8 = 0x01;
+pub(crate) const INTEGER: u8 = 0x02;
+pub(crate) const BIT_STRING: u8 = 0x03;
+pub(crate) const OCTET_STRING: u8 = 0x04;
+pub(crate) const NULL: u8 = 0x05;
+pub(crate) const OBJECT_IDENTIFIER: u8 = 0x06;
+const IA5_STRING: u8 = 0x16;
+const BMP_STRING: u8 = 0x1e;
+pub(crate) const UTC_TIME: u8 = 0x17;
+pub(crate) const GENERALIZED_TIME: u8 = 0x18;
+pub(crate) const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+pub(crate) const CONTEXT_0: u8 = 0xa0;
+pub(crate) const CONTEXT_1: u8 = 0xa1;
+pub(crate) const CONTEXT_2: u8 = 0xa2;
+const CONTEXT_3: u8 = 0xa3;
+/// The `dNSName` choice of `GeneralName`
+const DNS_NAME: u8 = 0x82;
+/// The `seed` choice of an ML-DSA private key
+#[cfg(feature = "ml-dsa")]
+const ML_DSA_SEED: u8 = 0x80;
+
+/// 1.2.840.10045.2.1
+pub(crate) const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// 1.2.840.10045.3.1.7
+pub(crate) const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+/// 1.3.132.0.34
+pub(crate) const OID_P384: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+/// 1.2.840.113549.1.1.1
+pub(crate) const OID_RSA_ENCRYPTION: &[u8] =
+    &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+/// 2.16.840.1.101.3.4.3.17
+#[cfg(feature = "ml-dsa")]
+const OID_ML_DSA_44: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x03, 0x11];
+/// 1.3.101.112
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+/// 1.2.840.10045.4.3.2
+pub(crate) const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+/// 1.2.840.10045.4.3.3
+const OID_ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+/// 1.2.840.113549.1.9.14
+const OID_EXTENSION_REQUEST: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x0e];
+/// 1.3.6.1.5.5.7.1.24
+const OID_TLS_FEATURE: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18];
+/// The `status_request` TLS extension, listed in a TLS feature extension for must-staple
+const TLS_STATUS_REQUEST: u8 = 5;
+/// 1.3.6.1.4.1.11129.2.4.2
+const OID_SCT_LIST: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02];
+/// 2.5.29.17
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+/// 1.2.840.113549.1.7.1
+const OID_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+/// 1.2.840.113549.1.9.20
+const OID_FRIENDLY_NAME: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x14];
+/// 1.2.840.113549.1.9.21
+const OID_LOCAL_KEY_ID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x15];
+/// 1.2.840.113549.1.9.22.1
+const OID_X509_CERTIFICATE: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x16, 0x01];
+/// 1.2.840.113549.1.12.10.1.2
+const OID_PKCS8_SHROUDED_KEY_BAG: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x02,
+];
+/// 1.2.840.113549.1.5.13
+const OID_PBES2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0d];
+/// 1.2.840.113549.1.5.12
+const OID_PBKDF2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0c];
+/// 1.2.840.113549.2.9
+const OID_HMAC_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x09];
+/// 2.16.840.1.101.3.4.1.42
+const OID_AES256_CBC: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2a];
+/// 1.2.840.113549.1.12.10.1.3
+const OID_CERT_BAG: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x03,
+];
+/// 2.16.840.1.101.3.4.2.1
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+/// 1.3.6.1.4.1.42.2.17.1.1, Sun's JKS key protection algorithm
+const OID_JKS_KEY_PROTECTOR: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x2a, 0x02, 0x11, 0x01, 0x01];
+
+/// The diversifier for deriving MAC keys in the PKCS #12 key derivation
+const PKCS12_MAC_ID: u8 = 3;
+const PKCS12_ITERATIONS: u32 = 2048;
+const JKS_MAGIC: u32 = 0xfeed_feed;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "ml-dsa")]
+    #[test]
+    fn ml_dsa_private_keys() {
+        let seed = [7; 32];
+        let algorithm = sequence(&[&oid(OID_ML_DSA_44)]);
+        let both = sequence(&[&tlv(OCTET_STRING, &seed), &tlv(OCTET_STRING, &[0; 2560])]);
+        for pkcs8 in [pkcs8_from_ml_dsa44_seed(&seed), pkcs8(&algorithm, &both)] {
+            match PrivateKey::from_pkcs8(&pkcs8) {
+                Ok(PrivateKey::MlDsa44(parsed)) => assert_eq!(parsed, seed),
+                _ => panic!("ML-DSA key not parsed"),
+            }
+        }
+
+        let expanded = pkcs8(&algorithm, &tlv(OCTET_STRING, &[0; 2560]));
+        assert!(matches!(
+            PrivateKey::from_pkcs8(&expanded),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    /// A subject alternative name extension with `names` as DNS names and an IP address
+    fn san(names: &[&str]) -> Vec<u8> {
+        let mut general_names = tlv(0x87, &[192, 0, 2, 1]);
+        for name in names {
+            general_names.extend(tlv(DNS_NAME, name.as_bytes()));
+        }
+        extension(OID_SUBJECT_ALT_NAME, &tlv(SEQUENCE, &general_names))
+    }
+
+    fn extension(id: &[u8], value: &[u8]) -> Vec<u8> {
+        sequence(&[&oid(id), &tlv(OCTET_STRING, value)])
+    }
+
+    /// A CSR requesting `extensions`, or without an extension request if there are none
+    fn request(extensions: &[&[u8]]) -> Vec<u8> {
+        let attributes = match extensions.is_empty() {
+            true => tlv(CONTEXT_0, &[]),
+            false => tlv(
+                CONTEXT_0,
+                &sequence(&[
+                    &oid(OID_EXTENSION_REQUEST),
+                    &tlv(SET, &sequence(extensions)),
+                ]),
+            ),
+        };
+        let info = sequence(&[
+            &tlv(INTEGER, &[0]),
+            &sequence(&[&tlv(
+                SET,
+                &sequence(&[&oid(&[0x55, 0x04, 0x03]), &tlv(0x0c, b"example.com")]),
+            )]),
+            &sequence(&[
+                &sequence(&[&oid(OID_EC_PUBLIC_KEY), &oid(OID_P256)]),
+                &tlv(BIT_STRING, &[0, 4]),
+            ]),
+            &attributes,
+        ]);
+        sequence(&[
+            &info,
+            &sequence(&[&oid(OID_ECDSA_WITH_SHA256)]),
+            &tlv(BIT_STRING, &[0]),
+        ])
+    }
+
+    /// A certificate with `serial` and `extensions`, or without extensions if there are none
+    fn certificate(serial: &[u8], extensions: &[&[u8]]) -> Vec<u8> {
+        let name = sequence(&[]);
+        let mut tbs = [
+            tlv(CONTEXT_0, &tlv(INTEGER, &[2])),
+            tlv(INTEGER, serial),
+            sequence(&[&oid(OID_ECDSA_WITH_SHA256)]),
+            name.clone(),
+            sequence(&[
+                &tlv(UTC_TIME, b"260101000000Z"),
+                &tlv(UTC_TIME, b"260401000000Z"),
+            ]),
+            name,
+            sequence(&[
+                &sequence(&[&oid(OID_EC_PUBLIC_KEY), &oid(OID_P256)]),
+                &tlv(BIT_STRING, &[0, 4]),
+            ]),
+        ]
+        .concat();
+        if !extensions.is_empty() {
+            tbs.extend(tlv(CONTEXT_3, &sequence(extensions)));
+        }
+        sequence(&[
+            &tlv(SEQUENCE, &tbs),
+            &sequence(&[&oid(OID_ECDSA_WITH_SHA256)]),
+            &tlv(BIT_STRING, &[0]),
+        ])
+    }
+
+    #[test]
+    fn serial_numbers() {
+        for (serial, expected) in [
+            (&[0x12, 0xab][..], "12ab"),
+            // A leading zero byte keeps the integer positive
+            (&[0x00, 0x8f, 0x01], "8f01"),
+            (&[0x00], "00"),
+            (&[0x01; 20], "0101010101010101010101010101010101010101"),
+        ] {
+            let certificate = certificate(serial, &[]);
+            assert_eq!(serial_number(&certificate).unwrap(), expected);
+        }
+
+        // Version 1 certificates have no explicit version
+        let certificate = sequence(&[&sequence(&[&tlv(INTEGER, &[7])])]);
+        assert_eq!(serial_number(&certificate).unwrap(), "07");
+    }
+
+    #[test]
+    fn malformed_serial_numbers() {
+        let certificate = certificate(&[0x12, 0xab], &[]);
+        for len in 0..certificate.len() {
+            assert!(serial_number(&certificate[..len]).is_err(), "{}", len);
+        }
+
+        let not_integer = sequence(&[&sequence(&[&tlv(OCTET_STRING, &[7])])]);
+        assert!(serial_number(&not_integer).is_err());
+        let overrun = sequence(&[&sequence(&[&[INTEGER, 0x84, 0xff, 0xff, 0xff, 0xff, 1]])]);
+        assert!(serial_number(&overrun).is_err());
+    }
+
+    /// Prefix `data` with its length as a TLS vector
+    fn vector(data: &[u8]) -> Vec<u8> {
+        [&(data.len() as u16).to_be_bytes()[..], data].concat()
+    }
+
+    /// A serialized SCT from the log with `id`, with a 2-byte extension
+    fn sct(id: u8, timestamp: u64) -> Vec<u8> {
+        [
+            &[0][..],
+            &[id; 32],
+            &timestamp.to_be_bytes(),
+            &vector(&[0xee, 0xff]),
+            &[4, 3],
+            &vector(&[0x30, 0x00]),
+        ]
+        .concat()
+    }
+
+    /// A certificate with an SCT list extension holding `list`, which is TLS-encoded
+    fn certificate_with_scts(list: &[u8]) -> Vec<u8> {
+        let extension = extension(OID_SCT_LIST, &tlv(OCTET_STRING, list));
+        certificate(&[1], &[&extension])
+    }
+
+    #[test]
+    fn embedded_scts() {
+        let list = vector(&[vector(&sct(1, 1_767_225_600_000)), vector(&sct(2, 0))].concat());
+        let parsed = scts(&certificate_with_scts(&list)).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(
+            parsed[0],
+            SignedCertificateTimestamp {
+                version: 0,
+                log_id: [1; 32],
+                timestamp: Date::from_calendar_date(2026, Month::January, 1)
+                    .unwrap()
+                    .midnight()
+                    .assume_utc(),
+                extensions: vec![0xee, 0xff],
+                hash_algorithm: 4,
+                signature_algorithm: 3,
+                signature: vec![0x30, 0x00],
+            }
+        );
+        assert_eq!(parsed[1].log_id, [2; 32]);
+        assert_eq!(parsed[1].timestamp, OffsetDateTime::UNIX_EPOCH);
+
+        // Certificates without extensions, or without the SCT list extension
+        assert!(scts(&certificate(&[1], &[])).unwrap().is_empty());
+        let other = extension(OID_TLS_FEATURE, &sequence(&[&tlv(INTEGER, &[5])]));
+        assert!(scts(&certificate(&[1], &[&other])).unwrap().is_empty());
+        assert!(scts(&certificate_with_scts(&vector(&[])))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn malformed_scts() {
+        let sct = sct(1, 0);
+        for list in [
+            // Vector lengths beyond the data, and data after the list
+            [&[0xff, 0xff][..], &vector(&sct)].concat(),
+            vector(&[&[0xff, 0xff][..], &sct].concat()),
+            [vector(&vector(&sct)), vec![0]].concat(),
+            vec![0],
+            // SCTs cut off in the fixed fields, the extensions or the signature
+            vector(&vector(&sct[..20])),
+            vector(&vector(&sct[..42])),
+            vector(&vector(&sct[..sct.len() - 1])),
+            vector(&vector(&sct[..sct.len() - 3])),
+        ] {
+            assert!(scts(&certificate_with_scts(&list)).is_err(), "{:?}", list);
+        }
+
+        let certificate = certificate_with_scts(&vector(&vector(&sct)));
+        for len in 0..certificate.len() {
+            assert!(scts(&certificate[..len]).is_err(), "{}", len);
+        }
+    }
+
+    #[test]
+    fn csr_names() {
+        let csr = request(&[&san(&["example.com", "www.example.com"])]);
+        assert_eq!(
+            csr_dns_names(&csr).unwrap(),
+            ["example.com", "www.example.com"]
+        );
+
+        // The name in the subject is not a subject alternative name
+        assert!(csr_dns_names(&request(&[])).unwrap().is_empty());
+        let tls_feature = extension(OID_TLS_FEATURE, &sequence(&[&tlv(INTEGER, &[5])]));
+        assert!(csr_dns_names(&request(&[&tls_feature])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_csrs() {
+        let csr = request(&[&san(&["example.com"])]);
+        for len in 0..csr.len() {
+            assert!(csr_dns_names(&csr[..len]).is_err(), "{}", len);
+        }
+
+        // A general name that claims more bytes than its sequence holds
+        let overrun = extension(OID_SUBJECT_ALT_NAME, &[SEQUENCE, 3, DNS_NAME, 5, b'a']);
+        assert!(csr_dns_names(&request(&[&overrun])).is_err());
+        let invalid = extension(OID_SUBJECT_ALT_NAME, &sequence(&[&tlv(DNS_NAME, &[0xff])]));
+        assert!(csr_dns_names(&request(&[&invalid])).is_err());
+        let not_sequence = extension(OID_SUBJECT_ALT_NAME, &tlv(SET, &[]));
+        assert!(csr_dns_names(&request(&[&not_sequence])).is_err());
+    }
+
+    #[test]
+    fn length_forms() {
+        assert_eq!(
+            expect(SEQUENCE, &[SEQUENCE, 0x81, 1, 7, 8]).unwrap(),
+            (&[7][..], &[8][..])
+        );
+        let long = tlv(OCTET_STRING, &[1; 300]);
+        assert_eq!(long[..4], [OCTET_STRING, 0x82, 0x01, 0x2c]);
+        assert_eq!(expect(OCTET_STRING, &long).unwrap().0.len(), 300);
+
+        for input in [
+            // Lengths beyond the input, up to the largest four-byte length
+            &[SEQUENCE, 0x84, 0xff, 0xff, 0xff, 0xff, 0][..],
+            &[SEQUENCE, 0x82, 0x01, 0x00, 0],
+            &[SEQUENCE, 0x02, 0],
+            // Lengths of more than four bytes, indefinite lengths and truncated lengths
+            &[SEQUENCE, 0x85, 0, 0, 0, 0, 1, 0],
+            &[SEQUENCE, 0x88, 0, 0, 0, 0, 0, 0, 0, 1, 0],
+            &[SEQUENCE, 0x80, 0, 0],
+            &[SEQUENCE, 0x82, 0x01],
+            &[SEQUENCE],
+            &[INTEGER, 0],
+            &[],
+        ] {
+            assert!(expect(SEQUENCE, input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn time_forms() {
+        let expected = Date::from_calendar_date(2026, Month::January, 2)
+            .unwrap()
+            .with_hms(3, 4, 5)
+            .unwrap()
+            .assume_utc();
+        for value in [&b"260102030405Z"[..], b"20260102030405Z"] {
+            let tag = match value.len() {
+                13 => UTC_TIME,
+                _ => GENERALIZED_TIME,
+            };
+            let input = [tlv(tag, value), vec![0xff]].concat();
+            assert_eq!(time(&input).unwrap(), (expected, &[0xff][..]));
+        }
+
+        let (fractional, _) = time(&tlv(GENERALIZED_TIME, b"20260102030405.987Z")).unwrap();
+        assert_eq!(fractional, expected);
+        let (y1999, _) = time(&tlv(UTC_TIME, b"991231235959Z")).unwrap();
+        assert_eq!(y1999.year(), 1999);
+    }
+
+    #[test]
+    fn invalid_times() {
+        for (tag, value) in [
+            (GENERALIZED_TIME, &b"20260102030405.Z"[..]),
+            (GENERALIZED_TIME, b"20260102030405.9a9Z"),
+            (GENERALIZED_TIME, b"202601020304.5Z"),
+            (GENERALIZED_TIME, b"20260102030405"),
+            (GENERALIZED_TIME, b"20261302030405Z"),
+            (UTC_TIME, b"260102030405.9Z"),
+            (UTC_TIME, b"2601020304Z"),
+            (INTEGER, b"260102030405Z"),
+        ] {
+            assert!(time(&tlv(tag, value)).is_err(), "{:?}", value);
+        }
+        assert!(time(&[]).is_err());
+        // Truncated within the value
+        assert!(time(&tlv(GENERALIZED_TIME, b"20260102030405Z")[..10]).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! Import accounts created by other ACME clients
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+
+use crate::{der, Error, Key};
+
+/// An existing ACME account created by another ACME client
+///
+/// Importing an account keeps its registration and rate limit standing with the CA. Pass
+/// the imported account to [`Account::from_imported()`](crate::Account::from_imported())
+/// together with the directory URL of the CA it was registered with, then call
+/// [`Account::credentials()`](crate::Account::credentials()) to store it in this crate's
+/// own format.
+///
+/// Both ECDSA P-256 and RSA account keys are supported.
+#[derive(Debug)]
+pub struct ImportedAccount {
+    pub(crate) id: String,
+    pub(crate) key: Key,
+}
+
+impl ImportedAccount {
+    /// Import an account from certbot's `regr.json` and `private_key.json` files
+    ///
+    /// These can be found in `/etc/letsencrypt/accounts/<server>/directory/<hash>/`.
+    pub fn from_certbot(regr_json: &[u8], private_key_json: &[u8]) -> Result<Self, Error> {
+        let regr = serde_json::from_slice::<Registration>(regr_json)?;
+        let jwk = serde_json::from_slice::<PrivateJwk>(private_key_json)?;
+        Ok(Self {
+            id: regr.uri,
+            key: Key::from_pkcs8_der(jwk.to_pkcs8()?)?,
+        })
+    }
+
+    /// Import an account from lego's `account.json` file and its account key
+    ///
+    /// These can be found in `.lego/accounts/<server>/<email>/`, with the PEM-encoded
+    /// key in the `keys/<email>.key` file.
+    pub fn from_lego(account_json: &[u8], key_pem: &[u8]) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct LegoAccount {
+            registration: Registration,
+        }
+
+        let account = serde_json::from_slice::<LegoAccount>(account_json)?;
+        Ok(Self {
+            id: account.registration.uri,
+            key: key_from_pem(key_pem)?,
+        })
+    }
+
+    /// Import an account from acme.sh's `account.key` and `ca.conf` files
+    ///
+    /// These can be found in `~/.acme.sh/ca/<server>/directory/`.
+    pub fn from_acme_sh(account_key_pem: &[u8], ca_conf: &[u8]) -> Result<Self, Error> {
+        let ca_conf =
+            std::str::from_utf8(ca_conf).map_err(|_| Error::Malformed("ca.conf is not UTF-8"))?;
+        let id = ca_conf
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("ACCOUNT_URL="))
+            .map(|value| value.trim_matches(|c| c == '\'' || c == '"'))
+            .filter(|value| !value.is_empty())
+            .ok_or(Error::Str("no ACCOUNT_URL found in ca.conf"))?;
+
+        Ok(Self {
+            id: id.to_owned(),
+            key: key_from_pem(account_key_pem)?,
+        })
+    }
+
+    /// Import an account from its account URL and a PEM-encoded private key
+    ///
+    /// PKCS#8 (`PRIVATE KEY`), SEC1 (`EC PRIVATE KEY`) and PKCS#1 (`RSA PRIVATE KEY`)
+    /// encodings are supported, matching the output of common openssl commands.
+    pub fn from_pem(id: String, key_pem: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            id,
+            key: key_from_pem(key_pem)?,
+        })
+    }
+
+    /// Import an account from its account URL and a private key in JWK form
+    pub fn from_jwk(id: String, key_jwk: &[u8]) -> Result<Self, Error> {
+        let jwk = serde_json::from_slice::<PrivateJwk>(key_jwk)?;
+        Ok(Self {
+            id,
+            key: Key::from_pkcs8_der(jwk.to_pkcs8()?)?,
+        })
+    }
+
+    /// The account URL, which identifies the account with the CA
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+fn key_from_pem(pem: &[u8]) -> Result<Key, Error> {
+    let (label, der) = der::pem_decode(pem)?;
+    Key::from_pkcs8_der(match label.as_str() {
+        "PRIVATE KEY" => der,
+        "EC PRIVATE KEY" => der::pkcs8_from_sec1(&der),
+        "RSA PRIVATE KEY" => der::pkcs8_from_pkcs1(&der),
+        _ => return Err(Error::Unsupported("PEM private key type")),
+    })
+}
+
+/// The registration resource as stored by certbot and lego
+#[derive(Deserialize)]
+struct Registration {
+    uri: String,
+}
+
+/// A private key in JWK form (RFC 7518 section 6)
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct PrivateJwk {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    d: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dq: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+    kty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p: Option<String>,
+    /// The seed of an AKP key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r#priv: Option<String>,
+    /// The public key of an AKP key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r#pub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    q: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qi: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<String>,
+}
+
+impl PrivateJwk {
+    pub(crate) fn from_pkcs8(pkcs8: &[u8]) -> Result<Self, Error> {
+        let encode = |value: &[u8]| Some(BASE64_URL_SAFE_NO_PAD.encode(value));
+        Ok(match der::PrivateKey::from_pkcs8(pkcs8)? {
+            der::PrivateKey::Ec { private, public } => {
+                let (x, y) = public
+                    .get(1..)
+                    .filter(|point| point.len() == 64)
+                    .ok_or(Error::Unsupported("EC public key encoding"))?
+                    .split_at(32);
+                Self {
+                    kty: "EC".to_owned(),
+                    crv: Some("P-256".to_owned()),
+                    d: encode(&private),
+                    x: encode(x),
+                    y: encode(y),
+                    ..Self::default()
+                }
+            }
+            der::PrivateKey::Rsa(components) => match components.as_slice() {
+                [n, e, d, p, q, dp, dq, qi] => Self {
+                    kty: "RSA".to_owned(),
+                    n: encode(n),
+                    e: encode(e),
+                    d: encode(d),
+                    p: encode(p),
+                    q: encode(q),
+                    dp: encode(dp),
+                    dq: encode(dq),
+                    qi: encode(qi),
+                    ..Self::default()
+                },
+                _ => return Err(Error::Malformed("RSA private key")),
+            },
+            // The AKP key type of draft-ietf-cose-dilithium
+            #[cfg(feature = "ml-dsa")]
+            der::PrivateKey::MlDsa44(mut seed) => {
+                let key = crate::mldsa::KeyPair::from_seed(&seed);
+                let jwk = Self {
+                    kty: "AKP".to_owned(),
+                    alg: Some("ML-DSA-44".to_owned()),
+                    r#priv: encode(&seed),
+                    r#pub: encode(key.public_key()),
+                    ..Self::default()
+                };
+                crate::zeroize(&mut seed);
+                jwk
+            }
+        })
+    }
+
+    fn to_pkcs8(&self) -> Result<Vec<u8>, Error> {
+        let decode = |value: &Option<String>| match value {
+            Some(value) => Ok(BASE64_URL_SAFE_NO_PAD.decode(value)?),
+            None => Err(Error::Str("missing JWK member")),
+        };
+
+        match self.kty.as_str() {
+            "EC" => {
+                if self.crv.as_deref() != Some("P-256") {
+                    return Err(Error::Unsupported("JWK curve"));
+                }
+
+                let mut public = vec![0x04];
+                public.extend(decode(&self.x)?);
+                public.extend(decode(&self.y)?);
+                Ok(der::pkcs8_from_sec1(&der::sec1(&decode(&self.d)?, &public)))
+            }
+            "RSA" => {
+                let members = [
+                    &self.n, &self.e, &self.d, &self.p, &self.q, &self.dp, &self.dq, &self.qi,
+                ];
+                let components = members
+                    .iter()
+                    .map(|member| decode(member))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(der::pkcs8_from_pkcs1(&der::pkcs1(&components)))
+            }
+            #[cfg(feature = "ml-dsa")]
+            "AKP" => {
+                if self.alg.as_deref() != Some("ML-DSA-44") {
+                    return Err(Error::Unsupported("JWK algorithm"));
+                }
+
+                let (mut value, mut seed) = (decode(&self.r#priv)?, [0; 32]);
+                let valid = value.len() == seed.len();
+                if valid {
+                    seed.copy_from_slice(&value);
+                }
+                crate::zeroize(&mut value);
+                if !valid {
+                    return Err(Error::Malformed("ML-DSA private key seed"));
+                }
+                let key = crate::mldsa::KeyPair::from_seed(&seed);
+                let pkcs8 = der::pkcs8_from_ml_dsa44_seed(&seed);
+                crate::zeroize(&mut seed);
+                match decode(&self.r#pub)? == key.public_key() {
+                    true => Ok(pkcs8),
+                    false => Err(Error::Malformed("JWK public key does not match the seed")),
+                }
+            }
+            _ => Err(Error::Unsupported("JWK key type")),
+        }
+    }
+}
@@ -1,36 +1,127 @@
 //! Async pure-Rust ACME (RFC 8555) client.
+//!
+//! # Cancellation
+//!
+//! All futures returned by this crate can be cancelled by dropping them, for example by
+//! racing them against a shutdown signal with `tokio::select!`. Dropping a future at any
+//! `.await` point leaves the [`Account`] and [`Order`] in a consistent state: the cached
+//! [`OrderState`] is only replaced once a complete response has been received, and a
+//! replay nonce that may have been used by an aborted request is discarded so the next
+//! request fetches a fresh one. The order itself stays alive on the server and can be
+//! picked up again later, for example through [`Order::to_resumable()`]. Any challenge
+//! responses that were provisioned outside of this crate (like DNS records) still need to
+//! be cleaned up by the caller.
 
 #![warn(unreachable_pub)]
 #![warn(missing_docs)]
 
-use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::future::Future;
+use std::mem;
+#[cfg(feature = "hyper-rustls")]
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
-use std::sync::Arc;
+#[cfg(feature = "hyper-rustls")]
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{compiler_fence, AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use hyper::body::{Bytes, HttpBody};
+#[cfg(feature = "hyper-rustls")]
+use hyper::client::connect::dns::{GaiResolver, Name};
 use hyper::client::connect::Connect;
 #[cfg(feature = "hyper-rustls")]
 use hyper::client::HttpConnector;
-use hyper::header::{CONTENT_TYPE, LOCATION};
-use hyper::{Body, Method, Request, Response};
+use hyper::header::{HeaderValue, CONTENT_TYPE, LOCATION, RETRY_AFTER, USER_AGENT};
+#[cfg(feature = "hyper-rustls")]
+use hyper::service::Service;
+#[cfg(feature = "hyper-rustls")]
+use hyper::Uri;
+use hyper::{Body, Method, Request, Response, StatusCode};
 use ring::digest::{digest, SHA256};
-use ring::hmac;
 use ring::rand::SystemRandom;
-use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use ring::signature::KeyPair as _;
+use ring::signature::{
+    EcdsaKeyPair, RsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING, RSA_PKCS1_SHA256,
+};
+use ring::{constant_time, hmac};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
-
-mod types;
-pub use types::{
-    AccountCredentials, Authorization, AuthorizationStatus, Challenge, ChallengeType, Error,
-    Identifier, LetsEncrypt, NewAccount, NewOrder, OrderState, OrderStatus, Problem,
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+mod aes;
+pub mod audit;
+use audit::{AuditEntry, AuditEvent};
+mod bulk;
+pub use bulk::{BulkIssuer, BulkRun, IssuancePriority, IssuanceResult, IssuanceSpec};
+mod cache;
+pub use cache::{AuthorizationCache, CachedAuthorization};
+mod clock;
+use clock::Deadline;
+pub use clock::{Clock, SleepFuture, SystemClock};
+pub mod coordinator;
+mod der;
+mod import;
+pub use import::ImportedAccount;
+#[cfg(feature = "ml-dsa")]
+mod mldsa;
+mod offline;
+pub use offline::{OfflineAccount, PendingRequest};
+mod onion;
+mod order_set;
+pub use order_set::{OrderSet, OrderSetOutcome};
+pub mod policy;
+mod renewal;
+pub use renewal::{
+    CaFailover, Hook, HookFuture, RenewalEvent, RenewalHandle, RenewalOutcome, RenewalReport,
+    RenewalService, RenewalSpec,
 };
+#[cfg(feature = "revocation")]
+mod revocation;
+#[cfg(feature = "revocation")]
+pub use revocation::RevocationStatus;
+mod rng;
+pub use rng::{Rng, SystemRng};
+mod scrypt;
+mod tkauth;
+use tkauth::AuthorityTokenResponse;
+pub use tkauth::{AuthorityTokenClaims, AuthorityTokenRequest, TnEntry};
+mod types;
+use der::PemScanner;
 use types::{
-    DirectoryUrls, Empty, FinalizeRequest, Header, JoseJson, Jwk, KeyOrKeyId, NewAccountPayload,
-    Signer, SigningAlgorithm,
+    parse_response, Empty, FinalizeRequest, Header, JoseJson, Jwk, JwsWriter, KeyOrKeyId,
+    NewAccountPayload, NewAuthorizationRequest, RateLimit, RevocationRequest, Signer,
+};
+pub use types::{
+    AccountConfig, AccountCredentials, Authorization, AuthorizationBatch, AuthorizationOutcome,
+    AuthorizationProgress, AuthorizationStatus, BehaviorProfile, CertStatus, CertificateChain,
+    CertificatePolicy, Challenge, ChallengeSelection, ChallengeStatus, ChallengeType,
+    CsrRequirements, Directory, DirectoryMeta, Environment, Error, HedgePolicy, Identifier,
+    KeyType, KnownCa, LetsEncrypt, NewAccount, NewAccountBuilder, NewOrder, NewOrderBuilder,
+    Observation, Observed, OrderFailure, OrderState, OrderStatus, PayloadFormat, PolicyViolation,
+    Problem, PropagationFailure, RateLimits, ReadyChallenge, ResponseMeta, ResumableOrder,
+    RetryPolicy, RevocationOutcome, RevocationReason, RevocationResult, SctPolicy,
+    SealedCredentials, SignedCertificateTimestamp, SigningAlgorithm, StateChange, Subproblem,
+    Timeouts, ValidationRecord,
 };
+#[cfg(feature = "hyper-rustls")]
+pub use types::{AddressFamily, ConnectSettings, Http2Settings};
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "preflight")]
+pub mod preflight;
+pub mod solver;
+pub mod store;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+#[cfg(feature = "zerossl")]
+mod zerossl;
+#[cfg(feature = "zerossl")]
+pub use zerossl::ZeroSslAuth;
 
 /// An ACME order as described in RFC 8555 (section 7.1.3)
 ///
@@ -38,12 +129,18 @@ use types::{
 /// type represents the stable identity of an order, while the [`Order::state()`] method
 /// gives you access to the current state of the order according to the server.
 ///
+/// Methods on `Order` are cancel-safe: see the [crate-level documentation](crate#cancellation).
+///
 /// <https://datatracker.ietf.org/doc/html/rfc8555#section-7.1.3>
 pub struct Order {
     account: Arc<AccountInner>,
     nonce: Option<String>,
     url: String,
     state: OrderState,
+    challenges: Vec<ReadyChallenge>,
+    finalize_extensions: serde_json::Map<String, serde_json::Value>,
+    /// The authorizations recorded in the audit log so far
+    audited: HashSet<String>,
 }
 
 impl Order {
@@ -62,20 +159,308 @@ impl Order {
     /// After the challenges have been set up, check the [`Order::state()`] to see
     /// if the order is ready to be finalized (or becomes invalid). Once it is
     /// ready, call `Order::finalize()` to get the certificate.
+    ///
+    /// Authorizations are retrieved [`AccountConfig::authorization_concurrency()`] at a time.
     pub async fn authorizations(&mut self) -> Result<Vec<Authorization>, Error> {
-        let mut authorizations = Vec::with_capacity(self.state.authorizations.len());
-        for url in &self.state.authorizations {
-            authorizations.push(self.account.get(&mut self.nonce, url).await?);
+        let urls = self.state.authorizations.clone();
+        self.fetch_chunked(&urls, true, |_| {}).await.into_result()
+    }
+
+    /// Retrieve the authorizations for this order, reporting progress and partial failures
+    ///
+    /// Meant for orders with many identifiers: authorizations are retrieved
+    /// [`AccountConfig::authorization_concurrency()`] at a time, and `progress` is called
+    /// after each chunk. Unlike [`Order::authorizations()`], an authorization that cannot be
+    /// retrieved does not stop the others; it is listed in [`AuthorizationBatch::failed`].
+    pub async fn fetch_authorizations(
+        &mut self,
+        progress: impl FnMut(AuthorizationProgress),
+    ) -> AuthorizationBatch {
+        let urls = self.state.authorizations.clone();
+        self.fetch_chunked(&urls, false, progress).await
+    }
+
+    /// Retrieve the authorizations at `urls` in chunks of concurrent requests
+    ///
+    /// Authorizations in the [`AuthorizationCache`] are not retrieved. Stops after the first
+    /// chunk with a failure if `fail_fast` is set.
+    async fn fetch_chunked(
+        &mut self,
+        urls: &[String],
+        fail_fast: bool,
+        mut progress: impl FnMut(AuthorizationProgress),
+    ) -> AuthorizationBatch {
+        let mut batch = AuthorizationBatch {
+            authorizations: Vec::with_capacity(urls.len()),
+            failed: Vec::new(),
+        };
+
+        let cache = self.account.client.config.authorization_cache.clone();
+        let mut uncached = Vec::with_capacity(urls.len());
+        for url in urls {
+            let authorization = match cache.as_ref().and_then(|cache| cache.authorization(url)) {
+                Some(authorization) => authorization,
+                None => {
+                    uncached.push(url.clone());
+                    continue;
+                }
+            };
+            match self.audit_authorization(&authorization) {
+                Ok(()) => batch.authorizations.push(authorization),
+                Err(error) => batch.failed.push((url.clone(), error)),
+            }
+        }
+        if uncached.len() < urls.len() {
+            progress(AuthorizationProgress {
+                fetched: batch.authorizations.len(),
+                failed: batch.failed.len(),
+                total: urls.len(),
+            });
+        }
+
+        let concurrency = self.account.client.config.authorization_concurrency.max(1);
+        for chunk in uncached.chunks(concurrency) {
+            if fail_fast && !batch.failed.is_empty() {
+                break;
+            }
+
+            let results = match chunk {
+                [url] => vec![self.authorization(url).await],
+                _ => {
+                    let account = &self.account;
+                    let futures = chunk
+                        .iter()
+                        .map(|url| {
+                            Box::pin(async move {
+                                let mut nonce = None;
+                                let result = account.get::<Authorization>(&mut nonce, url).await;
+                                account.client.recycle(nonce);
+                                result.map(|mut authorization| {
+                                    authorization.url = url.clone();
+                                    authorization
+                                })
+                            }) as Pin<Box<dyn Future<Output = _>>>
+                        })
+                        .collect();
+                    let results: Vec<Result<Authorization, Error>> = JoinAll::new(futures).await;
+                    self.update_cache(results.iter().filter_map(|result| result.as_ref().ok()))
+                        .await;
+                    results
+                }
+            };
+
+            for (url, result) in chunk.iter().zip(results) {
+                let result = match (chunk.len(), result) {
+                    (1, result) => result,
+                    (_, Ok(authorization)) => self
+                        .audit_authorization(&authorization)
+                        .map(|()| authorization),
+                    (_, Err(error)) => Err(error),
+                };
+                match result {
+                    Ok(authorization) => batch.authorizations.push(authorization),
+                    Err(error) => batch.failed.push((url.clone(), error)),
+                }
+            }
+            progress(AuthorizationProgress {
+                fetched: batch.authorizations.len(),
+                failed: batch.failed.len(),
+                total: urls.len(),
+            });
+        }
+
+        if cache.is_some() {
+            let position = urls
+                .iter()
+                .enumerate()
+                .map(|(idx, url)| (url.as_str(), idx))
+                .collect::<HashMap<_, _>>();
+            batch
+                .authorizations
+                .sort_by_key(|authz| position.get(authz.url.as_str()).copied());
+        }
+        batch
+    }
+
+    /// Retrieve a single authorization by its URL
+    ///
+    /// Use this with [`Authorization::url`] to refresh an authorization returned by
+    /// [`Order::authorizations()`].
+    pub async fn authorization(&mut self, url: &str) -> Result<Authorization, Error> {
+        let mut authorization = self
+            .account
+            .get::<Authorization>(&mut self.nonce, url)
+            .await?;
+        authorization.url = url.to_owned();
+        self.audit_authorization(&authorization)?;
+        self.update_cache([&authorization]).await;
+        Ok(authorization)
+    }
+
+    /// Bring the [`AuthorizationCache`] in line with `authorizations` retrieved from the server
+    async fn update_cache(&self, authorizations: impl IntoIterator<Item = &Authorization>) {
+        if let Some(cache) = &self.account.client.config.authorization_cache {
+            if cache.update(authorizations) {
+                cache.save().await;
+            }
+        }
+    }
+
+    /// Record `authorization` in the audit log the first time it is no longer pending
+    fn audit_authorization(&mut self, authorization: &Authorization) -> Result<(), Error> {
+        if authorization.status == AuthorizationStatus::Pending
+            || self.account.client.config.audit_log.is_none()
+            || self.audited.contains(&authorization.url)
+        {
+            return Ok(());
+        }
+
+        let challenge = authorization.challenges.iter().find(|challenge| {
+            matches!(
+                challenge.status,
+                ChallengeStatus::Valid | ChallengeStatus::Invalid
+            )
+        });
+        self.account.audit(AuditEvent::AuthorizationCompleted {
+            order: &self.url,
+            authorization: &authorization.url,
+            identifier: &authorization.identifier,
+            status: authorization.status,
+            challenge: challenge.map(|challenge| challenge.r#type),
+            error: challenge.and_then(|challenge| challenge.error.as_ref()),
+        })?;
+        self.audited.insert(authorization.url.clone());
+        Ok(())
+    }
+
+    /// Replace `authorization` with its current state on the server
+    ///
+    /// Polling loops can use this to wait for the authorizations that are still pending,
+    /// instead of retrieving all authorizations of the order with
+    /// [`Order::authorizations()`] every time. Returns the changes reported by
+    /// [`Authorization::diff()`].
+    pub async fn refresh_authorization(
+        &mut self,
+        authorization: &mut Authorization,
+    ) -> Result<Vec<StateChange>, Error> {
+        if authorization.url.is_empty() {
+            return Err(Error::Str("authorization URL is unknown"));
+        }
+
+        let refreshed = self.authorization(&authorization.url.clone()).await?;
+        let changes = Authorization::diff(authorization, &refreshed);
+        *authorization = refreshed;
+        Ok(changes)
+    }
+
+    /// Poll the authorizations of this order until all are valid or one is not
+    ///
+    /// Only pending authorizations are polled, following `policy`. Returns the outcome for
+    /// each identifier of the order once all authorizations are valid, or as soon as one of
+    /// them becomes invalid, expires or is revoked; the authorizations that were still
+    /// pending at that point are reported as [`AuthorizationOutcome::Pending`]. Fails with
+    /// [`Error::Timeout`] if authorizations are still pending after the last attempt.
+    ///
+    /// Authorizations are polled [`AccountConfig::authorization_concurrency()`] at a time,
+    /// and only the outcome of those that are no longer pending is kept.
+    pub async fn wait_authorizations(
+        &mut self,
+        policy: RetryPolicy,
+    ) -> Result<HashMap<Identifier, AuthorizationOutcome>, Error> {
+        let mut outcomes = HashMap::with_capacity(self.state.authorizations.len());
+        let mut pending = self.authorizations().await?;
+        let mut delay = policy.delay;
+        let mut attempts = 0;
+        loop {
+            let mut failed = false;
+            pending.retain(|authz| match authz.status {
+                AuthorizationStatus::Pending => true,
+                status => {
+                    failed |= status != AuthorizationStatus::Valid;
+                    outcomes.insert(authz.identifier.clone(), AuthorizationOutcome::from(authz));
+                    false
+                }
+            });
+            if failed || pending.is_empty() {
+                outcomes.extend(
+                    pending
+                        .iter()
+                        .map(|authz| (authz.identifier.clone(), AuthorizationOutcome::from(authz))),
+                );
+                return Ok(outcomes);
+            } else if attempts == policy.attempts {
+                return Err(Error::Timeout("authorization validation"));
+            }
+
+            self.sleep(policy.jittered(delay, &*self.account.client.config.rng))
+                .await;
+            attempts += 1;
+            delay = policy.next_delay(delay, None);
+            let urls = pending
+                .iter()
+                .map(|authz| authz.url.clone())
+                .collect::<Vec<_>>();
+            pending = self
+                .fetch_chunked(&urls, true, |_| {})
+                .await
+                .into_result()?;
         }
+    }
+
+    /// Retrieve the authorizations for this order that still need a challenge to be completed
+    ///
+    /// Servers may reuse authorizations that were validated for an earlier order, in which
+    /// case they are already `valid` and no challenge response needs to be set up for them.
+    /// If this returns an empty list, the order should be ready to be finalized.
+    pub async fn pending_authorizations(&mut self) -> Result<Vec<Authorization>, Error> {
+        let mut authorizations = self.authorizations().await?;
+        authorizations.retain(|authz| authz.status == AuthorizationStatus::Pending);
         Ok(authorizations)
     }
 
+    /// Pick one challenge per pending authorization, following the given preference order
+    ///
+    /// Retrieves the authorizations for this order, and for each authorization that is still
+    /// pending selects the first type in `preference` that the server offers. Authorizations
+    /// that are already valid are skipped. Identifiers for which none of the preferred types
+    /// are offered are listed in [`ChallengeSelection::unsupported`].
+    pub async fn select_challenges(
+        &mut self,
+        preference: &[ChallengeType],
+    ) -> Result<ChallengeSelection, Error> {
+        let policy = self.account.client.config.policy.clone();
+        let allowed = |identifier: &Identifier, ty: ChallengeType| match &policy {
+            Some(policy) => policy.allow_challenge(identifier, ty),
+            None => true,
+        };
+
+        let mut selection = ChallengeSelection::default();
+        for authz in self.pending_authorizations().await? {
+            let idx = preference
+                .iter()
+                .filter(|&&ty| allowed(&authz.identifier, ty))
+                .find_map(|&ty| authz.challenges.iter().position(|c| c.r#type == ty));
+
+            match idx {
+                Some(idx) => {
+                    let mut challenges = authz.challenges;
+                    selection
+                        .selected
+                        .push((authz.identifier, challenges.swap_remove(idx)));
+                }
+                None => selection.unsupported.push(authz.identifier),
+            }
+        }
+
+        Ok(selection)
+    }
+
     /// Create a [`KeyAuthorization`] for the given [`Challenge`]
     ///
     /// Signs the challenge's token with the account's private key and use the
     /// value from [`KeyAuthorization::as_str()`] as the challenge response.
     pub fn key_authorization(&self, challenge: &Challenge) -> KeyAuthorization {
-        KeyAuthorization::new(challenge, &self.account.key)
+        KeyAuthorization::new(&challenge.token, &self.account.key.thumb)
     }
 
     /// Request a certificate from the given Certificate Signing Request (CSR)
@@ -83,18 +468,65 @@ impl Order {
     /// Creating a CSR is outside of the scope of instant-acme. Make sure you pass in a
     /// DER representation of the CSR in `csr_der`. Call `certificate()` to retrieve the
     /// certificate chain once the order is in the appropriate state.
+    ///
+    /// If the server rejects the request with an `orderNotReady` error because validation
+    /// completed just after the order state was last polled, the order is refreshed and
+    /// finalization is retried a few times before the error is returned.
     pub async fn finalize(&mut self, csr_der: &[u8]) -> Result<(), Error> {
+        let config = &self.account.client.config;
+        let (clock, deadline) = (config.clock.clone(), config.timeouts.operation);
+        with_deadline(
+            &*clock,
+            deadline,
+            "order finalization",
+            self.finalize_inner(csr_der),
+        )
+        .await
+    }
+
+    async fn finalize_inner(&mut self, csr_der: &[u8]) -> Result<(), Error> {
+        if let Some(policy) = &self.account.client.config.policy {
+            policy.check_finalize(&self.state, csr_der)?;
+        }
+
+        let mut attempts = 1;
+        loop {
+            let problem = match self.submit_csr(csr_der).await {
+                Err(Error::Api(problem))
+                    if problem.r#type == ORDER_NOT_READY && attempts < FINALIZE_ATTEMPTS =>
+                {
+                    problem
+                }
+                result => return result,
+            };
+
+            attempts += 1;
+            let retry_after = self.poll().await?;
+            match self.state.status {
+                OrderStatus::Ready => {}
+                OrderStatus::Pending => {
+                    let delay = retry_after.unwrap_or(Duration::from_secs(1));
+                    self.sleep(delay.min(Duration::from_secs(10))).await;
+                }
+                // Finalized concurrently, for example by another process resuming the order
+                OrderStatus::Processing | OrderStatus::Valid => return Ok(()),
+                OrderStatus::Invalid => return Err(self.failure(Some(problem)).await),
+            }
+        }
+    }
+
+    async fn submit_csr(&mut self, csr_der: &[u8]) -> Result<(), Error> {
         let rsp = self
             .account
             .post(
-                Some(&FinalizeRequest::new(csr_der)),
+                Some(&FinalizeRequest::new(csr_der, &self.finalize_extensions)),
                 self.nonce.take(),
                 &self.state.finalize,
             )
             .await?;
 
         self.nonce = nonce_from_response(&rsp);
-        self.state = Problem::check::<OrderState>(rsp).await?;
+        self.state = OrderState::from_response(rsp).await?;
         Ok(())
     }
 
@@ -102,56 +534,285 @@ impl Order {
     ///
     /// If the cached order state is in `ready` or `processing` state, this will poll the server
     /// for the latest state. If the order is still in `processing` state after that, this will
-    /// return `Ok(None)`. If the order is in `valid` state, this will attempt to retrieve
-    /// the certificate from the server and return it as a `String`. If the order contains
-    /// an error or ends up in any state other than `valid` or `processing`, return an error.
-    pub async fn certificate(&mut self) -> Result<Option<String>, Error> {
+    /// return [`CertStatus::Processing`] with the delay from the server's `Retry-After` header,
+    /// if any. If the order is in `valid` state, this will attempt to retrieve the certificate
+    /// from the server and return it as [`CertStatus::Ready`]. If the order contains an error
+    /// or ends up in any state other than `valid` or `processing`, return an error.
+    pub async fn certificate(&mut self) -> Result<CertStatus, Error> {
+        match self.certificate_ready().await? {
+            true => self.download_certificate().await.map(CertStatus::Ready),
+            false => Ok(CertStatus::Processing {
+                retry_after: self.state.meta.retry_after,
+            }),
+        }
+    }
+
+    /// Stream the certificate chain for this order into `writer`
+    ///
+    /// Behaves like [`Order::certificate()`], but writes the PEM-encoded chain to `writer` as it
+    /// is received instead of buffering it, which avoids holding large chains in memory twice
+    /// when they are written straight to disk. `Vec<u8>` can be passed as a writer as well.
+    ///
+    /// The PEM block boundaries are checked as the chain arrives. Fails without reading the
+    /// rest of the chain if it turns out not to be PEM or if it is larger than `max_len`
+    /// bytes, in which case `writer` may have received part of the chain. Returns the number
+    /// of bytes written, or `Ok(None)` if the order is still processing. The delay requested
+    /// by the server is then available in the [`OrderState::meta`] of [`Order::state()`].
+    pub async fn write_certificate<W: AsyncWrite + Unpin>(
+        &mut self,
+        writer: &mut W,
+        max_len: usize,
+    ) -> Result<Option<usize>, Error> {
+        match self.certificate_ready().await? {
+            true => self.stream_certificate(writer, max_len).await.map(Some),
+            false => Ok(None),
+        }
+    }
+
+    /// Check that the certificate can be downloaded, polling the order if it is processing
+    ///
+    /// Returns `false` if the order is still processing.
+    async fn certificate_ready(&mut self) -> Result<bool, Error> {
         if matches!(self.state.status, OrderStatus::Processing) {
-            let rsp = self
-                .account
-                .post(None::<&Empty>, self.nonce.take(), &self.url)
-                .await?;
-            self.nonce = nonce_from_response(&rsp);
-            self.state = Problem::check::<OrderState>(rsp).await?;
+            self.poll().await?;
         }
 
-        if let Some(error) = &self.state.error {
+        if self.state.status == OrderStatus::Invalid {
+            return Err(self.failure(self.state.error.clone()).await);
+        } else if let Some(error) = &self.state.error {
             return Err(Error::Api(error.clone()));
         } else if self.state.status == OrderStatus::Processing {
-            return Ok(None);
+            return Ok(false);
         } else if self.state.status != OrderStatus::Valid {
-            return Err(Error::Str("invalid order state"));
+            return Err(Error::InvalidState {
+                expected: "valid or processing",
+                actual: format!("{:?}", self.state.status),
+            });
+        }
+
+        Ok(true)
+    }
+
+    /// Poll the order according to `policy` until the server has validated its challenges
+    ///
+    /// Call this once the challenges are ready, for example after
+    /// [`Order::present_challenges()`]. Returns once the order is no longer `pending`, with
+    /// [`Error::OrderExpired`] if it expires while pending, or with [`Error::Timeout`] if it
+    /// is still pending after the last poll. An order that becomes `invalid` is returned as
+    /// such by [`Order::state()`].
+    pub async fn wait_validated(&mut self, policy: RetryPolicy) -> Result<(), Error> {
+        let account = self.account.clone();
+        bulk::wait_validated(self, &account.client.config, policy).await
+    }
+
+    /// Finalize the order and wait for the certificate to be issued
+    ///
+    /// Submits the CSR in `csr_der` (unless the order has already been finalized), then polls
+    /// the order while it is `processing` according to `policy`, honoring any `Retry-After`
+    /// header sent by the server. Returns the problem reported by the server if the order
+    /// becomes invalid, or [`Error::Timeout`] if it is still processing after the last poll.
+    pub async fn finalize_and_collect(
+        &mut self,
+        csr_der: &[u8],
+        policy: RetryPolicy,
+    ) -> Result<CertificateChain, Error> {
+        let config = &self.account.client.config;
+        let (clock, deadline) = (config.clock.clone(), config.timeouts.operation);
+        let operation = self.finalize_and_collect_inner(csr_der, policy);
+        with_deadline(&*clock, deadline, "certificate issuance", operation).await
+    }
+
+    async fn finalize_and_collect_inner(
+        &mut self,
+        csr_der: &[u8],
+        policy: RetryPolicy,
+    ) -> Result<CertificateChain, Error> {
+        if !matches!(
+            self.state.status,
+            OrderStatus::Processing | OrderStatus::Valid
+        ) {
+            self.finalize_inner(csr_der).await?;
+        }
+
+        let mut delay = policy.delay;
+        let mut attempts = 0;
+        loop {
+            if self.state.status == OrderStatus::Invalid {
+                return Err(self.failure(self.state.error.clone()).await);
+            } else if let Some(error) = &self.state.error {
+                return Err(Error::Api(error.clone()));
+            }
+
+            match self.state.status {
+                OrderStatus::Valid => {
+                    return Ok(CertificateChain {
+                        pem: self.download_certificate().await?,
+                    })
+                }
+                OrderStatus::Processing if attempts < policy.attempts => {}
+                OrderStatus::Processing => return Err(Error::Timeout("certificate issuance")),
+                status => {
+                    return Err(Error::InvalidState {
+                        expected: "valid or processing",
+                        actual: format!("{status:?}"),
+                    })
+                }
+            }
+
+            self.sleep(policy.jittered(delay, &*self.account.client.config.rng))
+                .await;
+            attempts += 1;
+            let retry_after = self.poll().await?;
+            delay = policy.next_delay(delay, retry_after);
         }
+    }
 
+    /// Wait for `duration` on the account's clock
+    async fn sleep(&self, duration: Duration) {
+        self.account.client.config.clock.sleep(duration).await
+    }
+
+    /// Collect the authorizations that did not become valid into an [`Error::OrderFailed`]
+    async fn failure(&mut self, problem: Option<Problem>) -> Error {
+        let mut authorizations = self.authorizations().await.unwrap_or_default();
+        authorizations.retain(|authz| authz.status != AuthorizationStatus::Valid);
+        Error::OrderFailed(Box::new(OrderFailure {
+            problem,
+            authorizations,
+        }))
+    }
+
+    /// Download the certificate chain for an order in `valid` state
+    async fn download_certificate(&mut self) -> Result<String, Error> {
+        let mut pem = Vec::new();
+        self.stream_certificate(&mut pem, usize::MAX).await?;
+        Ok(String::from_utf8(pem).map_err(|_| "unable to decode certificate as UTF-8")?)
+    }
+
+    /// Stream the certificate chain for an order in `valid` state into `writer`
+    async fn stream_certificate<W: AsyncWrite + Unpin>(
+        &mut self,
+        writer: &mut W,
+        max_len: usize,
+    ) -> Result<usize, Error> {
         let cert_url = match &self.state.certificate {
             Some(cert_url) => cert_url,
             None => return Err(Error::Str("no certificate URL found")),
         };
 
-        let rsp = self
-            .account
-            .post(None::<&Empty>, self.nonce.take(), cert_url)
+        let account = &self.account;
+        let rsp = account
+            .client
+            .post_with(
+                None::<&Empty>,
+                self.nonce.take(),
+                &**account,
+                cert_url,
+                BodyMode::Streamed,
+            )
             .await?;
 
         self.nonce = nonce_from_response(&rsp);
-        let body = hyper::body::to_bytes(Problem::from_response(rsp).await?).await?;
-        Ok(Some(
-            String::from_utf8(body.to_vec())
-                .map_err(|_| "unable to decode certificate as UTF-8")?,
-        ))
+        let mut body = Problem::from_response(rsp).await?;
+        let config = &account.client.config;
+        let (clock, timeout) = (&*config.clock, config.timeouts.request);
+        let mut scanner = PemScanner::default();
+        let mut written = 0;
+        while let Some(chunk) = with_deadline(clock, timeout, "certificate chain", async {
+            Ok(body.data().await.transpose()?)
+        })
+        .await?
+        {
+            written += chunk.len();
+            if written > max_len {
+                return Err(Error::TooLarge("certificate chain"));
+            }
+
+            scanner.feed(&chunk)?;
+            writer.write_all(&chunk).await?;
+        }
+
+        scanner.finish()?;
+        writer.flush().await?;
+        if config.sct_policy != SctPolicy::Ignore && der::scts(&scanner.first_block()?)?.is_empty()
+        {
+            match config.sct_policy {
+                SctPolicy::Require => return Err(Error::MissingSct),
+                #[cfg(feature = "tracing")]
+                _ => tracing::warn!(order = %self.url, "certificate has no embedded SCTs"),
+                #[cfg(not(feature = "tracing"))]
+                _ => {}
+            }
+        }
+        if let Some(policy) = &config.certificate_policy {
+            let violations = policy.check(&scanner.first_block()?, &self.state)?;
+            match (violations.is_empty(), policy.warn_only) {
+                (true, _) => {}
+                (false, false) => return Err(Error::CertificatePolicy(violations)),
+                #[cfg(feature = "tracing")]
+                (false, true) => {
+                    for violation in violations {
+                        tracing::warn!(order = %self.url, %violation, "certificate policy");
+                    }
+                }
+                #[cfg(not(feature = "tracing"))]
+                (false, true) => {}
+            }
+        }
+        if let Some(policy) = &config.policy {
+            policy.check_certificate(&self.state, &scanner.first_block()?)?;
+        }
+        if config.audit_log.is_some() {
+            let leaf = scanner.first_block()?;
+            self.account.audit(AuditEvent::CertificateIssued {
+                order: &self.url,
+                serial: &der::serial_number(&leaf)?,
+                not_after: der::validity(&leaf)?.1,
+            })?;
+        }
+        Ok(written)
     }
 
     /// Notify the server that the given challenge is ready to be completed
     ///
     /// `challenge_url` should be the `Challenge::url` field.
     pub async fn set_challenge_ready(&mut self, challenge_url: &str) -> Result<(), Error> {
+        self.respond(challenge_url, &Empty {}).await
+    }
+
+    /// Complete a `tkauth-01` challenge with an authority token (RFC 9447 section 5)
+    ///
+    /// `token` is the ATC obtained from the challenge's
+    /// [token authority](Challenge::token_authority), for example with a request created by
+    /// [`Account::authority_token_request()`].
+    pub async fn submit_authority_token(
+        &mut self,
+        challenge_url: &str,
+        token: &str,
+    ) -> Result<(), Error> {
+        self.respond(challenge_url, &AuthorityTokenResponse { atc: token })
+            .await
+    }
+
+    async fn respond(
+        &mut self,
+        challenge_url: &str,
+        payload: &impl Serialize,
+    ) -> Result<(), Error> {
         let rsp = self
             .account
-            .post(Some(&Empty {}), self.nonce.take(), challenge_url)
+            .post(Some(payload), self.nonce.take(), challenge_url)
             .await?;
 
         self.nonce = nonce_from_response(&rsp);
-        let _ = Problem::check::<Challenge>(rsp).await?;
+        let challenge = Problem::check::<Challenge>(rsp).await?;
+        if !self.challenges.iter().any(|c| c.url == challenge.url) {
+            self.challenges.push(ReadyChallenge {
+                r#type: challenge.r#type,
+                url: challenge.url,
+                token: challenge.token,
+            });
+        }
         Ok(())
     }
 
@@ -162,14 +823,30 @@ impl Order {
 
     /// Refresh the current state of the order
     pub async fn refresh(&mut self) -> Result<&OrderState, Error> {
+        self.poll().await?;
+        Ok(&self.state)
+    }
+
+    /// Refresh the current state of the order, returning what changed
+    ///
+    /// See [`OrderState::diff()`] for the changes that are reported. Combine with
+    /// [`Order::refresh_authorization()`] to follow the authorizations as well.
+    pub async fn refresh_changes(&mut self) -> Result<Vec<StateChange>, Error> {
+        let old = self.state.clone();
+        self.poll().await?;
+        Ok(OrderState::diff(&old, &self.state))
+    }
+
+    /// Refresh the order state, returning the server's `Retry-After` delay if it sent one
+    async fn poll(&mut self) -> Result<Option<Duration>, Error> {
         let rsp = self
             .account
             .post(None::<&Empty>, self.nonce.take(), &self.url)
             .await?;
 
         self.nonce = nonce_from_response(&rsp);
-        self.state = Problem::check::<OrderState>(rsp).await?;
-        Ok(&self.state)
+        self.state = OrderState::from_response(rsp).await?;
+        Ok(self.state.meta.retry_after)
     }
 
     /// Get the last known state of the order
@@ -183,12 +860,56 @@ impl Order {
     pub fn url(&self) -> &str {
         &self.url
     }
+
+    /// Get the challenges that have been marked as ready for this order
+    ///
+    /// Challenges are recorded by [`Order::set_challenge_ready()`] and survive a round trip
+    /// through [`Order::to_resumable()`] and [`Account::resume_order()`].
+    pub fn ready_challenges(&self) -> &[ReadyChallenge] {
+        &self.challenges
+    }
+
+    /// Add a field that is not defined by RFC 8555 to the finalization request
+    ///
+    /// Use this for CA-specific extensions. The fields are sent by every following call to
+    /// [`Order::finalize()`], but are not part of the [`ResumableOrder`]. A field named
+    /// `csr` is ignored.
+    pub fn finalize_extension(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> &mut Self {
+        let name = name.into();
+        if name != "csr" {
+            self.finalize_extensions.insert(name, value.into());
+        }
+        self
+    }
+
+    /// Get a serializable snapshot of this order's progress
+    ///
+    /// Pass the snapshot to [`Account::resume_order()`] to continue processing the order,
+    /// potentially from a different process.
+    pub fn to_resumable(&self) -> ResumableOrder {
+        ResumableOrder {
+            url: self.url.clone(),
+            challenges: self.challenges.clone(),
+            expires: self.state.expires,
+        }
+    }
+}
+
+impl Drop for Order {
+    fn drop(&mut self) {
+        self.account.client.recycle(self.nonce.take());
+    }
 }
 
 /// An ACME account as described in RFC 8555 (section 7.1.2)
 ///
 /// Create an [`Account`] with [`Account::create()`] or restore it from serialized data
 /// by passing deserialized [`AccountCredentials`] to [`Account::from_credentials()`].
+/// Use [`Account::builder()`] to change settings through an [`AccountConfig`].
 ///
 /// The [`Account`] type is cheap to clone.
 ///
@@ -198,110 +919,175 @@ pub struct Account {
     inner: Arc<AccountInner>,
 }
 
+/// Omits the account key
+impl fmt::Debug for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Account")
+            .field("id", &self.inner.id)
+            .field("client", &self.inner.client)
+            .finish_non_exhaustive()
+    }
+}
+
 impl Account {
+    /// Start building an account with non-default settings
+    pub fn builder() -> AccountBuilder {
+        AccountBuilder::default()
+    }
+
     /// Restore an existing account from the given credentials
     ///
     /// The [`AccountCredentials`] type is opaque, but supports deserialization.
+    /// Credentials written by a newer version of this crate are accepted if their key type is
+    /// supported.
     #[cfg(feature = "hyper-rustls")]
-    pub fn from_credentials(credentials: AccountCredentials<'_>) -> Result<Self, Error> {
-        Ok(Self {
-            inner: Arc::new(AccountInner::from_credentials(
-                credentials,
-                Box::<DefaultClient>::default(),
-            )?),
-        })
+    pub fn from_credentials(credentials: AccountCredentials) -> Result<Self, Error> {
+        Self::builder().from_credentials(credentials)
     }
 
     /// Restore an existing account from the given credentials and HTTP client
     ///
     /// The [`AccountCredentials`] type is opaque, but supports deserialization.
+    /// Credentials written by a newer version of this crate are accepted if their key type is
+    /// supported.
     pub fn from_credentials_and_http(
-        credentials: AccountCredentials<'_>,
+        credentials: AccountCredentials,
         http: Box<dyn HttpClient>,
     ) -> Result<Self, Error> {
-        Ok(Self {
-            inner: Arc::new(AccountInner::from_credentials(credentials, http)?),
-        })
+        Self::builder().http(http).from_credentials(credentials)
+    }
+
+    /// Restore an existing account from the given credentials, updating its endpoint URLs
+    ///
+    /// If the credentials contain a [directory URL](AccountCredentials::directory_url()), the
+    /// directory is fetched again so that endpoints the server has moved since the credentials
+    /// were written are picked up. If the account creation endpoint changed, the account URL is
+    /// looked up again using the account key. Store the result of [`Account::credentials()`]
+    /// to persist the updated URLs.
+    #[cfg(feature = "hyper-rustls")]
+    pub async fn from_credentials_refreshed(
+        credentials: AccountCredentials,
+    ) -> Result<Self, Error> {
+        Self::builder()
+            .from_credentials_refreshed(credentials)
+            .await
+    }
+
+    /// Restore an existing account from the given credentials and HTTP client, updating its
+    /// endpoint URLs
+    ///
+    /// See [`Account::from_credentials_refreshed()`] for details.
+    pub async fn from_credentials_refreshed_and_http(
+        credentials: AccountCredentials,
+        http: Box<dyn HttpClient>,
+    ) -> Result<Self, Error> {
+        Self::builder()
+            .http(http)
+            .from_credentials_refreshed(credentials)
+            .await
+    }
+
+    /// Restore an account imported from another ACME client
+    ///
+    /// `server_url` should be the directory URL of the CA the account was registered with.
+    #[cfg(feature = "hyper-rustls")]
+    pub async fn from_imported(imported: ImportedAccount, server_url: &str) -> Result<Self, Error> {
+        Self::builder().from_imported(imported, server_url).await
+    }
+
+    /// Restore an account imported from another ACME client with a custom HTTP client
+    pub async fn from_imported_with_http(
+        imported: ImportedAccount,
+        server_url: &str,
+        http: Box<dyn HttpClient>,
+    ) -> Result<Self, Error> {
+        Self::builder()
+            .http(http)
+            .from_imported(imported, server_url)
+            .await
     }
 
     /// Create a new account on the `server_url` with the information in [`NewAccount`]
+    ///
+    /// If the server's directory indicates that external account binding is required, this
+    /// will fail early unless an `external_account` is passed.
     #[cfg(feature = "hyper-rustls")]
     pub async fn create(
-        account: &NewAccount<'_>,
+        account: &NewAccount,
         server_url: &str,
         external_account: Option<&ExternalAccountKey>,
     ) -> Result<Account, Error> {
-        Self::create_inner(
-            account,
-            external_account,
-            Client::new(server_url, Box::<DefaultClient>::default()).await?,
-        )
-        .await
+        Self::builder()
+            .create(account, server_url, external_account)
+            .await
     }
 
     /// Create a new account with a custom HTTP client
     pub async fn create_with_http(
-        account: &NewAccount<'_>,
+        account: &NewAccount,
         server_url: &str,
         external_account: Option<&ExternalAccountKey>,
         http: Box<dyn HttpClient>,
     ) -> Result<Account, Error> {
-        Self::create_inner(
-            account,
-            external_account,
-            Client::new(server_url, http).await?,
-        )
-        .await
+        Self::builder()
+            .http(http)
+            .create(account, server_url, external_account)
+            .await
     }
 
-    async fn create_inner(
-        account: &NewAccount<'_>,
+    /// Create a new account using explicitly provided endpoint URLs
+    ///
+    /// Use this for servers that don't provide a (usable) directory resource.
+    #[cfg(feature = "hyper-rustls")]
+    pub async fn create_with_directory(
+        account: &NewAccount,
+        directory: Directory,
         external_account: Option<&ExternalAccountKey>,
-        client: Client,
     ) -> Result<Account, Error> {
-        let key = Key::generate()?;
-        let payload = NewAccountPayload {
-            new_account: account,
-            external_account_binding: external_account
-                .map(|eak| {
-                    JoseJson::new(
-                        Some(&Jwk::new(&key.inner)),
-                        eak.header(None, &client.urls.new_account),
-                        eak,
-                    )
-                })
-                .transpose()?,
-        };
-
-        let rsp = client
-            .post(Some(&payload), None, &key, &client.urls.new_account)
-            .await?;
-
-        let account_url = rsp
-            .headers()
-            .get(LOCATION)
-            .and_then(|hv| hv.to_str().ok())
-            .map(|s| s.to_owned());
+        Self::builder()
+            .create_with_directory(account, directory, external_account)
+            .await
+    }
 
-        // The response redirects, we don't need the body
-        let _ = Problem::from_response(rsp).await?;
-        Ok(Self {
-            inner: Arc::new(AccountInner {
-                client,
-                key,
-                id: account_url.ok_or("failed to get account URL")?,
-            }),
-        })
+    /// Create a new account using explicitly provided endpoint URLs and HTTP client
+    pub async fn create_with_directory_and_http(
+        account: &NewAccount,
+        directory: Directory,
+        external_account: Option<&ExternalAccountKey>,
+        http: Box<dyn HttpClient>,
+    ) -> Result<Account, Error> {
+        Self::builder()
+            .http(http)
+            .create_with_directory(account, directory, external_account)
+            .await
     }
 
     /// Create a new order based on the given [`NewOrder`]
     ///
     /// Returns an [`Order`] instance. Use the [`Order::state()`] method to inspect its state.
-    pub async fn new_order<'a>(&'a self, order: &NewOrder<'_>) -> Result<Order, Error> {
-        let rsp = self
-            .inner
-            .post(Some(order), None, &self.inner.client.urls.new_order)
-            .await?;
+    ///
+    /// Orders with [ancestor domains](NewOrderBuilder::identifier_with_ancestor) fail if the
+    /// server does not advertise [`DirectoryMeta::subdomain_auth_allowed`].
+    pub async fn new_order(&self, order: &NewOrder) -> Result<Order, Error> {
+        let client = &self.inner.client;
+        if let Some(policy) = &client.config.policy {
+            policy.check_order(order)?;
+        }
+
+        let directory = client.fresh_directory().await;
+        if order.has_ancestor_domains() && !subdomain_auth_allowed(&directory) {
+            return Err(Error::Unsupported(
+                "server does not allow subdomain authorizations",
+            ));
+        }
+
+        client.new_orders.acquire(&*client.config.clock).await;
+        let url = directory.new_order.clone();
+        let mut rsp = self.inner.post(Some(order), None, &url).await?;
+        if let Some(url) = client.moved(&rsp, &url, |urls| &urls.new_order).await {
+            let nonce = nonce_from_response(&rsp);
+            rsp = self.inner.post(Some(order), nonce, &url).await?;
+        }
 
         let nonce = nonce_from_response(&rsp);
         let order_url = rsp
@@ -310,196 +1096,1546 @@ impl Account {
             .and_then(|hv| hv.to_str().ok())
             .map(|s| s.to_owned());
 
-        Ok(Order {
+        let created = Order {
             account: self.inner.clone(),
             nonce,
             // Order of fields matters! We return errors from Problem::check
             // before emitting an error if there is no order url. Or the
             // simple no url error hides the causing error in `Problem::check`.
-            state: Problem::check::<OrderState>(rsp).await?,
-            url: order_url.ok_or("no order URL found")?,
-        })
+            state: OrderState::from_response(rsp).await?,
+            url: order_url.ok_or(Error::MissingHeader("Location"))?,
+            challenges: Vec::new(),
+            finalize_extensions: serde_json::Map::new(),
+            audited: HashSet::new(),
+        };
+        self.inner.audit(AuditEvent::OrderCreated {
+            order: &created.url,
+            identifiers: order.identifiers(),
+        })?;
+        Ok(created)
     }
 
-    /// Get the account's credentials, which can be serialized
+    /// Pre-authorize the account for `identifier` (RFC 8555 section 7.4.1)
     ///
-    /// Pass the credentials to [`Account::from_credentials`] to regain access to the `Account`.
-    pub fn credentials(&self) -> AccountCredentials<'_> {
-        self.inner.credentials()
-    }
-}
+    /// If `subdomains` is set, the authorization also covers all subdomains of `identifier`
+    /// (RFC 9444), so that orders for them can be finalized without further challenges. This
+    /// requires the server to advertise [`DirectoryMeta::subdomain_auth_allowed`].
+    ///
+    /// Returns the URL of the authorization, which can be polled with
+    /// [`Account::get_resource()`], and its initial state. Complete one of its challenges with
+    /// [`Account::key_authorization()`] and [`Account::set_challenge_ready()`].
+    pub async fn new_authorization(
+        &self,
+        identifier: Identifier,
+        subdomains: bool,
+    ) -> Result<(String, Authorization), Error> {
+        let directory = self.inner.client.fresh_directory().await;
+        let url = directory.new_authz.clone().ok_or(Error::Unsupported(
+            "server does not support pre-authorization",
+        ))?;
+        if subdomains && !subdomain_auth_allowed(&directory) {
+            return Err(Error::Unsupported(
+                "server does not allow subdomain authorizations",
+            ));
+        }
 
-struct AccountInner {
-    client: Client,
-    key: Key,
-    id: String,
-}
+        let identifier = identifier.normalize()?;
+        if let Identifier::Dns(name) = &identifier {
+            if name.starts_with("*.") {
+                return Err(Error::InvalidIdentifier {
+                    identifier: name.clone(),
+                    reason: "wildcards cannot be pre-authorized",
+                });
+            }
+        }
 
-impl AccountInner {
-    fn from_credentials(
-        credentials: AccountCredentials<'_>,
-        http: Box<dyn HttpClient>,
-    ) -> Result<Self, Error> {
-        Ok(Self {
-            key: Key::from_pkcs8_der(BASE64_URL_SAFE_NO_PAD.decode(&credentials.key_pkcs8)?)?,
-            client: Client {
-                http,
-                urls: credentials.urls.into_owned(),
-            },
-            id: credentials.id.into_owned(),
-        })
+        let request = NewAuthorizationRequest {
+            identifier,
+            subdomain_auth_allowed: subdomains,
+        };
+        let rsp = self.inner.post(Some(&request), None, &url).await?;
+        self.inner.client.recycle(nonce_from_response(&rsp));
+        let authz_url = rsp
+            .headers()
+            .get(LOCATION)
+            .and_then(|hv| hv.to_str().ok())
+            .map(|s| s.to_owned());
+
+        let mut authorization = Problem::check::<Authorization>(rsp).await?;
+        authorization.url = authz_url.ok_or(Error::MissingHeader("Location"))?;
+        Ok((authorization.url.clone(), authorization))
     }
 
-    async fn get<T: DeserializeOwned>(
+    /// Create the request for an authority token for a TNAuthList `identifier` (RFC 9448)
+    ///
+    /// Send it to the token authority of a `tkauth-01` challenge, using whatever
+    /// authentication the token authority requires. Set `ca` when ordering a CA certificate.
+    /// The fingerprint is the SHA-256 JWK thumbprint (RFC 7638) of the account key.
+    pub fn authority_token_request(
         &self,
-        nonce: &mut Option<String>,
-        url: &str,
-    ) -> Result<T, Error> {
-        let rsp = self.post(None::<&Empty>, nonce.take(), url).await?;
-        *nonce = nonce_from_response(&rsp);
-        Problem::check(rsp).await
+        identifier: &Identifier,
+        ca: bool,
+    ) -> Result<AuthorityTokenRequest, Error> {
+        let thumbprint = BASE64_URL_SAFE_NO_PAD.decode(&self.inner.key.thumb)?;
+        AuthorityTokenRequest::new(identifier, ca, &thumbprint)
     }
 
-    async fn post(
-        &self,
-        payload: Option<&impl Serialize>,
-        nonce: Option<String>,
-        url: &str,
+    /// Create a [`KeyAuthorization`] for the given [`Challenge`]
+    ///
+    /// This is the same as [`Order::key_authorization()`], for challenges of authorizations
+    /// created with [`Account::new_authorization()`].
+    pub fn key_authorization(&self, challenge: &Challenge) -> KeyAuthorization {
+        KeyAuthorization::new(&challenge.token, &self.inner.key.thumb)
+    }
+
+    /// The HTTP-01 response for the challenge with `token`, without needing the challenge
+    ///
+    /// This is the [`KeyAuthorization`] that [`Account::key_authorization()`] returns for a
+    /// challenge with `token`, so that a responder can answer requests for any token of this
+    /// account. Fails if `token` is not base64url-encoded, as RFC 8555 requires of tokens,
+    /// so that tokens taken from request paths can't inject other content.
+    pub fn http01_response_for(&self, token: &str) -> Result<KeyAuthorization, Error> {
+        KeyAuthorization::for_token(token, &self.inner.key.thumb)
+    }
+
+    /// The [`ResponderKey`] of this account, for HTTP-01 responders on other machines
+    pub fn responder_key(&self) -> ResponderKey {
+        ResponderKey {
+            thumbprint: self.inner.key.thumb.clone(),
+        }
+    }
+
+    /// The SHA-256 JWK thumbprint (RFC 7638) of the account key, base64url-encoded
+    ///
+    /// Key authorizations are the token of the challenge, a dot and this thumbprint.
+    /// Stateless HTTP-01 responders can be provisioned with it to answer any challenge of
+    /// this account, like nginx with:
+    ///
+    /// ```text
+    /// location ~ "^/\.well-known/acme-challenge/([-_a-zA-Z0-9]+)$" {
+    ///     default_type text/plain;
+    ///     return 200 "$1.<thumbprint>";
+    /// }
+    /// ```
+    pub fn key_thumbprint(&self) -> &str {
+        &self.inner.key.thumb
+    }
+
+    /// Notify the server that the given challenge is ready to be completed
+    ///
+    /// This is the same as [`Order::set_challenge_ready()`], for challenges of authorizations
+    /// created with [`Account::new_authorization()`].
+    pub async fn set_challenge_ready(&self, challenge_url: &str) -> Result<Challenge, Error> {
+        let rsp = self
+            .inner
+            .post(Some(&Empty {}), None, challenge_url)
+            .await?;
+        self.inner.client.recycle(nonce_from_response(&rsp));
+        Problem::check(rsp).await
+    }
+
+    /// Restore an [`Order`] from a snapshot created by [`Order::to_resumable()`]
+    ///
+    /// The order state is re-synchronized from the server before it is returned.
+    pub async fn resume_order(&self, resumable: ResumableOrder) -> Result<Order, Error> {
+        let rsp = self
+            .inner
+            .post(None::<&Empty>, None, &resumable.url)
+            .await?;
+
+        Ok(Order {
+            account: self.inner.clone(),
+            nonce: nonce_from_response(&rsp),
+            state: OrderState::from_response(rsp).await?,
+            url: resumable.url,
+            challenges: resumable.challenges,
+            finalize_extensions: serde_json::Map::new(),
+            audited: HashSet::new(),
+        })
+    }
+
+    /// Revoke the DER-encoded certificate in `certificate_der` (RFC 8555 section 7.6)
+    ///
+    /// The certificate must have been issued to this account, or for identifiers that this
+    /// account currently holds authorizations for.
+    pub async fn revoke_certificate(
+        &self,
+        certificate_der: &[u8],
+        reason: Option<RevocationReason>,
+    ) -> Result<(), Error> {
+        let url = self
+            .inner
+            .client
+            .fresh_directory()
+            .await
+            .revoke_cert
+            .clone()
+            .ok_or(Error::Unsupported(
+                "server does not support certificate revocation",
+            ))?;
+
+        let request = RevocationRequest::new(certificate_der, reason);
+        let rsp = self.inner.post(Some(&request), None, &url).await?;
+        self.inner.client.recycle(nonce_from_response(&rsp));
+        let _ = Problem::from_response(rsp).await?;
+        if self.inner.client.config.audit_log.is_some() {
+            self.inner.audit(AuditEvent::CertificateRevoked {
+                serial: &der::serial_number(certificate_der)?,
+                reason,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Revoke many DER-encoded certificates, for example after a key compromise
+    ///
+    /// Up to `concurrency` revocation requests are in flight at a time. When the server
+    /// responds with a `rateLimited` problem, the rate-limited certificates are retried after
+    /// the `Retry-After` delay it sent, or after the delay of the account's
+    /// [`RetryPolicy`], until the policy's attempts are used up. Certificates that the server
+    /// reports as already revoked are not failures.
+    ///
+    /// Returns a result for every certificate, in the order they were passed in. One
+    /// certificate failing does not stop the others from being revoked.
+    pub async fn revoke_many<I>(
+        &self,
+        certificates: I,
+        reason: Option<RevocationReason>,
+        concurrency: usize,
+    ) -> Vec<RevocationResult>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        self.revoke_batch(None, certificates, reason, concurrency)
+            .await
+    }
+
+    /// Revoke many certificates like [`Account::revoke_many()`], recording progress in `store`
+    ///
+    /// Every certificate that is revoked, or that the server reports as already revoked, is
+    /// recorded with [`CertStore::save_revocation()`](store::CertStore::save_revocation()).
+    /// Certificates recorded by an earlier run are skipped without contacting the server, so
+    /// an interrupted run can be restarted with the same certificates.
+    pub async fn revoke_many_resumable<I>(
+        &self,
+        store: &dyn store::CertStore,
+        certificates: I,
+        reason: Option<RevocationReason>,
+        concurrency: usize,
+    ) -> Vec<RevocationResult>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        self.revoke_batch(Some(store), certificates, reason, concurrency)
+            .await
+    }
+
+    async fn revoke_batch<I>(
+        &self,
+        store: Option<&dyn store::CertStore>,
+        certificates: I,
+        reason: Option<RevocationReason>,
+        concurrency: usize,
+    ) -> Vec<RevocationResult>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let certificates = certificates.into_iter().collect::<Vec<_>>();
+        let mut results = Vec::with_capacity(certificates.len());
+        let mut pending = Vec::with_capacity(certificates.len());
+        for (index, certificate) in certificates.iter().enumerate() {
+            let serial = match der::serial_number(certificate.as_ref()) {
+                Ok(serial) => serial,
+                Err(error) => {
+                    results.push(RevocationResult {
+                        index,
+                        serial: None,
+                        outcome: Err(error),
+                    });
+                    continue;
+                }
+            };
+
+            let recorded = match store {
+                Some(store) => store.is_revoked(&serial).await,
+                None => Ok(false),
+            };
+            match recorded {
+                Ok(false) => pending.push((index, serial)),
+                recorded => results.push(RevocationResult {
+                    index,
+                    serial: Some(serial),
+                    outcome: recorded.map(|_| RevocationOutcome::Skipped),
+                }),
+            }
+        }
+
+        let config = &self.inner.client.config;
+        let policy = config.retry_policy;
+        let (mut delay, mut attempt) = (policy.delay, 1);
+        while !pending.is_empty() {
+            let (mut limited, mut retry_after) = (Vec::new(), None);
+            for chunk in pending.chunks(concurrency.max(1)) {
+                // Don't send more requests in this round once the server limits them
+                if !limited.is_empty() {
+                    limited.extend_from_slice(chunk);
+                    continue;
+                }
+
+                let futures = chunk
+                    .iter()
+                    .map(|(index, _)| {
+                        let certificate = certificates[*index].as_ref();
+                        Box::pin(self.revoke_certificate(certificate, reason))
+                            as Pin<Box<dyn Future<Output = _>>>
+                    })
+                    .collect();
+                for ((index, serial), result) in chunk.iter().zip(JoinAll::new(futures).await) {
+                    let outcome = match result {
+                        Ok(()) => Ok(RevocationOutcome::Revoked),
+                        Err(Error::Api(problem)) => match problem.r#type.as_str() {
+                            "urn:ietf:params:acme:error:alreadyRevoked" => {
+                                Ok(RevocationOutcome::AlreadyRevoked)
+                            }
+                            "urn:ietf:params:acme:error:rateLimited"
+                                if attempt < policy.attempts =>
+                            {
+                                let wait = problem.meta.as_ref().and_then(|m| m.retry_after);
+                                retry_after = retry_after.max(wait);
+                                limited.push((*index, serial.clone()));
+                                continue;
+                            }
+                            _ => Err(Error::Api(problem)),
+                        },
+                        Err(error) => Err(error),
+                    };
+
+                    let outcome = match (outcome, store) {
+                        (Ok(outcome), Some(store)) => {
+                            let revoked_at = self.inner.client.config.clock.now_utc();
+                            store
+                                .save_revocation(serial, revoked_at)
+                                .await
+                                .map(|()| outcome)
+                        }
+                        (outcome, _) => outcome,
+                    };
+                    results.push(RevocationResult {
+                        index: *index,
+                        serial: Some(serial.clone()),
+                        outcome,
+                    });
+                }
+            }
+
+            if limited.is_empty() {
+                break;
+            }
+            let wait = retry_after.map_or(delay, |d| d.min(policy.max_delay));
+            config
+                .clock
+                .sleep(policy.jittered(wait, &*config.rng))
+                .await;
+            delay = policy.next_delay(delay, None);
+            attempt += 1;
+            pending = limited;
+        }
+
+        results.sort_by_key(|result| result.index);
+        results
+    }
+
+    /// Send an authenticated POST-as-GET request for `url` (RFC 8555 section 6.3)
+    ///
+    /// This can be used to access resources that this crate does not model, like CA-specific
+    /// extensions. The response is returned as-is, without checking for problem documents.
+    pub async fn post_as_get(&self, url: &str) -> Result<Response<Body>, Error> {
+        let rsp = self.inner.post(None::<&Empty>, None, url).await?;
+        self.inner.client.recycle(nonce_from_response(&rsp));
+        Ok(rsp)
+    }
+
+    /// Fetch the resource at `url` with a POST-as-GET request and deserialize it as `T`
+    ///
+    /// Error responses from the server are returned as [`Error::Api`].
+    pub async fn get_resource<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        let mut nonce = None;
+        let result = self.inner.get(&mut nonce, url).await;
+        self.inner.client.recycle(nonce);
+        result
+    }
+
+    /// Send `payload` to `url` in a request signed with the account key
+    ///
+    /// Takes care of replay nonces and JWS signing, so that protocol extensions and drafts
+    /// can be used before this crate supports them. Error responses from the server are
+    /// returned as [`Error::Api`]; otherwise the response status, headers and body are
+    /// passed through unchanged.
+    pub async fn signed_request<T: Serialize>(
+        &self,
+        url: &str,
+        payload: &T,
+    ) -> Result<Response<Bytes>, Error> {
+        let rsp = self.inner.post(Some(payload), None, url).await?;
+        self.inner.client.recycle(nonce_from_response(&rsp));
+        let mut builder = Response::builder().status(rsp.status());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = rsp.headers().clone();
+        }
+
+        let body = hyper::body::to_bytes(Problem::from_response(rsp).await?).await?;
+        builder.body(body).map_err(|_| Error::Malformed("response"))
+    }
+
+    /// Get the account URL, which identifies the account on the server
+    pub fn id(&self) -> &str {
+        &self.inner.id
+    }
+
+    /// Get the endpoint URLs of the server this account belongs to
+    ///
+    /// If the account knows the URL of the server's directory, the directory is fetched again
+    /// once a day, and whenever an endpoint responds with `404 Not Found` or `410 Gone`.
+    pub fn directory(&self) -> Arc<Directory> {
+        self.inner.client.directory()
+    }
+
+    /// Get the HTTP client used by this account
+    ///
+    /// Pass it to [`AccountBuilder::shared_http()`] to let other accounts share its
+    /// connection pool.
+    pub fn http_client(&self) -> Arc<dyn HttpClient> {
+        self.inner.client.http.clone()
+    }
+
+    /// Get statistics on the connections used by this account's HTTP client
+    ///
+    /// Returns `None` if the client does not keep statistics; see [`HttpClient::stats()`].
+    pub fn http_stats(&self) -> Option<HttpStats> {
+        self.inner.client.http.stats()
+    }
+
+    /// Get the URL of the server's directory, if it is known
+    pub fn directory_url(&self) -> Option<&str> {
+        self.inner.client.directory_url.as_deref()
+    }
+
+    /// Get the environment of the account's CA, if it is a [`KnownCa`]
+    pub fn environment(&self) -> Option<Environment> {
+        Some(KnownCa::from_url(self.directory_url()?)?.environment())
+    }
+
+    /// Check that the account can be used to request `expected` certificates
+    ///
+    /// Fails with [`Error::EnvironmentMismatch`] if the account is registered with a known
+    /// CA of another environment, like a staging account used for production certificates.
+    /// Accounts with CAs that are not known pass the check.
+    pub fn check_environment(&self, expected: Environment) -> Result<(), Error> {
+        match self.environment() {
+            Some(actual) if actual != expected => {
+                Err(Error::EnvironmentMismatch { expected, actual })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the label of the CA the account is registered with, if one was set
+    ///
+    /// See [`AccountBuilder::ca_label()`].
+    pub fn ca_label(&self) -> Option<&str> {
+        self.inner.ca_label.as_deref()
+    }
+
+    /// Get the server's directory metadata, if it provided any
+    pub fn directory_meta(&self) -> Option<DirectoryMeta> {
+        self.inner.client.directory().meta.clone()
+    }
+
+    /// Get the account's credentials, which can be serialized
+    ///
+    /// Pass the credentials to [`Account::from_credentials`] to regain access to the `Account`.
+    pub fn credentials(&self) -> AccountCredentials {
+        self.inner.credentials()
+    }
+}
+
+fn subdomain_auth_allowed(directory: &Directory) -> bool {
+    directory
+        .meta
+        .as_ref()
+        .map_or(false, |meta| meta.subdomain_auth_allowed)
+}
+
+/// Builder for an [`Account`] with non-default settings
+///
+/// Created by [`Account::builder()`]. The constructors on [`Account`] are shortcuts for a
+/// builder with the default settings.
+#[derive(Default)]
+pub struct AccountBuilder {
+    http: Option<Arc<dyn HttpClient>>,
+    config: AccountConfig,
+    ca_label: Option<String>,
+    #[cfg(feature = "hyper-rustls")]
+    http2: Http2Settings,
+    #[cfg(feature = "hyper-rustls")]
+    connect: ConnectSettings,
+}
+
+impl AccountBuilder {
+    /// Use a custom HTTP client
+    ///
+    /// This is required if the `hyper-rustls` feature is disabled.
+    pub fn http(mut self, http: Box<dyn HttpClient>) -> Self {
+        self.http = Some(Arc::from(http));
+        self
+    }
+
+    /// Use an HTTP client that is shared with other accounts
+    ///
+    /// Accounts sharing a client also share its connection pool. Pass the result of
+    /// [`Account::http_client()`] to reuse the client of an existing account, including the
+    /// default client.
+    pub fn shared_http(mut self, http: Arc<dyn HttpClient>) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    /// Tune the HTTP/2 connection of the default HTTP client
+    ///
+    /// Ignored if a custom client is set with [`AccountBuilder::http()`] or
+    /// [`AccountBuilder::shared_http()`].
+    #[cfg(feature = "hyper-rustls")]
+    pub fn http2(mut self, settings: Http2Settings) -> Self {
+        self.http2 = settings;
+        self
+    }
+
+    /// Control how the default HTTP client resolves and connects to the CA
+    ///
+    /// Ignored if a custom client is set with [`AccountBuilder::http()`] or
+    /// [`AccountBuilder::shared_http()`].
+    #[cfg(feature = "hyper-rustls")]
+    pub fn connect(mut self, settings: ConnectSettings) -> Self {
+        self.connect = settings;
+        self
+    }
+
+    /// Use non-default settings for the account and the orders created from it
+    pub fn config(mut self, config: AccountConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Label the account with the CA it is registered with, like `letsencrypt-staging`
+    ///
+    /// The label is kept in the [`AccountCredentials`], so that tools can tell which CA or
+    /// environment stored credentials belong to. When restoring an account, it replaces the
+    /// label in the credentials.
+    pub fn ca_label(mut self, label: impl Into<String>) -> Self {
+        self.ca_label = Some(label.into());
+        self
+    }
+
+    /// Restore an existing account from the given credentials
+    ///
+    /// See [`Account::from_credentials()`] for details.
+    pub fn from_credentials(self, credentials: AccountCredentials) -> Result<Account, Error> {
+        let ca_label = self.ca_label.clone();
+        let (http, config) = self.into_parts()?;
+        Ok(Account {
+            inner: Arc::new(AccountInner::from_credentials(
+                credentials,
+                http,
+                config,
+                ca_label,
+            )?),
+        })
+    }
+
+    /// Restore an existing account from the given credentials, updating its endpoint URLs
+    ///
+    /// See [`Account::from_credentials_refreshed()`] for details.
+    pub async fn from_credentials_refreshed(
+        self,
+        credentials: AccountCredentials,
+    ) -> Result<Account, Error> {
+        let ca_label = self.ca_label.clone();
+        let (http, config) = self.into_parts()?;
+        let mut inner = AccountInner::from_credentials(credentials, http, config, ca_label)?;
+        let old = inner.client.directory().new_account.clone();
+        inner.client.refresh_directory().await?;
+
+        let new_account = inner.client.directory().new_account.clone();
+        if new_account != old {
+            let account = NewAccount {
+                contact: Vec::new(),
+                terms_of_service_agreed: false,
+                only_return_existing: true,
+            };
+            let payload = NewAccountPayload {
+                new_account: &account,
+                external_account_binding: None,
+            };
+
+            let rsp = inner
+                .client
+                .post(Some(&payload), None, &inner.key, &new_account)
+                .await?;
+            let account_url = rsp
+                .headers()
+                .get(LOCATION)
+                .and_then(|hv| hv.to_str().ok())
+                .map(|s| s.to_owned());
+
+            inner.client.recycle(nonce_from_response(&rsp));
+            let _ = Problem::from_response(rsp).await?;
+            inner.id = account_url.ok_or(Error::MissingHeader("Location"))?;
+        }
+
+        Ok(Account {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Restore an account imported from another ACME client
+    ///
+    /// See [`Account::from_imported()`] for details.
+    pub async fn from_imported(
+        self,
+        imported: ImportedAccount,
+        server_url: &str,
+    ) -> Result<Account, Error> {
+        let ca_label = self.ca_label.clone();
+        let (http, config) = self.into_parts()?;
+        Ok(Account {
+            inner: Arc::new(AccountInner {
+                client: Client::new(server_url, http, config).await?,
+                key: imported.key,
+                id: imported.id,
+                ca_label,
+                version: AccountCredentials::VERSION,
+                extra: serde_json::Map::new(),
+            }),
+        })
+    }
+
+    /// Create a new account on the `server_url` with the information in [`NewAccount`]
+    ///
+    /// See [`Account::create()`] for details.
+    pub async fn create(
+        self,
+        account: &NewAccount,
+        server_url: &str,
+        external_account: Option<&ExternalAccountKey>,
+    ) -> Result<Account, Error> {
+        let ca_label = self.ca_label.clone();
+        let (http, config) = self.into_parts()?;
+        let client = Client::new(server_url, http, config).await?;
+        Self::create_inner(account, external_account, client, ca_label).await
+    }
+
+    /// Create a new account using explicitly provided endpoint URLs
+    ///
+    /// See [`Account::create_with_directory()`] for details.
+    pub async fn create_with_directory(
+        self,
+        account: &NewAccount,
+        directory: Directory,
+        external_account: Option<&ExternalAccountKey>,
+    ) -> Result<Account, Error> {
+        let ca_label = self.ca_label.clone();
+        let (http, config) = self.into_parts()?;
+        let client = Client::with_directory(directory, None, http, config);
+        Self::create_inner(account, external_account, client, ca_label).await
+    }
+
+    async fn create_inner(
+        account: &NewAccount,
+        external_account: Option<&ExternalAccountKey>,
+        client: Client,
+        ca_label: Option<String>,
+    ) -> Result<Account, Error> {
+        let eab_required = client
+            .directory()
+            .meta
+            .as_ref()
+            .map_or(false, |meta| meta.external_account_required);
+        if eab_required && external_account.is_none() && !account.only_return_existing {
+            return Err(Error::Config(
+                "server requires external account binding, but no external account key given",
+            ));
+        }
+
+        let key = Key::generate(&*client.config.rng, client.config.key_algorithm)?;
+        let payload = |url: &str| -> Result<_, Error> {
+            Ok(NewAccountPayload {
+                new_account: account,
+                external_account_binding: external_account
+                    .map(|eak| {
+                        let jwk = Jwk::new(&key.inner);
+                        let format = client.config.payload_format;
+                        JoseJson::new(Some(&jwk), format, eak.header(None, url), eak)
+                    })
+                    .transpose()?,
+            })
+        };
+
+        let url = client.fresh_directory().await.new_account.clone();
+        let mut rsp = client.post(Some(&payload(&url)?), None, &key, &url).await?;
+        if let Some(url) = client.moved(&rsp, &url, |urls| &urls.new_account).await {
+            let nonce = nonce_from_response(&rsp);
+            rsp = client
+                .post(Some(&payload(&url)?), nonce, &key, &url)
+                .await?;
+        }
+
+        let account_url = rsp
+            .headers()
+            .get(LOCATION)
+            .and_then(|hv| hv.to_str().ok())
+            .map(|s| s.to_owned());
+
+        // The response redirects, we don't need the body
+        client.recycle(nonce_from_response(&rsp));
+        let _ = Problem::from_response(rsp).await?;
+        Ok(Account {
+            inner: Arc::new(AccountInner {
+                client,
+                key,
+                id: account_url.ok_or(Error::MissingHeader("Location"))?,
+                ca_label,
+                version: AccountCredentials::VERSION,
+                extra: serde_json::Map::new(),
+            }),
+        })
+    }
+
+    fn into_parts(self) -> Result<(Arc<dyn HttpClient>, AccountConfig), Error> {
+        let http = match self.http {
+            Some(http) => http,
+            #[cfg(feature = "hyper-rustls")]
+            None => Arc::new(DefaultClient::new(self.http2, self.connect)),
+            #[cfg(not(feature = "hyper-rustls"))]
+            None => return Err(Error::Config("no HTTP client configured")),
+        };
+
+        Ok((http, self.config))
+    }
+}
+
+struct AccountInner {
+    client: Client,
+    key: Key,
+    id: String,
+    ca_label: Option<String>,
+    /// The format version of the credentials the account was restored from
+    version: u32,
+    /// Fields of the credentials that this version of the crate doesn't know about
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl AccountInner {
+    fn from_credentials(
+        credentials: AccountCredentials,
+        http: Arc<dyn HttpClient>,
+        config: AccountConfig,
+        ca_label: Option<String>,
+    ) -> Result<Self, Error> {
+        // Newer versions may add fields, which are kept in `extra`, but are only usable if
+        // they use a kind of key that this version understands
+        let key = BASE64_URL_SAFE_NO_PAD
+            .decode(&credentials.key_pkcs8)
+            .map_err(Error::from)
+            .and_then(Key::from_pkcs8_der);
+        let key = match key {
+            Err(_) if credentials.version > AccountCredentials::VERSION => {
+                return Err(Error::Unsupported("account credentials version"));
+            }
+            key => key?,
+        };
+
+        Ok(Self {
+            key,
+            client: Client::with_directory(
+                credentials.urls,
+                credentials.directory_url,
+                http,
+                config,
+            ),
+            id: credentials.id,
+            ca_label: ca_label.or(credentials.ca_label),
+            version: credentials.version.max(AccountCredentials::VERSION),
+            extra: credentials.extra,
+        })
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        nonce: &mut Option<String>,
+        url: &str,
+    ) -> Result<T, Error> {
+        let rsp = self.post(None::<&Empty>, nonce.take(), url).await?;
+        *nonce = nonce_from_response(&rsp);
+        Problem::check(rsp).await
+    }
+
+    async fn post(
+        &self,
+        payload: Option<&impl Serialize>,
+        nonce: Option<String>,
+        url: &str,
+    ) -> Result<Response<Body>, Error> {
+        self.client.post(payload, nonce, self, url).await
+    }
+
+    /// Record `event` in the audit log, if one is configured
+    fn audit(&self, event: AuditEvent<'_>) -> Result<(), Error> {
+        match &self.client.config.audit_log {
+            Some(log) => log.record(&AuditEntry {
+                time: self.client.config.clock.now_utc(),
+                account: &self.id,
+                event,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn credentials(&self) -> AccountCredentials {
+        AccountCredentials {
+            version: self.version,
+            id: self.id.clone(),
+            key_pkcs8: BASE64_URL_SAFE_NO_PAD.encode(&self.key.pkcs8_der),
+            urls: Directory::clone(&self.client.directory()),
+            directory_url: self.client.directory_url.clone(),
+            ca_label: self.ca_label.clone(),
+            extra: self.extra.clone(),
+        }
+    }
+}
+
+impl Signer for AccountInner {
+    type Signature = <Key as Signer>::Signature;
+
+    fn header<'n, 'u: 'n, 's: 'u>(&'s self, nonce: Option<&'n str>, url: &'u str) -> Header<'n> {
+        debug_assert!(nonce.is_some());
+        Header {
+            alg: self.key.inner.algorithm(),
+            key: KeyOrKeyId::KeyId(&self.id),
+            nonce,
+            url,
+        }
+    }
+
+    fn sign(&self, payload: &[u8]) -> Result<Self::Signature, Error> {
+        self.key.sign(payload)
+    }
+}
+
+struct Client {
+    http: Arc<dyn HttpClient>,
+    config: AccountConfig,
+    /// The URL the directory was fetched from, if known
+    directory_url: Option<String>,
+    directory: RwLock<CachedDirectory>,
+    /// Unused replay nonces, up to [`AccountConfig::nonce_pool_size()`]
+    nonces: Mutex<Vec<String>>,
+    /// Whether a batch of [`AccountConfig::nonce_prefetch()`] nonces is being fetched
+    prefetching: AtomicBool,
+    hedging: Mutex<HedgeStats>,
+    requests: Throttle,
+    new_orders: Throttle,
+}
+
+impl Client {
+    async fn new(
+        server_url: &str,
+        http: Arc<dyn HttpClient>,
+        config: AccountConfig,
+    ) -> Result<Self, Error> {
+        // The placeholder directory is replaced before the client is returned
+        let directory = Directory::new(String::new(), String::new(), String::new());
+        let client = Self::with_directory(directory, Some(server_url.to_owned()), http, config);
+        client.refresh_directory().await?;
+        Ok(client)
+    }
+
+    fn with_directory(
+        directory: Directory,
+        directory_url: Option<String>,
+        http: Arc<dyn HttpClient>,
+        config: AccountConfig,
+    ) -> Self {
+        Client {
+            http,
+            directory_url,
+            directory: RwLock::new(CachedDirectory::new(directory, config.clock.now())),
+            nonces: Mutex::new(Vec::with_capacity(config.nonce_pool_size)),
+            prefetching: AtomicBool::new(false),
+            hedging: Mutex::new(HedgeStats::default()),
+            requests: Throttle::new(config.rate_limits.requests),
+            new_orders: Throttle::new(config.rate_limits.new_orders),
+            config,
+        }
+    }
+
+    /// The cached directory
+    fn directory(&self) -> Arc<Directory> {
+        self.directory.read().unwrap().urls.clone()
+    }
+
+    /// The cached directory, after fetching it again if it is older than [`DIRECTORY_TTL`]
+    async fn fresh_directory(&self) -> Arc<Directory> {
+        let fetched = self.directory.read().unwrap().fetched;
+        let stale = self.config.clock.now().saturating_duration_since(fetched) > DIRECTORY_TTL;
+        if stale {
+            // Keep using the cached URLs if the directory is unavailable
+            let _ = self.refresh_directory().await;
+        }
+        self.directory()
+    }
+
+    /// Fetch the directory again, if its URL is known
+    async fn refresh_directory(&self) -> Result<(), Error> {
+        let url = match &self.directory_url {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+
+        // Also counts failed attempts, so an unavailable directory is not hammered
+        self.directory.write().unwrap().fetched = self.config.clock.now();
+        let request = Request::builder()
+            .uri(url)
+            .body(Body::empty())
+            .map_err(|_| Error::Config("invalid URL"))?;
+        let rsp = self.send(request, BodyMode::Buffered).await?;
+        let status = rsp.status();
+        let body = hyper::body::to_bytes(rsp.into_body()).await?;
+        let directory = parse_response(status, &body)?;
+        *self.directory.write().unwrap() = CachedDirectory::new(directory, self.config.clock.now());
+        Ok(())
+    }
+
+    /// If `rsp` from `url` indicates that the `endpoint` has moved, refresh the directory
+    /// and return the new URL for it
+    async fn moved(
+        &self,
+        rsp: &Response<Body>,
+        url: &str,
+        endpoint: fn(&Directory) -> &String,
+    ) -> Option<String> {
+        if !matches!(rsp.status(), StatusCode::NOT_FOUND | StatusCode::GONE) {
+            return None;
+        }
+
+        self.refresh_directory().await.ok()?;
+        let new = endpoint(&self.directory()).clone();
+        match new != url {
+            true => Some(new),
+            false => None,
+        }
+    }
+
+    /// Keep an unused `nonce` for a later request, if the pool has room for it
+    fn recycle(&self, nonce: Option<String>) {
+        let nonce = match nonce {
+            Some(nonce) => nonce,
+            None => return,
+        };
+
+        let mut nonces = self.nonces.lock().unwrap();
+        if nonces.len() < self.config.nonce_pool_size {
+            nonces.push(nonce);
+        }
+    }
+
+    /// Take a nonce from the pool, or fetch a new one from the server
+    async fn nonce(&self) -> Result<String, Error> {
+        if let Some(nonce) = self.nonces.lock().unwrap().pop() {
+            return Ok(nonce);
+        }
+
+        let url = self.fresh_directory().await.new_nonce.clone();
+        let count = self.config.nonce_prefetch;
+        if count <= 1 || self.prefetching.swap(true, Ordering::AcqRel) {
+            return self.new_nonce(&url).await;
+        }
+
+        let _prefetching = Prefetching(&self.prefetching);
+        let fetches = (0..count)
+            .map(|_| Box::pin(self.new_nonce(&url)) as Pin<Box<dyn Future<Output = _>>>)
+            .collect::<Vec<_>>();
+        let (mut nonce, mut error) = (None, None);
+        for result in JoinAll::new(fetches).await {
+            match result {
+                Ok(fetched) if nonce.is_none() => nonce = Some(fetched),
+                Ok(fetched) => self.recycle(Some(fetched)),
+                Err(e) => error = error.or(Some(e)),
+            }
+        }
+
+        match (nonce, error) {
+            (Some(nonce), _) => Ok(nonce),
+            (None, error) => Err(error.unwrap_or(Error::MissingHeader("Replay-Nonce"))),
+        }
+    }
+
+    /// Fetch a new nonce from the newNonce resource at `url`, hedging if enabled
+    async fn new_nonce(&self, url: &str) -> Result<String, Error> {
+        self.hedged(
+            || Box::pin(self.fetch_new_nonce(url)),
+            || Box::pin(self.fetch_new_nonce(url)),
+        )
+        .await
+    }
+
+    async fn fetch_new_nonce(&self, url: &str) -> Result<String, Error> {
+        let mut nonce = self.fetch_nonce(Method::HEAD, url).await;
+        if !matches!(nonce, Ok(Some(_))) && self.config.profile != BehaviorProfile::Strict {
+            // Some proxies don't pass on HEAD requests; servers must also answer GET requests
+            // to newNonce (RFC 8555 section 7.2)
+            nonce = self.fetch_nonce(Method::GET, url).await;
+        }
+
+        nonce?.ok_or(Error::MissingHeader("Replay-Nonce"))
+    }
+
+    /// Request a nonce from the newNonce resource at `url` with `method`
+    async fn fetch_nonce(&self, method: Method, url: &str) -> Result<Option<String>, Error> {
+        let mut rsp = self.request_empty(method.clone(), url).await?;
+        if let Some(url) = self.moved(&rsp, url, |urls| &urls.new_nonce).await {
+            rsp = self.request_empty(method, &url).await?;
+        }
+
+        Ok(nonce_from_response(&rsp))
+    }
+
+    async fn post(
+        &self,
+        payload: Option<&impl Serialize>,
+        nonce: Option<String>,
+        signer: &impl Signer,
+        url: &str,
+    ) -> Result<Response<Body>, Error> {
+        self.post_with(payload, nonce, signer, url, BodyMode::Buffered)
+            .await
+    }
+
+    /// Sign and send `payload`, retrying according to the configured [`RetryPolicy`]
+    ///
+    /// Requests rejected with a `badNonce` problem are retried right away with the fresh
+    /// nonce from the rejection, and `503 Service Unavailable` responses after a delay.
+    async fn post_with(
+        &self,
+        payload: Option<&impl Serialize>,
+        mut nonce: Option<String>,
+        signer: &impl Signer,
+        url: &str,
+        mode: BodyMode,
+    ) -> Result<Response<Body>, Error> {
+        let mut jws = JwsWriter::new(payload, self.config.payload_format)?;
+        #[cfg(feature = "tracing")]
+        if self.config.log_bodies {
+            let payload = match payload {
+                Some(data) => redacted(&serde_json::to_vec(data)?),
+                None => String::new(),
+            };
+            tracing::debug!(url = %url, %payload, "ACME request");
+        }
+        let policy = self.config.retry_policy;
+        let mut delay = policy.delay;
+        let mut attempt = 1;
+        loop {
+            let current = match nonce.take() {
+                Some(nonce) => nonce,
+                None => self.nonce().await?,
+            };
+
+            let body = jws.sign(signer.header(Some(&current), url), signer)?;
+            let request = jose_request(url, body)?;
+
+            let rsp = match payload.is_none() && mode == BodyMode::Buffered {
+                // POST-as-GET requests are idempotent; the hedge is signed with its own nonce
+                true => {
+                    let hedge = || -> RequestFuture<'_, _> {
+                        Box::pin(async {
+                            let nonce = self.nonce().await?;
+                            let body = JwsWriter::new(None::<&Empty>, self.config.payload_format)?
+                                .sign(signer.header(Some(&nonce), url), signer)?;
+                            self.send(jose_request(url, body)?, mode).await
+                        })
+                    };
+                    let first =
+                        move || -> RequestFuture<'_, _> { Box::pin(self.send(request, mode)) };
+                    self.hedged(first, hedge).await?
+                }
+                false => self.send(request, mode).await?,
+            };
+            let status = rsp.status();
+            if attempt >= policy.attempts
+                || !matches!(
+                    status,
+                    StatusCode::BAD_REQUEST | StatusCode::SERVICE_UNAVAILABLE
+                )
+            {
+                return Ok(rsp);
+            }
+
+            let (parts, body) = rsp.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let rsp = Response::from_parts(parts, body);
+            match rsp.status() {
+                StatusCode::BAD_REQUEST if is_bad_nonce(rsp.body()) => {}
+                StatusCode::SERVICE_UNAVAILABLE => {
+                    let retry_after = rsp
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(types::parse_retry_after);
+                    let wait = retry_after.map_or(delay, |d| d.min(policy.max_delay));
+                    let wait = policy.jittered(wait, &*self.config.rng);
+                    self.config.clock.sleep(wait).await;
+                    delay = policy.next_delay(delay, None);
+                }
+                _ => return Ok(rsp.map(Body::from)),
+            }
+
+            nonce = nonce_from_response(&rsp);
+            attempt += 1;
+        }
+    }
+
+    /// Run the operation started by `first`, also starting `hedge` if it is slow
+    ///
+    /// Without a [`HedgePolicy`], or once the budget is used up, this just runs `first`.
+    async fn hedged<'a, T>(
+        &'a self,
+        first: impl FnOnce() -> RequestFuture<'a, T>,
+        hedge: impl FnOnce() -> RequestFuture<'a, T>,
+    ) -> Result<T, Error> {
+        let policy = match self.config.hedging {
+            Some(policy) => policy,
+            None => return first().await,
+        };
+
+        let delay = {
+            let mut stats = self.hedging.lock().unwrap();
+            stats.requests += 1;
+            match policy.delay {
+                Some(delay) => Some(delay),
+                None => stats.p95().map(|p95| p95.max(policy.min_delay)),
+            }
+        };
+        let delay = match delay {
+            Some(delay) => delay,
+            None => return self.timed(first()).await,
+        };
+
+        let start_hedge = move || {
+            let mut stats = self.hedging.lock().unwrap();
+            match stats.hedges * 100 < stats.requests * u64::from(policy.budget) {
+                true => {
+                    stats.hedges += 1;
+                    Some(hedge())
+                }
+                false => None,
+            }
+        };
+        self.timed(Hedged {
+            first: Some(first()),
+            timer: Some(self.config.clock.sleep(delay)),
+            start_hedge: Some(start_hedge),
+            hedge: None,
+            error: None,
+        })
+        .await
+    }
+
+    /// Run `operation`, recording how long it took for [`HedgePolicy::adaptive()`]
+    async fn timed<T>(
+        &self,
+        operation: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let start = self.config.clock.now();
+        let result = operation.await;
+        if result.is_ok() {
+            let elapsed = self.config.clock.now().saturating_duration_since(start);
+            self.hedging.lock().unwrap().record(elapsed);
+        }
+        result
+    }
+
+    async fn request_empty(&self, method: Method, url: &str) -> Result<Response<Body>, Error> {
+        let request = Request::builder()
+            .method(method)
+            .uri(url)
+            .body(Body::empty())
+            .map_err(|_| Error::Config("invalid URL"))?;
+
+        self.send(request, BodyMode::Buffered).await
+    }
+
+    /// Send `request` and read the response within the request timeout
+    ///
+    /// Error responses are always read completely; see [`BodyMode`] for other responses.
+    async fn send(
+        &self,
+        mut request: Request<Body>,
+        mode: BodyMode,
     ) -> Result<Response<Body>, Error> {
-        self.client.post(payload, nonce, self, url).await
+        if let Ok(user_agent) = HeaderValue::from_str(&self.config.user_agent) {
+            request.headers_mut().insert(USER_AGENT, user_agent);
+        }
+        #[cfg(feature = "tracing")]
+        let uri = request.uri().clone();
+
+        self.requests.acquire(&*self.config.clock).await;
+        let exchange = async {
+            let rsp = self.http.request(request).await?;
+            let status = rsp.status();
+            if mode == BodyMode::Streamed && !(status.is_client_error() || status.is_server_error())
+            {
+                return Ok(Err(rsp));
+            }
+
+            let (parts, body) = rsp.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            Ok(Ok(Response::from_parts(parts, body)))
+        };
+
+        let (clock, timeout) = (&*self.config.clock, self.config.timeouts.request);
+        let mut rsp = match with_deadline(clock, timeout, "HTTP response", exchange).await? {
+            Ok(rsp) => rsp,
+            Err(streamed) => {
+                if self.config.profile == BehaviorProfile::Strict {
+                    check_content_type(&streamed)?;
+                }
+                return Ok(streamed);
+            }
+        };
+        let status = rsp.status();
+        if self.config.profile == BehaviorProfile::Strict && !rsp.body().is_empty() {
+            check_content_type(&rsp)?;
+        }
+        if self.config.lenient_mode
+            && (status.is_client_error() || status.is_server_error())
+            && serde_json::from_slice::<Problem>(rsp.body()).is_err()
+        {
+            // Complete problem documents that leave out members, as RFC 7807 permits; other
+            // bodies fail with the status and an excerpt, as they would without lenient mode
+            let mut problem =
+                parse_response::<serde_json::Map<String, serde_json::Value>>(status, rsp.body())?;
+            problem
+                .entry("type")
+                .or_insert_with(|| "about:blank".into());
+            problem
+                .entry("status")
+                .or_insert_with(|| status.as_u16().into());
+            *rsp.body_mut() = Bytes::from(serde_json::to_vec(&problem)?);
+        }
+        #[cfg(feature = "tracing")]
+        if self.config.log_bodies {
+            let body = redacted(rsp.body());
+            tracing::debug!(url = %uri, status = status.as_u16(), %body, "ACME response");
+        }
+
+        Ok(rsp.map(Body::from))
     }
+}
 
-    fn credentials(&self) -> AccountCredentials<'_> {
-        AccountCredentials {
-            id: Cow::Borrowed(&self.id),
-            key_pkcs8: BASE64_URL_SAFE_NO_PAD.encode(&self.key.pkcs8_der),
-            urls: Cow::Borrowed(&self.client.urls),
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("client", &"..")
+            .field("urls", &self.directory())
+            .finish()
+    }
+}
+
+struct CachedDirectory {
+    urls: Arc<Directory>,
+    fetched: Instant,
+}
+
+impl CachedDirectory {
+    fn new(directory: Directory, fetched: Instant) -> Self {
+        Self {
+            urls: Arc::new(directory),
+            fetched,
         }
     }
 }
 
-impl Signer for AccountInner {
-    type Signature = <Key as Signer>::Signature;
+/// Whether [`Client::send()`] reads successful response bodies before returning
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BodyMode {
+    /// Read the complete body within the request timeout
+    Buffered,
+    /// Leave the body to be streamed by the caller
+    Streamed,
+}
 
-    fn header<'n, 'u: 'n, 's: 'u>(&'s self, nonce: Option<&'n str>, url: &'u str) -> Header<'n> {
-        debug_assert!(nonce.is_some());
-        Header {
-            alg: self.key.signing_algorithm,
-            key: KeyOrKeyId::KeyId(&self.id),
-            nonce,
-            url,
+/// A sliding window limit on how often an operation may start
+struct Throttle {
+    limit: Option<RateLimit>,
+    started: Mutex<VecDeque<Instant>>,
+}
+
+impl Throttle {
+    fn new(limit: Option<RateLimit>) -> Self {
+        Self {
+            limit,
+            started: Mutex::new(VecDeque::new()),
         }
     }
 
-    fn sign(&self, payload: &[u8]) -> Result<Self::Signature, Error> {
-        self.key.sign(payload)
+    /// Wait until the limit allows another operation, and record its start
+    async fn acquire(&self, clock: &dyn Clock) {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return,
+        };
+
+        loop {
+            let wait = {
+                let mut started = self.started.lock().unwrap();
+                let now = clock.now();
+                while started.front().map_or(false, |start| {
+                    now.saturating_duration_since(*start) >= limit.window
+                }) {
+                    started.pop_front();
+                }
+
+                match started.front() {
+                    Some(first) if started.len() >= limit.max as usize => {
+                        limit.window - now.saturating_duration_since(*first)
+                    }
+                    _ => {
+                        started.push_back(now);
+                        return;
+                    }
+                }
+            };
+
+            clock.sleep(wait).await;
+        }
     }
 }
 
-struct Client {
-    http: Box<dyn HttpClient>,
-    urls: DirectoryUrls,
+/// Whether `body` is a problem document of type `badNonce`
+fn is_bad_nonce(body: &[u8]) -> bool {
+    serde_json::from_slice::<Problem>(body).map_or(false, |problem| problem.r#type == BAD_NONCE)
 }
 
-impl Client {
-    async fn new(server_url: &str, http: Box<dyn HttpClient>) -> Result<Self, Error> {
-        let req = Request::builder()
-            .uri(server_url)
-            .body(Body::empty())
-            .unwrap();
-        let rsp = http.request(req).await?;
-        let body = hyper::body::to_bytes(rsp.into_body()).await?;
-        Ok(Client {
-            http,
-            urls: serde_json::from_slice(&body)?,
-        })
+/// A POST request with a JWS `body` for `url`
+fn jose_request(url: &str, body: Vec<u8>) -> Result<Request<Body>, Error> {
+    Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(CONTENT_TYPE, JOSE_JSON)
+        .body(Body::from(body))
+        .map_err(|_| Error::Config("invalid URL"))
+}
+
+type RequestFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + 'a>>;
+
+/// Latencies and counts of the requests that may be hedged
+#[derive(Default)]
+struct HedgeStats {
+    /// The most recent latencies, up to [`HEDGE_SAMPLES`]
+    latencies: VecDeque<Duration>,
+    requests: u64,
+    hedges: u64,
+}
+
+impl HedgeStats {
+    fn record(&mut self, latency: Duration) {
+        if self.latencies.len() == HEDGE_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
     }
 
-    async fn post(
-        &self,
-        payload: Option<&impl Serialize>,
-        mut nonce: Option<String>,
-        signer: &impl Signer,
-        url: &str,
-    ) -> Result<Response<Body>, Error> {
-        if nonce.is_none() {
-            let request = Request::builder()
-                .method(Method::HEAD)
-                .uri(&self.urls.new_nonce)
-                .body(Body::empty())
-                .unwrap();
+    /// The 95th percentile of the recent latencies, once there are enough of them
+    fn p95(&self) -> Option<Duration> {
+        if self.latencies.len() < HEDGE_MIN_SAMPLES {
+            return None;
+        }
 
-            let rsp = self.http.request(request).await?;
-            nonce = nonce_from_response(&rsp);
-        };
+        let mut sorted = self.latencies.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        Some(sorted[(sorted.len() * 95 / 100).min(sorted.len() - 1)])
+    }
+}
 
-        let nonce = nonce.ok_or("no nonce found")?;
-        let body = JoseJson::new(payload, signer.header(Some(&nonce), url), signer)?;
-        let request = Request::builder()
-            .method(Method::POST)
-            .uri(url)
-            .header(CONTENT_TYPE, JOSE_JSON)
-            .body(Body::from(serde_json::to_vec(&body)?))
-            .unwrap();
+/// Resolves with the first successful result of an operation and its hedge
+///
+/// The hedge is started when `timer` expires, unless the first attempt completed already.
+/// If both fail, resolves with the error of the first attempt.
+struct Hedged<'a, T, F> {
+    first: Option<RequestFuture<'a, T>>,
+    timer: Option<SleepFuture>,
+    start_hedge: Option<F>,
+    hedge: Option<RequestFuture<'a, T>>,
+    error: Option<Error>,
+}
+
+// The futures are boxed, and the other fields are never pinned
+impl<T, F> Unpin for Hedged<'_, T, F> {}
+
+impl<'a, T, F: FnOnce() -> Option<RequestFuture<'a, T>>> Future for Hedged<'a, T, F> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some(first) = &mut this.first {
+            if let Poll::Ready(result) = first.as_mut().poll(cx) {
+                this.first = None;
+                match result {
+                    Ok(value) => return Poll::Ready(Ok(value)),
+                    Err(e) if this.hedge.is_none() => return Poll::Ready(Err(e)),
+                    Err(e) => this.error = Some(e),
+                }
+            }
+        }
+
+        if let Some(timer) = &mut this.timer {
+            if timer.as_mut().poll(cx).is_ready() {
+                this.timer = None;
+                this.hedge = this.start_hedge.take().and_then(|start| start());
+            }
+        }
 
-        Ok(self.http.request(request).await?)
+        if let Some(hedge) = &mut this.hedge {
+            if let Poll::Ready(result) = hedge.as_mut().poll(cx) {
+                this.hedge = None;
+                match (result, this.first.is_none()) {
+                    (Ok(value), _) => return Poll::Ready(Ok(value)),
+                    (Err(e), true) => return Poll::Ready(Err(this.error.take().unwrap_or(e))),
+                    (Err(_), false) => {}
+                }
+            }
+        }
+
+        Poll::Pending
     }
 }
 
-impl fmt::Debug for Client {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Client")
-            .field("client", &"..")
-            .field("urls", &self.urls)
-            .finish()
+/// Clears [`Client::prefetching`] when dropped, even if the batch is cancelled
+struct Prefetching<'a>(&'a AtomicBool);
+
+impl Drop for Prefetching<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Resolves with the outputs of all futures, in order, once they have all completed
+struct JoinAll<'a, T> {
+    futures: Vec<Pin<Box<dyn Future<Output = T> + 'a>>>,
+    outputs: Vec<Option<T>>,
+}
+
+impl<'a, T> JoinAll<'a, T> {
+    fn new(futures: Vec<Pin<Box<dyn Future<Output = T> + 'a>>>) -> Self {
+        let outputs = futures.iter().map(|_| None).collect();
+        Self { futures, outputs }
+    }
+}
+
+impl<T: Unpin> Future for JoinAll<'_, T> {
+    type Output = Vec<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        for (future, output) in this.futures.iter_mut().zip(&mut this.outputs) {
+            if output.is_none() {
+                if let Poll::Ready(value) = future.as_mut().poll(cx) {
+                    *output = Some(value);
+                }
+            }
+        }
+
+        match this.outputs.iter().all(Option::is_some) {
+            true => Poll::Ready(this.outputs.drain(..).flatten().collect()),
+            false => Poll::Pending,
+        }
     }
 }
 
+/// Run `operation`, failing with [`Error::Timeout`] if it does not complete within `timeout`
+async fn with_deadline<T>(
+    clock: &dyn Clock,
+    timeout: Option<Duration>,
+    what: &'static str,
+    operation: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return operation.await,
+    };
+
+    let deadline = Deadline {
+        operation: Box::pin(operation),
+        expired: clock.sleep(timeout),
+    };
+    deadline.await.ok_or(Error::Timeout(what))?
+}
+
 struct Key {
     rng: SystemRandom,
-    signing_algorithm: SigningAlgorithm,
-    inner: EcdsaKeyPair,
+    inner: KeyPair,
     pkcs8_der: Vec<u8>,
     thumb: String,
 }
 
 impl Key {
-    fn generate() -> Result<Self, Error> {
-        let rng = SystemRandom::new();
-        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)?;
-        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref())?;
-        let thumb = BASE64_URL_SAFE_NO_PAD.encode(Jwk::thumb_sha256(&key)?);
-
-        Ok(Self {
-            rng,
-            signing_algorithm: SigningAlgorithm::Es256,
-            inner: key,
-            pkcs8_der: pkcs8.as_ref().to_vec(),
-            thumb,
+    fn generate(rng: &dyn Rng, algorithm: SigningAlgorithm) -> Result<Self, Error> {
+        Self::from_pkcs8_der(match algorithm {
+            SigningAlgorithm::Es256 => rng::generate_p256_pkcs8(rng)?,
+            #[cfg(feature = "ml-dsa")]
+            SigningAlgorithm::MlDsa44 => rng::generate_ml_dsa44_pkcs8(rng)?,
+            _ => return Err(Error::Unsupported("account key algorithm")),
         })
     }
 
     fn from_pkcs8_der(pkcs8_der: Vec<u8>) -> Result<Self, Error> {
-        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8_der)?;
-        let thumb = BASE64_URL_SAFE_NO_PAD.encode(Jwk::thumb_sha256(&key)?);
-
+        let inner = KeyPair::from_pkcs8(&pkcs8_der)?;
+        let thumb = BASE64_URL_SAFE_NO_PAD.encode(Jwk::thumb_sha256(&inner)?);
         Ok(Self {
             rng: SystemRandom::new(),
-            signing_algorithm: SigningAlgorithm::Es256,
-            inner: key,
+            inner,
             pkcs8_der,
             thumb,
         })
     }
 }
 
+impl Drop for Key {
+    fn drop(&mut self) {
+        zeroize(&mut self.pkcs8_der);
+    }
+}
+
+/// Omits the private key
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("signing_algorithm", &self.inner.algorithm())
+            .field("thumb", &self.thumb)
+            .finish_non_exhaustive()
+    }
+}
+
 impl Signer for Key {
-    type Signature = ring::signature::Signature;
+    type Signature = Vec<u8>;
 
     fn header<'n, 'u: 'n, 's: 'u>(&'s self, nonce: Option<&'n str>, url: &'u str) -> Header<'n> {
         debug_assert!(nonce.is_some());
         Header {
-            alg: self.signing_algorithm,
+            alg: self.inner.algorithm(),
             key: KeyOrKeyId::from_key(&self.inner),
             nonce,
             url,
@@ -507,20 +2643,126 @@ impl Signer for Key {
     }
 
     fn sign(&self, payload: &[u8]) -> Result<Self::Signature, Error> {
-        Ok(self.inner.sign(&self.rng, payload)?)
+        self.inner.sign(&self.rng, payload)
+    }
+}
+
+/// Supported types of account key pairs
+///
+/// Everything that depends on the type of key is defined here: how keys are parsed from
+/// PKCS #8, the JWS algorithm and signature encoding, and the members of the public JWK.
+/// Supporting another type of account key only takes another variant.
+enum KeyPair {
+    /// ECDSA using P-256, the default for new accounts
+    Ecdsa(EcdsaKeyPair),
+    /// RSA, as used by accounts imported from some other clients
+    Rsa(RsaKeyPair),
+    /// ML-DSA-44 (FIPS 204), for experimenting with post-quantum accounts
+    #[cfg(feature = "ml-dsa")]
+    MlDsa44(Box<mldsa::KeyPair>),
+}
+
+impl KeyPair {
+    /// Parse a PKCS #8 key of any supported type
+    fn from_pkcs8(pkcs8_der: &[u8]) -> Result<Self, Error> {
+        #[cfg(feature = "ml-dsa")]
+        if let Ok(der::PrivateKey::MlDsa44(mut seed)) = der::PrivateKey::from_pkcs8(pkcs8_der) {
+            let key = mldsa::KeyPair::from_seed(&seed);
+            zeroize(&mut seed);
+            return Ok(Self::MlDsa44(Box::new(key)));
+        }
+
+        match EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8_der) {
+            Ok(key) => Ok(Self::Ecdsa(key)),
+            Err(err) => match RsaKeyPair::from_pkcs8(pkcs8_der) {
+                Ok(key) => Ok(Self::Rsa(key)),
+                Err(_) => Err(err.into()),
+            },
+        }
+    }
+
+    /// The JWS algorithm of signatures made with this key
+    fn algorithm(&self) -> SigningAlgorithm {
+        match self {
+            Self::Ecdsa(_) => SigningAlgorithm::Es256,
+            Self::Rsa(_) => SigningAlgorithm::Rs256,
+            #[cfg(feature = "ml-dsa")]
+            Self::MlDsa44(_) => SigningAlgorithm::MlDsa44,
+        }
+    }
+
+    /// Sign `payload`, returning the signature as encoded in a JWS
+    fn sign(&self, rng: &SystemRandom, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            // The fixed encoding is the concatenation of r and s that JWS uses
+            Self::Ecdsa(key) => Ok(key.sign(rng, payload)?.as_ref().to_vec()),
+            Self::Rsa(key) => {
+                let mut signature = vec![0; key.public_modulus_len()];
+                key.sign(&RSA_PKCS1_SHA256, rng, payload, &mut signature)?;
+                Ok(signature)
+            }
+            #[cfg(feature = "ml-dsa")]
+            Self::MlDsa44(key) => key.sign(rng, payload),
+        }
+    }
+
+    /// The required members of the public JWK, which are also hashed for its thumbprint
+    fn jwk_members(&self) -> BTreeMap<&'static str, String> {
+        let mut members = BTreeMap::new();
+        match self {
+            Self::Ecdsa(key) => {
+                let (x, y) = key.public_key().as_ref()[1..].split_at(32);
+                members.insert("crv", "P-256".to_owned());
+                members.insert("kty", "EC".to_owned());
+                members.insert("x", BASE64_URL_SAFE_NO_PAD.encode(x));
+                members.insert("y", BASE64_URL_SAFE_NO_PAD.encode(y));
+            }
+            Self::Rsa(key) => {
+                let public = key.public_key();
+                let e = public.exponent().big_endian_without_leading_zero();
+                let n = public.modulus().big_endian_without_leading_zero();
+                members.insert("e", BASE64_URL_SAFE_NO_PAD.encode(e));
+                members.insert("kty", "RSA".to_owned());
+                members.insert("n", BASE64_URL_SAFE_NO_PAD.encode(n));
+            }
+            // The algorithm is a required member of AKP keys (draft-ietf-cose-dilithium)
+            #[cfg(feature = "ml-dsa")]
+            Self::MlDsa44(key) => {
+                members.insert("alg", "ML-DSA-44".to_owned());
+                members.insert("kty", "AKP".to_owned());
+                members.insert("pub", BASE64_URL_SAFE_NO_PAD.encode(key.public_key()));
+            }
+        }
+        members
     }
 }
 
 /// The response value to use for challenge responses
 ///
-/// Refer to the methods below to see which encoding to use for your challenge type.
+/// Refer to the methods below to see which encoding to use for your challenge type. The
+/// value is overwritten with zeroes when the `KeyAuthorization` is dropped.
 ///
 /// <https://datatracker.ietf.org/doc/html/rfc8555#section-8.1>
 pub struct KeyAuthorization(String);
 
 impl KeyAuthorization {
-    fn new(challenge: &Challenge, key: &Key) -> Self {
-        Self(format!("{}.{}", challenge.token, &key.thumb))
+    fn new(token: &str, thumbprint: &str) -> Self {
+        // Built in a buffer of the final size, so no copies are left behind to clear
+        let mut value = String::with_capacity(token.len() + 1 + thumbprint.len());
+        value.push_str(token);
+        value.push('.');
+        value.push_str(thumbprint);
+        Self(value)
+    }
+
+    /// The key authorization for `token`, which must be base64url-encoded like RFC 8555
+    /// requires, so that tokens taken from request paths can't inject other content
+    fn for_token(token: &str, thumbprint: &str) -> Result<Self, Error> {
+        let valid = |b: u8| b.is_ascii_alphanumeric() || b == b'-' || b == b'_';
+        match !token.is_empty() && token.bytes().all(valid) {
+            true => Ok(Self::new(token, thumbprint)),
+            false => Err(Error::Malformed("challenge token")),
+        }
     }
 
     /// Get the key authorization value
@@ -532,11 +2774,30 @@ impl KeyAuthorization {
 
     /// Get the SHA-256 digest of the key authorization
     ///
-    /// This can be used for TLS-ALPN-01 challenge responses.
+    /// This can be used for TLS-ALPN-01 challenge responses; see
+    /// [`KeyAuthorization::acme_identifier_extension_der()`] for the extension value that
+    /// holds it.
+    ///
+    /// <https://datatracker.ietf.org/doc/html/rfc8737#section-3>
+    pub fn digest(&self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(digest(&SHA256, self.0.as_bytes()).as_ref());
+        bytes
+    }
+
+    /// Get the DER-encoded value of the `acmeIdentifier` extension for TLS-ALPN-01
+    ///
+    /// This is the [`KeyAuthorization::digest()`] wrapped in an `OCTET STRING`, ready to be
+    /// used as the `extnValue` of the critical extension with OID `1.3.6.1.5.5.7.1.31` in the
+    /// self-signed validation certificate. Certificate libraries that take the contents of
+    /// `extnValue` expect exactly these bytes; the digest itself must not be passed instead.
     ///
     /// <https://datatracker.ietf.org/doc/html/rfc8737#section-3>
-    pub fn digest(&self) -> impl AsRef<[u8]> {
-        digest(&SHA256, self.0.as_bytes())
+    pub fn acme_identifier_extension_der(&self) -> Vec<u8> {
+        let mut der = Vec::with_capacity(34);
+        der.extend_from_slice(&[0x04, 0x20]);
+        der.extend_from_slice(&self.digest());
+        der
     }
 
     /// Get the base64-encoded SHA256 digest of the key authorization
@@ -545,6 +2806,98 @@ impl KeyAuthorization {
     pub fn dns_value(&self) -> String {
         BASE64_URL_SAFE_NO_PAD.encode(self.digest())
     }
+
+    /// Whether `token` is the token of the challenge this key authorization is for
+    ///
+    /// Use this in HTTP-01 responders to match the last path segment of requests for
+    /// `/.well-known/acme-challenge/<token>`. The comparison takes constant time, so that
+    /// response times don't reveal how much of a guessed token is correct.
+    pub fn is_for_token(&self, token: &str) -> bool {
+        let own = self.0.split('.').next().unwrap_or_default();
+        constant_time::verify_slices_are_equal(own.as_bytes(), token.as_bytes()).is_ok()
+    }
+
+    /// Whether `value` equals this key authorization, compared in constant time
+    pub fn matches(&self, value: &[u8]) -> bool {
+        constant_time::verify_slices_are_equal(self.0.as_bytes(), value).is_ok()
+    }
+}
+
+impl Drop for KeyAuthorization {
+    fn drop(&mut self) {
+        zeroize(&mut mem::take(&mut self.0).into_bytes());
+    }
+}
+
+/// What HTTP-01 responders need of an account key: its JWK thumbprint
+///
+/// Key authorizations only depend on the challenge token and the thumbprint of the account
+/// key, so a `ResponderKey` lets edge nodes answer challenges without the account
+/// credentials. It holds no private key material and serializes as `{"thumbprint": "..."}`.
+/// Get one from [`Account::responder_key()`], or from the public key of the account.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ResponderKey {
+    #[serde(deserialize_with = "deserialize_thumbprint")]
+    thumbprint: String,
+}
+
+impl ResponderKey {
+    /// A responder key from the base64url-encoded SHA-256 JWK thumbprint of an account key
+    ///
+    /// See [`Account::key_thumbprint()`].
+    pub fn from_thumbprint(thumbprint: impl Into<String>) -> Result<Self, Error> {
+        let thumbprint = thumbprint.into();
+        match BASE64_URL_SAFE_NO_PAD.decode(&thumbprint) {
+            Ok(digest) if digest.len() == SHA256.output_len => Ok(Self { thumbprint }),
+            _ => Err(Error::Malformed("JWK thumbprint")),
+        }
+    }
+
+    /// A responder key from the public JWK of an account key
+    ///
+    /// Supports EC, RSA and OKP keys. Members other than those the thumbprint covers are
+    /// ignored.
+    pub fn from_jwk(jwk: &serde_json::Value) -> Result<Self, Error> {
+        let required: &[&'static str] = match jwk.get("kty").and_then(|kty| kty.as_str()) {
+            Some("EC") => &["crv", "kty", "x", "y"],
+            Some("RSA") => &["e", "kty", "n"],
+            Some("OKP") => &["crv", "kty", "x"],
+            _ => return Err(Error::Unsupported("JWK key type")),
+        };
+
+        let mut members = BTreeMap::new();
+        for &name in required {
+            let value = jwk
+                .get(name)
+                .and_then(|value| value.as_str())
+                .ok_or(Error::Str("JWK lacks a required member"))?;
+            members.insert(name, value.to_owned());
+        }
+
+        let thumb = digest(&SHA256, &serde_json::to_vec(&members)?);
+        Ok(Self {
+            thumbprint: BASE64_URL_SAFE_NO_PAD.encode(thumb),
+        })
+    }
+
+    /// The base64url-encoded SHA-256 JWK thumbprint of the account key
+    pub fn thumbprint(&self) -> &str {
+        &self.thumbprint
+    }
+
+    /// The key authorization for the challenge with `token`
+    ///
+    /// Fails if `token` is not base64url-encoded. See [`Account::http01_response_for()`].
+    pub fn key_authorization(&self, token: &str) -> Result<KeyAuthorization, Error> {
+        KeyAuthorization::for_token(token, &self.thumbprint)
+    }
+}
+
+fn deserialize_thumbprint<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let thumbprint = String::deserialize(deserializer)?;
+    ResponderKey::from_thumbprint(thumbprint)
+        .map(|key| key.thumbprint)
+        .map_err(serde::de::Error::custom)
 }
 
 impl fmt::Debug for KeyAuthorization {
@@ -553,6 +2906,15 @@ impl fmt::Debug for KeyAuthorization {
     }
 }
 
+/// Overwrite `data` with zeroes in a way that the compiler does not optimize away
+pub(crate) fn zeroize(data: &mut [u8]) {
+    for byte in data.iter_mut() {
+        // Safety: `byte` is a valid, aligned reference to a `u8`
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
 /// A HMAC key used to link account creation requests to an external account
 ///
 /// See RFC 8555 section 7.3.4 for more information.
@@ -561,6 +2923,15 @@ pub struct ExternalAccountKey {
     key: hmac::Key,
 }
 
+/// Omits the HMAC key
+impl fmt::Debug for ExternalAccountKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExternalAccountKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
 impl ExternalAccountKey {
     /// Create a new external account key
     pub fn new(id: String, key_value: &[u8]) -> Self {
@@ -569,6 +2940,24 @@ impl ExternalAccountKey {
             key: hmac::Key::new(hmac::HMAC_SHA256, key_value),
         }
     }
+
+    /// Create a new external account key from a base64url-encoded HMAC key
+    ///
+    /// Most CAs hand out the key in this encoding, including Google Trust Services (as the
+    /// `b64MacKey` field returned by `gcloud publicca external-account-keys create`) and
+    /// ZeroSSL. Passing the encoded string to [`ExternalAccountKey::new()`] instead results
+    /// in opaque `malformed` or `unauthorized` errors from the server. Keys shorter than
+    /// the 256 bits required for HS256 (RFC 7518, section 3.2) are rejected.
+    pub fn from_base64(id: String, key_value: &str) -> Result<Self, Error> {
+        let key = BASE64_URL_SAFE_NO_PAD.decode(key_value.trim_end_matches('='))?;
+        if key.len() < 32 {
+            return Err(Error::Config(
+                "HS256 external account key must be at least 256 bits",
+            ));
+        }
+
+        Ok(Self::new(id, &key))
+    }
 }
 
 impl Signer for ExternalAccountKey {
@@ -589,14 +2978,162 @@ impl Signer for ExternalAccountKey {
     }
 }
 
-fn nonce_from_response(rsp: &Response<Body>) -> Option<String> {
+/// Percent-encode everything except RFC 3986 unreserved characters
+#[cfg(any(
+    feature = "azure-dns",
+    feature = "cloudflare-dns",
+    feature = "digitalocean-dns",
+    feature = "gcloud-dns",
+    feature = "hetzner-dns",
+    feature = "preflight",
+    feature = "zerossl"
+))]
+pub(crate) fn form_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+/// `body` for logging, with secrets in JSON replaced and other data cut off
+#[cfg(feature = "tracing")]
+fn redacted(body: &[u8]) -> String {
+    /// Private JWK members (RFC 7518 section 6) and other members holding secrets
+    const SECRETS: &[&str] = &[
+        "d",
+        "dp",
+        "dq",
+        "eab_hmac_key",
+        "externalAccountBinding",
+        "k",
+        "key_pkcs8",
+        "oth",
+        "p",
+        "password",
+        "q",
+        "qi",
+        "secret",
+    ];
+
+    fn redact(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(members) => {
+                for (name, value) in members.iter_mut() {
+                    match SECRETS.contains(&name.as_str()) {
+                        true => *value = serde_json::Value::from("<redacted>"),
+                        false => redact(value),
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(redact),
+            _ => {}
+        }
+    }
+
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(_) => {
+            let end = body.len().min(Error::BODY_EXCERPT_LEN);
+            String::from_utf8_lossy(&body[..end]).into_owned()
+        }
+    }
+}
+
+/// Check that `rsp` has a content type RFC 8555 allows for its status
+fn check_content_type<B>(rsp: &Response<B>) -> Result<(), Error> {
+    let status = rsp.status();
+    let content_type = rsp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    let expected: &[&str] = match status.is_client_error() || status.is_server_error() {
+        true => &["application/problem+json"],
+        false => &["application/json", "application/pem-certificate-chain"],
+    };
+    match expected.contains(&media_type.as_str()) {
+        true => Ok(()),
+        false => Err(Error::UnexpectedContentType {
+            status: status.as_u16(),
+            content_type: content_type.to_owned(),
+        }),
+    }
+}
+
+fn nonce_from_response<B>(rsp: &Response<B>) -> Option<String> {
     rsp.headers()
         .get(REPLAY_NONCE)
         .and_then(|hv| String::from_utf8(hv.as_ref().to_vec()).ok())
 }
 
 #[cfg(feature = "hyper-rustls")]
-struct DefaultClient(hyper::Client<hyper_rustls::HttpsConnector<HttpConnector>>);
+struct DefaultClient {
+    inner: hyper::Client<
+        CountingConnector<hyper_rustls::HttpsConnector<HttpConnector<FilteringResolver>>>,
+    >,
+    connections: Arc<AtomicU64>,
+    requests: AtomicU64,
+    /// Limits the requests in flight to [`Http2Settings::max_concurrent_streams()`]
+    streams: Option<Arc<tokio::sync::Semaphore>>,
+}
+
+#[cfg(feature = "hyper-rustls")]
+impl DefaultClient {
+    fn new(http2: Http2Settings, connect: ConnectSettings) -> Self {
+        let mut http = HttpConnector::new_with_resolver(FilteringResolver {
+            inner: GaiResolver::new(),
+            family: connect.family,
+            overrides: Arc::new(connect.overrides),
+        });
+        http.enforce_http(false);
+        if let Some(timeout) = connect.happy_eyeballs_timeout {
+            http.set_happy_eyeballs_timeout(timeout);
+        }
+        http.set_connect_timeout(connect.connect_timeout);
+
+        let connections = Arc::new(AtomicU64::new(0));
+        let connector = CountingConnector {
+            inner: hyper_rustls::HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_only()
+                .enable_http1()
+                .enable_http2()
+                .wrap_connector(http),
+            connections: connections.clone(),
+        };
+
+        let mut builder = hyper::Client::builder();
+        builder
+            .http2_adaptive_window(http2.adaptive_window)
+            .http2_initial_stream_window_size(http2.initial_stream_window_size)
+            .http2_initial_connection_window_size(http2.initial_connection_window_size)
+            .http2_keep_alive_interval(http2.keep_alive_interval);
+        Self {
+            inner: builder.build(connector),
+            connections,
+            requests: AtomicU64::new(0),
+            streams: http2
+                .max_concurrent_streams
+                .map(|max| Arc::new(tokio::sync::Semaphore::new(max))),
+        }
+    }
+}
 
 #[cfg(feature = "hyper-rustls")]
 impl HttpClient for DefaultClient {
@@ -604,23 +3141,105 @@ impl HttpClient for DefaultClient {
         &self,
         req: Request<Body>,
     ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>> {
-        Box::pin(self.0.request(req))
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let streams = match &self.streams {
+            Some(streams) => streams.clone(),
+            None => return Box::pin(self.inner.request(req)),
+        };
+
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            // The semaphore is never closed
+            let _permit = streams.acquire_owned().await;
+            inner.request(req).await
+        })
+    }
+
+    fn stats(&self) -> Option<HttpStats> {
+        Some(HttpStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+        })
     }
 }
 
 #[cfg(feature = "hyper-rustls")]
 impl Default for DefaultClient {
     fn default() -> Self {
-        Self(
-            hyper::Client::builder().build(
-                hyper_rustls::HttpsConnectorBuilder::new()
-                    .with_native_roots()
-                    .https_only()
-                    .enable_http1()
-                    .enable_http2()
-                    .build(),
-            ),
-        )
+        Self::new(Http2Settings::default(), ConnectSettings::default())
+    }
+}
+
+/// Resolves names with the system resolver or [`ConnectSettings::resolve()`] overrides,
+/// keeping only the addresses of the configured [`AddressFamily`]
+#[cfg(feature = "hyper-rustls")]
+#[derive(Clone)]
+struct FilteringResolver {
+    inner: GaiResolver,
+    family: AddressFamily,
+    overrides: Arc<HashMap<String, Vec<IpAddr>>>,
+}
+
+#[cfg(feature = "hyper-rustls")]
+impl Service<Name> for FilteringResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = std::io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let (family, host) = (self.family, name.as_str().to_owned());
+        let filter = move |addresses: Vec<SocketAddr>| {
+            let addresses = addresses
+                .into_iter()
+                .filter(|address| family.allows(&address.ip()))
+                .collect::<Vec<_>>();
+            match addresses.is_empty() {
+                true => Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no {family:?} addresses found for {host}"),
+                )),
+                false => Ok(addresses.into_iter()),
+            }
+        };
+
+        match self.overrides.get(&name.as_str().to_ascii_lowercase()) {
+            Some(addresses) => {
+                let addresses = addresses.iter().map(|&ip| SocketAddr::new(ip, 0)).collect();
+                Box::pin(std::future::ready(filter(addresses)))
+            }
+            None => {
+                let lookup = self.inner.call(name);
+                Box::pin(async move { filter(lookup.await?.collect()) })
+            }
+        }
+    }
+}
+
+/// A connector that counts the connections it opens
+#[cfg(feature = "hyper-rustls")]
+#[derive(Clone)]
+struct CountingConnector<C> {
+    inner: C,
+    connections: Arc<AtomicU64>,
+}
+
+#[cfg(feature = "hyper-rustls")]
+impl<C: Service<Uri>> Service<Uri> for CountingConnector<C> {
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = C::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.inner.call(uri)
     }
 }
 
@@ -631,6 +3250,13 @@ pub trait HttpClient: Send + Sync + 'static {
         &self,
         req: Request<Body>,
     ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>>;
+
+    /// Statistics on the connections used by this client, if it keeps them
+    ///
+    /// The default client used with the `hyper-rustls` feature keeps statistics.
+    fn stats(&self) -> Option<HttpStats> {
+        None
+    }
 }
 
 impl<C> HttpClient for hyper::Client<C>
@@ -645,5 +3271,39 @@ where
     }
 }
 
+impl<T: HttpClient + ?Sized> HttpClient for Arc<T> {
+    fn request(
+        &self,
+        req: Request<Body>,
+    ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>> {
+        (**self).request(req)
+    }
+
+    fn stats(&self) -> Option<HttpStats> {
+        (**self).stats()
+    }
+}
+
+/// Connection statistics for an [`HttpClient`]
+///
+/// Requests beyond the number of connections were sent over reused connections.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct HttpStats {
+    /// The number of requests sent
+    pub requests: u64,
+    /// The number of connections opened
+    pub connections: u64,
+}
+
+const BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+/// How long to cache the directory before fetching it again
+const DIRECTORY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How often [`Order::finalize()`] submits the CSR when the server reports `orderNotReady`
+const FINALIZE_ATTEMPTS: u32 = 3;
+/// How many recent latencies [`HedgePolicy::adaptive()`] is derived from
+const HEDGE_SAMPLES: usize = 100;
+/// How many latencies must be measured before requests are hedged adaptively
+const HEDGE_MIN_SAMPLES: usize = 20;
 const JOSE_JSON: &str = "application/jose+json";
+const ORDER_NOT_READY: &str = "urn:ietf:params:acme:error:orderNotReady";
 const REPLAY_NONCE: &str = "Replay-Nonce";
@@ -0,0 +1,756 @@
+//! ML-DSA-44 (FIPS 204) signing, for experimental post-quantum account keys
+//!
+//! ring has no ML-DSA implementation, so key generation and signing are implemented here,
+//! together with the SHAKE functions they are built on. Only the operations an ACME client
+//! needs exist: deriving a key pair from its 32-byte seed and signing. The arithmetic is not
+//! constant-time, so this must not be used where an attacker can time signing operations.
+//!
+//! The JOSE identifiers follow draft-ietf-cose-dilithium: the `ML-DSA-44` algorithm and the
+//! `AKP` key type, with the public key in the `pub` member.
+
+use std::fmt;
+
+use ring::rand::SecureRandom;
+
+use crate::Error;
+
+/// An ML-DSA-44 key pair, expanded from its seed for signing
+pub(crate) struct KeyPair {
+    public: Vec<u8>,
+    k: [u8; 32],
+    tr: [u8; 64],
+    /// The matrix A, in the NTT domain
+    a: Vec<[Poly; L]>,
+    s1: [Poly; L],
+    s2: [Poly; K],
+    t0: [Poly; K],
+}
+
+impl KeyPair {
+    /// Expand the key pair for `seed` (FIPS 204 algorithm 6)
+    pub(crate) fn from_seed(seed: &[u8; 32]) -> Self {
+        let mut expanded = [0; 128];
+        let mut h = Shake::v256();
+        h.absorb(seed);
+        h.absorb(&[K as u8, L as u8]);
+        h.squeeze(&mut expanded);
+        let (rho, rest) = expanded.split_at(32);
+        let (rho_prime, k) = rest.split_at(64);
+
+        let a = expand_a(rho);
+        let (s1, s2) = expand_s(rho_prime);
+        let mut s1_hat = s1;
+        for poly in s1_hat.iter_mut() {
+            ntt(poly);
+        }
+
+        let mut public = Vec::with_capacity(PUBLIC_KEY_LEN);
+        public.extend_from_slice(rho);
+        let mut t0 = [[0; N]; K];
+        let mut packer = BitPacker::new(&mut public);
+        for (i, row) in a.iter().enumerate() {
+            let mut t = multiply(row, &s1_hat);
+            inverse_ntt(&mut t);
+            for (j, coefficient) in t.iter_mut().enumerate() {
+                let (high, low) = power2_round(add(*coefficient, s2[i][j]));
+                packer.push(high, 10);
+                t0[i][j] = low;
+            }
+        }
+        packer.finish();
+
+        let mut tr = [0; 64];
+        let mut h = Shake::v256();
+        h.absorb(&public);
+        h.squeeze(&mut tr);
+
+        let mut key = Self {
+            public,
+            k: [0; 32],
+            tr,
+            a,
+            s1: s1_hat,
+            s2,
+            t0,
+        };
+        key.k.copy_from_slice(k);
+        for poly in key.s2.iter_mut().chain(key.t0.iter_mut()) {
+            ntt(poly);
+        }
+        expanded.fill(0);
+        key
+    }
+
+    /// The encoded public key
+    pub(crate) fn public_key(&self) -> &[u8] {
+        &self.public
+    }
+
+    /// Sign `message` with an empty context, hedged with randomness from `rng`
+    pub(crate) fn sign(&self, rng: &dyn SecureRandom, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut rnd = [0; 32];
+        rng.fill(&mut rnd)?;
+        let signature = self.sign_with(&rnd, message);
+        rnd.fill(0);
+        Ok(signature)
+    }
+
+    /// Sign `message` with an empty context (FIPS 204 algorithms 2 and 7)
+    fn sign_with(&self, rnd: &[u8; 32], message: &[u8]) -> Vec<u8> {
+        let mut mu = [0; 64];
+        let mut h = Shake::v256();
+        h.absorb(&self.tr);
+        // The domain separator of pure ML-DSA and the length of the empty context
+        h.absorb(&[0, 0]);
+        h.absorb(message);
+        h.squeeze(&mut mu);
+
+        let mut rho = [0; 64];
+        let mut h = Shake::v256();
+        h.absorb(&self.k);
+        h.absorb(rnd);
+        h.absorb(&mu);
+        h.squeeze(&mut rho);
+
+        let mut kappa = 0;
+        loop {
+            let y = expand_mask(&rho, kappa);
+            kappa += L as u16;
+            let mut y_hat = y;
+            for poly in y_hat.iter_mut() {
+                ntt(poly);
+            }
+
+            let mut w = [[0; N]; K];
+            let mut w1 = Vec::with_capacity(K * N * 6 / 8);
+            let mut packer = BitPacker::new(&mut w1);
+            for (i, row) in self.a.iter().enumerate() {
+                w[i] = multiply(row, &y_hat);
+                inverse_ntt(&mut w[i]);
+                for &coefficient in w[i].iter() {
+                    packer.push(decompose(coefficient).0, 6);
+                }
+            }
+            packer.finish();
+
+            let mut c_tilde = [0; 32];
+            let mut h = Shake::v256();
+            h.absorb(&mu);
+            h.absorb(&w1);
+            h.squeeze(&mut c_tilde);
+            let mut c = sample_in_ball(&c_tilde);
+            ntt(&mut c);
+
+            let mut z = [[0; N]; L];
+            for (i, z) in z.iter_mut().enumerate() {
+                *z = pointwise(&c, &self.s1[i]);
+                inverse_ntt(z);
+                for (coefficient, y) in z.iter_mut().zip(y[i].iter()) {
+                    *coefficient = add(*coefficient, *y);
+                }
+            }
+            if z.iter().any(|poly| norm(poly) >= GAMMA1 - BETA) {
+                continue;
+            }
+
+            let mut hints = Vec::with_capacity(OMEGA);
+            let mut counts = [0; K];
+            let mut valid = true;
+            for i in 0..K {
+                let mut cs2 = pointwise(&c, &self.s2[i]);
+                inverse_ntt(&mut cs2);
+                let mut ct0 = pointwise(&c, &self.t0[i]);
+                inverse_ntt(&mut ct0);
+                if norm(&ct0) >= GAMMA2 {
+                    valid = false;
+                    break;
+                }
+
+                for j in 0..N {
+                    let r = sub(w[i][j], cs2[j]);
+                    let (high, low) = decompose(r);
+                    if centered(low).abs() >= (GAMMA2 - BETA) as i32 {
+                        valid = false;
+                        break;
+                    }
+                    // MakeHint(-ct0, r + ct0): whether adding ct0 changes the high bits
+                    if decompose(add(r, ct0[j])).0 != high {
+                        hints.push(j as u8);
+                    }
+                }
+                counts[i] = hints.len();
+                if !valid || hints.len() > OMEGA {
+                    valid = false;
+                    break;
+                }
+            }
+            if !valid {
+                continue;
+            }
+
+            let mut signature = Vec::with_capacity(SIGNATURE_LEN);
+            signature.extend_from_slice(&c_tilde);
+            let mut packer = BitPacker::new(&mut signature);
+            for poly in z.iter() {
+                for &coefficient in poly.iter() {
+                    packer.push(sub(GAMMA1, coefficient), 18);
+                }
+            }
+            packer.finish();
+            hints.resize(OMEGA, 0);
+            signature.extend_from_slice(&hints);
+            signature.extend(counts.iter().map(|&count| count as u8));
+            return signature;
+        }
+    }
+}
+
+impl Drop for KeyPair {
+    fn drop(&mut self) {
+        self.k.fill(0);
+        for poly in self.s1.iter_mut().chain(&mut self.s2).chain(&mut self.t0) {
+            poly.fill(0);
+        }
+    }
+}
+
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPair").finish_non_exhaustive()
+    }
+}
+
+type Poly = [u32; N];
+
+/// Sample the matrix A in the NTT domain from `rho` (FIPS 204 algorithms 30 and 32)
+fn expand_a(rho: &[u8]) -> Vec<[Poly; L]> {
+    (0..K)
+        .map(|r| {
+            let mut row = [[0; N]; L];
+            for (s, poly) in row.iter_mut().enumerate() {
+                let mut g = Shake::v128();
+                g.absorb(rho);
+                g.absorb(&[s as u8, r as u8]);
+                let mut j = 0;
+                while j < N {
+                    let mut bytes = [0; 3];
+                    g.squeeze(&mut bytes);
+                    let z = u32::from_le_bytes([bytes[0], bytes[1], bytes[2] & 0x7f, 0]);
+                    if z < Q {
+                        poly[j] = z;
+                        j += 1;
+                    }
+                }
+            }
+            row
+        })
+        .collect()
+}
+
+/// Sample the secret vectors s1 and s2 from `rho` (FIPS 204 algorithms 31 and 33)
+fn expand_s(rho: &[u8]) -> ([Poly; L], [Poly; K]) {
+    let sample = |index: usize| {
+        let mut poly = [0; N];
+        let mut h = Shake::v256();
+        h.absorb(rho);
+        h.absorb(&(index as u16).to_le_bytes());
+        let mut j = 0;
+        while j < N {
+            let mut byte = [0];
+            h.squeeze(&mut byte);
+            for half in [byte[0] & 0x0f, byte[0] >> 4] {
+                // With eta = 2, half-bytes below 15 map to 2 - (b mod 5)
+                if half < 15 && j < N {
+                    poly[j] = sub(ETA, (half % 5) as u32);
+                    j += 1;
+                }
+            }
+        }
+        poly
+    };
+
+    let mut s1 = [[0; N]; L];
+    for (i, poly) in s1.iter_mut().enumerate() {
+        *poly = sample(i);
+    }
+    let mut s2 = [[0; N]; K];
+    for (i, poly) in s2.iter_mut().enumerate() {
+        *poly = sample(L + i);
+    }
+    (s1, s2)
+}
+
+/// Sample the mask y for attempt `kappa` from `rho` (FIPS 204 algorithm 34)
+fn expand_mask(rho: &[u8; 64], kappa: u16) -> [Poly; L] {
+    let mut y = [[0; N]; L];
+    for (r, poly) in y.iter_mut().enumerate() {
+        let mut bytes = [0; N * 18 / 8];
+        let mut h = Shake::v256();
+        h.absorb(rho);
+        h.absorb(&(kappa + r as u16).to_le_bytes());
+        h.squeeze(&mut bytes);
+        for (coefficient, value) in poly.iter_mut().zip(BitUnpacker::new(&bytes, 18)) {
+            *coefficient = sub(GAMMA1, value);
+        }
+    }
+    y
+}
+
+/// Sample the challenge polynomial with `TAU` coefficients of ±1 (FIPS 204 algorithm 29)
+fn sample_in_ball(seed: &[u8]) -> Poly {
+    let mut h = Shake::v256();
+    h.absorb(seed);
+    let mut signs = [0; 8];
+    h.squeeze(&mut signs);
+    let signs = u64::from_le_bytes(signs);
+
+    let mut c = [0; N];
+    for (bit, i) in (N - TAU..N).enumerate() {
+        let j = loop {
+            let mut byte = [0];
+            h.squeeze(&mut byte);
+            if byte[0] as usize <= i {
+                break byte[0] as usize;
+            }
+        };
+        c[i] = c[j];
+        c[j] = match (signs >> bit) & 1 {
+            0 => 1,
+            _ => Q - 1,
+        };
+    }
+    c
+}
+
+/// Split `r` into `r1 * 2^13 + r0` with r0 centered around zero (FIPS 204 algorithm 35)
+fn power2_round(r: u32) -> (u32, u32) {
+    let low = r & ((1 << D) - 1);
+    match low > 1 << (D - 1) {
+        true => ((r >> D) + 1, sub(low, 1 << D)),
+        false => (r >> D, low),
+    }
+}
+
+/// Split `r` into high bits and low bits around multiples of `2 * GAMMA2` (FIPS 204
+/// algorithm 36), with the low bits reduced modulo q
+fn decompose(r: u32) -> (u32, u32) {
+    let mut low = (r % (2 * GAMMA2)) as i32;
+    if low > GAMMA2 as i32 {
+        low -= 2 * GAMMA2 as i32;
+    }
+
+    let high = (r as i32 - low) as u32;
+    match high == Q - 1 {
+        true => (0, sub(reduce(low), 1)),
+        false => (high / (2 * GAMMA2), reduce(low)),
+    }
+}
+
+/// The largest absolute value of the coefficients of `poly`, centered around zero
+fn norm(poly: &Poly) -> u32 {
+    poly.iter()
+        .map(|&coefficient| centered(coefficient).unsigned_abs())
+        .max()
+        .unwrap_or(0)
+}
+
+fn centered(value: u32) -> i32 {
+    match value > (Q - 1) / 2 {
+        true => value as i32 - Q as i32,
+        false => value as i32,
+    }
+}
+
+/// The inner product of `row` with `vector`, in the NTT domain
+fn multiply(row: &[Poly; L], vector: &[Poly; L]) -> Poly {
+    let mut out = [0; N];
+    for (a, b) in row.iter().zip(vector) {
+        for (coefficient, product) in out.iter_mut().zip(pointwise(a, b)) {
+            *coefficient = add(*coefficient, product);
+        }
+    }
+    out
+}
+
+fn pointwise(a: &Poly, b: &Poly) -> Poly {
+    let mut out = [0; N];
+    for (out, (a, b)) in out.iter_mut().zip(a.iter().zip(b)) {
+        *out = mul(*a, *b);
+    }
+    out
+}
+
+/// The number-theoretic transform (FIPS 204 algorithm 41)
+fn ntt(w: &mut Poly) {
+    let mut m = 0;
+    let mut len = N / 2;
+    while len >= 1 {
+        for start in (0..N).step_by(2 * len) {
+            m += 1;
+            let zeta = ZETAS[m];
+            for j in start..start + len {
+                let t = mul(zeta, w[j + len]);
+                w[j + len] = sub(w[j], t);
+                w[j] = add(w[j], t);
+            }
+        }
+        len /= 2;
+    }
+}
+
+/// The inverse of [`ntt()`] (FIPS 204 algorithm 42)
+fn inverse_ntt(w: &mut Poly) {
+    let mut m = N;
+    let mut len = 1;
+    while len < N {
+        for start in (0..N).step_by(2 * len) {
+            m -= 1;
+            let zeta = Q - ZETAS[m];
+            for j in start..start + len {
+                let t = w[j];
+                w[j] = add(t, w[j + len]);
+                w[j + len] = mul(zeta, sub(t, w[j + len]));
+            }
+        }
+        len *= 2;
+    }
+
+    for coefficient in w.iter_mut() {
+        *coefficient = mul(*coefficient, N_INVERSE);
+    }
+}
+
+fn add(a: u32, b: u32) -> u32 {
+    (a + b) % Q
+}
+
+fn sub(a: u32, b: u32) -> u32 {
+    (a + Q - b) % Q
+}
+
+fn mul(a: u32, b: u32) -> u32 {
+    (a as u64 * b as u64 % Q as u64) as u32
+}
+
+fn reduce(value: i32) -> u32 {
+    value.rem_euclid(Q as i32) as u32
+}
+
+/// Powers of the 512th root of unity 1753 in bit-reversed order
+const ZETAS: [u32; N] = {
+    let mut zetas = [0; N];
+    let mut i = 0;
+    while i < N {
+        let mut exponent = (i as u8).reverse_bits();
+        let (mut power, mut base) = (1u64, 1753u64);
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                power = power * base % Q as u64;
+            }
+            base = base * base % Q as u64;
+            exponent >>= 1;
+        }
+        zetas[i] = power as u32;
+        i += 1;
+    }
+    zetas
+};
+
+/// Packs values of a fixed bit width, least significant bit first
+struct BitPacker<'a> {
+    out: &'a mut Vec<u8>,
+    buffer: u64,
+    bits: u32,
+}
+
+impl<'a> BitPacker<'a> {
+    fn new(out: &'a mut Vec<u8>) -> Self {
+        Self {
+            out,
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    fn push(&mut self, value: u32, width: u32) {
+        self.buffer |= (value as u64) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    /// All widths used here pack a polynomial into whole bytes, so nothing is left over
+    fn finish(self) {
+        debug_assert_eq!(self.bits, 0);
+    }
+}
+
+/// Unpacks values of a fixed bit width, least significant bit first
+struct BitUnpacker<'a> {
+    data: &'a [u8],
+    width: u32,
+    buffer: u64,
+    bits: u32,
+}
+
+impl<'a> BitUnpacker<'a> {
+    fn new(data: &'a [u8], width: u32) -> Self {
+        Self {
+            data,
+            width,
+            buffer: 0,
+            bits: 0,
+        }
+    }
+}
+
+impl Iterator for BitUnpacker<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        while self.bits < self.width {
+            let (&byte, rest) = self.data.split_first()?;
+            self.data = rest;
+            self.buffer |= (byte as u64) << self.bits;
+            self.bits += 8;
+        }
+
+        let value = self.buffer & ((1 << self.width) - 1);
+        self.buffer >>= self.width;
+        self.bits -= self.width;
+        Some(value as u32)
+    }
+}
+
+/// The SHAKE128 and SHAKE256 extendable-output functions (FIPS 202)
+struct Shake {
+    state: [u64; 25],
+    rate: usize,
+    position: usize,
+    squeezing: bool,
+}
+
+impl Shake {
+    fn v128() -> Self {
+        Self::new(168)
+    }
+
+    fn v256() -> Self {
+        Self::new(136)
+    }
+
+    fn new(rate: usize) -> Self {
+        Self {
+            state: [0; 25],
+            rate,
+            position: 0,
+            squeezing: false,
+        }
+    }
+
+    fn absorb(&mut self, data: &[u8]) {
+        debug_assert!(!self.squeezing);
+        for &byte in data {
+            self.xor(self.position, byte);
+            self.position += 1;
+            if self.position == self.rate {
+                keccak_f(&mut self.state);
+                self.position = 0;
+            }
+        }
+    }
+
+    fn squeeze(&mut self, out: &mut [u8]) {
+        if !self.squeezing {
+            self.xor(self.position, 0x1f);
+            self.xor(self.rate - 1, 0x80);
+            keccak_f(&mut self.state);
+            self.position = 0;
+            self.squeezing = true;
+        }
+
+        for byte in out {
+            if self.position == self.rate {
+                keccak_f(&mut self.state);
+                self.position = 0;
+            }
+            *byte = (self.state[self.position / 8] >> (8 * (self.position % 8))) as u8;
+            self.position += 1;
+        }
+    }
+
+    fn xor(&mut self, position: usize, byte: u8) {
+        self.state[position / 8] ^= (byte as u64) << (8 * (position % 8));
+    }
+}
+
+impl Drop for Shake {
+    fn drop(&mut self) {
+        self.state.fill(0);
+    }
+}
+
+/// The Keccak-f[1600] permutation
+fn keccak_f(a: &mut [u64; 25]) {
+    for round_constant in ROUND_CONSTANTS {
+        // θ
+        let mut c = [0; 5];
+        for (x, c) in c.iter_mut().enumerate() {
+            *c = a[x] ^ a[x + 5] ^ a[x + 10] ^ a[x + 15] ^ a[x + 20];
+        }
+        for x in 0..5 {
+            let d = c[(x + 4) % 5] ^ c[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                a[x + 5 * y] ^= d;
+            }
+        }
+
+        // ρ and π
+        let mut current = a[1];
+        for (&position, &rotation) in PI.iter().zip(RHO.iter()) {
+            let next = a[position];
+            a[position] = current.rotate_left(rotation);
+            current = next;
+        }
+
+        // χ
+        for y in 0..5 {
+            let row = [
+                a[5 * y],
+                a[5 * y + 1],
+                a[5 * y + 2],
+                a[5 * y + 3],
+                a[5 * y + 4],
+            ];
+            for x in 0..5 {
+                a[5 * y + x] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+            }
+        }
+
+        // ι
+        a[0] ^= round_constant;
+    }
+}
+
+/// The lanes visited by the combined ρ and π steps, starting from lane 1
+const PI: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+const RHO: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001,
+    0x0000000000008082,
+    0x800000000000808a,
+    0x8000000080008000,
+    0x000000000000808b,
+    0x0000000080000001,
+    0x8000000080008081,
+    0x8000000000008009,
+    0x000000000000008a,
+    0x0000000000000088,
+    0x0000000080008009,
+    0x000000008000000a,
+    0x000000008000808b,
+    0x800000000000008b,
+    0x8000000000008089,
+    0x8000000000008003,
+    0x8000000000008002,
+    0x8000000000000080,
+    0x000000000000800a,
+    0x800000008000000a,
+    0x8000000080008081,
+    0x8000000000008080,
+    0x0000000080000001,
+    0x8000000080008008,
+];
+
+// The ML-DSA-44 parameter set (FIPS 204 section 4)
+const Q: u32 = 8_380_417;
+const N: usize = 256;
+const D: u32 = 13;
+const K: usize = 4;
+const L: usize = 4;
+const ETA: u32 = 2;
+const TAU: usize = 39;
+const BETA: u32 = 78;
+const GAMMA1: u32 = 1 << 17;
+const GAMMA2: u32 = (Q - 1) / 88;
+const OMEGA: usize = 80;
+/// The inverse of 256 modulo q, which scales the result of the inverse NTT
+const N_INVERSE: u32 = 8_347_681;
+
+const PUBLIC_KEY_LEN: usize = 32 + K * N * 10 / 8;
+const SIGNATURE_LEN: usize = 32 + L * N * 18 / 8 + OMEGA + K;
+
+#[cfg(test)]
+mod tests {
+    use ring::digest::{digest, SHA256};
+
+    use super::*;
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn shake_vectors() {
+        // FIPS 202 examples for the empty message
+        let mut out = [0; 32];
+        Shake::v128().squeeze(&mut out);
+        assert_eq!(
+            hex(&out),
+            "7f9c2ba4e88f827d616045507605853ed73b8093f6efbc88eb1a6eacfa66ef26"
+        );
+        Shake::v256().squeeze(&mut out);
+        assert_eq!(
+            hex(&out),
+            "46b9dd2b0ba88d13233b3feb743eeb243fcd52ea62b81b82b50c27646ed5762f"
+        );
+    }
+
+    #[test]
+    fn openssl_vectors() {
+        // Generated with OpenSSL 3.5: `openssl genpkey -algorithm ML-DSA-44 -pkeyopt
+        // hexseed:000102…1f`, and `openssl pkeyutl -sign -rawin -pkeyopt deterministic:1`
+        // over the message `hello`
+        let mut seed = [0; 32];
+        for (i, byte) in seed.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let key = KeyPair::from_seed(&seed);
+        assert_eq!(key.public_key().len(), PUBLIC_KEY_LEN);
+        assert_eq!(
+            hex(&key.public_key()[..16]),
+            "d7b2b47254aae0db45e7930d4a98d2c9"
+        );
+        assert_eq!(
+            hex(digest(&SHA256, key.public_key()).as_ref()),
+            "9f107644c1084526af3bc8098680b05499a2325a644e388fb4f970e058d19d46"
+        );
+
+        let signature = key.sign_with(&[0; 32], b"hello");
+        assert_eq!(signature.len(), SIGNATURE_LEN);
+        assert_eq!(hex(&signature[..16]), "3f3aeaecb654982dab42daef29a7ebb0");
+        assert_eq!(
+            hex(digest(&SHA256, &signature).as_ref()),
+            "4d9736a262364bfe30a6d3d94dc5761f3f60316fafedd7c78156c3641f7a662a"
+        );
+    }
+
+    #[test]
+    fn hedged_signatures() {
+        let key = KeyPair::from_seed(&[7; 32]);
+        let rng = ring::rand::SystemRandom::new();
+        let first = key.sign(&rng, b"payload").unwrap();
+        let second = key.sign(&rng, b"payload").unwrap();
+        assert_eq!(first.len(), SIGNATURE_LEN);
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,1137 @@
+//! An in-memory ACME server for unit tests
+//!
+//! [`MockServer`] implements [`HttpClient`], so it can be passed to any of the `_with_http`
+//! constructors of [`Account`](crate::Account). It implements just enough of RFC 8555 to
+//! exercise account creation, order processing, pre-authorization, certificate download and
+//! revocation without network access: replay nonces are tracked, but JWS signatures are not
+//! verified and challenges are validated as soon as they are marked as ready.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), instant_acme::Error> {
+//! use instant_acme::mock::MockServer;
+//! use instant_acme::{Account, NewAccount};
+//!
+//! let server = MockServer::new();
+//! let account = Account::create_with_http(
+//!     &NewAccount::builder().terms_of_service_agreed(true).build()?,
+//!     MockServer::DIRECTORY_URL,
+//!     None,
+//!     Box::new(server.clone()),
+//! )
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`FaultInjector`] wraps any [`HttpClient`] (including [`MockServer`]) to simulate the
+//! failure modes of real CAs, so that retry and renewal logic can be tested deterministically.
+//! [`ReplayClient`] records exchanges with a real CA and replays them later, for regression
+//! tests that don't need network access. [`MockClock`] replaces real waits in polling,
+//! backoff, rate limiting and timeouts with a clock that the test advances, and
+//! [`SeededRng`] makes account keys and retry jitter reproducible.
+
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, LOCATION, RETRY_AFTER};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use rcgen::{Certificate, CertificateParams, CustomExtension};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::{Clock, CsrRequirements, HttpClient, SystemClock, REPLAY_NONCE};
+
+mod clock;
+pub use clock::MockClock;
+mod rng;
+pub use rng::SeededRng;
+mod replay;
+pub use replay::ReplayClient;
+
+/// An in-memory ACME server
+///
+/// Cloned values share the same server state.
+#[derive(Clone, Default)]
+pub struct MockServer {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockServer {
+    /// The directory URL to pass to [`Account`](crate::Account) constructors
+    pub const DIRECTORY_URL: &'static str = "https://acme.mock.invalid/directory";
+
+    /// Create a new server, which validates all challenges successfully
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail validation for all challenges that are marked as ready from now on
+    pub fn with_invalid_challenges(self) -> Self {
+        self.state.lock().unwrap().config.invalid_challenges = true;
+        self
+    }
+
+    /// Create authorizations in the `valid` state, as if they were reused from earlier orders
+    pub fn with_valid_authorizations(self) -> Self {
+        self.state.lock().unwrap().config.valid_authorizations = true;
+        self
+    }
+
+    /// Reuse valid authorizations for the same identifier in later orders
+    pub fn with_authorization_reuse(self) -> Self {
+        self.state.lock().unwrap().config.authorization_reuse = true;
+        self
+    }
+
+    /// Keep finalized orders in the `processing` state for the given number of polls
+    pub fn with_processing_polls(self, polls: u32) -> Self {
+        self.state.lock().unwrap().config.processing_polls = polls;
+        self
+    }
+
+    /// Let orders expire `lifetime` after they were created
+    ///
+    /// Orders that are not valid or processing by then are reported as `invalid`.
+    pub fn with_order_lifetime(self, lifetime: Duration) -> Self {
+        self.state.lock().unwrap().config.order_lifetime = Some(lifetime);
+        self
+    }
+
+    /// Issue certificates valid for `lifetime` from the time they are downloaded
+    ///
+    /// Without this, certificates are valid from 1975 until 4096.
+    pub fn with_certificate_lifetime(self, lifetime: Duration) -> Self {
+        self.state.lock().unwrap().config.certificate_lifetime = Some(lifetime);
+        self
+    }
+
+    /// Require external account binding for new accounts
+    pub fn with_external_account_required(self) -> Self {
+        self.state.lock().unwrap().config.external_account_required = true;
+        self
+    }
+
+    /// Advertise and honor subdomain authorizations (RFC 9444)
+    ///
+    /// Identifiers ordered with an ancestor domain get an authorization for the ancestor, and
+    /// valid authorizations for an ancestor are reused by later orders for its subdomains.
+    pub fn with_subdomain_authorizations(self) -> Self {
+        self.state.lock().unwrap().config.subdomain_authorizations = true;
+        self
+    }
+
+    /// Advertise `identities` as the `caaIdentities` of the directory metadata
+    ///
+    /// These are the issuer domain names that CAA records must name to allow the CA to issue.
+    pub fn with_caa_identities(self, identities: &[&str]) -> Self {
+        let identities = identities.iter().map(|&id| id.to_owned()).collect();
+        self.state.lock().unwrap().config.caa_identities = identities;
+        self
+    }
+
+    /// Embed two made-up signed certificate timestamps in issued certificates
+    ///
+    /// Their signatures are not valid; this is for testing [`SctPolicy`](crate::SctPolicy).
+    pub fn with_scts(self) -> Self {
+        self.state.lock().unwrap().config.scts = true;
+        self
+    }
+
+    /// State `requirements` for the CSR in a `csr-template` member of orders
+    ///
+    /// The CSRs submitted to finalize orders are not checked against them.
+    pub fn with_csr_requirements(self, requirements: CsrRequirements) -> Self {
+        self.state.lock().unwrap().config.csr_requirements = Some(requirements);
+        self
+    }
+
+    /// Set expiry times and certificate timestamps from `clock` instead of the system clock
+    ///
+    /// Pass the [`MockClock`] of the account to let authorizations and orders expire when it is
+    /// advanced.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        self.state.lock().unwrap().config.clock = Some(clock);
+        self
+    }
+
+    fn handle(&self, method: Method, path: &str, body: &[u8]) -> Response<Body> {
+        let mut state = self.state.lock().unwrap();
+        let (kind, id) = match path.trim_start_matches('/').split_once('/') {
+            Some((kind, id)) => (kind, id.parse::<usize>().ok()),
+            None => (path.trim_start_matches('/'), None),
+        };
+
+        match (method, kind) {
+            (Method::GET, "directory") => {
+                let directory = state.directory();
+                state.json(StatusCode::OK, directory)
+            }
+            (Method::HEAD | Method::GET, "new-nonce") => state.empty(StatusCode::OK),
+            (Method::POST, _) => {
+                let request = match state.verify(body) {
+                    Ok(request) => request,
+                    Err((ty, detail)) => {
+                        return state.problem(StatusCode::BAD_REQUEST, ty, detail);
+                    }
+                };
+
+                match (kind, id) {
+                    ("new-account", _) => state.new_account(&request),
+                    ("new-order", _) => state.new_order(&request),
+                    ("new-authz", _) => state.new_authz(&request),
+                    ("order", Some(id)) => state.order(id),
+                    ("authz", Some(id)) => state.authorization(id),
+                    ("chall", Some(id)) => state.challenge(id, request.payload.as_ref()),
+                    ("finalize", Some(id)) => state.finalize(id),
+                    ("cert", Some(id)) => state.certificate(id),
+                    ("revoke-cert", _) => state.revoke(&request),
+                    _ => state.problem(StatusCode::NOT_FOUND, "malformed", "unknown resource"),
+                }
+            }
+            _ => state.problem(StatusCode::METHOD_NOT_ALLOWED, "malformed", "bad method"),
+        }
+    }
+}
+
+impl HttpClient for MockServer {
+    fn request(
+        &self,
+        req: Request<Body>,
+    ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>> {
+        let server = self.clone();
+        Box::pin(async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_owned();
+            let body = hyper::body::to_bytes(req.into_body()).await?;
+            Ok(server.handle(method, &path, &body))
+        })
+    }
+}
+
+/// Wraps an [`HttpClient`] to inject faults into its responses
+///
+/// Faults are queued with [`FaultInjector::inject()`] or [`FaultInjector::inject_for()`] and
+/// each one applies to exactly one request, in the order they were queued. Requests that
+/// don't match a queued fault are passed through unchanged. Cloned values share the same
+/// queue, so faults can be queued after the client has been handed to an
+/// [`Account`](crate::Account).
+#[derive(Clone)]
+pub struct FaultInjector {
+    inner: Arc<dyn HttpClient>,
+    faults: Arc<Mutex<VecDeque<QueuedFault>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl FaultInjector {
+    /// Wrap `inner`, initially without any faults
+    pub fn new(inner: impl HttpClient) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            faults: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Wait for [`Fault::Delay`] with `clock` (defaults to [`SystemClock`])
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Apply `fault` to the next request
+    pub fn inject(&self, fault: Fault) {
+        self.faults
+            .lock()
+            .unwrap()
+            .push_back(QueuedFault { path: None, fault });
+    }
+
+    /// Apply `fault` to the next request whose URL path contains `path`
+    ///
+    /// For [`MockServer`], resource paths are `new-nonce`, `new-account`, `new-order`,
+    /// `revoke-cert`, `order/`, `authz/`, `chall/`, `finalize/` and `cert/`.
+    pub fn inject_for(&self, path: &str, fault: Fault) {
+        let path = Some(path.to_owned());
+        self.faults
+            .lock()
+            .unwrap()
+            .push_back(QueuedFault { path, fault });
+    }
+
+    /// The number of queued faults that have not been applied yet
+    pub fn pending(&self) -> usize {
+        self.faults.lock().unwrap().len()
+    }
+
+    fn take(&self, path: &str) -> Option<Fault> {
+        let mut faults = self.faults.lock().unwrap();
+        let idx = faults.iter().position(|queued| match &queued.path {
+            Some(filter) => path.contains(filter.as_str()),
+            None => true,
+        })?;
+        faults.remove(idx).map(|queued| queued.fault)
+    }
+}
+
+impl HttpClient for FaultInjector {
+    fn request(
+        &self,
+        req: Request<Body>,
+    ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>> {
+        let fault = match self.take(req.uri().path()) {
+            Some(fault) => fault,
+            None => return self.inner.request(req),
+        };
+
+        let synthetic = |status, ty, detail| {
+            let body = json!({
+                "type": format!("urn:ietf:params:acme:error:{ty}"),
+                "detail": detail,
+                "status": status,
+            });
+            let mut rsp = Response::new(Body::from(body.to_string()));
+            *rsp.status_mut() = StatusCode::from_u16(status).unwrap();
+            rsp.headers_mut()
+                .insert(CONTENT_TYPE, "application/problem+json".parse().unwrap());
+            rsp
+        };
+
+        match fault {
+            Fault::BadNonce => {
+                let rsp = synthetic(400, "badNonce", "injected bad nonce");
+                Box::pin(async move { Ok(rsp) })
+            }
+            Fault::ServiceUnavailable { retry_after } => {
+                let mut rsp = synthetic(503, "serverInternal", "injected service unavailable");
+                rsp.headers_mut()
+                    .insert(RETRY_AFTER, retry_after.as_secs().into());
+                Box::pin(async move { Ok(rsp) })
+            }
+            Fault::RateLimited { retry_after } => {
+                let mut rsp = synthetic(429, "rateLimited", "injected rate limit");
+                rsp.headers_mut()
+                    .insert(RETRY_AFTER, retry_after.as_secs().into());
+                Box::pin(async move { Ok(rsp) })
+            }
+            Fault::HtmlError(status) => {
+                let body = format!("<html><body><h1>{status} Error</h1></body></html>");
+                let mut rsp = Response::new(Body::from(body));
+                *rsp.status_mut() = StatusCode::from_u16(status).unwrap();
+                rsp.headers_mut()
+                    .insert(CONTENT_TYPE, "text/html".parse().unwrap());
+                Box::pin(async move { Ok(rsp) })
+            }
+            Fault::DropNonce => {
+                let fut = self.inner.request(req);
+                Box::pin(async move {
+                    let mut rsp = fut.await?;
+                    rsp.headers_mut().remove(REPLAY_NONCE);
+                    Ok(rsp)
+                })
+            }
+            Fault::TruncateBody(len) => {
+                let fut = self.inner.request(req);
+                Box::pin(async move {
+                    let (mut parts, body) = fut.await?.into_parts();
+                    let mut body = hyper::body::to_bytes(body).await?;
+                    body.truncate(len);
+                    parts.headers.remove(CONTENT_LENGTH);
+                    Ok(Response::from_parts(parts, Body::from(body)))
+                })
+            }
+            Fault::Delay(delay) => {
+                let (inner, sleep) = (self.inner.clone(), self.clock.sleep(delay));
+                Box::pin(async move {
+                    sleep.await;
+                    inner.request(req).await
+                })
+            }
+        }
+    }
+}
+
+struct QueuedFault {
+    path: Option<String>,
+    fault: Fault,
+}
+
+/// A fault to inject with [`FaultInjector`]
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Fault {
+    /// Pass the request through, but remove the `Replay-Nonce` header from the response
+    DropNonce,
+    /// Respond with a `badNonce` error without passing the request through
+    BadNonce,
+    /// Respond with `503 Service Unavailable` and a `Retry-After` header
+    ServiceUnavailable {
+        /// The value of the `Retry-After` header
+        retry_after: Duration,
+    },
+    /// Respond with a `429 Too Many Requests` `rateLimited` error and a `Retry-After` header
+    RateLimited {
+        /// The value of the `Retry-After` header
+        retry_after: Duration,
+    },
+    /// Respond with the given status and an HTML error page, like a proxy or gateway would
+    HtmlError(u16),
+    /// Pass the request through, but truncate the response body to the given length
+    TruncateBody(usize),
+    /// Wait for the given duration before passing the request through
+    Delay(Duration),
+}
+
+#[derive(Default)]
+struct State {
+    config: Config,
+    next_nonce: u64,
+    nonces: HashSet<String>,
+    accounts: usize,
+    orders: Vec<MockOrder>,
+    authorizations: Vec<MockAuthorization>,
+    revoked: HashSet<String>,
+}
+
+impl State {
+    fn now(&self) -> OffsetDateTime {
+        match &self.config.clock {
+            Some(clock) => clock.now_utc(),
+            None => OffsetDateTime::now_utc(),
+        }
+    }
+
+    fn directory(&self) -> Value {
+        json!({
+            "newNonce": url("new-nonce", None),
+            "newAccount": url("new-account", None),
+            "newOrder": url("new-order", None),
+            "revokeCert": url("revoke-cert", None),
+            "newAuthz": url("new-authz", None),
+            "meta": {
+                "externalAccountRequired": self.config.external_account_required,
+                "subdomainAuthAllowed": self.config.subdomain_authorizations,
+                "caaIdentities": self.config.caa_identities,
+            },
+        })
+    }
+
+    /// Decode a JWS request body, returning the problem type and detail if it is rejected
+    fn verify(&mut self, body: &[u8]) -> Result<SignedRequest, (&'static str, &'static str)> {
+        let jws = serde_json::from_slice::<Jws>(body).map_err(|_| ("malformed", "bad JWS"))?;
+        let protected = BASE64_URL_SAFE_NO_PAD
+            .decode(&jws.protected)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Protected>(&data).ok())
+            .ok_or(("malformed", "bad protected header"))?;
+
+        if !self.nonces.remove(&protected.nonce) {
+            return Err(("badNonce", "unknown or reused nonce"));
+        }
+
+        let payload = match jws.payload.is_empty() {
+            true => None,
+            false => BASE64_URL_SAFE_NO_PAD
+                .decode(&jws.payload)
+                .ok()
+                .and_then(|data| serde_json::from_slice(&data).ok()),
+        };
+
+        Ok(SignedRequest { payload })
+    }
+
+    fn new_account(&mut self, request: &SignedRequest) -> Response<Body> {
+        let payload = request.payload.as_ref();
+        let eab = payload.and_then(|p| p.get("externalAccountBinding"));
+        if self.config.external_account_required && eab.is_none() {
+            let detail = "external account binding required";
+            return self.problem(StatusCode::BAD_REQUEST, "externalAccountRequired", detail);
+        }
+
+        self.accounts += 1;
+        let mut rsp = self.json(StatusCode::CREATED, json!({ "status": "valid" }));
+        location(&mut rsp, &url("account", Some(self.accounts)));
+        rsp
+    }
+
+    fn new_order(&mut self, request: &SignedRequest) -> Response<Body> {
+        let identifiers = request
+            .payload
+            .as_ref()
+            .and_then(|p| p.get("identifiers"))
+            .and_then(|ids| ids.as_array())
+            .cloned()
+            .unwrap_or_default();
+        if identifiers.is_empty() {
+            let detail = "order has no identifiers";
+            return self.problem(StatusCode::BAD_REQUEST, "malformed", detail);
+        }
+
+        let status = match self.config.valid_authorizations {
+            true => "valid",
+            false => "pending",
+        };
+
+        let mut identifiers = identifiers;
+        let mut authorizations = Vec::with_capacity(identifiers.len());
+        for identifier in &mut identifiers {
+            let ancestor = identifier
+                .as_object_mut()
+                .and_then(|id| id.remove("ancestorDomain"))
+                .and_then(|ancestor| ancestor.as_str().map(|s| s.to_owned()));
+            if ancestor.is_some() && !self.config.subdomain_authorizations {
+                let detail = "subdomain authorizations are not supported";
+                return self.problem(StatusCode::BAD_REQUEST, "malformed", detail);
+            }
+
+            // Reuse authorizations for an ancestor that are valid, or that were requested by an
+            // earlier identifier of this order
+            let name = identifier["value"].as_str().unwrap_or_default();
+            let name = name.strip_prefix("*.").unwrap_or(name);
+            let reuse = self.config.authorization_reuse;
+            let now = self.now();
+            let existing = (0..self.authorizations.len()).find(|idx| {
+                let authz = &self.authorizations[*idx];
+                let valid = authz.status == "valid" && authz.expires > now;
+                (authz.subdomains
+                    && is_subdomain(name, authz.identifier["value"].as_str().unwrap_or_default())
+                    && (valid || authorizations.contains(idx)))
+                    || (reuse && valid && authz.identifier == *identifier)
+            });
+            if let Some(idx) = existing {
+                if !authorizations.contains(&idx) {
+                    authorizations.push(idx);
+                }
+                continue;
+            }
+
+            let (identifier, subdomains) = match ancestor {
+                Some(ancestor) => (json!({ "type": "dns", "value": ancestor }), true),
+                None => (identifier.clone(), false),
+            };
+            authorizations.push(self.authorizations.len());
+            self.authorizations.push(MockAuthorization {
+                identifier,
+                status,
+                challenge_status: status,
+                token: format!("token-{}", self.authorizations.len()),
+                subdomains,
+                expires: now + AUTHORIZATION_LIFETIME,
+            });
+        }
+
+        let id = self.orders.len();
+        let now = self.now();
+        self.orders.push(MockOrder {
+            identifiers,
+            authorizations,
+            finalized: false,
+            polls_remaining: self.config.processing_polls,
+            expires: self.config.order_lifetime.map(|lifetime| now + lifetime),
+        });
+
+        let mut rsp = self.json(StatusCode::CREATED, self.order_json(id));
+        location(&mut rsp, &url("order", Some(id)));
+        rsp
+    }
+
+    fn order(&mut self, id: usize) -> Response<Body> {
+        if id >= self.orders.len() {
+            return self.problem(StatusCode::NOT_FOUND, "malformed", "no such order");
+        }
+
+        let order = &mut self.orders[id];
+        if order.finalized && order.polls_remaining > 0 {
+            order.polls_remaining -= 1;
+        }
+
+        self.json(StatusCode::OK, self.order_json(id))
+    }
+
+    fn new_authz(&mut self, request: &SignedRequest) -> Response<Body> {
+        let payload = request.payload.as_ref();
+        let identifier = match payload.and_then(|p| p.get("identifier")) {
+            Some(identifier) => identifier.clone(),
+            None => return self.problem(StatusCode::BAD_REQUEST, "malformed", "no identifier"),
+        };
+
+        let subdomains = payload
+            .and_then(|p| p.get("subdomainAuthAllowed"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        if subdomains && !self.config.subdomain_authorizations {
+            let detail = "subdomain authorizations are not supported";
+            return self.problem(StatusCode::BAD_REQUEST, "malformed", detail);
+        }
+
+        let id = self.authorizations.len();
+        self.authorizations.push(MockAuthorization {
+            identifier,
+            status: "pending",
+            challenge_status: "pending",
+            token: format!("token-{id}"),
+            subdomains,
+            expires: self.now() + AUTHORIZATION_LIFETIME,
+        });
+
+        let body = self.authorizations[id].json(id);
+        let mut rsp = self.json(StatusCode::CREATED, body);
+        location(&mut rsp, &url("authz", Some(id)));
+        rsp
+    }
+
+    fn authorization(&mut self, id: usize) -> Response<Body> {
+        match self.authorizations.get(id) {
+            Some(authz) => {
+                let body = authz.json(id);
+                self.json(StatusCode::OK, body)
+            }
+            None => self.problem(StatusCode::NOT_FOUND, "malformed", "no such authorization"),
+        }
+    }
+
+    fn challenge(&mut self, id: usize, payload: Option<&Value>) -> Response<Body> {
+        let n = CHALLENGE_TYPES.len();
+        let invalid = self.config.invalid_challenges;
+        let (authz, ty) = match self.authorizations.get_mut(id / n) {
+            Some(authz) => match authz.challenge_types().get(id % n) {
+                Some(&ty) => (authz, ty),
+                None => {
+                    return self.problem(StatusCode::NOT_FOUND, "malformed", "no such challenge")
+                }
+            },
+            None => return self.problem(StatusCode::NOT_FOUND, "malformed", "no such challenge"),
+        };
+
+        // tkauth-01 challenges are answered with an authority token, and onion-csr-01
+        // challenges with a CSR containing the nonce
+        let ready = match (ty, payload) {
+            ("onion-csr-01", Some(payload)) => payload
+                .get("csr")
+                .and_then(|csr| csr.as_str())
+                .and_then(|csr| BASE64_URL_SAFE_NO_PAD.decode(csr).ok())
+                .map_or(false, |csr| {
+                    let nonce = authz.token.as_bytes();
+                    csr.windows(nonce.len()).any(|window| window == nonce)
+                }),
+            ("tkauth-01", Some(payload)) => {
+                payload.get("atc").and_then(|atc| atc.as_str()).is_some()
+            }
+            (_, payload) => payload.is_some(),
+        };
+        if ready && authz.status == "pending" {
+            let status = match invalid {
+                true => "invalid",
+                false => "valid",
+            };
+            authz.status = status;
+            authz.challenge_status = status;
+        }
+
+        let body = authz.challenge_json(id, ty);
+        self.json(StatusCode::OK, body)
+    }
+
+    fn finalize(&mut self, id: usize) -> Response<Body> {
+        if self.order_status(id) != Some("ready") {
+            let detail = "order is not ready";
+            return self.problem(StatusCode::FORBIDDEN, "orderNotReady", detail);
+        }
+
+        self.orders[id].finalized = true;
+        self.json(StatusCode::OK, self.order_json(id))
+    }
+
+    fn certificate(&mut self, id: usize) -> Response<Body> {
+        if self.order_status(id) != Some("valid") {
+            return self.problem(StatusCode::NOT_FOUND, "malformed", "no such certificate");
+        }
+
+        let names = self.orders[id]
+            .identifiers
+            .iter()
+            .filter(|id| id["type"] == "dns")
+            .filter_map(|id| id.get("value").and_then(|v| v.as_str()))
+            .map(|name| name.to_owned())
+            .collect::<Vec<_>>();
+        let mut params = CertificateParams::new(names);
+        if let Some(lifetime) = self.config.certificate_lifetime {
+            params.not_before = self.now();
+            params.not_after = self.now() + lifetime;
+        }
+        if self.config.scts {
+            params.custom_extensions.push(mock_scts(self.now()));
+        }
+        let pem = Certificate::from_params(params).and_then(|cert| cert.serialize_pem());
+        match pem {
+            Ok(pem) => {
+                let mut rsp = self.empty(StatusCode::OK);
+                *rsp.body_mut() = Body::from(pem);
+                rsp.headers_mut().insert(
+                    CONTENT_TYPE,
+                    "application/pem-certificate-chain".parse().unwrap(),
+                );
+                rsp
+            }
+            Err(_) => {
+                let detail = "failed to generate certificate";
+                self.problem(StatusCode::INTERNAL_SERVER_ERROR, "serverInternal", detail)
+            }
+        }
+    }
+
+    fn revoke(&mut self, request: &SignedRequest) -> Response<Body> {
+        let certificate = request
+            .payload
+            .as_ref()
+            .and_then(|p| p.get("certificate"))
+            .and_then(|cert| cert.as_str());
+        match certificate {
+            Some(cert) if self.revoked.insert(cert.to_owned()) => self.empty(StatusCode::OK),
+            Some(_) => {
+                let detail = "certificate already revoked";
+                self.problem(StatusCode::BAD_REQUEST, "alreadyRevoked", detail)
+            }
+            None => self.problem(StatusCode::BAD_REQUEST, "malformed", "missing certificate"),
+        }
+    }
+
+    fn order_status(&self, id: usize) -> Option<&'static str> {
+        let order = self.orders.get(id)?;
+        let statuses = order
+            .authorizations
+            .iter()
+            .map(|&authz| self.authorizations[authz].status);
+
+        let mut all_valid = true;
+        for status in statuses {
+            match status {
+                "valid" => {}
+                "pending" => all_valid = false,
+                _ => return Some("invalid"),
+            }
+        }
+
+        let expired = matches!(order.expires, Some(expires) if expires <= self.now());
+        Some(match (all_valid, order.finalized, order.polls_remaining) {
+            (_, false, _) if expired => "invalid",
+            (false, _, _) => "pending",
+            (true, false, _) => "ready",
+            (true, true, 0) => "valid",
+            (true, true, _) => "processing",
+        })
+    }
+
+    fn order_json(&self, id: usize) -> Value {
+        let order = &self.orders[id];
+        let status = self.order_status(id).unwrap_or("invalid");
+        let mut body = json!({
+            "status": status,
+            "identifiers": order.identifiers,
+            "authorizations": order
+                .authorizations
+                .iter()
+                .map(|&authz| url("authz", Some(authz)))
+                .collect::<Vec<_>>(),
+            "finalize": url("finalize", Some(id)),
+        });
+
+        if let Some(expires) = order
+            .expires
+            .and_then(|expires| expires.format(&Rfc3339).ok())
+        {
+            body["expires"] = json!(expires);
+        }
+        if let Some(requirements) = &self.config.csr_requirements {
+            body["csr-template"] = json!(requirements);
+        }
+        if status == "valid" {
+            body["certificate"] = json!(url("cert", Some(id)));
+        } else if status == "invalid" {
+            body["error"] = json!({
+                "type": "urn:ietf:params:acme:error:unauthorized",
+                "detail": "authorization failed",
+                "status": 403,
+            });
+        }
+
+        body
+    }
+
+    fn json(&mut self, status: StatusCode, body: Value) -> Response<Body> {
+        let mut rsp = self.empty(status);
+        *rsp.body_mut() = Body::from(body.to_string());
+        rsp.headers_mut()
+            .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        rsp
+    }
+
+    fn problem(&mut self, status: StatusCode, ty: &str, detail: &str) -> Response<Body> {
+        let body = json!({
+            "type": format!("urn:ietf:params:acme:error:{ty}"),
+            "detail": detail,
+            "status": status.as_u16(),
+        });
+
+        let mut rsp = self.json(status, body);
+        rsp.headers_mut()
+            .insert(CONTENT_TYPE, "application/problem+json".parse().unwrap());
+        rsp
+    }
+
+    fn empty(&mut self, status: StatusCode) -> Response<Body> {
+        self.next_nonce += 1;
+        let nonce = format!("mock-nonce-{}", self.next_nonce);
+        self.nonces.insert(nonce.clone());
+
+        let mut rsp = Response::new(Body::empty());
+        *rsp.status_mut() = status;
+        rsp.headers_mut()
+            .insert(REPLAY_NONCE, nonce.parse().unwrap());
+        rsp
+    }
+}
+
+#[derive(Default)]
+struct Config {
+    invalid_challenges: bool,
+    valid_authorizations: bool,
+    authorization_reuse: bool,
+    processing_polls: u32,
+    order_lifetime: Option<Duration>,
+    certificate_lifetime: Option<Duration>,
+    external_account_required: bool,
+    subdomain_authorizations: bool,
+    caa_identities: Vec<String>,
+    scts: bool,
+    csr_requirements: Option<CsrRequirements>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+struct MockOrder {
+    identifiers: Vec<Value>,
+    authorizations: Vec<usize>,
+    finalized: bool,
+    polls_remaining: u32,
+    expires: Option<OffsetDateTime>,
+}
+
+struct MockAuthorization {
+    identifier: Value,
+    status: &'static str,
+    challenge_status: &'static str,
+    token: String,
+    subdomains: bool,
+    expires: OffsetDateTime,
+}
+
+impl MockAuthorization {
+    /// TNAuthList identifiers only get a tkauth-01 challenge, and .onion addresses an
+    /// onion-csr-01 challenge
+    fn challenge_types(&self) -> &'static [&'static str] {
+        let value = self.identifier["value"].as_str().unwrap_or_default();
+        match &self.identifier["type"] {
+            ty if ty == "TNAuthList" => &["tkauth-01"],
+            _ if value.ends_with(".onion") => &["onion-csr-01"],
+            _ => &CHALLENGE_TYPES,
+        }
+    }
+
+    fn json(&self, id: usize) -> Value {
+        let challenges = self
+            .challenge_types()
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| self.challenge_json(id * CHALLENGE_TYPES.len() + i, ty))
+            .collect::<Vec<_>>();
+        let mut body = json!({
+            "identifier": self.identifier,
+            "status": self.status,
+            "challenges": challenges,
+        });
+
+        if let Ok(expires) = self.expires.format(&Rfc3339) {
+            body["expires"] = json!(expires);
+        }
+        if self.subdomains {
+            body["subdomainAuthAllowed"] = json!(true);
+        }
+        body
+    }
+
+    fn challenge_json(&self, id: usize, ty: &str) -> Value {
+        let mut body = json!({
+            "type": ty,
+            "url": url("chall", Some(id)),
+            "token": self.token,
+            "status": self.challenge_status,
+        });
+
+        if ty == "onion-csr-01" {
+            body["nonce"] = json!(BASE64_URL_SAFE_NO_PAD.encode(&self.token));
+        }
+        if ty == "tkauth-01" {
+            body["tkauth-type"] = json!("atc");
+            body["token-authority"] = json!("https://authority.mock.invalid/");
+        }
+
+        if self.challenge_status == "invalid" {
+            body["error"] = json!({
+                "type": "urn:ietf:params:acme:error:incorrectResponse",
+                "detail": "mock server rejected the challenge response",
+                "status": 403,
+            });
+        }
+
+        body
+    }
+}
+
+struct SignedRequest {
+    payload: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Jws {
+    protected: String,
+    payload: String,
+}
+
+#[derive(Deserialize)]
+struct Protected {
+    nonce: String,
+}
+
+const CHALLENGE_TYPES: [&str; 3] = ["http-01", "dns-01", "tls-alpn-01"];
+/// How long authorizations are valid, like with Let's Encrypt
+const AUTHORIZATION_LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+fn url(kind: &str, id: Option<usize>) -> String {
+    match id {
+        Some(id) => format!("https://acme.mock.invalid/{kind}/{id}"),
+        None => format!("https://acme.mock.invalid/{kind}"),
+    }
+}
+
+/// An SCT list extension with two SCTs from made-up logs
+fn mock_scts(now: OffsetDateTime) -> CustomExtension {
+    let mut list = Vec::new();
+    for log in [1u8, 2] {
+        let mut sct = vec![0];
+        sct.extend_from_slice(&[log; 32]);
+        let now = now.unix_timestamp_nanos() / 1_000_000;
+        sct.extend_from_slice(&(now as u64).to_be_bytes());
+        // No extensions, then an ECDSA with SHA-256 signature
+        sct.extend_from_slice(&[0, 0, 4, 3, 0, 8]);
+        sct.extend_from_slice(&[0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01]);
+        list.extend_from_slice(&(sct.len() as u16).to_be_bytes());
+        list.extend_from_slice(&sct);
+    }
+
+    let mut value = (list.len() as u16).to_be_bytes().to_vec();
+    value.extend_from_slice(&list);
+    let mut content = vec![0x04, value.len() as u8];
+    content.extend_from_slice(&value);
+    CustomExtension::from_oid_content(&[1, 3, 6, 1, 4, 1, 11129, 2, 4, 2], content)
+}
+
+/// Whether `name` is a strict subdomain of `ancestor`
+fn is_subdomain(name: &str, ancestor: &str) -> bool {
+    name.strip_suffix(ancestor)
+        .map_or(false, |prefix| prefix.len() > 1 && prefix.ends_with('.'))
+}
+
+fn location(rsp: &mut Response<Body>, url: &str) {
+    rsp.headers_mut().insert(LOCATION, url.parse().unwrap());
+}
+
+/// Helpers for the unit tests of other modules
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::sync::Mutex;
+
+    use rcgen::{Certificate, CertificateParams, DistinguishedName};
+
+    use crate::solver::{ChallengeSolver, SolverChallenge, SolverFuture};
+    use crate::{Account, AccountConfig, ChallengeType, HttpClient, NewAccount};
+
+    /// Create an account on the [`MockServer`](super::MockServer) behind `http`
+    pub(crate) async fn account(http: impl HttpClient, config: AccountConfig) -> Account {
+        let new_account = NewAccount::builder()
+            .terms_of_service_agreed(true)
+            .build()
+            .unwrap();
+        Account::builder()
+            .http(Box::new(http))
+            .config(config)
+            .create(&new_account, super::MockServer::DIRECTORY_URL, None)
+            .await
+            .unwrap()
+    }
+
+    /// A DER-encoded CSR for `names`
+    pub(crate) fn csr(names: &[&str]) -> Vec<u8> {
+        let names = names
+            .iter()
+            .map(|&name| name.to_owned())
+            .collect::<Vec<_>>();
+        let mut params = CertificateParams::new(names);
+        params.distinguished_name = DistinguishedName::new();
+        Certificate::from_params(params)
+            .unwrap()
+            .serialize_request_der()
+            .unwrap()
+    }
+
+    /// An HTTP-01 solver that records the domains it presents and cleans up
+    #[derive(Default)]
+    pub(crate) struct RecordingSolver {
+        pub(crate) presented: Mutex<Vec<String>>,
+        pub(crate) cleaned: Mutex<Vec<String>>,
+    }
+
+    impl ChallengeSolver for RecordingSolver {
+        fn challenge_type(&self) -> ChallengeType {
+            ChallengeType::Http01
+        }
+
+        fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+            let domain = challenge.domain().to_owned();
+            self.presented.lock().unwrap().push(domain);
+            Box::pin(async { Ok(()) })
+        }
+
+        fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+            let domain = challenge.domain().to_owned();
+            self.cleaned.lock().unwrap().push(domain);
+            Box::pin(async { Ok(()) })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::account;
+    use super::*;
+    use crate::clock::Deadline;
+    use crate::{AccountConfig, Error, Identifier, NewOrder, OrderStatus, RetryPolicy};
+
+    #[tokio::test]
+    async fn clock_expiry() {
+        let clock = MockClock::at(OffsetDateTime::from_unix_timestamp(1_800_000_000).unwrap());
+        let server = MockServer::new()
+            .with_order_lifetime(Duration::from_secs(3600))
+            .with_clock(Arc::new(clock.clone()));
+        let account = account(server, AccountConfig::new()).await;
+        let new_order = NewOrder::builder()
+            .identifier(Identifier::Dns("example.com".to_owned()))
+            .build()
+            .unwrap();
+
+        let mut order = account.new_order(&new_order).await.unwrap();
+        let expires = order.state().expires;
+        assert_eq!(expires.unwrap().unix_timestamp(), 1_800_003_600);
+        assert_eq!(order.refresh().await.unwrap().status, OrderStatus::Pending);
+
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(order.refresh().await.unwrap().status, OrderStatus::Invalid);
+    }
+
+    #[tokio::test]
+    async fn injected_faults() {
+        let clock = MockClock::new();
+        let faults = FaultInjector::new(MockServer::new()).clock(Arc::new(clock.clone()));
+        let config = AccountConfig::new()
+            .clock(Arc::new(clock.clone()))
+            .retry_policy(RetryPolicy::new().jitter(false));
+        let account = account(faults.clone(), config).await;
+        let new_order = NewOrder::builder()
+            .identifier(Identifier::Dns("example.com".to_owned()))
+            .build()
+            .unwrap();
+
+        // Faults for other paths wait for a matching request
+        faults.inject_for("finalize/", Fault::BadNonce);
+        let mut order = account.new_order(&new_order).await.unwrap();
+        assert_eq!(faults.pending(), 1);
+
+        // badNonce rejections and missing nonces are recovered from right away
+        faults.inject_for("order/", Fault::DropNonce);
+        faults.inject_for("order/", Fault::BadNonce);
+        let (state, elapsed) = advancing(&clock, order.refresh()).await;
+        assert_eq!(state.unwrap().status, OrderStatus::Pending);
+        assert_eq!(elapsed, Duration::ZERO);
+        assert_eq!(faults.pending(), 2);
+        let (state, elapsed) = advancing(&clock, order.refresh()).await;
+        assert_eq!(state.unwrap().status, OrderStatus::Pending);
+        assert_eq!(elapsed, Duration::ZERO);
+        assert_eq!(faults.pending(), 1);
+
+        // 503 responses are retried after their Retry-After delay
+        let retry_after = Duration::from_secs(30);
+        faults.inject_for("new-order", Fault::ServiceUnavailable { retry_after });
+        let (result, elapsed) = advancing(&clock, account.new_order(&new_order)).await;
+        assert!(result.is_ok());
+        assert_eq!(elapsed, retry_after);
+
+        // Delays are waited out on the injector's clock, up to the request timeout
+        faults.inject(Fault::Delay(Duration::from_secs(10)));
+        let (state, elapsed) = advancing(&clock, order.refresh()).await;
+        assert_eq!(state.unwrap().status, OrderStatus::Pending);
+        assert_eq!(elapsed, Duration::from_secs(10));
+        faults.inject(Fault::Delay(Duration::from_secs(90)));
+        let (state, elapsed) = advancing(&clock, order.refresh()).await;
+        assert!(matches!(state, Err(Error::Timeout("HTTP response"))));
+        assert_eq!(elapsed, Duration::from_secs(30));
+
+        // Other faults are reported to the caller
+        faults.inject_for("new-order", Fault::RateLimited { retry_after });
+        match account.new_order(&new_order).await {
+            Err(Error::Api(problem)) => {
+                assert_eq!(problem.r#type, "urn:ietf:params:acme:error:rateLimited");
+                assert_eq!(problem.meta.unwrap().retry_after, Some(retry_after));
+            }
+            result => panic!("unexpected result: {:?}", result.map(|_| ())),
+        }
+
+        faults.inject_for("order/", Fault::HtmlError(502));
+        match order.refresh().await {
+            Err(Error::UnexpectedResponse { status, body, .. }) => {
+                assert_eq!(status, 502);
+                assert!(body.starts_with("<html>"), "{body}");
+            }
+            result => panic!("unexpected result: {result:?}"),
+        }
+
+        faults.inject_for("order/", Fault::TruncateBody(10));
+        match order.refresh().await {
+            Err(Error::UnexpectedResponse { status, body, .. }) => {
+                assert_eq!(status, 200);
+                assert_eq!(body.len(), 10);
+            }
+            result => panic!("unexpected result: {result:?}"),
+        }
+        assert_eq!(faults.pending(), 1);
+    }
+
+    /// Run `operation` while advancing `clock` a second at a time, returning how long it took
+    async fn advancing<T>(clock: &MockClock, operation: impl Future<Output = T>) -> (T, Duration) {
+        let start = clock.now();
+        let output = Deadline {
+            operation: Box::pin(operation),
+            expired: Box::pin(async {
+                for _ in 0..600 {
+                    tokio::task::yield_now().await;
+                    clock.advance(Duration::from_secs(1));
+                }
+            }),
+        };
+        let output = output.await.expect("operation did not complete in time");
+        (output, clock.now() - start)
+    }
+}
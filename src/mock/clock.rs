@@ -0,0 +1,111 @@
+//! A manually advanced clock
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use time::OffsetDateTime;
+
+use crate::{Clock, SleepFuture};
+
+/// A [`Clock`] that only moves when it is advanced
+///
+/// Pass it to [`AccountConfig::clock()`](crate::AccountConfig::clock()) to test polling,
+/// backoff, rate limiting and timeouts without real waits. Sleeps complete once
+/// [`MockClock::advance()`] has moved the clock past their deadline, so the code under test
+/// is typically driven concurrently with the test's calls to `advance()`, for example with
+/// `tokio::join!`. The wall-clock time returned by [`Clock::now_utc()`] advances along with
+/// it. Cloned values share the same time.
+#[derive(Clone)]
+pub struct MockClock {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    now: Instant,
+    now_utc: OffsetDateTime,
+    /// Tasks with sleeps waiting for the clock to advance
+    waiting: Vec<Waker>,
+}
+
+impl MockClock {
+    /// A clock that starts at the current time
+    pub fn new() -> Self {
+        Self::at(OffsetDateTime::now_utc())
+    }
+
+    /// A clock whose wall-clock time starts at `time`
+    pub fn at(time: OffsetDateTime) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                now: Instant::now(),
+                now_utc: time,
+                waiting: Vec::new(),
+            })),
+        }
+    }
+
+    /// Move the clock forward by `duration`, completing sleeps that have expired
+    pub fn advance(&self, duration: Duration) {
+        let waiting = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.now += duration;
+            inner.now_utc += duration;
+            std::mem::take(&mut inner.waiting)
+        };
+
+        for waker in waiting {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+
+    fn now_utc(&self) -> OffsetDateTime {
+        self.inner.lock().unwrap().now_utc
+    }
+
+    fn sleep(&self, duration: Duration) -> SleepFuture {
+        Box::pin(Sleep {
+            deadline: self.now() + duration,
+            clock: self.inner.clone(),
+        })
+    }
+}
+
+struct Sleep {
+    deadline: Instant,
+    clock: Arc<Mutex<Inner>>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut inner = self.clock.lock().unwrap();
+        if inner.now >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        if !inner
+            .waiting
+            .iter()
+            .any(|waker| waker.will_wake(cx.waker()))
+        {
+            inner.waiting.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
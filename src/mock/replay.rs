@@ -0,0 +1,396 @@
+use std::fs;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use base64::prelude::{Engine, BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Map, Value};
+
+use crate::{Error, HttpClient};
+
+/// Records ACME exchanges to disk and replays them in tests
+///
+/// In recording mode, requests are passed through to another [`HttpClient`] (typically one
+/// talking to a staging CA) and every exchange is kept in memory until it is written out with
+/// [`ReplayClient::save()`]. Request bodies are stored as decoded JWS objects with the account
+/// public key and all signatures scrubbed. Response bodies are stored base64-encoded, so that
+/// binary ones (like DER certificates) are replayed byte for byte.
+///
+/// In replay mode, each request is checked against the next recorded exchange: the method and
+/// URL must match exactly, while the JWS protected header and payload only need to match
+/// structurally (same members with the same JSON types), since nonces, keys and timestamps
+/// differ between runs. On a mismatch, a `500` response with a `malformed` problem document
+/// describing the difference is returned, which surfaces as [`Error::Api`].
+#[derive(Clone)]
+pub struct ReplayClient {
+    inner: Option<Arc<dyn HttpClient>>,
+    state: Arc<Mutex<Recording>>,
+}
+
+impl ReplayClient {
+    /// Record the exchanges made through `inner`
+    pub fn record(inner: impl HttpClient) -> Self {
+        Self {
+            inner: Some(Arc::new(inner)),
+            state: Arc::default(),
+        }
+    }
+
+    /// Replay the exchanges from a recording written by [`ReplayClient::save()`]
+    pub fn replay(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let data = fs::read(path)?;
+        Self::from_json(&data)
+    }
+
+    /// Replay the exchanges from a serialized recording
+    pub fn from_json(data: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            inner: None,
+            state: Arc::new(Mutex::new(serde_json::from_slice(data)?)),
+        })
+    }
+
+    /// Write the recorded exchanges to `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        Ok(fs::write(path, self.to_json()?)?)
+    }
+
+    /// Serialize the recorded exchanges
+    pub fn to_json(&self) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec_pretty(&*self.state.lock().unwrap())?)
+    }
+
+    /// The number of recorded exchanges that have not been replayed yet
+    ///
+    /// Tests typically assert that this is zero at the end, to make sure the code under test
+    /// did not skip any requests. In recording mode, this is always zero.
+    pub fn remaining(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        match self.inner {
+            Some(_) => 0,
+            None => state.exchanges.len() - state.position,
+        }
+    }
+
+    fn replay_one(&self, request: &RecordedRequest) -> Response<Body> {
+        let mut state = self.state.lock().unwrap();
+        let expected = match state.exchanges.get(state.position) {
+            Some(exchange) => exchange,
+            None => return mismatch(format!("unexpected request to {}", request.url)),
+        };
+
+        if expected.request.method != request.method || expected.request.url != request.url {
+            return mismatch(format!(
+                "expected {} {}, got {} {}",
+                expected.request.method, expected.request.url, request.method, request.url
+            ));
+        }
+
+        for (name, expected, actual) in [
+            (
+                "protected header",
+                &expected.request.protected,
+                &request.protected,
+            ),
+            ("payload", &expected.request.payload, &request.payload),
+        ] {
+            if shape(expected) != shape(actual) {
+                return mismatch(format!(
+                    "{name} for {} does not match recording: expected {expected}, got {actual}",
+                    request.url
+                ));
+            }
+        }
+
+        let response = expected.response.to_response();
+        state.position += 1;
+        response
+    }
+}
+
+impl HttpClient for ReplayClient {
+    fn request(
+        &self,
+        req: Request<Body>,
+    ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>> {
+        let this = self.clone();
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let recorded = RecordedRequest::new(&parts, &body);
+
+            let inner = match &this.inner {
+                Some(inner) => inner,
+                None => return Ok(this.replay_one(&recorded)),
+            };
+
+            let rsp = inner
+                .request(Request::from_parts(parts, Body::from(body)))
+                .await?;
+            let (parts, body) = rsp.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let response = RecordedResponse {
+                status: parts.status.as_u16(),
+                headers: RECORDED_HEADERS
+                    .iter()
+                    .filter_map(|&name| {
+                        let value = parts.headers.get(name)?.to_str().ok()?;
+                        Some((name.to_owned(), value.to_owned()))
+                    })
+                    .collect(),
+                body: body.to_vec(),
+            };
+
+            this.state.lock().unwrap().exchanges.push(Exchange {
+                request: recorded,
+                response,
+            });
+            Ok(Response::from_parts(parts, Body::from(body)))
+        })
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Recording {
+    exchanges: Vec<Exchange>,
+    #[serde(skip)]
+    position: usize,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Exchange {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Deserialize, Serialize)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    protected: Value,
+    payload: Value,
+}
+
+impl RecordedRequest {
+    fn new(parts: &hyper::http::request::Parts, body: &[u8]) -> Self {
+        let jws = serde_json::from_slice::<Value>(body).unwrap_or(Value::Null);
+        let decode = |member: &str| {
+            let data = jws.get(member).and_then(Value::as_str)?;
+            match data.is_empty() {
+                true => Some(Value::String(String::new())),
+                false => serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(data).ok()?).ok(),
+            }
+        };
+
+        let mut protected = decode("protected").unwrap_or(Value::Null);
+        let mut payload = decode("payload").unwrap_or(Value::Null);
+        scrub(&mut protected);
+        if let Some(eab) = payload.get_mut("externalAccountBinding") {
+            *eab = json!({
+                "protected": decode_nested(eab, "protected"),
+                "payload": "scrubbed",
+            });
+        }
+
+        Self {
+            method: parts.method.to_string(),
+            url: parts.uri.to_string(),
+            protected,
+            payload,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    #[serde(
+        serialize_with = "serialize_base64",
+        deserialize_with = "deserialize_base64"
+    )]
+    body: Vec<u8>,
+}
+
+impl RecordedResponse {
+    fn to_response(&self) -> Response<Body> {
+        let mut rsp = Response::new(Body::from(self.body.clone()));
+        *rsp.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                rsp.headers_mut().append(name, value);
+            }
+        }
+        rsp
+    }
+}
+
+fn serialize_base64<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64_STANDARD.encode(data))
+}
+
+fn deserialize_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let data = String::deserialize(deserializer)?;
+    BASE64_STANDARD
+        .decode(data)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Remove the account public key from a protected header
+fn scrub(protected: &mut Value) {
+    if let Some(jwk) = protected.get_mut("jwk") {
+        let members = match jwk {
+            Value::Object(members) => members.keys().cloned().collect::<Vec<_>>(),
+            _ => Vec::new(),
+        };
+        *jwk = Value::Object(Map::from_iter(
+            members.into_iter().map(|k| (k, json!("scrubbed"))),
+        ));
+    }
+}
+
+/// Decode and scrub the protected header of a nested (flattened) JWS
+fn decode_nested(jws: &Value, member: &str) -> Value {
+    let mut value = jws
+        .get(member)
+        .and_then(Value::as_str)
+        .and_then(|data| BASE64_URL_SAFE_NO_PAD.decode(data).ok())
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or(Value::Null);
+    scrub(&mut value);
+    value
+}
+
+/// Reduce a JSON value to its structure, replacing scalars by their type
+fn shape(value: &Value) -> Value {
+    match value {
+        Value::Null => json!("null"),
+        Value::Bool(_) => json!("bool"),
+        Value::Number(_) => json!("number"),
+        Value::String(_) => json!("string"),
+        Value::Array(values) => Value::Array(values.iter().map(shape).collect()),
+        Value::Object(members) => Value::Object(
+            members
+                .iter()
+                .map(|(key, value)| (key.clone(), shape(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn mismatch(detail: String) -> Response<Body> {
+    let body = json!({
+        "type": "urn:ietf:params:acme:error:malformed",
+        "detail": format!("replay mismatch: {detail}"),
+        "status": 500,
+    });
+    let mut rsp = Response::new(Body::from(body.to_string()));
+    *rsp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+    rsp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        HeaderValue::from_static("application/problem+json"),
+    );
+    rsp
+}
+
+const RECORDED_HEADERS: [&str; 5] = [
+    "content-type",
+    "link",
+    "location",
+    "replay-nonce",
+    "retry-after",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bulk::{BulkIssuer, IssuanceSpec};
+    use crate::mock::testing::{account, csr, RecordingSolver};
+    use crate::mock::MockServer;
+    use crate::{AccountConfig, Identifier};
+
+    #[tokio::test]
+    async fn recorded_session() {
+        let recorder = ReplayClient::record(MockServer::new());
+        let recorded = issue(recorder.clone()).await.unwrap();
+        let recording = recorder.to_json().unwrap();
+        assert!(!String::from_utf8_lossy(&recording).contains("signature"));
+
+        let replay = ReplayClient::from_json(&recording).unwrap();
+        let replayed = issue(replay.clone()).await.unwrap();
+        assert_eq!(replayed.pem(), recorded.pem());
+        assert_eq!(replay.remaining(), 0);
+
+        // Requests that the recording does not expect get an error response
+        let replay = ReplayClient::from_json(&recording).unwrap();
+        let new_account = crate::NewAccount::builder()
+            .terms_of_service_agreed(false)
+            .only_return_existing(true)
+            .build()
+            .unwrap();
+        let result = crate::Account::builder()
+            .http(Box::new(replay))
+            .create(&new_account, "https://acme.example/other", None)
+            .await;
+        match result {
+            Err(Error::UnexpectedResponse {
+                status: 500, body, ..
+            }) => {
+                assert!(body.contains("replay mismatch"))
+            }
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    #[tokio::test]
+    async fn binary_bodies() {
+        let body = vec![0x30, 0x82, 0xff, 0x00, 0xc3];
+        let recorder = ReplayClient::record(Binary(body.clone()));
+        let request = || {
+            Request::get("https://acme.example/cert/1")
+                .body(Body::empty())
+                .unwrap()
+        };
+        recorder.request(request()).await.unwrap();
+
+        let replay = ReplayClient::from_json(&recorder.to_json().unwrap()).unwrap();
+        let rsp = replay.request(request()).await.unwrap();
+        assert_eq!(rsp.headers()["content-type"], "application/pkix-cert");
+        let replayed = hyper::body::to_bytes(rsp.into_body()).await.unwrap();
+        assert_eq!(replayed, body);
+    }
+
+    async fn issue(http: ReplayClient) -> Result<crate::CertificateChain, Error> {
+        let account = account(http, AccountConfig::new()).await;
+        let issuer = BulkIssuer::new(account, Arc::new(RecordingSolver::default()));
+        let spec = IssuanceSpec::new(
+            vec![Identifier::Dns("example.com".to_owned())],
+            csr(&["example.com"]),
+        );
+        let mut run = issuer.run([spec]);
+        run.next().await.unwrap().result
+    }
+
+    /// Responds to every request with a DER body
+    struct Binary(Vec<u8>);
+
+    impl HttpClient for Binary {
+        fn request(
+            &self,
+            _: Request<Body>,
+        ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>> {
+            let rsp = Response::builder()
+                .header("content-type", "application/pkix-cert")
+                .body(Body::from(self.0.clone()))
+                .unwrap();
+            Box::pin(async move { Ok(rsp) })
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! A deterministic source of randomness
+
+use std::sync::Mutex;
+
+use ring::digest::{Context, SHA256};
+
+use crate::{Error, Rng};
+
+/// An [`Rng`] that produces the same bytes for the same seed
+///
+/// Pass it to [`AccountConfig::rng()`](crate::AccountConfig::rng()) (with the `danger`
+/// feature) to generate the same account keys and retry jitter in every test run. The output
+/// is SHA-256 of the seed and a block counter; it is predictable by design and must never be
+/// used outside of tests.
+pub struct SeededRng {
+    seed: Vec<u8>,
+    state: Mutex<State>,
+}
+
+struct State {
+    counter: u64,
+    block: [u8; 32],
+    used: usize,
+}
+
+impl SeededRng {
+    /// A generator whose output is determined by `seed`
+    pub fn new(seed: impl Into<Vec<u8>>) -> Self {
+        Self {
+            seed: seed.into(),
+            state: Mutex::new(State {
+                counter: 0,
+                block: [0; 32],
+                used: 32,
+            }),
+        }
+    }
+}
+
+impl Rng for SeededRng {
+    fn fill(&self, dest: &mut [u8]) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        for byte in dest {
+            if state.used == state.block.len() {
+                let mut ctx = Context::new(&SHA256);
+                ctx.update(&self.seed);
+                ctx.update(&state.counter.to_be_bytes());
+                state.block.copy_from_slice(ctx.finish().as_ref());
+                state.counter += 1;
+                state.used = 0;
+            }
+
+            *byte = state.block[state.used];
+            state.used += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+//! Accounts whose key never leaves an offline signer
+//!
+//! An [`OfflineAccount`] only knows the account URL and the algorithm of its key. Requests
+//! are built in two phases: [`OfflineAccount::prepare()`] returns a [`PendingRequest`] with
+//! the exact JWS signing input, which is signed elsewhere, like in an air-gapped HSM
+//! ceremony, and [`OfflineAccount::submit()`] sends it with the detached signature.
+
+use std::sync::Arc;
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Response};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "hyper-rustls")]
+use crate::DefaultClient;
+use crate::{
+    der, nonce_from_response, AccountConfig, BodyMode, Client, Directory, Error, Header,
+    HttpClient, KeyOrKeyId, Problem, SigningAlgorithm, JOSE_JSON,
+};
+
+/// An account whose requests are signed outside of this process
+///
+/// The account must already exist, for example created with the same key through another
+/// client. Every request needs its own replay nonce, so signatures can't be reused, and a
+/// request rejected with a `badNonce` problem must be prepared and signed again.
+pub struct OfflineAccount {
+    client: Client,
+    id: String,
+    algorithm: SigningAlgorithm,
+}
+
+impl OfflineAccount {
+    /// An account at `account_url` on the server with the directory at `directory_url`
+    ///
+    /// `algorithm` is the algorithm of the account key, which can't be
+    /// [`SigningAlgorithm::Hs256`].
+    #[cfg(feature = "hyper-rustls")]
+    pub async fn new(
+        directory_url: &str,
+        account_url: String,
+        algorithm: SigningAlgorithm,
+    ) -> Result<Self, Error> {
+        let http = Box::new(DefaultClient::default());
+        Self::new_with_http(directory_url, account_url, algorithm, http).await
+    }
+
+    /// An account at `account_url` on the server with the directory at `directory_url`,
+    /// using the given HTTP client
+    ///
+    /// See [`OfflineAccount::new()`].
+    pub async fn new_with_http(
+        directory_url: &str,
+        account_url: String,
+        algorithm: SigningAlgorithm,
+        http: Box<dyn HttpClient>,
+    ) -> Result<Self, Error> {
+        if matches!(algorithm, SigningAlgorithm::Hs256) {
+            return Err(Error::Config("account keys can't use HS256"));
+        }
+
+        let http = Arc::from(http);
+        Ok(Self {
+            client: Client::new(directory_url, http, AccountConfig::default()).await?,
+            id: account_url,
+            algorithm,
+        })
+    }
+
+    /// Prepare a request of `payload` to `url`, or a POST-as-GET request if it is `None`
+    ///
+    /// Takes a replay nonce from the server, which the signature covers.
+    pub async fn prepare(
+        &self,
+        url: &str,
+        payload: Option<&impl Serialize>,
+    ) -> Result<PendingRequest, Error> {
+        let nonce = self.client.nonce().await?;
+        let header = Header {
+            alg: self.algorithm,
+            key: KeyOrKeyId::KeyId(&self.id),
+            nonce: Some(&nonce),
+            url,
+        };
+
+        let payload = match payload {
+            Some(data) => {
+                BASE64_URL_SAFE_NO_PAD.encode(self.client.config.payload_format.to_vec(data)?)
+            }
+            None => String::new(),
+        };
+        Ok(PendingRequest {
+            url: url.to_owned(),
+            protected: BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+            payload,
+            algorithm: self.algorithm,
+        })
+    }
+
+    /// Send `request` with the `signature` of its [signing input](PendingRequest::signing_input())
+    ///
+    /// ES256 signatures are accepted both in the fixed `r || s` encoding that JWS uses and
+    /// DER-encoded, as most HSMs return them. Error responses from the server are returned
+    /// as [`Error::Api`]; otherwise the response status, headers and body are passed
+    /// through unchanged.
+    pub async fn submit(
+        &self,
+        request: PendingRequest,
+        signature: &[u8],
+    ) -> Result<Response<Bytes>, Error> {
+        let signature = match request.algorithm {
+            SigningAlgorithm::Es256 if signature.len() != 64 => {
+                der::ecdsa_signature_fixed(signature, 32)?
+            }
+            _ => signature.to_vec(),
+        };
+
+        let body = serde_json::json!({
+            "protected": request.protected,
+            "payload": request.payload,
+            "signature": BASE64_URL_SAFE_NO_PAD.encode(signature),
+        });
+        let http_request = Request::builder()
+            .method(Method::POST)
+            .uri(&request.url)
+            .header(CONTENT_TYPE, JOSE_JSON)
+            .body(Body::from(serde_json::to_vec(&body)?))
+            .map_err(|_| Error::Config("invalid request URL"))?;
+
+        let rsp = self.client.send(http_request, BodyMode::Buffered).await?;
+        self.client.recycle(nonce_from_response(&rsp));
+        let mut builder = Response::builder().status(rsp.status());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = rsp.headers().clone();
+        }
+
+        let body = hyper::body::to_bytes(Problem::from_response(rsp).await?).await?;
+        builder.body(body).map_err(|_| Error::Malformed("response"))
+    }
+
+    /// Get the account URL, which identifies the account on the server
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Get the endpoint URLs of the server this account belongs to
+    pub fn directory(&self) -> Arc<Directory> {
+        self.client.directory()
+    }
+}
+
+/// A request from [`OfflineAccount::prepare()`] waiting for its signature
+///
+/// Supports serialization, so that it can be kept while the signature is made.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PendingRequest {
+    url: String,
+    protected: String,
+    payload: String,
+    algorithm: SigningAlgorithm,
+}
+
+impl PendingRequest {
+    /// The data to sign: the base64url-encoded protected header and payload, joined by a dot
+    pub fn signing_input(&self) -> String {
+        format!("{}.{}", self.protected, self.payload)
+    }
+
+    /// The algorithm the signature must be made with
+    pub fn algorithm(&self) -> SigningAlgorithm {
+        self.algorithm
+    }
+
+    /// The URL the request is for
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+
+    use ring::rand::SystemRandom;
+    use ring::signature::{
+        EcdsaKeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1_SIGNING,
+        ECDSA_P256_SHA256_FIXED,
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::mock::MockServer;
+
+    #[tokio::test]
+    async fn detached_signatures() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap();
+
+        let spy = Spy::default();
+        let account_url = "https://acme.mock.invalid/account/1".to_owned();
+        let http = Box::new(spy.clone());
+        let account = OfflineAccount::new_with_http(
+            MockServer::DIRECTORY_URL,
+            account_url,
+            SigningAlgorithm::Es256,
+            http,
+        )
+        .await
+        .unwrap();
+
+        let payload = json!({"identifiers": [{"type": "dns", "value": "example.com"}]});
+        let url = account.directory().new_order.clone();
+        let request = account.prepare(&url, Some(&payload)).await.unwrap();
+        assert_eq!(request.url(), url);
+
+        // The request can be kept while it is signed elsewhere
+        let request = serde_json::to_string(&request).unwrap();
+        let request = serde_json::from_str::<PendingRequest>(&request).unwrap();
+        let input = request.signing_input();
+        let signature = key.sign(&rng, input.as_bytes()).unwrap();
+        let rsp = account
+            .submit(request.clone(), signature.as_ref())
+            .await
+            .unwrap();
+        assert_eq!(rsp.status(), 201);
+        let order = serde_json::from_slice::<Value>(rsp.body()).unwrap();
+        assert_eq!(order["status"], "pending");
+
+        // DER-encoded signatures are sent in the fixed encoding JWS requires
+        let sent = serde_json::from_slice::<Value>(&spy.last()).unwrap();
+        let protected = BASE64_URL_SAFE_NO_PAD
+            .decode(sent["protected"].as_str().unwrap())
+            .unwrap();
+        let header = serde_json::from_slice::<Value>(&protected).unwrap();
+        assert_eq!(header["alg"], "ES256");
+        assert_eq!(header["kid"], account.id());
+        assert_eq!(header["url"], url);
+        let signature = BASE64_URL_SAFE_NO_PAD
+            .decode(sent["signature"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(signature.len(), 64);
+        let verifier = UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, key.public_key());
+        assert!(verifier.verify(input.as_bytes(), &signature).is_ok());
+
+        // Each signature covers its own nonce, so a request can't be sent twice
+        match account.submit(request, &signature).await {
+            Err(Error::Api(problem)) => {
+                assert_eq!(problem.r#type, "urn:ietf:params:acme:error:badNonce");
+            }
+            result => panic!("unexpected result: {result:?}"),
+        }
+
+        let http = Box::new(MockServer::new());
+        let url = "https://acme.mock.invalid/account/2".to_owned();
+        let result = OfflineAccount::new_with_http(
+            MockServer::DIRECTORY_URL,
+            url,
+            SigningAlgorithm::Hs256,
+            http,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    /// Passes requests to a [`MockServer`], keeping the body of the last one
+    #[derive(Clone, Default)]
+    struct Spy {
+        server: MockServer,
+        last: Arc<Mutex<Bytes>>,
+    }
+
+    impl Spy {
+        fn last(&self) -> Bytes {
+            self.last.lock().unwrap().clone()
+        }
+    }
+
+    impl HttpClient for Spy {
+        fn request(
+            &self,
+            req: Request<Body>,
+        ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>> {
+            let spy = self.clone();
+            Box::pin(async move {
+                let (parts, body) = req.into_parts();
+                let body = hyper::body::to_bytes(body).await?;
+                *spy.last.lock().unwrap() = body.clone();
+                let req = Request::from_parts(parts, Body::from(body));
+                spy.server.request(req).await
+            })
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Certificates for Tor `.onion` addresses with `onion-csr-01` challenges (RFC 9799)
+//!
+//! The CA proves that the applicant controls a hidden service by asking for a CSR signed
+//! with the service's Ed25519 identity key, containing a nonce from the CA and one from the
+//! applicant. [`Order::onion_csr()`] builds that CSR and [`Order::submit_onion_csr()`]
+//! sends it.
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use serde::Serialize;
+
+use crate::{der, Challenge, ChallengeType, Error, Identifier, Order};
+
+impl Order {
+    /// Build the CSR that answers an `onion-csr-01` `challenge` for `identifier`
+    ///
+    /// `public_key` is the 32-byte Ed25519 identity key of the hidden service, which must
+    /// match the `.onion` address. `sign` signs the given data with the corresponding
+    /// private key and returns the 64-byte Ed25519 signature; Tor keeps hidden service keys
+    /// in an expanded form that most Ed25519 libraries can't load, so signing is left to
+    /// the caller. The CA's nonce and a random applicant nonce are embedded as the
+    /// `caSigningNonce` and `applicantSigningNonce` attributes.
+    pub fn onion_csr(
+        &self,
+        identifier: &Identifier,
+        challenge: &Challenge,
+        public_key: &[u8],
+        sign: impl FnOnce(&[u8]) -> Result<Vec<u8>, Error>,
+    ) -> Result<Vec<u8>, Error> {
+        if challenge.r#type != ChallengeType::OnionCsr01 {
+            return Err(Error::Unsupported("not an onion-csr-01 challenge"));
+        }
+
+        let name = match identifier {
+            Identifier::Dns(name) => name,
+            _ => return Err(Error::Unsupported("onion-csr-01 requires a DNS identifier")),
+        };
+        if onion_public_key(name).as_deref() != Some(public_key) {
+            return Err(Error::InvalidIdentifier {
+                identifier: name.clone(),
+                reason: "not the .onion address of the public key",
+            });
+        }
+
+        let ca_nonce = match &challenge.nonce {
+            Some(nonce) => BASE64_URL_SAFE_NO_PAD.decode(nonce)?,
+            None => return Err(Error::Str("onion-csr-01 challenge without nonce")),
+        };
+        let mut applicant_nonce = [0; 16];
+        self.account.client.config.rng.fill(&mut applicant_nonce)?;
+        let attributes: [(&[u8], &[u8]); 2] = [
+            (OID_CA_SIGNING_NONCE, &ca_nonce),
+            (OID_APPLICANT_SIGNING_NONCE, &applicant_nonce),
+        ];
+
+        der::csr(
+            &[name],
+            der::CsrKey::Ed25519(public_key),
+            &attributes,
+            false,
+            sign,
+        )
+    }
+
+    /// Complete an `onion-csr-01` challenge with a CSR from [`Order::onion_csr()`]
+    pub async fn submit_onion_csr(
+        &mut self,
+        challenge_url: &str,
+        csr_der: &[u8],
+    ) -> Result<(), Error> {
+        let response = OnionCsrResponse {
+            csr: BASE64_URL_SAFE_NO_PAD.encode(csr_der),
+        };
+        self.respond(challenge_url, &response).await
+    }
+}
+
+/// The Ed25519 public key encoded in a version 3 `.onion` address
+///
+/// The address is the base32 encoding of the key, a two-byte checksum and the version byte.
+/// The checksum is not verified.
+fn onion_public_key(name: &str) -> Option<Vec<u8>> {
+    let name = name.strip_prefix("*.").unwrap_or(name);
+    let address = name.strip_suffix(".onion")?;
+    let address = address.rsplit('.').next()?;
+    if address.len() != 56 {
+        return None;
+    }
+
+    let mut bits = 0u64;
+    let mut count = 0;
+    let mut decoded = Vec::with_capacity(35);
+    for c in address.bytes() {
+        let value = match c.to_ascii_lowercase() {
+            c @ b'a'..=b'z' => c - b'a',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        bits = (bits << 5) | u64::from(value);
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+
+    match decoded.get(34) {
+        Some(3) => Some(decoded[..32].to_vec()),
+        _ => None,
+    }
+}
+
+#[derive(Serialize)]
+struct OnionCsrResponse {
+    csr: String,
+}
+
+/// 2.23.140.41
+const OID_CA_SIGNING_NONCE: &[u8] = &[0x67, 0x81, 0x0c, 0x29];
+/// 2.23.140.42
+const OID_APPLICANT_SIGNING_NONCE: &[u8] = &[0x67, 0x81, 0x0c, 0x2a];
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::time::Duration;
+
+    use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+
+    use super::*;
+    use crate::mock::testing::account;
+    use crate::mock::MockServer;
+    use crate::{AccountConfig, NewOrder, OrderStatus, RetryPolicy};
+
+    /// The version 3 `.onion` address of `public_key`, with a zero checksum
+    fn onion_address(public_key: &[u8]) -> String {
+        let mut data = public_key.to_vec();
+        data.extend([0, 0, 3]);
+        let (mut bits, mut count) = (0u64, 0);
+        let mut address = String::with_capacity(62);
+        for byte in data {
+            bits = (bits << 8) | u64::from(byte);
+            count += 8;
+            while count >= 5 {
+                count -= 5;
+                let value = (bits >> count) as u8 & 0x1f;
+                address.push(match value {
+                    0..=25 => (b'a' + value) as char,
+                    _ => (b'2' + value - 26) as char,
+                });
+            }
+        }
+        address.push_str(".onion");
+        address
+    }
+
+    #[tokio::test]
+    async fn onion_csr_challenges() {
+        let key = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+        let public_key = key.public_key().as_ref();
+        let identifier = Identifier::Dns(onion_address(public_key));
+        assert_eq!(onion_public_key(identifier.value()).unwrap(), public_key);
+
+        let account = account(MockServer::new(), AccountConfig::new()).await;
+        let new_order = NewOrder::builder()
+            .identifier(identifier.clone())
+            .build()
+            .unwrap();
+        let mut order = account.new_order(&new_order).await.unwrap();
+        let mut challenges = order.authorizations().await.unwrap().remove(0).challenges;
+        let mut challenge = challenges.remove(0);
+        assert_eq!(challenge.r#type, ChallengeType::OnionCsr01);
+
+        // The CSR is signed by the caller with the hidden service key
+        let mut signed = Vec::new();
+        let csr = order
+            .onion_csr(&identifier, &challenge, public_key, |data| {
+                signed = data.to_vec();
+                Ok(key.sign(data).as_ref().to_vec())
+            })
+            .unwrap();
+        let signature = key.sign(&signed);
+        let verifier = UnparsedPublicKey::new(&ED25519, public_key);
+        assert!(verifier.verify(&signed, signature.as_ref()).is_ok());
+        assert!(csr.windows(signed.len()).any(|window| window == signed));
+
+        order.submit_onion_csr(&challenge.url, &csr).await.unwrap();
+        let policy = RetryPolicy::new().delay(Duration::from_millis(1));
+        order.wait_validated(policy).await.unwrap();
+        assert_eq!(order.state().status, OrderStatus::Ready);
+
+        // A key for another address, or another type of challenge, is rejected
+        let other = Ed25519KeyPair::from_seed_unchecked(&[8; 32]).unwrap();
+        let result = order.onion_csr(
+            &identifier,
+            &challenge,
+            other.public_key().as_ref(),
+            |_| unreachable!(),
+        );
+        assert!(matches!(result, Err(Error::InvalidIdentifier { .. })));
+        challenge.r#type = ChallengeType::Http01;
+        let result = order.onion_csr(&identifier, &challenge, public_key, |_| unreachable!());
+        assert!(matches!(result, Err(Error::Unsupported(_))));
+    }
+}
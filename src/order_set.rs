@@ -0,0 +1,428 @@
+//! Processing a group of orders concurrently, as one unit
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use crate::bulk::wait_validated;
+use crate::der;
+use crate::renewal::new_key_and_csr;
+use crate::solver::{ChallengeSolver, SolverGuard};
+use crate::store::{issuing_ca, StoredCertificate};
+use crate::{Account, Error, Identifier, NewOrder, Order, RetryPolicy};
+
+/// Processes a group of orders concurrently and cleans up after all of them
+///
+/// [`OrderSet::run()`] creates all orders at once, presents their challenges with the
+/// solver and waits until they are validated. It then hands each ready order to a closure,
+/// which typically finalizes it. The orders are processed within the task that polls the
+/// returned future, like spawning a task per order and joining them, but without outliving
+/// the call.
+///
+/// The challenges are cleaned up once every order is done, whether it succeeded, failed or
+/// was cancelled. With [`OrderSet::fail_fast()`], the first failure cancels the other
+/// orders. If the future is dropped before it completes, the challenges presented so far
+/// are cleaned up in the background, as described for [`SolverGuard`].
+///
+/// [`OrderSet::issue()`] splits an identifier set that does not fit in one order, for
+/// example because it exceeds the CA's limit of identifiers per order, into several orders
+/// and issues them as one set.
+///
+/// Use a [`BulkIssuer`](crate::BulkIssuer) instead to issue many certificates with bounded
+/// concurrency.
+pub struct OrderSet {
+    account: Account,
+    solver: Arc<dyn ChallengeSolver>,
+    wildcard_solver: Option<Arc<dyn ChallengeSolver>>,
+    policy: RetryPolicy,
+    fail_fast: bool,
+}
+
+impl OrderSet {
+    /// Process orders through `account`, completing challenges with `solver`
+    pub fn new(account: Account, solver: Arc<dyn ChallengeSolver>) -> Self {
+        Self {
+            account,
+            solver,
+            wildcard_solver: None,
+            policy: RetryPolicy::default(),
+            fail_fast: false,
+        }
+    }
+
+    /// Complete the challenges of orders with wildcard identifiers with `solver`
+    ///
+    /// Wildcard identifiers can only be validated with DNS-01, so use this to combine them
+    /// with an HTTP-01 solver for the other identifiers. [`OrderSet::issue()`] then puts
+    /// wildcard identifiers in orders of their own.
+    pub fn wildcard_solver(mut self, solver: Arc<dyn ChallengeSolver>) -> Self {
+        self.wildcard_solver = Some(solver);
+        self
+    }
+
+    /// Set how challenge validation is polled
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Cancel the remaining orders as soon as one of them fails (defaults to `false`)
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Issue certificates for `identifiers`, with at most `max_identifiers` per order
+    ///
+    /// The identifiers are split into orders as by [`OrderSet::partition()`] and processed
+    /// as by [`OrderSet::run()`], so that the challenges of all orders are provisioned in
+    /// the same pass. Each certificate gets a new P-256 key, generated with the account's
+    /// [`Rng`](crate::Rng), unless the order's [`CsrRequirements`](crate::CsrRequirements)
+    /// call for another type. Only DNS identifiers are supported.
+    pub async fn issue(
+        &self,
+        identifiers: &[Identifier],
+        max_identifiers: usize,
+    ) -> Result<OrderSetOutcome<StoredCertificate>, Error> {
+        let orders = self.partition(identifiers, max_identifiers)?;
+        let policy = self.policy;
+        let rng = &*self.account.inner.client.config.rng;
+        let outcome = self
+            .run(&orders, |new_order, mut order| async move {
+                let requirements = order.state().csr_requirements();
+                let (csr, pkcs8) =
+                    new_key_and_csr(new_order.identifiers(), requirements.as_ref(), rng)?;
+                Ok(StoredCertificate {
+                    identifiers: new_order.identifiers().to_vec(),
+                    chain: order.finalize_and_collect(&csr, policy).await?,
+                    private_key_pem: der::pem_encode("PRIVATE KEY", &pkcs8),
+                    ca: issuing_ca(&self.account),
+                })
+            })
+            .await;
+        Ok(outcome)
+    }
+
+    /// Split `identifiers` into orders of at most `max_identifiers` identifiers
+    ///
+    /// Duplicate identifiers are dropped. If a [`OrderSet::wildcard_solver()`] is set,
+    /// wildcard identifiers are put in separate orders from the other identifiers, so that
+    /// each order can be completed with a single solver. With a DNS-01 solver for all of
+    /// them, a wildcard and its base domain can share an order; the solver then has to
+    /// provision two TXT records with the same name.
+    pub fn partition(
+        &self,
+        identifiers: &[Identifier],
+        max_identifiers: usize,
+    ) -> Result<Vec<NewOrder>, Error> {
+        let mut seen = HashSet::with_capacity(identifiers.len());
+        let (mut wildcards, mut others) = (Vec::new(), Vec::new());
+        for identifier in identifiers {
+            if !seen.insert(identifier.canonical()) {
+                continue;
+            }
+
+            match self.wildcard_solver.is_some() && is_wildcard(identifier) {
+                true => wildcards.push(identifier.clone()),
+                false => others.push(identifier.clone()),
+            }
+        }
+
+        let mut orders = Vec::new();
+        for group in [others, wildcards] {
+            if !group.is_empty() {
+                orders.extend(
+                    NewOrder::builder()
+                        .identifiers(group)
+                        .build_split(max_identifiers)?,
+                );
+            }
+        }
+        match orders.is_empty() {
+            true => Err(Error::Config("order must contain at least one identifier")),
+            false => Ok(orders),
+        }
+    }
+
+    /// Create an order for each of `orders` and pass each once it is ready to `ready`
+    ///
+    /// `ready` is called with the [`NewOrder`] the order was created from. Returns once all
+    /// orders are done and their challenges have been cleaned up, with a result for each
+    /// order in the order they were given.
+    pub async fn run<'a, T, F, Fut>(&self, orders: &'a [NewOrder], ready: F) -> OrderSetOutcome<T>
+    where
+        F: Fn(&'a NewOrder, Order) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let guards = Mutex::new(Vec::with_capacity(orders.len()));
+        let scope = Scope {
+            tasks: orders
+                .iter()
+                .map(|order| Some(Box::pin(self.process(order, &guards, &ready))))
+                .collect(),
+            results: orders.iter().map(|_| None).collect(),
+            fail_fast: self.fail_fast,
+        };
+        let results = scope.await;
+
+        let mut cleanup = Ok(());
+        let guards = guards.into_inner().unwrap_or_else(|e| e.into_inner());
+        for guard in guards {
+            if let (Err(e), Ok(())) = (guard.cleanup().await, &cleanup) {
+                cleanup = Err(e);
+            }
+        }
+
+        OrderSetOutcome { results, cleanup }
+    }
+
+    async fn process<'a, T, Fut>(
+        &self,
+        new_order: &'a NewOrder,
+        guards: &Mutex<Vec<SolverGuard>>,
+        ready: &impl Fn(&'a NewOrder, Order) -> Fut,
+    ) -> Result<T, Error>
+    where
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let solver = match &self.wildcard_solver {
+            Some(solver) if new_order.identifiers().iter().any(is_wildcard) => solver,
+            _ => &self.solver,
+        };
+        let mut order = self.account.new_order(new_order).await?;
+        let guard = order.present_challenges(solver.clone()).await?;
+        guards.lock().unwrap().push(guard);
+        wait_validated(&mut order, &self.account.inner.client.config, self.policy).await?;
+        ready(new_order, order).await
+    }
+}
+
+/// The outcome of [`OrderSet::run()`]
+#[derive(Debug)]
+pub struct OrderSetOutcome<T> {
+    /// The result for each order, in the order they were given
+    ///
+    /// Orders cancelled because another order failed with [`OrderSet::fail_fast()`] set
+    /// report [`Error::Cancelled`].
+    pub results: Vec<Result<T, Error>>,
+    /// The first error encountered while cleaning up the challenges
+    pub cleanup: Result<(), Error>,
+}
+
+impl<T> OrderSetOutcome<T> {
+    /// The results of all orders, or the first error of any order or of the cleanup
+    pub fn into_result(self) -> Result<Vec<T>, Error> {
+        let results = self.results.into_iter().collect::<Result<Vec<_>, _>>()?;
+        self.cleanup?;
+        Ok(results)
+    }
+}
+
+fn is_wildcard(identifier: &Identifier) -> bool {
+    matches!(identifier, Identifier::Dns(name) if name.starts_with("*."))
+}
+
+/// Resolves with the results of all `tasks` once they have completed or were cancelled
+struct Scope<F: Future> {
+    tasks: Vec<Option<Pin<Box<F>>>>,
+    results: Vec<Option<F::Output>>,
+    fail_fast: bool,
+}
+
+// The tasks are boxed, and the other fields are never pinned
+impl<F: Future> Unpin for Scope<F> {}
+
+impl<T, F: Future<Output = Result<T, Error>>> Future for Scope<F> {
+    type Output = Vec<Result<T, Error>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut failed = false;
+        for (task, result) in this.tasks.iter_mut().zip(&mut this.results) {
+            if let Some(future) = task {
+                if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                    failed |= output.is_err();
+                    *result = Some(output);
+                    *task = None;
+                }
+            }
+        }
+
+        if failed && this.fail_fast {
+            for (task, result) in this.tasks.iter_mut().zip(&mut this.results) {
+                if task.take().is_some() {
+                    *result = Some(Err(Error::Cancelled("another order failed")));
+                }
+            }
+        }
+
+        match this.tasks.iter().all(Option::is_none) {
+            true => Poll::Ready(this.results.drain(..).flatten().collect()),
+            false => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::mock::testing::{account, RecordingSolver};
+    use crate::mock::MockServer;
+    use crate::AccountConfig;
+
+    fn orders(names: &[&str]) -> Vec<NewOrder> {
+        names
+            .iter()
+            .map(|&name| {
+                NewOrder::builder()
+                    .identifier(Identifier::Dns(name.to_owned()))
+                    .build()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    /// Fails the order for `b.example.com`, and waits forever on the others if `wait` is set
+    async fn ready(new_order: &NewOrder, wait: bool) -> Result<String, Error> {
+        let name = new_order.identifiers()[0].value().to_owned();
+        match name.as_str() {
+            "b.example.com" => Err(Error::Config("order failed")),
+            _ if wait => std::future::pending().await,
+            _ => Ok(name),
+        }
+    }
+
+    fn quick() -> RetryPolicy {
+        RetryPolicy::new().delay(Duration::from_millis(1))
+    }
+
+    fn sorted(names: &Mutex<Vec<String>>) -> Vec<String> {
+        let mut names = names.lock().unwrap().clone();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn failures() {
+        let names = ["a.example.com", "b.example.com", "c.example.com"];
+        for fail_fast in [false, true] {
+            let account = account(MockServer::new(), AccountConfig::new()).await;
+            let solver = Arc::new(RecordingSolver::default());
+            let set = OrderSet::new(account, solver.clone())
+                .retry_policy(quick())
+                .fail_fast(fail_fast);
+            let orders = orders(&names);
+            let outcome = set
+                .run(&orders, |new_order, _| ready(new_order, fail_fast))
+                .await;
+
+            // One failure cancels the other orders only with fail_fast
+            assert!(outcome.cleanup.is_ok());
+            let results = outcome.results;
+            assert!(matches!(results[1], Err(Error::Config("order failed"))));
+            for result in [&results[0], &results[2]] {
+                match (fail_fast, result) {
+                    (true, Err(Error::Cancelled(_))) | (false, Ok(_)) => {}
+                    (_, result) => panic!("unexpected result: {result:?}"),
+                }
+            }
+
+            // Every order's challenges were cleaned up before returning
+            assert_eq!(sorted(&solver.presented), names);
+            assert_eq!(sorted(&solver.cleaned), names);
+        }
+    }
+
+    #[tokio::test]
+    async fn split_issuance() {
+        let account = account(MockServer::new(), AccountConfig::new()).await;
+        let solver = Arc::new(RecordingSolver::default());
+        let wildcards = Arc::new(RecordingSolver::default());
+        let set = OrderSet::new(account, solver.clone())
+            .wildcard_solver(wildcards.clone())
+            .retry_policy(quick());
+        let identifiers = [
+            "a.example.com",
+            "*.example.com",
+            "b.example.com",
+            "A.example.com",
+            "c.example.com",
+        ]
+        .iter()
+        .map(|&name| Identifier::Dns(name.to_owned()))
+        .collect::<Vec<_>>();
+
+        // Duplicates are dropped, and wildcards get an order of their own
+        let certificates = set.issue(&identifiers, 2).await.unwrap();
+        let certificates = certificates.into_result().unwrap();
+        let issued = certificates
+            .iter()
+            .map(|certificate| {
+                certificate
+                    .identifiers
+                    .iter()
+                    .map(|identifier| identifier.value())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            issued,
+            [
+                vec!["a.example.com", "b.example.com"],
+                vec!["c.example.com"],
+                vec!["*.example.com"],
+            ]
+        );
+
+        // Each certificate covers the identifiers of its order, with a key of its own
+        for certificate in &certificates {
+            let names = certificate.chain.dns_names().unwrap();
+            let expected = certificate.identifiers.iter().map(|id| id.value());
+            assert!(expected.eq(names.iter().map(String::as_str)), "{names:?}");
+        }
+        assert_ne!(
+            certificates[0].private_key_pem,
+            certificates[1].private_key_pem
+        );
+
+        let names = ["a.example.com", "b.example.com", "c.example.com"];
+        assert_eq!(sorted(&solver.presented), names);
+        assert_eq!(sorted(&solver.cleaned), names);
+        assert_eq!(sorted(&wildcards.cleaned), sorted(&wildcards.presented));
+        assert_eq!(wildcards.presented.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn cancellation() {
+        let account = account(MockServer::new(), AccountConfig::new()).await;
+        let solver = Arc::new(RecordingSolver::default());
+        let set = OrderSet::new(account, solver.clone()).retry_policy(quick());
+        let orders = orders(&["a.example.com", "c.example.com"]);
+
+        // Drop the set once both orders wait in the closure
+        let run = set.run(&orders, |new_order, _| ready(new_order, true));
+        let dropped = crate::clock::Deadline {
+            operation: Box::pin(run),
+            expired: Box::pin(async {
+                while solver.presented.lock().unwrap().len() < 2 {
+                    tokio::task::yield_now().await;
+                }
+                tokio::task::yield_now().await;
+            }),
+        };
+        assert!(dropped.await.is_none());
+
+        // The challenges are cleaned up in the background
+        for _ in 0..100 {
+            if solver.cleaned.lock().unwrap().len() == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(sorted(&solver.cleaned), ["a.example.com", "c.example.com"]);
+    }
+}
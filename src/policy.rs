@@ -0,0 +1,294 @@
+//! Central rules for which certificates an account may obtain
+//!
+//! Set a [`Policy`] with [`AccountConfig::policy()`](crate::AccountConfig::policy()) to have
+//! it consulted before every order is created, for every challenge that is selected, before
+//! every order is finalized and after every certificate is downloaded. [`PolicyRules`]
+//! covers common rules and combines them with other policies:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use instant_acme::policy::PolicyRules;
+//! use instant_acme::{AccountConfig, ChallengeType, KeyType};
+//!
+//! let rules = PolicyRules::new()
+//!     .challenge_types("*.corp.example", [ChallengeType::Dns01])
+//!     .deny_key_type(KeyType::Rsa);
+//! let config = AccountConfig::default().policy(Arc::new(rules));
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{
+    der, CertificatePolicy, ChallengeType, Error, Identifier, KeyType, NewOrder, OrderState,
+};
+
+/// Decides whether an account may take a step towards a certificate
+///
+/// All methods allow everything by default. Denials are returned as errors from the
+/// operation that consulted the policy, preferably as [`Error::PolicyDenied`].
+pub trait Policy: Send + Sync + 'static {
+    /// Check `order` before it is created by
+    /// [`Account::new_order()`](crate::Account::new_order())
+    fn check_order(&self, order: &NewOrder) -> Result<(), Error> {
+        let _ = order;
+        Ok(())
+    }
+
+    /// Whether a challenge of type `challenge` may be used for `identifier`
+    ///
+    /// Consulted by [`Order::select_challenges()`](crate::Order::select_challenges()) and
+    /// [`Order::present_challenges()`](crate::Order::present_challenges()), which treat
+    /// challenges that are not allowed as if the server had not offered them.
+    fn allow_challenge(&self, identifier: &Identifier, challenge: ChallengeType) -> bool {
+        let _ = (identifier, challenge);
+        true
+    }
+
+    /// Check the DER-encoded `csr` before it is submitted to finalize `order`
+    fn check_finalize(&self, order: &OrderState, csr: &[u8]) -> Result<(), Error> {
+        let _ = (order, csr);
+        Ok(())
+    }
+
+    /// Check the DER-encoded end-entity `certificate` downloaded for `order`
+    ///
+    /// The check runs after the whole chain has been received, so a writer passed to
+    /// [`Order::write_certificate()`](crate::Order::write_certificate()) has the chain even
+    /// if the certificate is rejected.
+    fn check_certificate(&self, order: &OrderState, certificate: &[u8]) -> Result<(), Error> {
+        let _ = (order, certificate);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for dyn Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Policy").finish_non_exhaustive()
+    }
+}
+
+impl Policy for CertificatePolicy {
+    fn check_certificate(&self, order: &OrderState, certificate: &[u8]) -> Result<(), Error> {
+        let violations = self.check(certificate, order)?;
+        match violations.is_empty() || self.warn_only {
+            true => Ok(()),
+            false => Err(Error::CertificatePolicy(violations)),
+        }
+    }
+}
+
+/// A [`Policy`] built from common rules, combined with other policies
+///
+/// A step is allowed if every rule and every policy added with [`PolicyRules::and()`] allows
+/// it. Name patterns are either a DNS name, which only matches that name, or a wildcard like
+/// `*.corp.example`, which matches `corp.example` itself and every name below it, including
+/// wildcard names.
+#[derive(Debug, Default)]
+pub struct PolicyRules {
+    allowed_identifiers: Vec<String>,
+    challenge_types: Vec<(String, Vec<ChallengeType>)>,
+    denied_key_types: Vec<KeyType>,
+    policies: Vec<Arc<dyn Policy>>,
+}
+
+impl PolicyRules {
+    /// Rules that allow everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only allow orders for DNS names that match one of the added patterns
+    ///
+    /// Without patterns, orders for any identifier are allowed. Once a pattern is added,
+    /// identifiers other than DNS names are denied.
+    pub fn allow_identifiers(mut self, pattern: impl Into<String>) -> Self {
+        self.allowed_identifiers.push(pattern.into());
+        self
+    }
+
+    /// Only allow challenges of `types` for DNS names that match `pattern`
+    ///
+    /// If several patterns match a name, the challenge type must be allowed by all of them.
+    pub fn challenge_types(
+        mut self,
+        pattern: impl Into<String>,
+        types: impl IntoIterator<Item = ChallengeType>,
+    ) -> Self {
+        self.challenge_types
+            .push((pattern.into(), types.into_iter().collect()));
+        self
+    }
+
+    /// Deny certificate signing requests and certificates with keys of `key_type`
+    pub fn deny_key_type(mut self, key_type: KeyType) -> Self {
+        self.denied_key_types.push(key_type);
+        self
+    }
+
+    /// Also require `policy` to allow every step
+    pub fn and(mut self, policy: Arc<dyn Policy>) -> Self {
+        self.policies.push(policy);
+        self
+    }
+
+    fn check_key(&self, key_type: Option<KeyType>) -> Result<(), Error> {
+        match key_type {
+            Some(key_type) if self.denied_key_types.contains(&key_type) => Err(
+                Error::PolicyDenied(format!("{key_type:?} keys are not allowed")),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Policy for PolicyRules {
+    fn check_order(&self, order: &NewOrder) -> Result<(), Error> {
+        if !self.allowed_identifiers.is_empty() {
+            for identifier in order.identifiers() {
+                let allowed = match identifier {
+                    Identifier::Dns(name) => self
+                        .allowed_identifiers
+                        .iter()
+                        .any(|pattern| matches(pattern, name)),
+                    _ => false,
+                };
+                if !allowed {
+                    return Err(Error::PolicyDenied(format!(
+                        "identifier {:?} is not allowed",
+                        identifier.value()
+                    )));
+                }
+            }
+        }
+
+        self.policies
+            .iter()
+            .try_for_each(|policy| policy.check_order(order))
+    }
+
+    fn allow_challenge(&self, identifier: &Identifier, challenge: ChallengeType) -> bool {
+        let allowed = match identifier {
+            Identifier::Dns(name) => self
+                .challenge_types
+                .iter()
+                .filter(|(pattern, _)| matches(pattern, name))
+                .all(|(_, types)| types.contains(&challenge)),
+            _ => true,
+        };
+        allowed
+            && self
+                .policies
+                .iter()
+                .all(|policy| policy.allow_challenge(identifier, challenge))
+    }
+
+    fn check_finalize(&self, order: &OrderState, csr: &[u8]) -> Result<(), Error> {
+        if !self.denied_key_types.is_empty() {
+            self.check_key(der::csr_public_key(csr)?.0)?;
+        }
+        self.policies
+            .iter()
+            .try_for_each(|policy| policy.check_finalize(order, csr))
+    }
+
+    fn check_certificate(&self, order: &OrderState, certificate: &[u8]) -> Result<(), Error> {
+        if !self.denied_key_types.is_empty() {
+            self.check_key(der::public_key(certificate)?.0)?;
+        }
+        self.policies
+            .iter()
+            .try_for_each(|policy| policy.check_certificate(order, certificate))
+    }
+}
+
+/// Whether the DNS `name` matches `pattern`, ignoring case
+pub(crate) fn matches(pattern: &str, name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    match pattern.strip_prefix("*.") {
+        Some(domain) => {
+            let base = name.strip_prefix("*.").unwrap_or(name);
+            let parent = base.len().checked_sub(domain.len() + 1).and_then(|dot| {
+                match base.as_bytes()[dot] {
+                    b'.' => base.get(dot + 1..),
+                    _ => None,
+                }
+            });
+            base.eq_ignore_ascii_case(domain)
+                || parent.map_or(false, |parent| parent.eq_ignore_ascii_case(domain))
+        }
+        None => name.eq_ignore_ascii_case(pattern),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        assert!(matches("*.corp.example", "corp.example"));
+        assert!(matches("*.corp.example", "a.b.CORP.example."));
+        assert!(matches("*.corp.example", "*.corp.example"));
+        assert!(!matches("*.corp.example", "evilcorp.example"));
+        assert!(!matches("*.corp.example", "example"));
+        assert!(matches("www.example.com", "WWW.example.com"));
+        assert!(!matches("www.example.com", "a.www.example.com"));
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn account_policy() {
+        use crate::mock::testing::{account, csr};
+        use crate::mock::MockServer;
+        use crate::{AccountConfig, OrderStatus};
+
+        let rules = Arc::new(
+            PolicyRules::new()
+                .allow_identifiers("*.corp.example")
+                .challenge_types("*.dns.corp.example", [ChallengeType::Dns01])
+                .deny_key_type(KeyType::EcdsaP256),
+        );
+        let new_order = |names: &[&str]| {
+            NewOrder::builder()
+                .identifiers(names.iter().map(|&name| Identifier::Dns(name.to_owned())))
+                .build()
+                .unwrap()
+        };
+
+        // Orders with identifiers outside the allowed patterns are not created
+        let config = AccountConfig::new().policy(rules.clone());
+        let pending = account(MockServer::new(), config).await;
+        let denied = new_order(&["www.corp.example", "www.example.com"]);
+        match pending.new_order(&denied).await {
+            Err(Error::PolicyDenied(reason)) => assert!(reason.contains("www.example.com")),
+            result => panic!("unexpected result: {:?}", result.map(|_| ())),
+        }
+
+        // Challenges that are not allowed are treated as if they were not offered
+        let names = ["www.corp.example", "a.dns.corp.example"];
+        let mut order = pending.new_order(&new_order(&names)).await.unwrap();
+        let selection = order
+            .select_challenges(&[ChallengeType::Http01])
+            .await
+            .unwrap();
+        let selected = selection
+            .selected
+            .iter()
+            .map(|(identifier, _)| identifier.value())
+            .collect::<Vec<_>>();
+        assert_eq!(selected, ["www.corp.example"]);
+        let unsupported = selection.unsupported;
+        assert_eq!(unsupported, [Identifier::Dns(names[1].to_owned())]);
+
+        // CSRs with denied key types are rejected before they are submitted
+        let server = MockServer::new().with_valid_authorizations();
+        let account = account(server, AccountConfig::new().policy(rules)).await;
+        let mut order = account.new_order(&new_order(&names)).await.unwrap();
+        match order.finalize(&csr(&names)).await {
+            Err(Error::PolicyDenied(reason)) => assert!(reason.contains("EcdsaP256")),
+            result => panic!("unexpected result: {result:?}"),
+        }
+        assert_eq!(order.refresh().await.unwrap().status, OrderStatus::Ready);
+    }
+}
@@ -0,0 +1,658 @@
+//! Checks to run before creating an order
+//!
+//! These checks catch configuration problems that would otherwise only surface as a failed
+//! validation, which wastes the CA's failed validation rate limit quota. DNS lookups are done
+//! with [`DohResolver`], using the JSON API for DNS over HTTPS offered by most public
+//! resolvers. [`Perspectives`] checks that challenge responses are visible from several
+//! vantage points, much like the multi-perspective validation done by Let's Encrypt.
+
+use std::time::Duration;
+
+use hyper::header::ACCEPT;
+use hyper::{Body, Request};
+use serde::Deserialize;
+
+use crate::solver::SolverChallenge;
+use crate::{
+    form_encode, Account, ChallengeType, Clock, Error, HttpClient, Identifier, NewOrder,
+    Observation, Observed, PropagationFailure,
+};
+
+/// A DNS resolver using the JSON API for DNS over HTTPS
+///
+/// See <https://developers.google.com/speed/public-dns/docs/doh/json> and
+/// <https://developers.cloudflare.com/1.1.1.1/encryption/dns-over-https/make-api-requests/dns-json/>.
+pub struct DohResolver {
+    url: String,
+    http: Box<dyn HttpClient>,
+}
+
+impl DohResolver {
+    /// Use the resolver with the given JSON API endpoint
+    #[cfg(feature = "hyper-rustls")]
+    pub fn new(url: String) -> Self {
+        Self::with_http(url, Box::<crate::DefaultClient>::default())
+    }
+
+    /// Use Cloudflare's public resolver
+    #[cfg(feature = "hyper-rustls")]
+    pub fn cloudflare() -> Self {
+        Self::new("https://cloudflare-dns.com/dns-query".to_owned())
+    }
+
+    /// Use Google's public resolver
+    #[cfg(feature = "hyper-rustls")]
+    pub fn google() -> Self {
+        Self::new("https://dns.google/resolve".to_owned())
+    }
+
+    /// Use the resolver with the given JSON API endpoint and a custom HTTP client
+    pub fn with_http(url: String, http: Box<dyn HttpClient>) -> Self {
+        Self { url, http }
+    }
+
+    /// Look up the CAA records for `name`
+    ///
+    /// Returns an empty list if the name does not exist or has no CAA records.
+    pub async fn caa(&self, name: &str) -> Result<Vec<CaaRecord>, Error> {
+        self.query(name, CAA)
+            .await?
+            .iter()
+            .map(|data| CaaRecord::parse(data))
+            .collect()
+    }
+
+    /// Look up the TXT records for `name`, with the character strings of each record joined
+    pub async fn txt(&self, name: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .query(name, TXT)
+            .await?
+            .iter()
+            .map(|data| match data.starts_with('"') {
+                // Quoted character strings, as returned by Cloudflare
+                true => data.split('"').skip(1).step_by(2).collect(),
+                false => data.clone(),
+            })
+            .collect())
+    }
+
+    async fn query(&self, name: &str, rtype: u16) -> Result<Vec<String>, Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Response {
+            status: u32,
+            #[serde(default)]
+            answer: Vec<Answer>,
+        }
+
+        #[derive(Deserialize)]
+        struct Answer {
+            r#type: u16,
+            data: String,
+        }
+
+        let separator = match self.url.contains('?') {
+            true => '&',
+            false => '?',
+        };
+        let request = Request::builder()
+            .uri(format!(
+                "{}{separator}name={}&type={rtype}",
+                self.url,
+                form_encode(name)
+            ))
+            .header(ACCEPT, "application/dns-json")
+            .body(Body::empty())
+            .map_err(|_| Error::Config("invalid DNS over HTTPS resolver URL"))?;
+
+        let rsp = self.http.request(request).await?;
+        if !rsp.status().is_success() {
+            return Err(Error::backend_status("DNS over HTTPS", rsp.status()));
+        }
+
+        let body = hyper::body::to_bytes(rsp.into_body()).await?;
+        let rsp = serde_json::from_slice::<Response>(&body)?;
+        match rsp.status {
+            NOERROR | NXDOMAIN => Ok(rsp
+                .answer
+                .into_iter()
+                .filter(|answer| answer.r#type == rtype)
+                .map(|answer| answer.data)
+                .collect()),
+            status => Err(Error::Backend {
+                service: "DNS over HTTPS",
+                reason: format!("DNS response code {status}"),
+            }),
+        }
+    }
+}
+
+/// A CAA record (RFC 8659)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CaaRecord {
+    /// The flags, of which only the issuer critical flag (128) is defined
+    pub flags: u8,
+    /// The property tag, like `issue` or `iodef`
+    pub tag: String,
+    /// The property value
+    pub value: String,
+}
+
+impl CaaRecord {
+    /// Parse the presentation format (`0 issue "letsencrypt.org"`) or the generic
+    /// RFC 3597 format (`\# 22 00 05 69 73 ...`) returned by DNS over HTTPS resolvers
+    fn parse(data: &str) -> Result<Self, Error> {
+        const MALFORMED: Error = Error::Malformed("CAA record");
+
+        if let Some(generic) = data.strip_prefix("\\#") {
+            let bytes = generic
+                .split_whitespace()
+                .skip(1)
+                .collect::<String>()
+                .as_bytes()
+                .chunks(2)
+                .map(|pair| {
+                    let pair = std::str::from_utf8(pair).map_err(|_| MALFORMED)?;
+                    u8::from_str_radix(pair, 16).map_err(|_| MALFORMED)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            return match bytes.as_slice() {
+                [flags, len, rest @ ..] if rest.len() >= *len as usize => {
+                    let (tag, value) = rest.split_at(*len as usize);
+                    Ok(Self {
+                        flags: *flags,
+                        tag: String::from_utf8_lossy(tag).into_owned(),
+                        value: String::from_utf8_lossy(value).into_owned(),
+                    })
+                }
+                _ => Err(MALFORMED),
+            };
+        }
+
+        let mut parts = data.splitn(3, ' ');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(flags), Some(tag), Some(value)) => Ok(Self {
+                flags: flags.parse().map_err(|_| MALFORMED)?,
+                tag: tag.to_owned(),
+                value: value.trim_matches('"').to_owned(),
+            }),
+            _ => Err(MALFORMED),
+        }
+    }
+
+    /// The issuer domain name of an `issue` or `issuewild` property, if any
+    fn issuer(&self) -> Option<&str> {
+        let issuer = self.value.split(';').next()?.trim();
+        match issuer.is_empty() {
+            true => None,
+            false => Some(issuer),
+        }
+    }
+}
+
+impl Account {
+    /// Check that CAA records allow this account's CA to issue for all identifiers in `order`
+    ///
+    /// Looks up the relevant CAA record set for each identifier as described in RFC 8659 and
+    /// compares its `issue` (or, for wildcard names, `issuewild`) properties against the
+    /// `caaIdentities` from the CA's directory metadata. Fails with [`Error::CaaForbidden`]
+    /// for the first identifier the CA may not issue for. If the CA does not advertise any
+    /// CAA identities, the check is skipped.
+    pub async fn check_caa(&self, order: &NewOrder, resolver: &DohResolver) -> Result<(), Error> {
+        let identities = match self.directory_meta() {
+            Some(meta) if !meta.caa_identities.is_empty() => meta.caa_identities,
+            _ => return Ok(()),
+        };
+
+        for identifier in order.identifiers() {
+            let identifier = match identifier {
+                Identifier::Dns(name) => name,
+                _ => continue,
+            };
+            let (wildcard, name) = match identifier.strip_prefix("*.") {
+                Some(name) => (true, name),
+                None => (false, identifier.as_str()),
+            };
+
+            let records = relevant_caa(resolver, name).await?;
+            if !permits(&records, wildcard, &identities) {
+                return Err(Error::CaaForbidden {
+                    identifier: identifier.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that challenge responses can be seen from multiple vantage points
+///
+/// DNS-01 records are looked up through each of the configured [`DohResolver`]s. HTTP-01
+/// responses are fetched directly from this host and through each probe URL, which lets
+/// callers deploy small fetchers in other regions or networks to catch geo-split DNS and
+/// firewall rules before the CA tries to validate. TLS-ALPN-01 challenges are not checked.
+///
+/// [`Order::present_challenges_visible()`](crate::Order::present_challenges_visible()) waits
+/// for responses to become visible from all perspectives before the CA is asked to validate.
+pub struct Perspectives {
+    resolvers: Vec<DohResolver>,
+    probes: Vec<String>,
+    poll_interval: Duration,
+    http: Box<dyn HttpClient>,
+}
+
+impl Perspectives {
+    /// Check DNS-01 records through the given `resolvers`
+    #[cfg(feature = "hyper-rustls")]
+    pub fn new(resolvers: Vec<DohResolver>) -> Self {
+        Self::with_http(
+            resolvers,
+            Box::new(
+                hyper::Client::builder().build(
+                    hyper_rustls::HttpsConnectorBuilder::new()
+                        .with_native_roots()
+                        .https_or_http()
+                        .enable_http1()
+                        .enable_http2()
+                        .build(),
+                ),
+            ),
+        )
+    }
+
+    /// Check DNS-01 records through the given `resolvers`, with a custom HTTP client
+    ///
+    /// The HTTP client is used for HTTP-01 requests, so it must support plain HTTP.
+    pub fn with_http(resolvers: Vec<DohResolver>, http: Box<dyn HttpClient>) -> Self {
+        Self {
+            resolvers,
+            probes: Vec::new(),
+            poll_interval: Duration::from_secs(5),
+            http,
+        }
+    }
+
+    /// Also fetch HTTP-01 responses through the probe at `template`
+    ///
+    /// The template must contain `{url}`, which is replaced with the percent-encoded
+    /// challenge URL (like `https://probe.example.com/fetch?url={url}`). The probe must
+    /// respond with the body it received.
+    pub fn probe(mut self, template: String) -> Self {
+        self.probes.push(template);
+        self
+    }
+
+    /// Set how often responses are looked up again while waiting for them (defaults to 5
+    /// seconds)
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Check that the responses for all `challenges` are visible from every perspective
+    ///
+    /// Fails with [`Error::ChallengeNotVisible`] for the first response that is missing.
+    /// Redirects are not followed.
+    pub async fn check(&self, challenges: &[SolverChallenge]) -> Result<(), Error> {
+        for challenge in challenges {
+            match challenge.r#type {
+                ChallengeType::Dns01 => self.check_dns(challenge).await?,
+                ChallengeType::Http01 => self.check_http(challenge).await?,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn check_dns(&self, challenge: &SolverChallenge) -> Result<(), Error> {
+        let name = challenge.dns_record_name();
+        let value = challenge.key_authorization.dns_value();
+        for resolver in &self.resolvers {
+            if !resolver.txt(&name).await?.contains(&value) {
+                return Err(not_visible(challenge, &resolver.url));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn check_http(&self, challenge: &SolverChallenge) -> Result<(), Error> {
+        match self
+            .observe_http(challenge)
+            .await
+            .into_iter()
+            .find(|o| !o.visible)
+        {
+            Some(observation) => Err(not_visible(challenge, &observation.perspective)),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait until the responses for all `challenges` are visible from every perspective
+    ///
+    /// Fails with [`Error::ChallengeNotPropagated`] for the first response that is still
+    /// missing after `timeout`.
+    pub(crate) async fn wait(
+        &self,
+        challenges: &[SolverChallenge],
+        timeout: Duration,
+        clock: &dyn Clock,
+    ) -> Result<(), Error> {
+        let start = clock.now();
+        let mut pending = challenges.iter().collect::<Vec<_>>();
+        loop {
+            let mut missing = None;
+            let mut still_pending = Vec::with_capacity(pending.len());
+            for challenge in pending {
+                let (name, expected, observations) = match challenge.r#type {
+                    ChallengeType::Dns01 => (
+                        challenge.dns_record_name(),
+                        challenge.key_authorization.dns_value(),
+                        self.observe_dns(challenge).await,
+                    ),
+                    ChallengeType::Http01 => (
+                        http_url(challenge),
+                        challenge.key_authorization.as_str().to_owned(),
+                        self.observe_http(challenge).await,
+                    ),
+                    _ => continue,
+                };
+
+                if !observations.iter().all(|o| o.visible) {
+                    still_pending.push(challenge);
+                    missing.get_or_insert((challenge, name, expected, observations));
+                }
+            }
+
+            let (challenge, name, expected, observations) = match missing {
+                Some(missing) => missing,
+                None => return Ok(()),
+            };
+
+            let waited = clock.now().saturating_duration_since(start);
+            if waited >= timeout {
+                return Err(Error::ChallengeNotPropagated(Box::new(
+                    PropagationFailure {
+                        identifier: challenge.identifier.value().to_owned(),
+                        r#type: challenge.r#type,
+                        name,
+                        expected,
+                        waited,
+                        observations,
+                    },
+                )));
+            }
+
+            clock.sleep(self.poll_interval.min(timeout - waited)).await;
+            pending = still_pending;
+        }
+    }
+
+    async fn observe_dns(&self, challenge: &SolverChallenge) -> Vec<Observation> {
+        let name = challenge.dns_record_name();
+        let value = challenge.key_authorization.dns_value();
+        let mut observations = Vec::with_capacity(self.resolvers.len());
+        for resolver in &self.resolvers {
+            let (visible, observed) = match resolver.txt(&name).await {
+                Ok(values) => (values.contains(&value), Observed::Values(values)),
+                Err(e) => (false, Observed::Failed(e.to_string())),
+            };
+            observations.push(Observation {
+                perspective: resolver.url.clone(),
+                visible,
+                observed,
+            });
+        }
+        observations
+    }
+
+    async fn observe_http(&self, challenge: &SolverChallenge) -> Vec<Observation> {
+        let url = http_url(challenge);
+        let mut observations = Vec::with_capacity(self.probes.len() + 1);
+        observations.push(self.fetch("this host", &url, challenge).await);
+        for template in &self.probes {
+            let probe = template.replace("{url}", &form_encode(&url));
+            observations.push(self.fetch(template, &probe, challenge).await);
+        }
+        observations
+    }
+
+    /// What `perspective` sees when fetching the response to `challenge` from `url`
+    async fn fetch(
+        &self,
+        perspective: &str,
+        url: &str,
+        challenge: &SolverChallenge,
+    ) -> Observation {
+        let observed = self.get(url).await;
+        let visible = match &observed {
+            Observed::Values(body) => *body == [challenge.key_authorization.as_str()],
+            _ => false,
+        };
+        Observation {
+            perspective: perspective.to_owned(),
+            visible,
+            observed,
+        }
+    }
+
+    async fn get(&self, url: &str) -> Observed {
+        let request = match Request::builder().uri(url).body(Body::empty()) {
+            Ok(request) => request,
+            Err(e) => return Observed::Failed(e.to_string()),
+        };
+
+        let rsp = match self.http.request(request).await {
+            Ok(rsp) if rsp.status().is_success() => rsp,
+            Ok(rsp) => return Observed::Status(rsp.status().as_u16()),
+            Err(e) => return Observed::Failed(e.to_string()),
+        };
+
+        match hyper::body::to_bytes(rsp.into_body()).await {
+            Ok(body) => Observed::Values(vec![String::from_utf8_lossy(&body).trim().to_owned()]),
+            Err(e) => Observed::Failed(e.to_string()),
+        }
+    }
+}
+
+/// The URL the CA fetches the response to the HTTP-01 `challenge` from
+fn http_url(challenge: &SolverChallenge) -> String {
+    format!(
+        "http://{}/.well-known/acme-challenge/{}",
+        challenge.domain(),
+        challenge.token
+    )
+}
+
+fn not_visible(challenge: &SolverChallenge, perspective: &str) -> Error {
+    Error::ChallengeNotVisible {
+        identifier: challenge.identifier.value().to_owned(),
+        perspective: perspective.to_owned(),
+    }
+}
+
+/// Find the relevant CAA record set by climbing the DNS tree from `name` (RFC 8659 section 3)
+async fn relevant_caa(resolver: &DohResolver, name: &str) -> Result<Vec<CaaRecord>, Error> {
+    let mut name = name.trim_end_matches('.');
+    loop {
+        let records = resolver.caa(name).await?;
+        if !records.is_empty() {
+            return Ok(records);
+        }
+
+        match name.split_once('.') {
+            Some((_, parent)) if !parent.is_empty() => name = parent,
+            _ => return Ok(Vec::new()),
+        }
+    }
+}
+
+/// Whether the CAA record set permits issuance by a CA with the given `identities`
+fn permits(records: &[CaaRecord], wildcard: bool, identities: &[String]) -> bool {
+    const KNOWN: [&str; 3] = ["issue", "issuewild", "iodef"];
+    let unknown_critical = records.iter().any(|record| {
+        record.flags & 128 != 0
+            && !KNOWN
+                .iter()
+                .any(|known| record.tag.eq_ignore_ascii_case(known))
+    });
+    if unknown_critical {
+        return false;
+    }
+
+    let with_tag = |tag: &str| {
+        records
+            .iter()
+            .filter(|record| record.tag.eq_ignore_ascii_case(tag))
+            .collect::<Vec<_>>()
+    };
+
+    let mut properties = match wildcard {
+        true => with_tag("issuewild"),
+        false => Vec::new(),
+    };
+    if properties.is_empty() {
+        properties = with_tag("issue");
+    }
+    if properties.is_empty() {
+        return true;
+    }
+
+    properties.iter().any(|record| match record.issuer() {
+        Some(issuer) => identities
+            .iter()
+            .any(|identity| identity.eq_ignore_ascii_case(issuer)),
+        None => false,
+    })
+}
+
+const NOERROR: u32 = 0;
+const NXDOMAIN: u32 = 3;
+const TXT: u16 = 16;
+const CAA: u16 = 257;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caa_records() {
+        let record = CaaRecord::parse("0 issue \"letsencrypt.org; validationmethods=dns-01\"");
+        let record = record.unwrap();
+        assert_eq!((record.flags, record.tag.as_str()), (0, "issue"));
+        assert_eq!(record.issuer(), Some("letsencrypt.org"));
+
+        // 0 issue "ca.test", in the generic format
+        let generic = "\\# 14 00 05 69 73 73 75 65 63 61 2e 74 65 73 74";
+        let record = CaaRecord::parse(generic).unwrap();
+        assert_eq!(record.tag, "issue");
+        assert_eq!(record.value, "ca.test");
+
+        assert_eq!(CaaRecord::parse("0 issue \";\"").unwrap().issuer(), None);
+        assert!(CaaRecord::parse("\\# 3 00 05 69").is_err());
+        assert!(CaaRecord::parse("issue").is_err());
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn caa_checks() {
+        use std::collections::HashMap;
+        use std::future::Future;
+        use std::pin::Pin;
+
+        use hyper::Response;
+        use serde_json::json;
+
+        use crate::mock::testing::account;
+        use crate::mock::MockServer;
+        use crate::AccountConfig;
+
+        /// Answers DNS over HTTPS queries for CAA records from a fixed zone
+        struct Zone(HashMap<&'static str, Vec<&'static str>>);
+
+        impl HttpClient for Zone {
+            fn request(
+                &self,
+                req: Request<Body>,
+            ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>> {
+                let query = req.uri().query().unwrap_or_default();
+                let name = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("name="))
+                    .unwrap_or_default();
+                assert!(query.ends_with("&type=257"), "{query}");
+                let answer = self
+                    .0
+                    .get(name)
+                    .into_iter()
+                    .flatten()
+                    .map(|data| json!({"name": name, "type": CAA, "TTL": 300, "data": data}));
+                let body = json!({"Status": NOERROR, "Answer": answer.collect::<Vec<_>>()});
+                let rsp = Response::new(Body::from(body.to_string()));
+                Box::pin(async move { Ok(rsp) })
+            }
+        }
+
+        let zone = Zone(HashMap::from([
+            (
+                "example.com",
+                vec!["0 issue \"mock.invalid\"", "0 iodef \"x\""],
+            ),
+            (
+                "other.test",
+                vec!["0 issue \"ca.test\"", "0 issue \"Mock.Invalid\""],
+            ),
+            ("denied.test", vec!["0 issue \"ca.test\""]),
+            (
+                "wild.test",
+                vec!["0 issue \"mock.invalid\"", "0 issuewild \";\""],
+            ),
+            (
+                "critical.test",
+                vec!["0 issue \"mock.invalid\"", "128 tbs \"x\""],
+            ),
+        ]));
+        let resolver =
+            DohResolver::with_http("https://doh.test/resolve".to_owned(), Box::new(zone));
+        let server = MockServer::new().with_caa_identities(&["mock.invalid"]);
+        let unlisted = account(MockServer::new(), AccountConfig::new()).await;
+        let account = account(server, AccountConfig::new()).await;
+        let order = |name: &str| {
+            NewOrder::builder()
+                .identifier(Identifier::Dns(name.to_owned()))
+                .build()
+                .unwrap()
+        };
+
+        // Names without records of their own fall back to their parent domains
+        for name in [
+            "example.com",
+            "www.sub.example.com",
+            "other.test",
+            "wild.test",
+            "none.test",
+        ] {
+            let result = account.check_caa(&order(name), &resolver).await;
+            assert!(result.is_ok(), "{name}: {result:?}");
+        }
+
+        for name in [
+            "denied.test",
+            "www.denied.test",
+            "*.wild.test",
+            "critical.test",
+        ] {
+            match account.check_caa(&order(name), &resolver).await {
+                Err(Error::CaaForbidden { identifier }) => assert_eq!(identifier, name),
+                result => panic!("unexpected result for {name}: {result:?}"),
+            }
+        }
+
+        // Without CAA identities in the directory, the check is skipped
+        let result = unlisted.check_caa(&order("denied.test"), &resolver).await;
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,838 @@
+//! Checking whether an issued certificate has been revoked
+
+use hyper::body::HttpBody;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, StatusCode};
+use ring::digest::{digest, Digest, SHA1_FOR_LEGACY_USE_ONLY};
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+use time::OffsetDateTime;
+
+use crate::der::{
+    self, expect, oid, sequence, tlv, BIT_STRING, CONTEXT_0, CONTEXT_1, GENERALIZED_TIME, INTEGER,
+    NULL, OBJECT_IDENTIFIER, OCTET_STRING, OID_ECDSA_WITH_SHA256, OID_EC_PUBLIC_KEY, OID_P256,
+    OID_P384, OID_RSA_ENCRYPTION, SEQUENCE, UTC_TIME,
+};
+use crate::{CertificateChain, Clock, Error, HttpClient, RevocationReason};
+
+/// The revocation status of a certificate, as reported by its CA
+///
+/// Returned by [`CertificateChain::revocation_status()`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RevocationStatus {
+    /// The certificate has not been revoked
+    Good,
+    /// The certificate has been revoked
+    Revoked {
+        /// When the certificate was revoked
+        revoked_at: OffsetDateTime,
+        /// The reason given for the revocation, if any
+        reason: Option<RevocationReason>,
+    },
+    /// The OCSP responder does not know the certificate
+    Unknown,
+}
+
+impl CertificateChain {
+    /// Ask the CA whether the end-entity certificate has been revoked
+    ///
+    /// Queries the OCSP responder named in the certificate's authority information access
+    /// extension, falling back to the CRL distribution points if there is no responder or it
+    /// cannot be reached. The chain must include the issuer of the certificate: OCSP
+    /// responses must be signed by the issuer or by an OCSP responder certificate it issued,
+    /// and CRLs by the issuer itself. Stale responses and CRLs are rejected.
+    ///
+    /// Requests are sent through `http`, for example the
+    /// [Account::http_client()](crate::Account::http_client()) of the account that ordered
+    /// the certificate. Responses are checked for staleness against `clock`.
+    pub async fn revocation_status(
+        &self,
+        http: &dyn HttpClient,
+        clock: &dyn Clock,
+    ) -> Result<RevocationStatus, Error> {
+        let now = clock.now_utc();
+        let certificates = self.der()?;
+        let (leaf, issuer) = match certificates.as_slice() {
+            [leaf, issuer, ..] => (Certificate::parse(leaf)?, Certificate::parse(issuer)?),
+            _ => {
+                return Err(Error::RevocationStatus(
+                    "certificate chain does not include the issuer",
+                ))
+            }
+        };
+        if leaf.issuer != issuer.subject {
+            return Err(Error::RevocationStatus(
+                "second certificate in chain is not the issuer",
+            ));
+        }
+
+        let (ocsp_urls, crl_urls) = match der::certificate_extensions(leaf.der)? {
+            Some(extensions) => (ocsp_urls(extensions)?, crl_urls(extensions)?),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        let mut result = Err(Error::RevocationStatus(
+            "certificate has no OCSP responder or CRL distribution point",
+        ));
+        for url in &ocsp_urls {
+            result = ocsp(http, url, &leaf, &issuer, now).await;
+            if result.is_ok() {
+                return result;
+            }
+        }
+        for url in &crl_urls {
+            result = crl(http, url, &leaf, &issuer, now).await;
+            if result.is_ok() {
+                return result;
+            }
+        }
+        result
+    }
+}
+
+/// Ask the OCSP responder at `url` about `leaf`
+async fn ocsp(
+    http: &dyn HttpClient,
+    url: &str,
+    leaf: &Certificate<'_>,
+    issuer: &Certificate<'_>,
+    now: OffsetDateTime,
+) -> Result<RevocationStatus, Error> {
+    let (name_hash, key_hash) = issuer_hashes(issuer);
+    let cert_id = sequence(&[
+        &sequence(&[&oid(OID_SHA1), &tlv(NULL, &[])]),
+        &tlv(OCTET_STRING, name_hash.as_ref()),
+        &tlv(OCTET_STRING, key_hash.as_ref()),
+        &tlv(INTEGER, leaf.serial),
+    ]);
+    let body = sequence(&[&sequence(&[&sequence(&[&sequence(&[&cert_id])])])]);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(CONTENT_TYPE, "application/ocsp-request")
+        .body(Body::from(body))
+        .map_err(|_| Error::Malformed("revocation URL in certificate"))?;
+    let response = fetch(http, request, 64 * 1024).await?;
+    ocsp_status(&response, leaf, issuer, now)
+}
+
+/// The status of `leaf` in the DER-encoded OCSP `response`
+fn ocsp_status(
+    response: &[u8],
+    leaf: &Certificate<'_>,
+    issuer: &Certificate<'_>,
+    now: OffsetDateTime,
+) -> Result<RevocationStatus, Error> {
+    let (response, _) = expect(SEQUENCE, response)?;
+    let (status, rest) = expect(ENUMERATED, response)?;
+    if status != [0] {
+        return Err(Error::RevocationStatus("OCSP responder returned an error"));
+    }
+    let (bytes, _) = expect(CONTEXT_0, rest)?;
+    let (bytes, _) = expect(SEQUENCE, bytes)?;
+    let (response_type, bytes) = expect(OBJECT_IDENTIFIER, bytes)?;
+    if response_type != OID_OCSP_BASIC {
+        return Err(Error::Unsupported("OCSP response type"));
+    }
+    let (basic, _) = expect(OCTET_STRING, bytes)?;
+    let (basic, _) = expect(SEQUENCE, basic)?;
+    let (signed, rest) = Signed::parse(basic)?;
+
+    // The response is signed by the issuer, or by a responder certificate it issued
+    let responder = match rest.first() {
+        Some(&CONTEXT_0) => {
+            let (certs, _) = expect(CONTEXT_0, rest)?;
+            let (certs, _) = expect(SEQUENCE, certs)?;
+            let (responder, _) = element(SEQUENCE, certs)?;
+            Some(Certificate::parse(responder)?)
+        }
+        _ => None,
+    };
+    let signer = match &responder {
+        Some(responder) if responder.der != issuer.der => {
+            if responder.issuer != issuer.subject {
+                return Err(Error::RevocationStatus(
+                    "OCSP responder was not issued by the issuer",
+                ));
+            }
+            issuer.key.verify(&responder.signed)?;
+            if !responder.is_ocsp_signer()? {
+                return Err(Error::RevocationStatus(
+                    "OCSP responder is not authorized to sign",
+                ));
+            }
+            &responder.key
+        }
+        _ => &issuer.key,
+    };
+    signer.verify(&signed)?;
+
+    let (mut data, _) = expect(SEQUENCE, signed.data)?;
+    if data.first() == Some(&CONTEXT_0) {
+        data = expect(CONTEXT_0, data)?.1;
+    }
+    // Skip the responder ID and the production time
+    let (_, data) = expect(*data.first().unwrap_or(&0), data)?;
+    let (_, data) = expect(GENERALIZED_TIME, data)?;
+    let (mut responses, _) = expect(SEQUENCE, data)?;
+    let (name_hash, key_hash) = issuer_hashes(issuer);
+    while !responses.is_empty() {
+        let (single, rest) = expect(SEQUENCE, responses)?;
+        responses = rest;
+
+        // Serial numbers are only unique per issuer, so the whole certificate ID must match
+        let (cert_id, single) = expect(SEQUENCE, single)?;
+        let (algorithm, cert_id) = expect(SEQUENCE, cert_id)?;
+        let (algorithm, _) = expect(OBJECT_IDENTIFIER, algorithm)?;
+        let (issuer_name_hash, cert_id) = expect(OCTET_STRING, cert_id)?;
+        let (issuer_key_hash, cert_id) = expect(OCTET_STRING, cert_id)?;
+        let (serial, _) = expect(INTEGER, cert_id)?;
+        if algorithm != OID_SHA1
+            || issuer_name_hash != name_hash.as_ref()
+            || issuer_key_hash != key_hash.as_ref()
+            || serial != leaf.serial
+        {
+            continue;
+        }
+
+        let tag = *single.first().unwrap_or(&0);
+        let (info, single) = expect(tag, single)?;
+        let status = match tag {
+            OCSP_GOOD => RevocationStatus::Good,
+            OCSP_REVOKED => {
+                let (revoked_at, info) = der::time(info)?;
+                let reason = match info.first() {
+                    Some(&CONTEXT_0) => {
+                        let (reason, _) = expect(CONTEXT_0, info)?;
+                        reason_code(expect(ENUMERATED, reason)?.0)
+                    }
+                    _ => None,
+                };
+                RevocationStatus::Revoked { revoked_at, reason }
+            }
+            OCSP_UNKNOWN => RevocationStatus::Unknown,
+            _ => return Err(Error::Malformed("OCSP response")),
+        };
+
+        let (_, single) = der::time(single)?;
+        if single.first() == Some(&CONTEXT_0) {
+            let (next_update, _) = expect(CONTEXT_0, single)?;
+            if der::time(next_update)?.0 < now {
+                return Err(Error::RevocationStatus("OCSP response is stale"));
+            }
+        }
+        return Ok(status);
+    }
+
+    Err(Error::RevocationStatus(
+        "OCSP response does not cover the certificate",
+    ))
+}
+
+/// Look up `leaf` in the CRL at `url`
+async fn crl(
+    http: &dyn HttpClient,
+    url: &str,
+    leaf: &Certificate<'_>,
+    issuer: &Certificate<'_>,
+    now: OffsetDateTime,
+) -> Result<RevocationStatus, Error> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(url)
+        .body(Body::empty())
+        .map_err(|_| Error::Malformed("revocation URL in certificate"))?;
+    let list = fetch(http, request, 32 * 1024 * 1024).await?;
+    crl_status(&list, leaf, issuer, now)
+}
+
+/// The status of `leaf` in the DER-encoded certificate revocation `list`
+fn crl_status(
+    list: &[u8],
+    leaf: &Certificate<'_>,
+    issuer: &Certificate<'_>,
+    now: OffsetDateTime,
+) -> Result<RevocationStatus, Error> {
+    let (list, _) = expect(SEQUENCE, list)?;
+    let (signed, _) = Signed::parse(list)?;
+    issuer.key.verify(&signed)?;
+
+    let (mut tbs, _) = expect(SEQUENCE, signed.data)?;
+    if tbs.first() == Some(&INTEGER) {
+        tbs = expect(INTEGER, tbs)?.1;
+    }
+    let (_, tbs) = expect(SEQUENCE, tbs)?;
+    let (crl_issuer, tbs) = element(SEQUENCE, tbs)?;
+    if crl_issuer != leaf.issuer {
+        return Err(Error::RevocationStatus(
+            "CRL was not issued by the certificate's issuer",
+        ));
+    }
+    let (_, mut tbs) = der::time(tbs)?;
+    if matches!(tbs.first(), Some(&UTC_TIME | &GENERALIZED_TIME)) {
+        let (next_update, rest) = der::time(tbs)?;
+        if next_update < now {
+            return Err(Error::RevocationStatus("CRL is stale"));
+        }
+        tbs = rest;
+    }
+    if tbs.first() != Some(&SEQUENCE) {
+        return Ok(RevocationStatus::Good);
+    }
+
+    let (mut revoked, _) = expect(SEQUENCE, tbs)?;
+    while !revoked.is_empty() {
+        let (entry, rest) = expect(SEQUENCE, revoked)?;
+        revoked = rest;
+        let (serial, entry) = expect(INTEGER, entry)?;
+        if serial != leaf.serial {
+            continue;
+        }
+
+        let (revoked_at, extensions) = der::time(entry)?;
+        let reason = match extensions.is_empty() {
+            true => None,
+            false => match der::find_extension(extensions, OID_REASON_CODE)? {
+                Some(reason) => reason_code(expect(ENUMERATED, reason)?.0),
+                None => None,
+            },
+        };
+        return Ok(RevocationStatus::Revoked { revoked_at, reason });
+    }
+
+    Ok(RevocationStatus::Good)
+}
+
+/// The hashes of the name and the key of `issuer` in OCSP certificate IDs
+///
+/// RFC 5019 responders only need to support SHA-1 certificate IDs, so these are requested.
+fn issuer_hashes(issuer: &Certificate<'_>) -> (Digest, Digest) {
+    (
+        digest(&SHA1_FOR_LEGACY_USE_ONLY, issuer.subject),
+        digest(&SHA1_FOR_LEGACY_USE_ONLY, issuer.key.bytes),
+    )
+}
+
+/// Send `request` and return the body of a `200 OK` response of at most `max_len` bytes
+async fn fetch(
+    http: &dyn HttpClient,
+    request: Request<Body>,
+    max_len: usize,
+) -> Result<Vec<u8>, Error> {
+    let response = http.request(request).await?;
+    if response.status() != StatusCode::OK {
+        return Err(Error::RevocationStatus(
+            "revocation information is unavailable",
+        ));
+    }
+
+    let mut body = response.into_body();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if data.len() + chunk.len() > max_len {
+            return Err(Error::TooLarge("revocation information"));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// The OCSP responder URLs in the authority information access extension
+fn ocsp_urls(extensions: &[u8]) -> Result<Vec<String>, Error> {
+    let mut urls = Vec::new();
+    let value = match der::find_extension(extensions, OID_AUTHORITY_INFO_ACCESS)? {
+        Some(value) => value,
+        None => return Ok(urls),
+    };
+
+    let (mut descriptions, _) = expect(SEQUENCE, value)?;
+    while !descriptions.is_empty() {
+        let (description, rest) = expect(SEQUENCE, descriptions)?;
+        descriptions = rest;
+        let (method, location) = expect(OBJECT_IDENTIFIER, description)?;
+        if method == OID_AD_OCSP && location.first() == Some(&URI) {
+            urls.push(uri(expect(URI, location)?.0)?);
+        }
+    }
+    Ok(urls)
+}
+
+/// The URLs of the full names in the CRL distribution points extension
+fn crl_urls(extensions: &[u8]) -> Result<Vec<String>, Error> {
+    let mut urls = Vec::new();
+    let value = match der::find_extension(extensions, OID_CRL_DISTRIBUTION_POINTS)? {
+        Some(value) => value,
+        None => return Ok(urls),
+    };
+
+    let (mut points, _) = expect(SEQUENCE, value)?;
+    while !points.is_empty() {
+        let (point, rest) = expect(SEQUENCE, points)?;
+        points = rest;
+        if point.first() != Some(&CONTEXT_0) {
+            continue;
+        }
+        let (name, _) = expect(CONTEXT_0, point)?;
+        if name.first() != Some(&CONTEXT_0) {
+            continue;
+        }
+        let (mut names, _) = expect(CONTEXT_0, name)?;
+        while let Some(&tag) = names.first() {
+            let (name, rest) = expect(tag, names)?;
+            names = rest;
+            if tag == URI {
+                urls.push(uri(name)?);
+            }
+        }
+    }
+    Ok(urls)
+}
+
+fn uri(value: &[u8]) -> Result<String, Error> {
+    match std::str::from_utf8(value) {
+        Ok(uri) => Ok(uri.to_owned()),
+        Err(_) => Err(Error::Malformed("URI in certificate")),
+    }
+}
+
+fn reason_code(code: &[u8]) -> Option<RevocationReason> {
+    match code {
+        [code] => RevocationReason::from_code(*code),
+        _ => None,
+    }
+}
+
+/// The parts of a DER-encoded X.509 certificate needed to check its revocation status
+struct Certificate<'a> {
+    der: &'a [u8],
+    signed: Signed<'a>,
+    serial: &'a [u8],
+    issuer: &'a [u8],
+    subject: &'a [u8],
+    key: PublicKey<'a>,
+}
+
+impl<'a> Certificate<'a> {
+    fn parse(der: &'a [u8]) -> Result<Self, Error> {
+        let (certificate, _) = expect(SEQUENCE, der)?;
+        let (signed, _) = Signed::parse(certificate)?;
+
+        let (mut tbs, _) = expect(SEQUENCE, signed.data)?;
+        if tbs.first() == Some(&CONTEXT_0) {
+            tbs = expect(CONTEXT_0, tbs)?.1;
+        }
+        let (serial, tbs) = expect(INTEGER, tbs)?;
+        let (_, tbs) = expect(SEQUENCE, tbs)?;
+        let (issuer, tbs) = element(SEQUENCE, tbs)?;
+        let (_, tbs) = expect(SEQUENCE, tbs)?;
+        let (subject, tbs) = element(SEQUENCE, tbs)?;
+        let (spki, _) = expect(SEQUENCE, tbs)?;
+        let (algorithm, spki) = expect(SEQUENCE, spki)?;
+        let (key_type, parameters) = expect(OBJECT_IDENTIFIER, algorithm)?;
+        let curve = match parameters.first() {
+            Some(&OBJECT_IDENTIFIER) => Some(expect(OBJECT_IDENTIFIER, parameters)?.0),
+            _ => None,
+        };
+
+        Ok(Self {
+            der,
+            signed,
+            serial,
+            issuer,
+            subject,
+            key: PublicKey {
+                key_type,
+                curve,
+                bytes: bit_string(spki)?.0,
+            },
+        })
+    }
+
+    /// Whether the extended key usage extension allows signing OCSP responses
+    fn is_ocsp_signer(&self) -> Result<bool, Error> {
+        let extensions = match der::certificate_extensions(self.der)? {
+            Some(extensions) => extensions,
+            None => return Ok(false),
+        };
+        let mut usages = match der::find_extension(extensions, OID_EXTENDED_KEY_USAGE)? {
+            Some(value) => expect(SEQUENCE, value)?.0,
+            None => return Ok(false),
+        };
+        while !usages.is_empty() {
+            let (usage, rest) = expect(OBJECT_IDENTIFIER, usages)?;
+            if usage == OID_KP_OCSP_SIGNING {
+                return Ok(true);
+            }
+            usages = rest;
+        }
+        Ok(false)
+    }
+}
+
+/// A subject public key, as found in a certificate
+struct PublicKey<'a> {
+    key_type: &'a [u8],
+    curve: Option<&'a [u8]>,
+    bytes: &'a [u8],
+}
+
+impl PublicKey<'_> {
+    /// Check that `signed` was signed with this key
+    fn verify(&self, signed: &Signed<'_>) -> Result<(), Error> {
+        let algorithm: &'static dyn VerificationAlgorithm =
+            match (signed.algorithm, self.key_type, self.curve) {
+                (OID_SHA256_WITH_RSA, OID_RSA_ENCRYPTION, _) => {
+                    &signature::RSA_PKCS1_2048_8192_SHA256
+                }
+                (OID_SHA384_WITH_RSA, OID_RSA_ENCRYPTION, _) => {
+                    &signature::RSA_PKCS1_2048_8192_SHA384
+                }
+                (OID_SHA512_WITH_RSA, OID_RSA_ENCRYPTION, _) => {
+                    &signature::RSA_PKCS1_2048_8192_SHA512
+                }
+                (OID_ECDSA_WITH_SHA256, OID_EC_PUBLIC_KEY, Some(OID_P256)) => {
+                    &signature::ECDSA_P256_SHA256_ASN1
+                }
+                (OID_ECDSA_WITH_SHA256, OID_EC_PUBLIC_KEY, Some(OID_P384)) => {
+                    &signature::ECDSA_P384_SHA256_ASN1
+                }
+                (OID_ECDSA_WITH_SHA384, OID_EC_PUBLIC_KEY, Some(OID_P256)) => {
+                    &signature::ECDSA_P256_SHA384_ASN1
+                }
+                (OID_ECDSA_WITH_SHA384, OID_EC_PUBLIC_KEY, Some(OID_P384)) => {
+                    &signature::ECDSA_P384_SHA384_ASN1
+                }
+                _ => return Err(Error::Unsupported("signature algorithm")),
+            };
+
+        UnparsedPublicKey::new(algorithm, self.bytes)
+            .verify(signed.data, signed.signature)
+            .map_err(|_| Error::RevocationStatus("invalid signature on revocation information"))
+    }
+}
+
+/// Data signed by a certificate authority: a certificate, a CRL or an OCSP response
+struct Signed<'a> {
+    /// The signed data, including its header
+    data: &'a [u8],
+    algorithm: &'a [u8],
+    signature: &'a [u8],
+}
+
+impl<'a> Signed<'a> {
+    /// Split the contents of a signed structure, returning the remaining fields
+    fn parse(input: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        let (data, rest) = element(SEQUENCE, input)?;
+        let (algorithm, rest) = expect(SEQUENCE, rest)?;
+        let (algorithm, _) = expect(OBJECT_IDENTIFIER, algorithm)?;
+        let (signature, rest) = bit_string(rest)?;
+        let signed = Self {
+            data,
+            algorithm,
+            signature,
+        };
+        Ok((signed, rest))
+    }
+}
+
+/// Read a `BIT STRING` without unused bits
+fn bit_string(input: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    match expect(BIT_STRING, input)? {
+        ([0, bits @ ..], rest) => Ok((bits, rest)),
+        _ => Err(Error::Malformed("DER data")),
+    }
+}
+
+/// Read an element with `tag`, returning it with its header
+fn element(tag: u8, input: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let (_, rest) = expect(tag, input)?;
+    Ok((&input[..input.len() - rest.len()], rest))
+}
+
+const ENUMERATED: u8 = 0x0a;
+const URI: u8 = 0x86;
+const OCSP_GOOD: u8 = 0x80;
+const OCSP_REVOKED: u8 = CONTEXT_1;
+const OCSP_UNKNOWN: u8 = 0x82;
+
+/// 1.3.14.3.2.26
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+/// 1.2.840.113549.1.1.11
+const OID_SHA256_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+/// 1.2.840.113549.1.1.12
+const OID_SHA384_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+/// 1.2.840.113549.1.1.13
+const OID_SHA512_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+/// 1.2.840.10045.4.3.3
+const OID_ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+/// 1.3.6.1.5.5.7.1.1
+const OID_AUTHORITY_INFO_ACCESS: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x01];
+/// 1.3.6.1.5.5.7.48.1
+const OID_AD_OCSP: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01];
+/// 1.3.6.1.5.5.7.48.1.1
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+/// 2.5.29.31
+const OID_CRL_DISTRIBUTION_POINTS: &[u8] = &[0x55, 0x1d, 0x1f];
+/// 2.5.29.37
+const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+/// 1.3.6.1.5.5.7.3.9
+const OID_KP_OCSP_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+/// 2.5.29.21
+const OID_REASON_CODE: &[u8] = &[0x55, 0x1d, 0x15];
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+    use super::*;
+    use crate::der::CONTEXT_2;
+
+    /// A CA certificate with its signing key, and the DER of a leaf it issued
+    struct Fixture {
+        ca: Vec<u8>,
+        key: EcdsaKeyPair,
+        leaf: Vec<u8>,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let mut params = rcgen::CertificateParams::new(Vec::new());
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            params
+                .distinguished_name
+                .push(rcgen::DnType::CommonName, name);
+            let ca = rcgen::Certificate::from_params(params).unwrap();
+
+            let mut params = rcgen::CertificateParams::new(vec!["example.com".to_owned()]);
+            params.serial_number = Some(0x1234);
+            let leaf = rcgen::Certificate::from_params(params).unwrap();
+
+            let pkcs8 = ca.serialize_private_key_der();
+            Self {
+                ca: ca.serialize_der().unwrap(),
+                key: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &pkcs8).unwrap(),
+                leaf: leaf.serialize_der_with_signer(&ca).unwrap(),
+            }
+        }
+
+        /// Wrap `tbs` in a structure signed with the CA key
+        fn sign(&self, tbs: &[u8]) -> Vec<u8> {
+            let signature = self.key.sign(&SystemRandom::new(), tbs).unwrap();
+            sequence(&[
+                tbs,
+                &sequence(&[&oid(OID_ECDSA_WITH_SHA256)]),
+                &tlv(BIT_STRING, &[&[0], signature.as_ref()].concat()),
+            ])
+        }
+
+        fn ocsp(&self, single: &[u8]) -> Vec<u8> {
+            let data = sequence(&[
+                &tlv(CONTEXT_2, &tlv(OCTET_STRING, &[0; 20])),
+                &tlv(GENERALIZED_TIME, b"20260102030405.123Z"),
+                &sequence(&[single]),
+            ]);
+            let bytes = sequence(&[&oid(OID_OCSP_BASIC), &tlv(OCTET_STRING, &self.sign(&data))]);
+            sequence(&[&tlv(ENUMERATED, &[0]), &tlv(CONTEXT_0, &bytes)])
+        }
+
+        fn crl(&self, revoked: &[u8]) -> Vec<u8> {
+            let ca = Certificate::parse(&self.ca).unwrap();
+            let tbs = sequence(&[
+                &tlv(INTEGER, &[1]),
+                &sequence(&[&oid(OID_ECDSA_WITH_SHA256)]),
+                ca.subject,
+                &tlv(UTC_TIME, b"260102030405Z"),
+                &tlv(GENERALIZED_TIME, NEXT_UPDATE),
+                &sequence(&[revoked]),
+            ]);
+            self.sign(&tbs)
+        }
+    }
+
+    /// A `SingleResponse` for `serial` with the given issuer hashes and `status`
+    fn single(name_hash: &[u8], key_hash: &[u8], serial: &[u8], status: &[u8]) -> Vec<u8> {
+        let cert_id = sequence(&[
+            &sequence(&[&oid(OID_SHA1), &tlv(NULL, &[])]),
+            &tlv(OCTET_STRING, name_hash),
+            &tlv(OCTET_STRING, key_hash),
+            &tlv(INTEGER, serial),
+        ]);
+        sequence(&[
+            &cert_id,
+            status,
+            &tlv(GENERALIZED_TIME, b"20260102030405Z"),
+            &tlv(CONTEXT_0, &tlv(GENERALIZED_TIME, NEXT_UPDATE)),
+        ])
+    }
+
+    fn revoked_at() -> OffsetDateTime {
+        der::time(&tlv(GENERALIZED_TIME, b"20260101000000Z"))
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn ocsp_good_and_revoked() {
+        let fixture = Fixture::new("Test CA");
+        let (ca, leaf) = (
+            Certificate::parse(&fixture.ca).unwrap(),
+            Certificate::parse(&fixture.leaf).unwrap(),
+        );
+        let (name_hash, key_hash) = issuer_hashes(&ca);
+        let (name_hash, key_hash) = (name_hash.as_ref(), key_hash.as_ref());
+
+        let good = single(name_hash, key_hash, leaf.serial, &tlv(OCSP_GOOD, &[]));
+        let response = fixture.ocsp(&good);
+        assert_eq!(
+            ocsp_status(&response, &leaf, &ca, now()).unwrap(),
+            RevocationStatus::Good
+        );
+
+        let info = [
+            tlv(GENERALIZED_TIME, b"20260101000000.5Z"),
+            tlv(CONTEXT_0, &tlv(ENUMERATED, &[1])),
+        ]
+        .concat();
+        let revoked = single(name_hash, key_hash, leaf.serial, &tlv(OCSP_REVOKED, &info));
+        assert_eq!(
+            ocsp_status(&fixture.ocsp(&revoked), &leaf, &ca, now()).unwrap(),
+            RevocationStatus::Revoked {
+                revoked_at: revoked_at(),
+                reason: Some(RevocationReason::KeyCompromise),
+            }
+        );
+    }
+
+    #[test]
+    fn ocsp_requires_matching_cert_id() {
+        let fixture = Fixture::new("Test CA");
+        let (ca, leaf) = (
+            Certificate::parse(&fixture.ca).unwrap(),
+            Certificate::parse(&fixture.leaf).unwrap(),
+        );
+        let (name_hash, key_hash) = issuer_hashes(&ca);
+        let (name_hash, key_hash) = (name_hash.as_ref(), key_hash.as_ref());
+        let good = tlv(OCSP_GOOD, &[]);
+
+        // The same serial under another issuer's name or key, or another serial
+        for single in [
+            single(&[0; 20], key_hash, leaf.serial, &good),
+            single(name_hash, &[0; 20], leaf.serial, &good),
+            single(name_hash, key_hash, &[0x12, 0x35], &good),
+        ] {
+            let error = ocsp_status(&fixture.ocsp(&single), &leaf, &ca, now()).unwrap_err();
+            assert!(error.to_string().contains("does not cover"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn ocsp_rejects_bad_responses() {
+        let fixture = Fixture::new("Test CA");
+        let (ca, leaf) = (
+            Certificate::parse(&fixture.ca).unwrap(),
+            Certificate::parse(&fixture.leaf).unwrap(),
+        );
+        let (name_hash, key_hash) = issuer_hashes(&ca);
+        let good = single(
+            name_hash.as_ref(),
+            key_hash.as_ref(),
+            leaf.serial,
+            &tlv(OCSP_GOOD, &[]),
+        );
+        let response = fixture.ocsp(&good);
+
+        for len in [0, 1, 10, response.len() / 2, response.len() - 1] {
+            assert!(ocsp_status(&response[..len], &leaf, &ca, now()).is_err());
+        }
+
+        // Flipping a bit in the response data invalidates the signature
+        let mut tampered = response.clone();
+        let position = tampered.len() - 120;
+        tampered[position] ^= 1;
+        assert!(ocsp_status(&tampered, &leaf, &ca, now()).is_err());
+
+        let unauthorized = sequence(&[&tlv(ENUMERATED, &[6])]);
+        assert!(ocsp_status(&unauthorized, &leaf, &ca, now()).is_err());
+    }
+
+    #[test]
+    fn crl_lookup() {
+        let fixture = Fixture::new("Test CA");
+        let (ca, leaf) = (
+            Certificate::parse(&fixture.ca).unwrap(),
+            Certificate::parse(&fixture.leaf).unwrap(),
+        );
+
+        let other = sequence(&[
+            &tlv(INTEGER, &[0x12, 0x35]),
+            &tlv(UTC_TIME, b"260101000000Z"),
+        ]);
+        assert_eq!(
+            crl_status(&fixture.crl(&other), &leaf, &ca, now()).unwrap(),
+            RevocationStatus::Good
+        );
+
+        let extension = sequence(&[
+            &oid(OID_REASON_CODE),
+            &tlv(OCTET_STRING, &tlv(ENUMERATED, &[1])),
+        ]);
+        let entry = sequence(&[
+            &tlv(INTEGER, leaf.serial),
+            &tlv(UTC_TIME, b"260101000000Z"),
+            &sequence(&[&extension]),
+        ]);
+        let list = fixture.crl(&[other, entry].concat());
+        assert_eq!(
+            crl_status(&list, &leaf, &ca, now()).unwrap(),
+            RevocationStatus::Revoked {
+                revoked_at: revoked_at(),
+                reason: Some(RevocationReason::KeyCompromise),
+            }
+        );
+
+        for len in [0, 3, list.len() / 2, list.len() - 1] {
+            assert!(crl_status(&list[..len], &leaf, &ca, now()).is_err());
+        }
+        // A list from another issuer is rejected even when its signature is valid
+        let other = Fixture::new("Other CA");
+        let other_ca = Certificate::parse(&other.ca).unwrap();
+        let error = crl_status(&other.crl(&[]), &leaf, &other_ca, now()).unwrap_err();
+        assert!(error.to_string().contains("not issued"), "{}", error);
+    }
+
+    #[test]
+    fn stale_information() {
+        let fixture = Fixture::new("Test CA");
+        let (ca, leaf) = (
+            Certificate::parse(&fixture.ca).unwrap(),
+            Certificate::parse(&fixture.leaf).unwrap(),
+        );
+        // Just after the next update of the fixture's responses and lists
+        let later = OffsetDateTime::from_unix_timestamp(32_503_680_000).unwrap();
+
+        let (name_hash, key_hash) = issuer_hashes(&ca);
+        let (name_hash, key_hash) = (name_hash.as_ref(), key_hash.as_ref());
+        let good = single(name_hash, key_hash, leaf.serial, &tlv(OCSP_GOOD, &[]));
+        let response = fixture.ocsp(&good);
+        assert!(ocsp_status(&response, &leaf, &ca, now()).is_ok());
+        match ocsp_status(&response, &leaf, &ca, later) {
+            Err(Error::RevocationStatus(reason)) => assert_eq!(reason, "OCSP response is stale"),
+            result => panic!("unexpected result: {result:?}"),
+        }
+
+        let list = fixture.crl(&[]);
+        assert!(crl_status(&list, &leaf, &ca, now()).is_ok());
+        match crl_status(&list, &leaf, &ca, later) {
+            Err(Error::RevocationStatus(reason)) => assert_eq!(reason, "CRL is stale"),
+            result => panic!("unexpected result: {result:?}"),
+        }
+    }
+
+    fn now() -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(1_800_000_000).unwrap()
+    }
+
+    const NEXT_UPDATE: &[u8] = b"29991231235959Z";
+}
@@ -0,0 +1,106 @@
+//! Sources of randomness for key generation and backoff jitter
+
+use std::fmt;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{
+    EcdsaKeyPair, EcdsaSigningAlgorithm, Ed25519KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING,
+    ECDSA_P384_SHA384_FIXED_SIGNING,
+};
+
+use crate::Error;
+
+/// A source of randomness for an [`Account`](crate::Account) and the orders created from it
+///
+/// The randomness is used to generate account keys and to randomize retry delays (see
+/// [`RetryPolicy::jitter()`](crate::RetryPolicy::jitter())). The default [`SystemRng`] uses
+/// the operating system's generator. With the `danger` feature, another source can be set with
+/// [`AccountConfig::rng()`](crate::AccountConfig::rng()), for example a seeded generator to
+/// make tests reproducible or an organizational DRBG. Signatures always use the system
+/// generator.
+pub trait Rng: Send + Sync + 'static {
+    /// Fill `dest` with random bytes
+    fn fill(&self, dest: &mut [u8]) -> Result<(), Error>;
+}
+
+impl fmt::Debug for dyn Rng {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rng").finish_non_exhaustive()
+    }
+}
+
+/// An [`Rng`] backed by the operating system's secure random number generator
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRng;
+
+impl Rng for SystemRng {
+    fn fill(&self, dest: &mut [u8]) -> Result<(), Error> {
+        Ok(SystemRandom::new().fill(dest)?)
+    }
+}
+
+/// Generate a P-256 key pair in PKCS#8 format from `rng`
+pub(crate) fn generate_p256_pkcs8(rng: &dyn Rng) -> Result<Vec<u8>, Error> {
+    generate_ecdsa_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &mut [0; 32], rng)
+}
+
+/// Generate a P-384 key pair in PKCS#8 format from `rng`
+pub(crate) fn generate_p384_pkcs8(rng: &dyn Rng) -> Result<Vec<u8>, Error> {
+    generate_ecdsa_pkcs8(&ECDSA_P384_SHA384_FIXED_SIGNING, &mut [0; 48], rng)
+}
+
+/// Generate an Ed25519 key pair in PKCS#8 format from `rng`
+pub(crate) fn generate_ed25519_pkcs8(rng: &dyn Rng) -> Result<Vec<u8>, Error> {
+    let mut seed = [0; 32];
+    rng.fill(&mut seed)?;
+    let fixed = ring::test::rand::FixedSliceRandom { bytes: &seed };
+    let result = Ed25519KeyPair::generate_pkcs8(&fixed)
+        .map(|pkcs8| pkcs8.as_ref().to_vec())
+        .map_err(Error::Crypto);
+    seed.fill(0);
+    result
+}
+
+/// Generate an ML-DSA-44 key pair in PKCS#8 format from `rng`
+#[cfg(feature = "ml-dsa")]
+pub(crate) fn generate_ml_dsa44_pkcs8(rng: &dyn Rng) -> Result<Vec<u8>, Error> {
+    let mut seed = [0; 32];
+    rng.fill(&mut seed)?;
+    let pkcs8 = crate::der::pkcs8_from_ml_dsa44_seed(&seed);
+    seed.fill(0);
+    Ok(pkcs8)
+}
+
+/// Generate an ECDSA key pair for `algorithm` with a private scalar the size of `scalar`
+///
+/// ring only accepts its own generators, so the private scalar is drawn from `rng` and
+/// handed to ring through a fixed-output generator. ring rejects candidates that are not a
+/// valid scalar, in which case another one is drawn.
+fn generate_ecdsa_pkcs8(
+    algorithm: &'static EcdsaSigningAlgorithm,
+    scalar: &mut [u8],
+    rng: &dyn Rng,
+) -> Result<Vec<u8>, Error> {
+    let mut result = Err(Error::Crypto(ring::error::Unspecified));
+    for _ in 0..4 {
+        rng.fill(scalar)?;
+        let fixed = ring::test::rand::FixedSliceRandom { bytes: scalar };
+        if let Ok(pkcs8) = EcdsaKeyPair::generate_pkcs8(algorithm, &fixed) {
+            result = Ok(pkcs8.as_ref().to_vec());
+            break;
+        }
+    }
+
+    scalar.fill(0);
+    result
+}
+
+/// A random value in `0..=max`
+pub(crate) fn below_or_equal(rng: &dyn Rng, max: u64) -> Result<u64, Error> {
+    let mut bytes = [0; 8];
+    rng.fill(&mut bytes)?;
+    Ok(match max.checked_add(1) {
+        Some(range) => u64::from_le_bytes(bytes) % range,
+        None => u64::from_le_bytes(bytes),
+    })
+}
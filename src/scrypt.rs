@@ -0,0 +1,177 @@
+//! The scrypt key derivation function (RFC 7914), for sealing credentials with a passphrase
+//!
+//! ring only offers PBKDF2, which an attacker with GPUs or ASICs can compute far more cheaply
+//! than a server can. scrypt makes every guess fill a large buffer in memory, which is what
+//! makes it expensive to attack in parallel. PBKDF2-HMAC-SHA256 from ring is used for the
+//! steps around the memory-hard mixing, as the RFC specifies.
+
+use std::num::NonZeroU32;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+use ring::pbkdf2;
+
+use crate::zeroize;
+
+/// Derive `out.len()` bytes from `passphrase` and `salt` with cost parameters `2^log_n`, `r`
+/// and `p`
+///
+/// Needs `128 * r * 2^log_n` bytes of memory; the parameters must be checked by the caller.
+pub(crate) fn derive(
+    passphrase: &[u8],
+    salt: &[u8],
+    log_n: u8,
+    r: usize,
+    p: usize,
+    out: &mut [u8],
+) {
+    let once = NonZeroU32::new(1).unwrap();
+    let mut b = vec![0; p * 128 * r];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, once, salt, passphrase, &mut b);
+
+    let mut x = vec![0; 32 * r];
+    let mut y = vec![0; 32 * r];
+    let mut v = vec![0; (1 << log_n) * 32 * r];
+    for chunk in b.chunks_mut(128 * r) {
+        for (word, bytes) in x.iter_mut().zip(chunk.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        ro_mix(&mut x, &mut y, &mut v);
+        for (word, bytes) in x.iter().zip(chunk.chunks_mut(4)) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, once, &b, passphrase, out);
+    zeroize(&mut b);
+    for words in [&mut x, &mut y, &mut v] {
+        clear(words);
+    }
+}
+
+/// The memory-hard mixing of one block `x` of `32 * r` words, with `v` holding `N` blocks
+fn ro_mix(x: &mut [u32], y: &mut [u32], v: &mut [u32]) {
+    let len = x.len();
+    let n = v.len() / len;
+    for block in v.chunks_mut(len) {
+        block.copy_from_slice(x);
+        block_mix(x, y);
+    }
+
+    for _ in 0..n {
+        // Integerify: the low bits of the first word of the last 64-byte block
+        let j = x[len - 16] as usize & (n - 1);
+        for (word, other) in x.iter_mut().zip(&v[j * len..(j + 1) * len]) {
+            *word ^= other;
+        }
+        block_mix(x, y);
+    }
+}
+
+/// BlockMix with Salsa20/8 over the `2 * r` 64-byte blocks in `b`, using `y` as scratch space
+fn block_mix(b: &mut [u32], y: &mut [u32]) {
+    let blocks = b.len() / 16;
+    let mut x = [0; 16];
+    x.copy_from_slice(&b[b.len() - 16..]);
+    for (i, block) in b.chunks(16).enumerate() {
+        for (word, other) in x.iter_mut().zip(block) {
+            *word ^= other;
+        }
+        salsa20_8(&mut x);
+        // Even blocks go to the first half of the output, odd blocks to the second half
+        let position = (i / 2 + (i % 2) * blocks / 2) * 16;
+        y[position..position + 16].copy_from_slice(&x);
+    }
+
+    b.copy_from_slice(y);
+    x.fill(0);
+}
+
+/// The Salsa20/8 core, with the input added to the output
+fn salsa20_8(block: &mut [u32; 16]) {
+    let mut x = *block;
+    for _ in 0..4 {
+        for [a, b, c, d] in [[0, 4, 8, 12], [5, 9, 13, 1], [10, 14, 2, 6], [15, 3, 7, 11]] {
+            quarter_round(&mut x, a, b, c, d);
+        }
+        for [a, b, c, d] in [[0, 1, 2, 3], [5, 6, 7, 4], [10, 11, 8, 9], [15, 12, 13, 14]] {
+            quarter_round(&mut x, a, b, c, d);
+        }
+    }
+
+    for (word, mixed) in block.iter_mut().zip(x) {
+        *word = word.wrapping_add(mixed);
+    }
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+    x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+    x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+    x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+}
+
+/// Overwrite `words` with zeroes, like [`zeroize()`]
+fn clear(words: &mut [u32]) {
+    for word in words.iter_mut() {
+        // Safety: `word` is a valid, aligned reference to a `u32`
+        unsafe { std::ptr::write_volatile(word, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rfc_7914_vectors() {
+        // RFC 7914 section 12
+        for (passphrase, salt, log_n, r, p, expected) in [
+            (
+                "",
+                "",
+                4,
+                1,
+                1,
+                "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+                 fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906",
+            ),
+            (
+                "password",
+                "NaCl",
+                10,
+                8,
+                16,
+                "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+                 2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640",
+            ),
+            (
+                "pleaseletmein",
+                "SodiumChloride",
+                14,
+                8,
+                1,
+                "7023bdcb3afd7348461c06cd81fd38ebfda8fbba904f8e3ea9b543f6545da1f2\
+                 d5432955613f0fcf62d49705242a9af9e61e85dc0d651e40dfcf017b45575887",
+            ),
+        ] {
+            let mut out = [0; 64];
+            derive(
+                passphrase.as_bytes(),
+                salt.as_bytes(),
+                log_n,
+                r,
+                p,
+                &mut out,
+            );
+            assert_eq!(out[..], unhex(expected)[..], "{passphrase:?}");
+        }
+    }
+}
@@ -0,0 +1,457 @@
+//! Pluggable challenge solvers
+//!
+//! A [`ChallengeSolver`] provisions the response for a challenge (for example, by creating a
+//! TXT record through a DNS provider's API) and removes it again once validation is done.
+//! [`Order::present_challenges()`] uses a solver to set up responses for all pending
+//! authorizations of an order.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::{mem, thread};
+
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request};
+use serde::Serialize;
+
+use crate::{ChallengeType, Error, HttpClient, Identifier, KeyAuthorization, Order};
+
+#[cfg(feature = "azure-dns")]
+mod azure;
+#[cfg(feature = "azure-dns")]
+pub use azure::{AzureAuth, AzureDnsSolver};
+#[cfg(feature = "cloudflare-dns")]
+mod cloudflare;
+#[cfg(feature = "cloudflare-dns")]
+pub use cloudflare::CloudflareSolver;
+#[cfg(feature = "desec-dns")]
+mod desec;
+#[cfg(feature = "desec-dns")]
+pub use desec::DesecSolver;
+#[cfg(feature = "digitalocean-dns")]
+mod digitalocean;
+#[cfg(feature = "digitalocean-dns")]
+pub use digitalocean::DigitalOceanSolver;
+#[cfg(feature = "dns-server")]
+mod dns_server;
+#[cfg(feature = "dns-server")]
+pub use dns_server::DnsServerSolver;
+#[cfg(feature = "gcloud-dns")]
+mod gcloud;
+#[cfg(feature = "gcloud-dns")]
+pub use gcloud::{GoogleCloudAuth, GoogleCloudDnsSolver};
+#[cfg(feature = "hetzner-dns")]
+mod hetzner;
+#[cfg(feature = "hetzner-dns")]
+pub use hetzner::HetznerSolver;
+#[cfg(any(feature = "azure-dns", feature = "gcloud-dns"))]
+mod oauth;
+#[cfg(any(
+    feature = "azure-dns",
+    feature = "cloudflare-dns",
+    feature = "desec-dns",
+    feature = "digitalocean-dns",
+    feature = "gcloud-dns",
+    feature = "hetzner-dns"
+))]
+mod provider;
+
+/// The future returned by [`ChallengeSolver`] methods
+pub type SolverFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + 'a>>;
+
+/// Provisions and removes challenge responses
+pub trait ChallengeSolver: Send + Sync {
+    /// The type of challenge this solver can complete
+    fn challenge_type(&self) -> ChallengeType;
+
+    /// Make the response for `challenge` available to the CA
+    fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a>;
+
+    /// Remove the response for `challenge`
+    fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a>;
+
+    /// Remove stale challenge responses for `domain` left behind by earlier runs
+    ///
+    /// The DNS-01 solvers for DNS providers present each response together with a TXT value
+    /// marking it as presented by this crate (`instant-acme-owned=` followed by the
+    /// response), which the CA ignores. They remove the marked responses and the markers
+    /// from the `_acme-challenge` TXT record for `domain`, leaving the responses of other ACME
+    /// clients and other values in place. This must not be run while other orders for
+    /// `domain` are being validated through this crate. The default implementation does
+    /// nothing.
+    fn gc<'a>(&'a self, domain: &'a str) -> SolverFuture<'a> {
+        let _ = domain;
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// A challenge to be completed by a [`ChallengeSolver`]
+#[derive(Debug)]
+pub struct SolverChallenge {
+    /// The identifier being authorized
+    pub identifier: Identifier,
+    /// The type of the challenge
+    pub r#type: ChallengeType,
+    /// The challenge URL
+    pub url: String,
+    /// The challenge token
+    pub token: String,
+    /// The key authorization for the challenge
+    pub key_authorization: KeyAuthorization,
+}
+
+impl SolverChallenge {
+    /// The domain being authorized, without any wildcard prefix
+    pub fn domain(&self) -> &str {
+        let name = self.identifier.value();
+        name.strip_prefix("*.").unwrap_or(name)
+    }
+
+    /// The name of the TXT record for a DNS-01 challenge, without a trailing dot
+    pub fn dns_record_name(&self) -> String {
+        record_name(self.domain())
+    }
+
+    /// The name of the TXT record for a DNS-01 challenge, relative to `zone`
+    ///
+    /// For `www.example.com` in the `example.com` zone, this is `_acme-challenge.www`.
+    pub fn relative_record_name(&self, zone: &str) -> Result<String, Error> {
+        relative_name(&self.dns_record_name(), zone)
+    }
+}
+
+/// Challenges presented by a [`ChallengeSolver`] that still need to be cleaned up
+///
+/// Call and await [`SolverGuard::cleanup()`] once the order is no longer pending, which
+/// reports whether the responses were removed.
+///
+/// Dropping the guard instead (because the task processing the order panicked or was
+/// cancelled) is a best-effort fallback: the remaining cleanups are started on a background
+/// thread with its own runtime, since they can't be awaited in `drop()`. Nothing waits for
+/// them, so they may not finish before the process exits, and failures are only logged (with
+/// the `tracing` feature). Leftovers can be removed later with [`ChallengeSolver::gc()`].
+pub struct SolverGuard {
+    solver: Arc<dyn ChallengeSolver>,
+    challenges: Vec<SolverChallenge>,
+}
+
+impl SolverGuard {
+    /// The challenges that have been presented
+    pub fn challenges(&self) -> &[SolverChallenge] {
+        &self.challenges
+    }
+
+    /// Clean up all presented challenges
+    ///
+    /// Attempts to clean up every challenge, even if some of them fail, and returns the
+    /// first error encountered.
+    pub async fn cleanup(mut self) -> Result<(), Error> {
+        let challenges = mem::take(&mut self.challenges);
+        cleanup(&*self.solver, &challenges).await
+    }
+}
+
+impl Drop for SolverGuard {
+    fn drop(&mut self) {
+        if self.challenges.is_empty() {
+            return;
+        }
+
+        let solver = self.solver.clone();
+        let challenges = mem::take(&mut self.challenges);
+        thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build();
+            let _result = match rt {
+                Ok(rt) => rt.block_on(cleanup(&*solver, &challenges)),
+                Err(e) => Err(e.into()),
+            };
+            #[cfg(feature = "tracing")]
+            if let Err(error) = _result {
+                tracing::warn!(%error, "failed to clean up challenges of a dropped guard");
+            }
+        });
+    }
+}
+
+impl Order {
+    /// Present challenge responses for all pending authorizations using `solver`
+    ///
+    /// Selects a challenge of type [`ChallengeSolver::challenge_type()`] for each pending
+    /// authorization, presents all responses and then marks the challenges as ready. Returns
+    /// a [`SolverGuard`] for the presented challenges, which should be cleaned up once the
+    /// order is no longer pending. If any step fails, the responses presented so far are
+    /// cleaned up before the error is returned.
+    ///
+    /// Authorizations that the server has reused from earlier orders are already valid and
+    /// are skipped; if all of them are, nothing is presented and the order can be finalized.
+    pub async fn present_challenges(
+        &mut self,
+        solver: Arc<dyn ChallengeSolver>,
+    ) -> Result<SolverGuard, Error> {
+        let guard = self.present(solver).await?;
+        self.ready(guard).await
+    }
+
+    /// Present challenge responses like [`Order::present_challenges()`], waiting until they
+    /// are visible from all `perspectives` before marking the challenges as ready
+    ///
+    /// If a response is not visible from every perspective within `timeout`, the presented
+    /// responses are cleaned up and [`Error::ChallengeNotPropagated`] is returned, listing
+    /// what each perspective saw and what was expected. The CA is not asked to validate, so
+    /// this does not count against its failed validation limit.
+    #[cfg(feature = "preflight")]
+    pub async fn present_challenges_visible(
+        &mut self,
+        solver: Arc<dyn ChallengeSolver>,
+        perspectives: &crate::preflight::Perspectives,
+        timeout: std::time::Duration,
+    ) -> Result<SolverGuard, Error> {
+        let guard = self.present(solver).await?;
+        let clock = self.account.client.config.clock.clone();
+        if let Err(e) = perspectives.wait(&guard.challenges, timeout, &*clock).await {
+            let _ = guard.cleanup().await;
+            return Err(e);
+        }
+        self.ready(guard).await
+    }
+
+    /// Present the responses for all pending authorizations, without marking them ready
+    async fn present(&mut self, solver: Arc<dyn ChallengeSolver>) -> Result<SolverGuard, Error> {
+        let selection = self.select_challenges(&[solver.challenge_type()]).await?;
+        if !selection.unsupported.is_empty() {
+            return Err(Error::Unsupported(
+                "challenge type not offered for all identifiers",
+            ));
+        }
+
+        let mut guard = SolverGuard {
+            solver,
+            challenges: Vec::with_capacity(selection.selected.len()),
+        };
+
+        for (identifier, challenge) in selection.selected {
+            let challenge = SolverChallenge {
+                key_authorization: self.key_authorization(&challenge),
+                identifier,
+                r#type: challenge.r#type,
+                url: challenge.url,
+                token: challenge.token,
+            };
+
+            // Add the challenge before presenting it, so partial responses get cleaned up
+            guard.challenges.push(challenge);
+            let challenge = &guard.challenges[guard.challenges.len() - 1];
+            if let Err(e) = guard.solver.present(challenge).await {
+                let _ = guard.cleanup().await;
+                return Err(e);
+            }
+        }
+
+        Ok(guard)
+    }
+
+    /// Mark the challenges presented by `guard` as ready
+    async fn ready(&mut self, guard: SolverGuard) -> Result<SolverGuard, Error> {
+        for idx in 0..guard.challenges.len() {
+            if let Err(e) = self.set_challenge_ready(&guard.challenges[idx].url).await {
+                let _ = guard.cleanup().await;
+                return Err(e);
+            }
+        }
+
+        Ok(guard)
+    }
+}
+
+/// Clean up all `challenges` with `solver`
+///
+/// Attempts to clean up every challenge, even if some of them fail, and returns the
+/// first error encountered.
+pub async fn cleanup(
+    solver: &dyn ChallengeSolver,
+    challenges: &[SolverChallenge],
+) -> Result<(), Error> {
+    let mut result = Ok(());
+    for challenge in challenges {
+        if let (Err(e), Ok(())) = (solver.cleanup(challenge).await, &result) {
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// The name of the DNS-01 TXT record for `domain`, without a trailing dot
+fn record_name(domain: &str) -> String {
+    format!("_acme-challenge.{}", domain.trim_start_matches("*."))
+}
+
+/// Get the name of `record` relative to `zone`
+fn relative_name(record: &str, zone: &str) -> Result<String, Error> {
+    match record.strip_suffix(zone.trim_end_matches('.')) {
+        Some(relative) if relative.ends_with('.') => Ok(relative.trim_end_matches('.').to_owned()),
+        _ => Err(Error::Config("challenge record is not in the DNS zone")),
+    }
+}
+
+/// A DNS-01 solver that runs an external command
+///
+/// The command is run as `<program> [args...] <action> <record name> <value>`, where the action
+/// is `present` or `cleanup` and the record name is the fully qualified name of the TXT record
+/// (with a trailing dot). The same values are also passed in the `ACME_ACTION`,
+/// `ACME_DOMAIN`, `ACME_RECORD_NAME` and `ACME_RECORD_VALUE` environment variables. The
+/// command must exit successfully once the record has been created or removed.
+///
+/// This is compatible with the interface of lego's `exec` DNS provider.
+#[cfg(feature = "exec-solver")]
+#[derive(Clone, Debug)]
+pub struct ExecSolver {
+    program: std::path::PathBuf,
+    args: Vec<String>,
+}
+
+#[cfg(feature = "exec-solver")]
+impl ExecSolver {
+    /// Create a solver that runs `program`
+    pub fn new(program: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Pass an extra argument to the command, before the action
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    async fn run(&self, action: &str, challenge: &SolverChallenge) -> Result<(), Error> {
+        let record_name = format!("{}.", challenge.dns_record_name());
+        let value = challenge.key_authorization.dns_value();
+        let status = tokio::process::Command::new(&self.program)
+            .args(&self.args)
+            .args([action, &record_name, &value])
+            .env("ACME_ACTION", action)
+            .env("ACME_DOMAIN", challenge.domain())
+            .env("ACME_RECORD_NAME", &record_name)
+            .env("ACME_RECORD_VALUE", &value)
+            .kill_on_drop(true)
+            .status()
+            .await?;
+
+        match status.success() {
+            true => Ok(()),
+            false => Err(Error::Backend {
+                service: "solver command",
+                reason: status.to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(feature = "exec-solver")]
+impl ChallengeSolver for ExecSolver {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Dns01
+    }
+
+    fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(self.run("present", challenge))
+    }
+
+    fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(self.run("cleanup", challenge))
+    }
+}
+
+/// A DNS-01 solver that calls a webhook
+///
+/// For each action, a `POST` request is sent to the configured URL with a JSON body like
+/// this, and the webhook must respond with a `2xx` status once the record has been created
+/// or removed:
+///
+/// ```json
+/// {
+///   "action": "present",
+///   "domain": "example.com",
+///   "record_name": "_acme-challenge.example.com.",
+///   "value": "LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"
+/// }
+/// ```
+pub struct WebhookSolver {
+    url: String,
+    bearer_token: Option<String>,
+    http: Box<dyn HttpClient>,
+}
+
+impl WebhookSolver {
+    /// Create a solver that calls the webhook at `url`
+    #[cfg(feature = "hyper-rustls")]
+    pub fn new(url: String) -> Self {
+        Self::with_http(url, Box::<crate::DefaultClient>::default())
+    }
+
+    /// Create a solver that calls the webhook at `url` with a custom HTTP client
+    pub fn with_http(url: String, http: Box<dyn HttpClient>) -> Self {
+        Self {
+            url,
+            bearer_token: None,
+            http,
+        }
+    }
+
+    /// Authenticate to the webhook with the given bearer token
+    pub fn bearer_token(mut self, token: String) -> Self {
+        self.bearer_token = Some(token);
+        self
+    }
+
+    async fn call(&self, action: &str, challenge: &SolverChallenge) -> Result<(), Error> {
+        #[derive(Serialize)]
+        struct WebhookRequest<'a> {
+            action: &'a str,
+            domain: &'a str,
+            record_name: &'a str,
+            value: &'a str,
+        }
+
+        let body = serde_json::to_vec(&WebhookRequest {
+            action,
+            domain: challenge.domain(),
+            record_name: &format!("{}.", challenge.dns_record_name()),
+            value: &challenge.key_authorization.dns_value(),
+        })?;
+
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(token) = &self.bearer_token {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        let request = request
+            .body(Body::from(body))
+            .map_err(|_| Error::Config("invalid webhook URL"))?;
+        match self.http.request(request).await?.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(Error::backend_status("webhook solver", status)),
+        }
+    }
+}
+
+impl ChallengeSolver for WebhookSolver {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Dns01
+    }
+
+    fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(self.call("present", challenge))
+    }
+
+    fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(self.call("cleanup", challenge))
+    }
+}
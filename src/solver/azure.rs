@@ -0,0 +1,480 @@
+//! DNS-01 solver for Azure DNS
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use super::oauth::TokenCache;
+use super::provider::{challenge_values, owned_values, send};
+use super::{record_name, relative_name, ChallengeSolver, SolverChallenge, SolverFuture};
+use crate::preflight::DohResolver;
+use crate::{form_encode, ChallengeType, Clock, Error, HttpClient, SystemClock};
+
+/// Credentials for the Azure Resource Manager API
+pub struct AzureAuth(Credentials);
+
+impl AzureAuth {
+    /// Authenticate with the managed identity of the current Azure resource
+    ///
+    /// Pass the client ID of a user-assigned identity, or `None` to use the system-assigned
+    /// identity. The identity needs the `DNS Zone Contributor` role on the zone.
+    pub fn managed_identity(client_id: Option<String>) -> Self {
+        Self(Credentials::ManagedIdentity { client_id })
+    }
+
+    /// Authenticate as a service principal with a client secret
+    pub fn client_secret(tenant_id: String, client_id: String, client_secret: String) -> Self {
+        Self(Credentials::ClientSecret {
+            tenant_id,
+            client_id,
+            client_secret,
+        })
+    }
+}
+
+enum Credentials {
+    ManagedIdentity {
+        client_id: Option<String>,
+    },
+    ClientSecret {
+        tenant_id: String,
+        client_id: String,
+        client_secret: String,
+    },
+}
+
+/// A DNS-01 solver that manages TXT records in an Azure DNS zone
+///
+/// Records are added to (and removed from) the existing TXT record set for the challenge
+/// name, so that the same name can be used for multiple challenges at once. Azure DNS
+/// does not report when a change has reached all of its name servers, so after presenting a
+/// response the solver looks it up through a public resolver until it is visible (up to 5
+/// minutes, which covers negative caching of the name for the zone's default SOA TTL).
+pub struct AzureDnsSolver {
+    subscription_id: String,
+    resource_group: String,
+    zone: String,
+    auth: AzureAuth,
+    http: Arc<dyn HttpClient>,
+    resolver: DohResolver,
+    token: TokenCache,
+    clock: Arc<dyn Clock>,
+}
+
+impl AzureDnsSolver {
+    /// Create a solver for the DNS zone `zone` (like `example.com`) in the given resource group
+    #[cfg(feature = "hyper-rustls")]
+    pub fn new(
+        subscription_id: String,
+        resource_group: String,
+        zone: String,
+        auth: AzureAuth,
+    ) -> Self {
+        let http = super::provider::default_http();
+        Self::with_http(subscription_id, resource_group, zone, auth, http)
+    }
+
+    /// Create a solver with a custom HTTP client
+    ///
+    /// The HTTP client must support plain HTTP when using [`AzureAuth::managed_identity()`].
+    /// It is also used to look up presented responses through Google's public resolver.
+    pub fn with_http(
+        subscription_id: String,
+        resource_group: String,
+        zone: String,
+        auth: AzureAuth,
+        http: Box<dyn HttpClient>,
+    ) -> Self {
+        let http = Arc::<dyn HttpClient>::from(http);
+        Self {
+            subscription_id,
+            resource_group,
+            zone: zone.trim_end_matches('.').to_owned(),
+            auth,
+            resolver: DohResolver::with_http(GOOGLE_DOH.to_owned(), Box::new(http.clone())),
+            http,
+            token: TokenCache::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Time token expiry and propagation polling with `clock` (defaults to [`SystemClock`])
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Look up presented responses through `resolver` (defaults to Google's public resolver)
+    pub fn resolver(mut self, resolver: DohResolver) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Wait until the response for `challenge` is visible through the resolver
+    async fn propagated(&self, challenge: &SolverChallenge) -> Result<(), Error> {
+        let name = challenge.dns_record_name();
+        let value = challenge.key_authorization.dns_value();
+        for _ in 0..PROPAGATION_POLLS {
+            // Lookup failures are retried like missing records until the deadline
+            if let Ok(values) = self.resolver.txt(&name).await {
+                if values.contains(&value) {
+                    return Ok(());
+                }
+            }
+
+            self.clock.sleep(PROPAGATION_INTERVAL).await;
+        }
+
+        Err(Error::Timeout("Azure DNS propagation"))
+    }
+
+    /// Apply `edit` to the values of the TXT record set called `relative` in the zone
+    async fn update(
+        &self,
+        relative: String,
+        edit: impl FnOnce(&mut Vec<String>),
+    ) -> Result<(), Error> {
+        let url = format!(
+            "https://management.azure.com/subscriptions/{}/resourceGroups/{}\
+             /providers/Microsoft.Network/dnsZones/{}/TXT/{}?api-version={API_VERSION}",
+            self.subscription_id, self.resource_group, self.zone, relative
+        );
+
+        let (status, body) = self.request(Method::GET, &url, Body::empty()).await?;
+        let old = match status {
+            StatusCode::NOT_FOUND => Vec::new(),
+            status if status.is_success() => serde_json::from_slice::<RecordSet>(&body)?
+                .properties
+                .txt_records
+                .into_iter()
+                .map(|record| record.value.concat())
+                .collect(),
+            status => return Err(Error::backend_status("Azure DNS", status)),
+        };
+
+        let mut values = old.clone();
+        edit(&mut values);
+        if values == old {
+            return Ok(());
+        }
+
+        let (status, _) = match values.is_empty() {
+            true => self.request(Method::DELETE, &url, Body::empty()).await?,
+            false => {
+                let body = serde_json::to_vec(&RecordSet {
+                    properties: Properties {
+                        ttl: 60,
+                        txt_records: values
+                            .into_iter()
+                            .map(|value| TxtRecord { value: vec![value] })
+                            .collect(),
+                    },
+                })?;
+                self.request(Method::PUT, &url, Body::from(body)).await?
+            }
+        };
+
+        match status.is_success() {
+            true => Ok(()),
+            false => Err(Error::backend_status("Azure DNS", status)),
+        }
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        url: &str,
+        body: Body,
+    ) -> Result<(StatusCode, hyper::body::Bytes), Error> {
+        let request = Request::builder()
+            .method(method)
+            .uri(url)
+            .header(
+                AUTHORIZATION,
+                format!("Bearer {}", self.access_token().await?),
+            )
+            .header(CONTENT_TYPE, "application/json");
+        send(&*self.http, request, body).await
+    }
+
+    async fn access_token(&self) -> Result<String, Error> {
+        if let Some(token) = self.token.get(&*self.clock) {
+            return Ok(token);
+        }
+
+        let (status, body) = match &self.auth.0 {
+            Credentials::ManagedIdentity { client_id } => {
+                let mut url = format!(
+                    "{IMDS_TOKEN_URL}?api-version=2018-02-01&resource={}",
+                    form_encode(RESOURCE)
+                );
+                if let Some(client_id) = client_id {
+                    url.push_str(&format!("&client_id={}", form_encode(client_id)));
+                }
+
+                let request = Request::builder().uri(url).header("Metadata", "true");
+                send(&*self.http, request, Body::empty()).await?
+            }
+            Credentials::ClientSecret {
+                tenant_id,
+                client_id,
+                client_secret,
+            } => {
+                let request = Request::builder()
+                    .method(Method::POST)
+                    .uri(format!(
+                        "https://login.microsoftonline.com/{tenant_id}/oauth2/v2.0/token"
+                    ))
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+                let body = format!(
+                    "grant_type=client_credentials&client_id={}&client_secret={}&scope={}",
+                    form_encode(client_id),
+                    form_encode(client_secret),
+                    form_encode(&format!("{RESOURCE}.default")),
+                );
+                send(&*self.http, request, Body::from(body)).await?
+            }
+        };
+
+        match status.is_success() {
+            true => self.token.set(&body, &*self.clock),
+            false => Err(Error::backend_status("Azure access token", status)),
+        }
+    }
+}
+
+impl ChallengeSolver for AzureDnsSolver {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Dns01
+    }
+
+    fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(async move {
+            let relative = challenge.relative_record_name(&self.zone)?;
+            self.update(relative, move |values| {
+                for value in challenge_values(challenge) {
+                    if !values.contains(&value) {
+                        values.push(value);
+                    }
+                }
+            })
+            .await?;
+            self.propagated(challenge).await
+        })
+    }
+
+    fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        let remove = challenge_values(challenge);
+        Box::pin(async move {
+            let relative = challenge.relative_record_name(&self.zone)?;
+            self.update(relative, move |values| {
+                values.retain(|v| !remove.contains(v))
+            })
+            .await
+        })
+    }
+
+    fn gc<'a>(&'a self, domain: &'a str) -> SolverFuture<'a> {
+        Box::pin(async move {
+            let relative = relative_name(&record_name(domain), &self.zone)?;
+            self.update(relative, |values| {
+                let owned = owned_values(values);
+                values.retain(|v| !owned.contains(v))
+            })
+            .await
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct RecordSet {
+    properties: Properties,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Properties {
+    #[serde(rename = "TTL")]
+    ttl: u32,
+    #[serde(rename = "TXTRecords", default)]
+    txt_records: Vec<TxtRecord>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct TxtRecord {
+    value: Vec<String>,
+}
+
+/// Lookups of a presented response: every 5 seconds for up to 5 minutes
+const PROPAGATION_POLLS: u32 = 60;
+const PROPAGATION_INTERVAL: Duration = Duration::from_secs(5);
+
+const GOOGLE_DOH: &str = "https://dns.google/resolve";
+const API_VERSION: &str = "2018-05-01";
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const RESOURCE: &str = "https://management.azure.com/";
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+
+    use hyper::Response;
+
+    use super::*;
+    use crate::mock::MockClock;
+    use crate::{Identifier, KeyAuthorization};
+
+    #[tokio::test]
+    async fn propagation() {
+        let (azure, clock) = (FakeAzure::new(3), MockClock::new());
+        let solver = solver(&azure, &clock);
+        let challenge = challenge();
+
+        let (result, _) = tokio::join!(solver.present(&challenge), drive(&clock));
+        result.unwrap();
+        assert_eq!(azure.0.lookups.load(Ordering::SeqCst), 3);
+        assert!(visible(&azure.0.values.lock().unwrap(), &challenge));
+    }
+
+    #[tokio::test]
+    async fn propagation_timeout() {
+        let (azure, clock) = (FakeAzure::new(u32::MAX), MockClock::new());
+        let (solver, challenge) = (solver(&azure, &clock), challenge());
+
+        let (result, _) = tokio::join!(solver.present(&challenge), drive(&clock));
+        assert!(matches!(
+            result,
+            Err(Error::Timeout("Azure DNS propagation"))
+        ));
+        assert_eq!(azure.0.lookups.load(Ordering::SeqCst), PROPAGATION_POLLS);
+    }
+
+    fn solver(azure: &FakeAzure, clock: &MockClock) -> AzureDnsSolver {
+        let auth = AzureAuth::client_secret("tenant".into(), "client".into(), "secret".into());
+        AzureDnsSolver::with_http(
+            "subscription".into(),
+            "group".into(),
+            "example.com".into(),
+            auth,
+            Box::new(azure.clone()),
+        )
+        .clock(Arc::new(clock.clone()))
+    }
+
+    fn challenge() -> SolverChallenge {
+        SolverChallenge {
+            identifier: Identifier::Dns("www.example.com".to_owned()),
+            r#type: ChallengeType::Dns01,
+            url: "https://acme.example/chall/1".to_owned(),
+            token: "token".to_owned(),
+            key_authorization: KeyAuthorization::new("token", "thumbprint"),
+        }
+    }
+
+    fn visible(values: &[String], challenge: &SolverChallenge) -> bool {
+        values.contains(&challenge.key_authorization.dns_value())
+    }
+
+    /// Advance `clock` past every propagation poll
+    async fn drive(clock: &MockClock) {
+        for _ in 0..PROPAGATION_POLLS * 2 {
+            tokio::task::yield_now().await;
+            clock.advance(PROPAGATION_INTERVAL);
+        }
+    }
+
+    /// Azure token, Resource Manager and DNS over HTTPS endpoints, where records become
+    /// visible on the `visible_after`th lookup
+    #[derive(Clone)]
+    struct FakeAzure(Arc<Fake>);
+
+    struct Fake {
+        values: Mutex<Vec<String>>,
+        lookups: AtomicU32,
+        visible_after: u32,
+    }
+
+    impl FakeAzure {
+        fn new(visible_after: u32) -> Self {
+            Self(Arc::new(Fake {
+                values: Mutex::default(),
+                lookups: AtomicU32::new(0),
+                visible_after,
+            }))
+        }
+
+        fn respond(&self, method: &Method, host: &str, body: &[u8]) -> (StatusCode, String) {
+            match (method, host) {
+                (&Method::POST, "login.microsoftonline.com") => (
+                    StatusCode::OK,
+                    r#"{"access_token":"token","expires_in":3600}"#.to_owned(),
+                ),
+                (&Method::GET, "management.azure.com") => {
+                    let values = self.0.values.lock().unwrap().clone();
+                    let set = RecordSet {
+                        properties: Properties {
+                            ttl: 60,
+                            txt_records: values
+                                .iter()
+                                .map(|value| TxtRecord {
+                                    value: vec![value.clone()],
+                                })
+                                .collect(),
+                        },
+                    };
+                    match values.is_empty() {
+                        true => (StatusCode::NOT_FOUND, String::new()),
+                        false => (StatusCode::OK, serde_json::to_string(&set).unwrap()),
+                    }
+                }
+                (&Method::PUT, "management.azure.com") => {
+                    let set = serde_json::from_slice::<RecordSet>(body).unwrap();
+                    *self.0.values.lock().unwrap() = set
+                        .properties
+                        .txt_records
+                        .into_iter()
+                        .map(|record| record.value.concat())
+                        .collect();
+                    (StatusCode::OK, String::new())
+                }
+                (&Method::GET, "dns.google") => {
+                    let lookups = self.0.lookups.fetch_add(1, Ordering::SeqCst) + 1;
+                    let values = match lookups >= self.0.visible_after {
+                        true => self.0.values.lock().unwrap().clone(),
+                        false => Vec::new(),
+                    };
+                    let answer = values
+                        .iter()
+                        .map(|value| serde_json::json!({"type": 16, "data": value}))
+                        .collect::<Vec<_>>();
+                    let rsp = serde_json::json!({"Status": 0, "Answer": answer});
+                    (StatusCode::OK, rsp.to_string())
+                }
+                _ => (StatusCode::NOT_FOUND, String::new()),
+            }
+        }
+    }
+
+    impl HttpClient for FakeAzure {
+        fn request(
+            &self,
+            req: Request<Body>,
+        ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>> {
+            let azure = self.clone();
+            Box::pin(async move {
+                let (method, host) = (req.method().clone(), req.uri().host().map(str::to_owned));
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                let (status, body) = azure.respond(&method, host.as_deref().unwrap_or(""), &body);
+                Ok(Response::builder()
+                    .status(status)
+                    .body(Body::from(body))
+                    .unwrap())
+            })
+        }
+    }
+}
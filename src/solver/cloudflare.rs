@@ -0,0 +1,180 @@
+//! DNS-01 solver for Cloudflare DNS
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::provider::{challenge_values, owned_values, send, unquote};
+use super::{record_name, ChallengeSolver, SolverChallenge, SolverFuture};
+use crate::{form_encode, ChallengeType, Error, HttpClient};
+
+/// A DNS-01 solver that manages TXT records in a Cloudflare zone
+///
+/// Authenticates with an API token that has the `Zone:Read` and `DNS:Edit` permissions for
+/// the zone.
+pub struct CloudflareSolver {
+    zone: String,
+    token: String,
+    http: Box<dyn HttpClient>,
+    zone_id: Mutex<Option<String>>,
+}
+
+impl CloudflareSolver {
+    /// Create a solver for the Cloudflare zone `zone` (like `example.com`)
+    #[cfg(feature = "hyper-rustls")]
+    pub fn new(zone: String, token: String) -> Self {
+        Self::with_http(zone, token, super::provider::default_http())
+    }
+
+    /// Create a solver with a custom HTTP client
+    pub fn with_http(zone: String, token: String, http: Box<dyn HttpClient>) -> Self {
+        Self {
+            zone: zone.trim_end_matches('.').to_owned(),
+            token,
+            http,
+            zone_id: Mutex::new(None),
+        }
+    }
+
+    async fn add(&self, challenge: &SolverChallenge) -> Result<(), Error> {
+        let url = format!("{API_URL}/zones/{}/dns_records", self.zone_id().await?);
+        for value in challenge_values(challenge) {
+            let body = serde_json::to_vec(&json!({
+                "type": "TXT",
+                "name": challenge.dns_record_name(),
+                "content": value,
+                "ttl": 60,
+            }))?;
+
+            match self.request(Method::POST, &url, Body::from(body)).await? {
+                (status, _) if status.is_success() => {}
+                (status, _) => return Err(Error::backend_status("Cloudflare DNS", status)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delete the TXT records called `name` (a FQDN without trailing dot) with the values
+    /// that `select` picks from all of their values
+    async fn remove(
+        &self,
+        name: &str,
+        select: impl FnOnce(&[String]) -> HashSet<String>,
+    ) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct Records {
+            result: Vec<Record>,
+        }
+
+        #[derive(Deserialize)]
+        struct Record {
+            id: String,
+            content: String,
+        }
+
+        let zone_id = self.zone_id().await?;
+        let url = format!(
+            "{API_URL}/zones/{zone_id}/dns_records?type=TXT&name={}&per_page=100",
+            form_encode(name)
+        );
+        let records = match self.request(Method::GET, &url, Body::empty()).await? {
+            (status, body) if status.is_success() => serde_json::from_slice::<Records>(&body)?,
+            (status, _) => return Err(Error::backend_status("Cloudflare DNS", status)),
+        };
+
+        let values = records
+            .result
+            .iter()
+            .map(|record| unquote(&record.content).to_owned())
+            .collect::<Vec<_>>();
+        let selected = select(&values);
+        for (record, value) in records.result.iter().zip(&values) {
+            if !selected.contains(value) {
+                continue;
+            }
+
+            let url = format!("{API_URL}/zones/{zone_id}/dns_records/{}", record.id);
+            match self.request(Method::DELETE, &url, Body::empty()).await?.0 {
+                StatusCode::OK | StatusCode::NOT_FOUND => {}
+                status => return Err(Error::backend_status("Cloudflare DNS", status)),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn zone_id(&self) -> Result<String, Error> {
+        #[derive(Deserialize)]
+        struct Zones {
+            result: Vec<Zone>,
+        }
+
+        #[derive(Deserialize)]
+        struct Zone {
+            id: String,
+            name: String,
+        }
+
+        if let Some(id) = &*self.zone_id.lock().unwrap() {
+            return Ok(id.clone());
+        }
+
+        let url = format!("{API_URL}/zones?name={}", form_encode(&self.zone));
+        let zones = match self.request(Method::GET, &url, Body::empty()).await? {
+            (status, body) if status.is_success() => serde_json::from_slice::<Zones>(&body)?,
+            (status, _) => return Err(Error::backend_status("Cloudflare DNS", status)),
+        };
+
+        let id = zones
+            .result
+            .into_iter()
+            .find(|zone| zone.name == self.zone)
+            .map(|zone| zone.id)
+            .ok_or(Error::Str("Cloudflare zone not found"))?;
+        *self.zone_id.lock().unwrap() = Some(id.clone());
+        Ok(id)
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        url: &str,
+        body: Body,
+    ) -> Result<(StatusCode, hyper::body::Bytes), Error> {
+        let request = Request::builder()
+            .method(method)
+            .uri(url)
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .header(CONTENT_TYPE, "application/json");
+        send(&*self.http, request, body).await
+    }
+}
+
+impl ChallengeSolver for CloudflareSolver {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Dns01
+    }
+
+    fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(self.add(challenge))
+    }
+
+    fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(async move {
+            let name = challenge.dns_record_name();
+            let values = challenge_values(challenge).into_iter().collect();
+            self.remove(&name, |_| values).await
+        })
+    }
+
+    fn gc<'a>(&'a self, domain: &'a str) -> SolverFuture<'a> {
+        Box::pin(async move { self.remove(&record_name(domain), owned_values).await })
+    }
+}
+
+const API_URL: &str = "https://api.cloudflare.com/client/v4";
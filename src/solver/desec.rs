@@ -0,0 +1,142 @@
+//! DNS-01 solver for deSEC
+
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::provider::{challenge_values, owned_values, send, unquote};
+use super::{record_name, relative_name, ChallengeSolver, SolverChallenge, SolverFuture};
+use crate::{ChallengeType, Error, HttpClient};
+
+/// A DNS-01 solver that manages TXT records for a domain hosted at [deSEC](https://desec.io/)
+///
+/// Records are added to (and removed from) the existing TXT record set for the challenge
+/// name, so that the same name can be used for multiple challenges at once. deSEC takes up
+/// to a minute to distribute changes to its anycast name servers and does not report when
+/// this is done, so callers should wait (or check the public DNS) before marking the
+/// challenges as ready.
+pub struct DesecSolver {
+    domain: String,
+    token: String,
+    http: Box<dyn HttpClient>,
+}
+
+impl DesecSolver {
+    /// Create a solver for the deSEC domain `domain` (like `example.com`)
+    #[cfg(feature = "hyper-rustls")]
+    pub fn new(domain: String, token: String) -> Self {
+        Self::with_http(domain, token, super::provider::default_http())
+    }
+
+    /// Create a solver with a custom HTTP client
+    pub fn with_http(domain: String, token: String, http: Box<dyn HttpClient>) -> Self {
+        Self {
+            domain: domain.trim_end_matches('.').to_owned(),
+            token,
+            http,
+        }
+    }
+
+    /// Apply `edit` to the TXT record set called `subname` in the domain
+    async fn update(
+        &self,
+        subname: String,
+        edit: impl FnOnce(&mut Vec<String>),
+    ) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct RecordSet {
+            records: Vec<String>,
+        }
+
+        let url = format!("{API_URL}/{}/rrsets/{subname}/TXT/", self.domain);
+        let old = match self.request(Method::GET, &url, Body::empty()).await? {
+            (StatusCode::NOT_FOUND, _) => Vec::new(),
+            (status, body) if status.is_success() => {
+                serde_json::from_slice::<RecordSet>(&body)?.records
+            }
+            (status, _) => return Err(Error::backend_status("deSEC", status)),
+        };
+
+        let mut records = old.clone();
+        edit(&mut records);
+        if records == old {
+            return Ok(());
+        }
+
+        // A bulk PUT creates, replaces or (with an empty record list) deletes the record set
+        let body = serde_json::to_vec(&json!([{
+            "subname": subname,
+            "type": "TXT",
+            "ttl": MIN_TTL,
+            "records": records,
+        }]))?;
+
+        let url = format!("{API_URL}/{}/rrsets/", self.domain);
+        match self.request(Method::PUT, &url, Body::from(body)).await? {
+            (status, _) if status.is_success() => Ok(()),
+            (status, _) => Err(Error::backend_status("deSEC", status)),
+        }
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        url: &str,
+        body: Body,
+    ) -> Result<(StatusCode, hyper::body::Bytes), Error> {
+        let request = Request::builder()
+            .method(method)
+            .uri(url)
+            .header(AUTHORIZATION, format!("Token {}", self.token))
+            .header(CONTENT_TYPE, "application/json");
+        send(&*self.http, request, body).await
+    }
+}
+
+impl ChallengeSolver for DesecSolver {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Dns01
+    }
+
+    fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(async move {
+            let subname = challenge.relative_record_name(&self.domain)?;
+            self.update(subname, move |records| {
+                for value in challenge_values(challenge) {
+                    let value = format!("\"{value}\"");
+                    if !records.contains(&value) {
+                        records.push(value);
+                    }
+                }
+            })
+            .await
+        })
+    }
+
+    fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        let values = challenge_values(challenge);
+        Box::pin(async move {
+            let subname = challenge.relative_record_name(&self.domain)?;
+            self.update(subname, move |records| {
+                records.retain(|v| !values.iter().any(|value| value == unquote(v)))
+            })
+            .await
+        })
+    }
+
+    fn gc<'a>(&'a self, domain: &'a str) -> SolverFuture<'a> {
+        Box::pin(async move {
+            let subname = relative_name(&record_name(domain), &self.domain)?;
+            self.update(subname, |records| {
+                let owned = owned_values(records);
+                records.retain(|v| !owned.contains(unquote(v)))
+            })
+            .await
+        })
+    }
+}
+
+const API_URL: &str = "https://desec.io/api/v1/domains";
+/// deSEC rejects record sets with a lower TTL
+const MIN_TTL: u32 = 3600;
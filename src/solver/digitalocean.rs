@@ -0,0 +1,145 @@
+//! DNS-01 solver for DigitalOcean DNS
+
+use std::collections::HashSet;
+
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::provider::{challenge_values, owned_values, send, unquote};
+use super::{record_name, ChallengeSolver, SolverChallenge, SolverFuture};
+use crate::{form_encode, ChallengeType, Error, HttpClient};
+
+/// A DNS-01 solver that manages TXT records in a DigitalOcean domain
+///
+/// Authenticates with a personal access token that has the `domain:create`, `domain:read`
+/// and `domain:delete` scopes.
+pub struct DigitalOceanSolver {
+    domain: String,
+    token: String,
+    http: Box<dyn HttpClient>,
+}
+
+impl DigitalOceanSolver {
+    /// Create a solver for the DigitalOcean domain `domain` (like `example.com`)
+    #[cfg(feature = "hyper-rustls")]
+    pub fn new(domain: String, token: String) -> Self {
+        Self::with_http(domain, token, super::provider::default_http())
+    }
+
+    /// Create a solver with a custom HTTP client
+    pub fn with_http(domain: String, token: String, http: Box<dyn HttpClient>) -> Self {
+        Self {
+            domain: domain.trim_end_matches('.').to_owned(),
+            token,
+            http,
+        }
+    }
+
+    async fn add(&self, challenge: &SolverChallenge) -> Result<(), Error> {
+        let url = format!("{API_URL}/{}/records", self.domain);
+        for value in challenge_values(challenge) {
+            let body = serde_json::to_vec(&json!({
+                "type": "TXT",
+                "name": challenge.relative_record_name(&self.domain)?,
+                "data": value,
+                "ttl": 30,
+            }))?;
+
+            match self.request(Method::POST, &url, Body::from(body)).await? {
+                (StatusCode::CREATED, _) => {}
+                (status, _) => return Err(Error::backend_status("DigitalOcean DNS", status)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delete the TXT records called `name` (a FQDN without trailing dot) with the values
+    /// that `select` picks from all of their values
+    async fn remove(
+        &self,
+        name: &str,
+        select: impl FnOnce(&[String]) -> HashSet<String>,
+    ) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct Records {
+            domain_records: Vec<Record>,
+        }
+
+        #[derive(Deserialize)]
+        struct Record {
+            id: u64,
+            data: String,
+        }
+
+        let url = format!(
+            "{API_URL}/{}/records?type=TXT&name={}",
+            self.domain,
+            form_encode(name)
+        );
+        let records = match self.request(Method::GET, &url, Body::empty()).await? {
+            (status, body) if status.is_success() => serde_json::from_slice::<Records>(&body)?,
+            (status, _) => return Err(Error::backend_status("DigitalOcean DNS", status)),
+        };
+
+        let values = records
+            .domain_records
+            .iter()
+            .map(|record| unquote(&record.data).to_owned())
+            .collect::<Vec<_>>();
+        let selected = select(&values);
+        for (record, value) in records.domain_records.iter().zip(&values) {
+            if !selected.contains(value) {
+                continue;
+            }
+
+            let url = format!("{API_URL}/{}/records/{}", self.domain, record.id);
+            match self.request(Method::DELETE, &url, Body::empty()).await?.0 {
+                StatusCode::NO_CONTENT | StatusCode::NOT_FOUND => {}
+                status => return Err(Error::backend_status("DigitalOcean DNS", status)),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        url: &str,
+        body: Body,
+    ) -> Result<(StatusCode, hyper::body::Bytes), Error> {
+        let request = Request::builder()
+            .method(method)
+            .uri(url)
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .header(CONTENT_TYPE, "application/json");
+        send(&*self.http, request, body).await
+    }
+}
+
+impl ChallengeSolver for DigitalOceanSolver {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Dns01
+    }
+
+    fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(self.add(challenge))
+    }
+
+    fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(async move {
+            let name = challenge.dns_record_name();
+            let values = challenge_values(challenge).into_iter().collect();
+            self.remove(&name, |_| values).await
+        })
+    }
+
+    fn gc<'a>(&'a self, domain: &'a str) -> SolverFuture<'a> {
+        Box::pin(async move { self.remove(&record_name(domain), owned_values).await })
+    }
+}
+
+const API_URL: &str = "https://api.digitalocean.com/v2/domains";
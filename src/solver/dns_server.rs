@@ -0,0 +1,434 @@
+//! DNS-01 solver that answers challenge queries with an embedded DNS server
+//!
+//! The server is a small responder written for this solver instead of being built on
+//! hickory-dns. It only needs to parse one question and answer `TXT` queries for the names
+//! presented through the solver, while hickory's server would bring its full protocol stack
+//! and dependency tree to every user of the feature. Everything else is left out: queries over TCP, EDNS, DNSSEC, zone transfers and `SOA` or `NS` records,
+//! which the parent zone provides through the delegation. Malformed queries get `FORMERR`,
+//! other opcodes `NOTIMP`, and answers that don't fit in a 512-byte UDP response are sent
+//! truncated, without records.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::task::JoinHandle;
+
+use super::{ChallengeSolver, SolverChallenge, SolverFuture};
+use crate::{ChallengeType, Error};
+
+/// A DNS-01 solver that serves challenge TXT records from an embedded DNS server
+///
+/// The server is authoritative for the configured zones and only knows the `_acme-challenge`
+/// TXT records presented through the solver. Delegate each zone (or just its
+/// `_acme-challenge` names) to the host running the solver with `NS` records, or point
+/// `CNAME` records for the challenge names into a zone served here, to issue wildcard
+/// certificates without a DNS provider API.
+///
+/// The server answers queries over UDP on the bound address. Other names in the zones get
+/// empty answers and names outside of them are refused. A response fits about 8 values for
+/// one name, plenty for a wildcard and its base domain validated at once; beyond that,
+/// resolvers get a truncated response. The server stops when the solver is dropped.
+pub struct DnsServerSolver {
+    zones: Vec<String>,
+    records: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl DnsServerSolver {
+    /// Start serving the DNS `zones` (like `example.com`) on `addr`
+    ///
+    /// Must be called from within a Tokio runtime. To be reachable by the CA, the server
+    /// usually needs to listen on port 53 of a public address.
+    pub async fn bind(
+        addr: impl ToSocketAddrs,
+        zones: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Self, Error> {
+        let zones = zones
+            .into_iter()
+            .map(|zone| normalize(&zone.into()))
+            .collect::<Vec<_>>();
+        let socket = UdpSocket::bind(addr).await?;
+        let local_addr = socket.local_addr()?;
+        let records = Arc::new(Mutex::new(HashMap::new()));
+        let task = tokio::spawn(serve(socket, zones.clone(), records.clone()));
+        Ok(Self {
+            zones,
+            records,
+            local_addr,
+            task,
+        })
+    }
+
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn update(
+        &self,
+        challenge: &SolverChallenge,
+        change: impl FnOnce(&mut Vec<String>, String),
+    ) -> Result<(), Error> {
+        let name = normalize(&challenge.dns_record_name());
+        if !in_zone(&self.zones, &name) {
+            return Err(Error::Config(
+                "challenge record is not in a zone served by the DNS server",
+            ));
+        }
+
+        let mut records = self.records.lock().unwrap();
+        let values = records.entry(name.clone()).or_default();
+        change(values, challenge.key_authorization.dns_value());
+        if values.is_empty() {
+            records.remove(&name);
+        }
+        Ok(())
+    }
+}
+
+impl ChallengeSolver for DnsServerSolver {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Dns01
+    }
+
+    fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(async move {
+            self.update(challenge, |values, value| {
+                if !values.contains(&value) {
+                    values.push(value);
+                }
+            })
+        })
+    }
+
+    fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(async move {
+            self.update(challenge, |values, value| {
+                values.retain(|existing| *existing != value)
+            })
+        })
+    }
+}
+
+impl Drop for DnsServerSolver {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(
+    socket: UdpSocket,
+    zones: Vec<String>,
+    records: Arc<Mutex<HashMap<String, Vec<String>>>>,
+) {
+    let mut buf = [0; MAX_UDP_LEN];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+
+        let response = match respond(&buf[..len], &zones, &records.lock().unwrap()) {
+            Some(response) => response,
+            None => continue,
+        };
+
+        // The resolver retries if the response gets lost
+        let _ = socket.send_to(&response, peer).await;
+    }
+}
+
+/// Build the response to the DNS `query`, or `None` if it should be ignored
+fn respond(
+    query: &[u8],
+    zones: &[String],
+    records: &HashMap<String, Vec<String>>,
+) -> Option<Vec<u8>> {
+    if query.len() < HEADER_LEN || query[2] & FLAG_QR != 0 {
+        return None;
+    }
+
+    // Echo the ID, opcode and RD flag, and mark the answer as authoritative
+    let mut response = query[..4].to_vec();
+    response[2] = (query[2] & 0x79) | FLAG_QR | FLAG_AA;
+    response[3] = 0;
+    response.extend_from_slice(&[0; 8]);
+
+    let opcode = (query[2] >> 3) & 0x0f;
+    if opcode != 0 {
+        response[3] = RCODE_NOTIMP;
+        return Some(response);
+    }
+
+    let question = match (u16::from_be_bytes([query[4], query[5]]), question(query)) {
+        (1, Some(question)) => question,
+        _ => {
+            response[3] = RCODE_FORMERR;
+            return Some(response);
+        }
+    };
+
+    response[5] = 1; // QDCOUNT
+    response.extend_from_slice(&query[HEADER_LEN..question.end]);
+    if question.class != CLASS_IN || !in_zone(zones, &question.name) {
+        response[2] &= !FLAG_AA;
+        response[3] = RCODE_REFUSED;
+        return Some(response);
+    }
+
+    let values = match (question.r#type, records.get(&question.name)) {
+        (TYPE_TXT | TYPE_ANY, Some(values)) => values,
+        _ => return Some(response),
+    };
+
+    let question_len = response.len();
+    response[7] = values.len() as u8; // ANCOUNT
+    for value in values {
+        // A compression pointer to the name in the question
+        response.extend_from_slice(&[0xc0, HEADER_LEN as u8]);
+        response.extend_from_slice(&TYPE_TXT.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&TTL.to_be_bytes());
+        response.extend_from_slice(&(value.len() as u16 + 1).to_be_bytes());
+        response.push(value.len() as u8);
+        response.extend_from_slice(value.as_bytes());
+    }
+
+    // Without the answers, resolvers retry over TCP, which this server doesn't offer
+    if response.len() > MAX_UDP_LEN || values.len() > u8::MAX as usize {
+        response.truncate(question_len);
+        response[2] |= FLAG_TC;
+        response[7] = 0;
+    }
+    Some(response)
+}
+
+/// Parse the question following the header of `query`
+fn question(query: &[u8]) -> Option<Question> {
+    let mut labels = Vec::new();
+    let mut pos = HEADER_LEN;
+    loop {
+        let len = *query.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        } else if len > 63 {
+            // Compression pointers make no sense in the first name of a message
+            return None;
+        }
+
+        // Dots in labels would let a name pass for one in another zone
+        let label = std::str::from_utf8(query.get(pos..pos + len)?).ok()?;
+        if label.contains('.') {
+            return None;
+        }
+        labels.push(label.to_ascii_lowercase());
+        pos += len;
+    }
+    if pos - HEADER_LEN > MAX_NAME_LEN {
+        return None;
+    }
+
+    let fields = query.get(pos..pos + 4)?;
+    Some(Question {
+        name: labels.join("."),
+        r#type: u16::from_be_bytes([fields[0], fields[1]]),
+        class: u16::from_be_bytes([fields[2], fields[3]]),
+        end: pos + 4,
+    })
+}
+
+struct Question {
+    name: String,
+    r#type: u16,
+    class: u16,
+    end: usize,
+}
+
+/// Whether the normalized `name` is in one of `zones`
+fn in_zone(zones: &[String], name: &str) -> bool {
+    zones.iter().any(|zone| {
+        name == zone
+            || name
+                .strip_suffix(zone.as_str())
+                .map_or(false, |prefix| prefix.ends_with('.'))
+    })
+}
+
+/// Lowercase `name` and remove any trailing dot
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+const HEADER_LEN: usize = 12;
+const MAX_UDP_LEN: usize = 512;
+/// The maximum length of an encoded name, including the length octets
+const MAX_NAME_LEN: usize = 255;
+const FLAG_QR: u8 = 0x80;
+const FLAG_AA: u8 = 0x04;
+const FLAG_TC: u8 = 0x02;
+const RCODE_FORMERR: u8 = 1;
+const RCODE_NOTIMP: u8 = 4;
+const RCODE_REFUSED: u8 = 5;
+const TYPE_TXT: u16 = 16;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Responses change with every order, so resolvers should not cache them
+const TTL: u32 = 0;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A query with ID 0x1234 and recursion desired, with a question for `name`
+    fn query(name: &str, r#type: u16, class: u16) -> Vec<u8> {
+        let labels = name.split('.').map(str::as_bytes).collect::<Vec<_>>();
+        query_labels(&labels, r#type, class)
+    }
+
+    fn query_labels(labels: &[&[u8]], r#type: u16, class: u16) -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in labels {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label);
+        }
+        query.push(0);
+        query.extend_from_slice(&r#type.to_be_bytes());
+        query.extend_from_slice(&class.to_be_bytes());
+        query
+    }
+
+    fn answer(query: &[u8], values: &[&str]) -> Vec<u8> {
+        let values = values.iter().map(|value| value.to_string()).collect();
+        let records = HashMap::from([("_acme-challenge.example.com".to_owned(), values)]);
+        respond(query, &["example.com".to_owned()], &records).unwrap()
+    }
+
+    const CHALLENGE: &str = "_ACME-challenge.Example.com";
+
+    #[test]
+    fn txt_answer() {
+        let query = query(CHALLENGE, TYPE_TXT, CLASS_IN);
+        let response = answer(&query, &["abc", "defg"]);
+        assert_eq!(
+            response[..HEADER_LEN],
+            [0x12, 0x34, 0x85, 0x00, 0, 1, 0, 2, 0, 0, 0, 0]
+        );
+        assert_eq!(response[HEADER_LEN..query.len()], query[HEADER_LEN..]);
+
+        let mut expected = Vec::new();
+        for value in ["abc", "defg"] {
+            // A compression pointer to the question, TXT, IN and a TTL of 0
+            expected.extend_from_slice(&[0xc0, 12, 0, 16, 0, 1, 0, 0, 0, 0]);
+            expected.extend_from_slice(&[0, value.len() as u8 + 1, value.len() as u8]);
+            expected.extend_from_slice(value.as_bytes());
+        }
+        assert_eq!(response[query.len()..], expected[..]);
+
+        let any = super::tests::query(CHALLENGE, TYPE_ANY, CLASS_IN);
+        assert_eq!(answer(&any, &["abc"])[7], 1);
+    }
+
+    #[test]
+    fn empty_and_refused_answers() {
+        // Other names and types in the zone have no records
+        for query in [
+            query("www.example.com", TYPE_TXT, CLASS_IN),
+            query(CHALLENGE, 1, CLASS_IN),
+        ] {
+            let response = answer(&query, &["abc"]);
+            assert_eq!(response[2..4], [0x85, 0x00]);
+            assert_eq!(response[6..8], [0, 0]);
+            assert_eq!(response.len(), query.len());
+        }
+
+        // Names outside the zone, including ones that only end the same way, and other classes
+        for query in [
+            query("_acme-challenge.badexample.com", TYPE_TXT, CLASS_IN),
+            query(CHALLENGE, TYPE_TXT, 3),
+        ] {
+            let response = answer(&query, &["abc"]);
+            assert_eq!(response[2..4], [0x81, RCODE_REFUSED]);
+            assert_eq!(response[HEADER_LEN..], query[HEADER_LEN..]);
+        }
+    }
+
+    #[test]
+    fn other_messages() {
+        let mut notify = query(CHALLENGE, TYPE_TXT, CLASS_IN);
+        notify[2] = 4 << 3;
+        assert_eq!(
+            answer(&notify, &["abc"]),
+            [0x12, 0x34, 0xa4, RCODE_NOTIMP, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        // Responses and messages shorter than a header are ignored
+        let mut response = query(CHALLENGE, TYPE_TXT, CLASS_IN);
+        response[2] |= FLAG_QR;
+        assert!(respond(&response, &[], &HashMap::new()).is_none());
+        assert!(respond(&response[..HEADER_LEN - 1], &[], &HashMap::new()).is_none());
+    }
+
+    #[test]
+    fn label_bounds() {
+        let rcode = |name: &str| answer(&query(name, TYPE_TXT, CLASS_IN), &[])[3];
+        let long = "a".repeat(63);
+        assert_eq!(rcode(&format!("{long}.example.com")), 0);
+        assert_eq!(rcode(&format!("a{long}.example.com")), RCODE_FORMERR);
+
+        // Three labels of 63 bytes and one of 62 encode to 256 bytes, one more than allowed
+        let labels = [&long[..]; 3].join(".");
+        assert_eq!(rcode(&format!("{labels}.{}", &long[..62])), RCODE_FORMERR);
+        assert_eq!(rcode(&format!("{labels}.{}", &long[..61])), RCODE_REFUSED);
+    }
+
+    #[test]
+    fn malformed_questions() {
+        let query = query(CHALLENGE, TYPE_TXT, CLASS_IN);
+        let mut pointer = query[..HEADER_LEN].to_vec();
+        pointer.extend_from_slice(&[0xc0, 12, 0, 16, 0, 1]);
+        let dotted = query_labels(&[b"_acme-challenge.example", b"com"], TYPE_TXT, CLASS_IN);
+        let invalid = query_labels(&[&[0xff], b"example", b"com"], TYPE_TXT, CLASS_IN);
+        let mut no_question = query.clone();
+        no_question[5] = 0;
+        let mut two_questions = query.clone();
+        two_questions[5] = 2;
+
+        for malformed in [pointer, dotted, invalid, no_question, two_questions] {
+            let response = answer(&malformed, &["abc"]);
+            assert_eq!(response[3], RCODE_FORMERR, "{:?}", malformed);
+            assert_eq!(response.len(), HEADER_LEN);
+        }
+
+        // Cut off in a label, before the root label or in the type and class
+        for len in HEADER_LEN..query.len() {
+            assert_eq!(answer(&query[..len], &["abc"])[3], RCODE_FORMERR, "{}", len);
+        }
+    }
+
+    #[test]
+    fn truncation() {
+        let query = query(CHALLENGE, TYPE_TXT, CLASS_IN);
+        let value = "a".repeat(43);
+        let values = vec![value.as_str(); 9];
+        assert_eq!(answer(&query, &values[..8]).len(), 493);
+
+        let response = answer(&query, &values);
+        assert_eq!(response[2], 0x85 | FLAG_TC);
+        assert_eq!(response[6..8], [0, 0]);
+        assert_eq!(response[HEADER_LEN..], query[HEADER_LEN..]);
+    }
+
+    #[test]
+    fn zones_match_whole_labels() {
+        let zones = ["example.com".to_owned()];
+        assert!(in_zone(&zones, "example.com"));
+        assert!(in_zone(&zones, "_acme-challenge.example.com"));
+        assert!(!in_zone(&zones, "badexample.com"));
+        assert!(!in_zone(&zones, "com"));
+        assert_eq!(normalize("Example.COM."), "example.com");
+    }
+}
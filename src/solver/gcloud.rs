@@ -0,0 +1,332 @@
+//! DNS-01 solver for Google Cloud DNS
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request};
+use ring::rand::SystemRandom;
+use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use time::OffsetDateTime;
+
+use super::oauth::TokenCache;
+use super::provider::{challenge_values, owned_values, send, unquote};
+use super::{record_name, ChallengeSolver, SolverChallenge, SolverFuture};
+use crate::{der, form_encode, ChallengeType, Clock, Error, HttpClient, SystemClock};
+
+/// Credentials for the Google Cloud DNS API
+pub struct GoogleCloudAuth(Credentials);
+
+impl GoogleCloudAuth {
+    /// Authenticate with a service account key, as downloaded from the Cloud console
+    ///
+    /// The service account needs the `dns.changes.*` and `dns.resourceRecordSets.*`
+    /// permissions on the project, for example through the `roles/dns.admin` role.
+    pub fn service_account(key_json: &[u8]) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct ServiceAccountKey {
+            client_email: String,
+            private_key: String,
+            #[serde(default = "default_token_uri")]
+            token_uri: String,
+        }
+
+        let key = serde_json::from_slice::<ServiceAccountKey>(key_json)?;
+        let (label, pkcs8) = der::pem_decode(key.private_key.as_bytes())?;
+        if label != "PRIVATE KEY" {
+            return Err(Error::Unsupported("service account key type"));
+        }
+
+        Ok(Self(Credentials::ServiceAccount {
+            email: key.client_email,
+            token_uri: key.token_uri,
+            key: Box::new(RsaKeyPair::from_pkcs8(&pkcs8)?),
+        }))
+    }
+
+    /// Authenticate as the service account attached to the current Compute Engine instance,
+    /// GKE workload or Cloud Run service, using the metadata server
+    pub fn metadata_server() -> Self {
+        Self(Credentials::MetadataServer)
+    }
+}
+
+enum Credentials {
+    ServiceAccount {
+        email: String,
+        token_uri: String,
+        key: Box<RsaKeyPair>,
+    },
+    MetadataServer,
+}
+
+/// A DNS-01 solver that manages TXT records in a Google Cloud DNS managed zone
+///
+/// Records are added to (and removed from) the existing TXT record set for the challenge
+/// name, so that the same name can be used for multiple challenges at once (as happens
+/// for an order for both `example.com` and `*.example.com`). After each change, the solver
+/// waits until Cloud DNS reports that the change has been applied to all authoritative
+/// servers.
+pub struct GoogleCloudDnsSolver {
+    project: String,
+    managed_zone: String,
+    auth: GoogleCloudAuth,
+    http: Box<dyn HttpClient>,
+    token: TokenCache,
+    clock: Arc<dyn Clock>,
+}
+
+impl GoogleCloudDnsSolver {
+    /// Create a solver for the managed zone named `managed_zone` in `project`
+    #[cfg(feature = "hyper-rustls")]
+    pub fn new(project: String, managed_zone: String, auth: GoogleCloudAuth) -> Self {
+        Self::with_http(project, managed_zone, auth, super::provider::default_http())
+    }
+
+    /// Create a solver with a custom HTTP client
+    ///
+    /// The HTTP client must support plain HTTP when using
+    /// [`GoogleCloudAuth::metadata_server()`].
+    pub fn with_http(
+        project: String,
+        managed_zone: String,
+        auth: GoogleCloudAuth,
+        http: Box<dyn HttpClient>,
+    ) -> Self {
+        Self {
+            project,
+            managed_zone,
+            auth,
+            http,
+            token: TokenCache::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Time token expiry and propagation polling with `clock` (defaults to [`SystemClock`])
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Apply `edit` to the TXT record set called `name`
+    async fn update(&self, name: String, edit: impl FnOnce(&mut Vec<String>)) -> Result<(), Error> {
+        let existing = self.record_set(&name).await?;
+        let old = existing
+            .as_ref()
+            .map(|rrset| rrset.rrdatas.clone())
+            .unwrap_or_default();
+        let mut rrdatas = old.clone();
+        edit(&mut rrdatas);
+        if rrdatas == old {
+            return Ok(());
+        }
+
+        let additions = match rrdatas.is_empty() {
+            true => vec![],
+            false => vec![RecordSet {
+                name,
+                r#type: "TXT".to_owned(),
+                ttl: 60,
+                rrdatas,
+            }],
+        };
+
+        self.change(existing.into_iter().collect(), additions).await
+    }
+
+    async fn record_set(&self, name: &str) -> Result<Option<RecordSet>, Error> {
+        #[derive(Deserialize)]
+        struct RecordSets {
+            #[serde(default)]
+            rrsets: Vec<RecordSet>,
+        }
+
+        let url = format!(
+            "{}/rrsets?name={}&type=TXT",
+            self.zone_url(),
+            form_encode(name)
+        );
+        let body = self.request(Method::GET, &url, Body::empty()).await?;
+        let mut rrsets = serde_json::from_slice::<RecordSets>(&body)?.rrsets;
+        Ok(rrsets.pop())
+    }
+
+    async fn change(
+        &self,
+        deletions: Vec<RecordSet>,
+        additions: Vec<RecordSet>,
+    ) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct Change {
+            id: String,
+            status: String,
+        }
+
+        let body = serde_json::to_vec(&json!({
+            "deletions": deletions,
+            "additions": additions,
+        }))?;
+        let url = format!("{}/changes", self.zone_url());
+        let body = self.request(Method::POST, &url, Body::from(body)).await?;
+        let mut change = serde_json::from_slice::<Change>(&body)?;
+
+        for _ in 0..60 {
+            if change.status == "done" {
+                return Ok(());
+            }
+
+            self.clock.sleep(Duration::from_secs(2)).await;
+            let url = format!("{}/changes/{}", self.zone_url(), change.id);
+            let body = self.request(Method::GET, &url, Body::empty()).await?;
+            change = serde_json::from_slice(&body)?;
+        }
+
+        Err(Error::Timeout("Google Cloud DNS change"))
+    }
+
+    async fn request(&self, method: Method, url: &str, body: Body) -> Result<Vec<u8>, Error> {
+        let request = Request::builder()
+            .method(method)
+            .uri(url)
+            .header(
+                AUTHORIZATION,
+                format!("Bearer {}", self.access_token().await?),
+            )
+            .header(CONTENT_TYPE, "application/json");
+        let (status, body) = send(&*self.http, request, body).await?;
+        match status.is_success() {
+            true => Ok(body.to_vec()),
+            false => Err(Error::backend_status("Google Cloud DNS", status)),
+        }
+    }
+
+    async fn access_token(&self) -> Result<String, Error> {
+        if let Some(token) = self.token.get(&*self.clock) {
+            return Ok(token);
+        }
+
+        let (status, body) = match &self.auth.0 {
+            Credentials::ServiceAccount {
+                email,
+                token_uri,
+                key,
+            } => {
+                let assertion = jwt(email, token_uri, key, self.clock.now_utc())?;
+                let request = Request::builder()
+                    .method(Method::POST)
+                    .uri(token_uri)
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+                let body = format!(
+                    "grant_type={}&assertion={assertion}",
+                    form_encode("urn:ietf:params:oauth:grant-type:jwt-bearer")
+                );
+                send(&*self.http, request, Body::from(body)).await?
+            }
+            Credentials::MetadataServer => {
+                let request = Request::builder()
+                    .uri(METADATA_TOKEN_URL)
+                    .header("Metadata-Flavor", "Google");
+                send(&*self.http, request, Body::empty()).await?
+            }
+        };
+
+        match status.is_success() {
+            true => self.token.set(&body, &*self.clock),
+            false => Err(Error::backend_status("Google Cloud access token", status)),
+        }
+    }
+
+    fn zone_url(&self) -> String {
+        format!(
+            "https://dns.googleapis.com/dns/v1/projects/{}/managedZones/{}",
+            self.project, self.managed_zone
+        )
+    }
+}
+
+impl ChallengeSolver for GoogleCloudDnsSolver {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Dns01
+    }
+
+    fn present<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        Box::pin(self.update(fqdn(challenge), move |rrdatas| {
+            for value in challenge_values(challenge) {
+                let value = format!("\"{value}\"");
+                if !rrdatas.contains(&value) {
+                    rrdatas.push(value);
+                }
+            }
+        }))
+    }
+
+    fn cleanup<'a>(&'a self, challenge: &'a SolverChallenge) -> SolverFuture<'a> {
+        let values = challenge_values(challenge);
+        Box::pin(self.update(fqdn(challenge), move |rrdatas| {
+            rrdatas.retain(|v| !values.iter().any(|value| value == unquote(v)))
+        }))
+    }
+
+    fn gc<'a>(&'a self, domain: &'a str) -> SolverFuture<'a> {
+        let name = format!("{}.", record_name(domain));
+        Box::pin(self.update(name, |rrdatas| {
+            let owned = owned_values(rrdatas);
+            rrdatas.retain(|v| !owned.contains(unquote(v)))
+        }))
+    }
+}
+
+fn fqdn(challenge: &SolverChallenge) -> String {
+    format!("{}.", challenge.dns_record_name())
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct RecordSet {
+    name: String,
+    r#type: String,
+    ttl: u32,
+    rrdatas: Vec<String>,
+}
+
+/// Create a signed JWT to exchange for an access token (RFC 7523)
+fn jwt(
+    email: &str,
+    token_uri: &str,
+    key: &RsaKeyPair,
+    now: OffsetDateTime,
+) -> Result<String, Error> {
+    let now = now.unix_timestamp();
+    let header = BASE64_URL_SAFE_NO_PAD.encode(br#"{"alg":"RS256","typ":"JWT"}"#);
+    let claims = BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&json!({
+        "iss": email,
+        "scope": SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + 3600,
+    }))?);
+
+    let message = format!("{header}.{claims}");
+    let mut signature = vec![0; key.public_modulus_len()];
+    key.sign(
+        &RSA_PKCS1_SHA256,
+        &SystemRandom::new(),
+        message.as_bytes(),
+        &mut signature,
+    )?;
+    Ok(format!(
+        "{message}.{}",
+        BASE64_URL_SAFE_NO_PAD.encode(signature)
+    ))
+}
+
+fn default_token_uri() -> String {
+    "https://oauth2.googleapis.com/token".to_owned()
+}
+
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const SCOPE: &str = "https://www.googleapis.com/auth/ndev.clouddns.readwrite";
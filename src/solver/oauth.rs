@@ -0,0 +1,51 @@
+//! OAuth 2.0 access token handling for the cloud provider solvers
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{Clock, Error};
+
+/// An OAuth 2.0 access token, cached until shortly before it expires
+#[derive(Default)]
+pub(super) struct TokenCache(Mutex<Option<(String, Instant)>>);
+
+impl TokenCache {
+    pub(super) fn get(&self, clock: &dyn Clock) -> Option<String> {
+        match &*self.0.lock().unwrap() {
+            Some((token, expires)) if clock.now() < *expires => Some(token.clone()),
+            _ => None,
+        }
+    }
+
+    /// Cache the token from an OAuth 2.0 token response body and return it
+    pub(super) fn set(&self, body: &[u8], clock: &dyn Clock) -> Result<String, Error> {
+        let rsp = serde_json::from_slice::<TokenResponse>(body)?;
+        let lifetime = Duration::from_secs(rsp.expires_in.saturating_sub(60));
+        *self.0.lock().unwrap() = Some((rsp.access_token.clone(), clock.now() + lifetime));
+        Ok(rsp.access_token)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(deserialize_with = "number_or_string")]
+    expires_in: u64,
+}
+
+/// Some token endpoints (like Azure's instance metadata service) encode numbers as strings
+fn number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Number(u64),
+        String(String),
+    }
+
+    match Value::deserialize(deserializer)? {
+        Value::Number(n) => Ok(n),
+        Value::String(s) => s.parse().map_err(serde::de::Error::custom),
+    }
+}
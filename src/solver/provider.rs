@@ -0,0 +1,98 @@
+//! Helpers shared by the DNS provider solvers
+
+use std::collections::HashSet;
+
+use hyper::body::Bytes;
+use hyper::{Body, StatusCode};
+
+use super::SolverChallenge;
+use crate::{Error, HttpClient};
+
+/// A HTTP client for provider APIs, which also allows plain HTTP for metadata endpoints
+#[cfg(feature = "hyper-rustls")]
+pub(super) fn default_http() -> Box<dyn HttpClient> {
+    Box::new(
+        hyper::Client::builder().build(
+            hyper_rustls::HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_or_http()
+                .enable_http1()
+                .enable_http2()
+                .build(),
+        ),
+    )
+}
+
+/// Send `request` with `body` and collect the response body
+pub(super) async fn send(
+    http: &dyn HttpClient,
+    request: hyper::http::request::Builder,
+    body: Body,
+) -> Result<(StatusCode, Bytes), Error> {
+    let request = request
+        .body(body)
+        .map_err(|_| Error::Config("invalid DNS provider request"))?;
+    let rsp = http.request(request).await?;
+    let status = rsp.status();
+    Ok((status, hyper::body::to_bytes(rsp.into_body()).await?))
+}
+
+/// The TXT values to present for `challenge`: the response, and a value marking it as ours
+///
+/// The CA ignores values that don't match the expected response. The marker lets
+/// [`ChallengeSolver::gc()`](super::ChallengeSolver::gc()) tell the leftovers of this crate
+/// apart from the responses of other ACME clients.
+pub(super) fn challenge_values(challenge: &SolverChallenge) -> Vec<String> {
+    let value = challenge.key_authorization.dns_value();
+    let owner = format!("{OWNER_PREFIX}{value}");
+    vec![value, owner]
+}
+
+/// The values among (possibly quoted) TXT `values` that were presented by this crate
+///
+/// These are the markers added by [`challenge_values()`] and the responses they mark.
+pub(super) fn owned_values(values: &[String]) -> HashSet<String> {
+    let values = values.iter().map(|v| unquote(v)).collect::<Vec<_>>();
+    let mut owned = HashSet::new();
+    for value in &values {
+        if let Some(response) = value.strip_prefix(OWNER_PREFIX) {
+            owned.insert((*value).to_owned());
+            owned.insert(response.to_owned());
+        }
+    }
+    owned
+}
+
+/// A TXT record value without surrounding quotes
+pub(super) fn unquote(value: &str) -> &str {
+    value.trim_matches('"')
+}
+
+/// The prefix of the values marking challenge responses as presented by this crate
+const OWNER_PREFIX: &str = "instant-acme-owned=";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owned_leftovers() {
+        let ours = "a".repeat(43);
+        let theirs = "b".repeat(43);
+        let values = [
+            format!("\"{ours}\""),
+            format!("\"{OWNER_PREFIX}{ours}\""),
+            format!("\"{theirs}\""),
+            "v=spf1 -all".to_owned(),
+            // A marker whose response was already removed
+            format!("{OWNER_PREFIX}{}", "c".repeat(43)),
+        ];
+
+        let owned = owned_values(&values);
+        assert!(owned.contains(&ours));
+        assert!(owned.contains(&format!("{OWNER_PREFIX}{ours}")));
+        assert!(owned.contains(&format!("{OWNER_PREFIX}{}", "c".repeat(43))));
+        assert!(!owned.contains(&theirs));
+        assert!(!owned.contains("v=spf1 -all"));
+    }
+}
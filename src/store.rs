@@ -0,0 +1,831 @@
+//! Storage for account credentials and issued certificates
+//!
+//! A [`CertStore`] keeps [`AccountCredentials`] and [`StoredCertificate`]s under names chosen
+//! by the application, so that accounts can be reused and certificates renewed across runs.
+//! [`FileStore`] keeps them in a directory, with the certificate chain and private key in
+//! PEM files that servers can be pointed at directly. Servers that need other formats can be
+//! given a [`StoredCertificate::export()`] in one of the [`ExportFormat`]s.
+
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::{Duration, SystemTime};
+
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::{
+    der, Account, AccountCredentials, CachedAuthorization, CertificateChain, Clock, Error,
+    Identifier, ResumableOrder,
+};
+
+/// The future returned by [`CertStore`] methods
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + 'a>>;
+
+/// Persistent storage for account credentials and certificates
+pub trait CertStore: Send + Sync {
+    /// Load the credentials stored as `name`, if any
+    fn load_account<'a>(&'a self, name: &'a str) -> StoreFuture<'a, Option<AccountCredentials>>;
+
+    /// Store `credentials` as `name`, replacing any credentials stored before
+    fn save_account<'a>(
+        &'a self,
+        name: &'a str,
+        credentials: &'a AccountCredentials,
+    ) -> StoreFuture<'a, ()>;
+
+    /// Load the certificate stored as `name`, if any
+    fn load_certificate<'a>(&'a self, name: &'a str) -> StoreFuture<'a, Option<StoredCertificate>>;
+
+    /// Store `certificate` as `name`, replacing any certificate stored before
+    fn save_certificate<'a>(
+        &'a self,
+        name: &'a str,
+        certificate: &'a StoredCertificate,
+    ) -> StoreFuture<'a, ()>;
+
+    /// Load the order in progress stored as `key`, if any
+    ///
+    /// Orders are stored by [`BulkIssuer`](crate::BulkIssuer) and
+    /// [`RenewalService`](crate::RenewalService) under an [`order_key()`], so that a run
+    /// that was interrupted can be resumed without creating the order again. The default
+    /// implementation stores no orders.
+    fn load_order<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<ResumableOrder>> {
+        let _ = key;
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Store the order in progress `order` as `key`, replacing any order stored before
+    fn save_order<'a>(&'a self, key: &'a str, order: &'a ResumableOrder) -> StoreFuture<'a, ()> {
+        let _ = (key, order);
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Remove the order stored as `key`, if any
+    fn remove_order<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        let _ = key;
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Whether the certificate with the hexadecimal `serial` was recorded as revoked
+    ///
+    /// Revocations are recorded by
+    /// [`Account::revoke_many_resumable()`](crate::Account::revoke_many_resumable()), so that
+    /// an interrupted run does not revoke certificates again. The default implementation
+    /// records no revocations.
+    fn is_revoked<'a>(&'a self, serial: &'a str) -> StoreFuture<'a, bool> {
+        let _ = serial;
+        Box::pin(async { Ok(false) })
+    }
+
+    /// Record that the certificate with the hexadecimal `serial` was revoked at `revoked_at`
+    fn save_revocation<'a>(
+        &'a self,
+        serial: &'a str,
+        revoked_at: OffsetDateTime,
+    ) -> StoreFuture<'a, ()> {
+        let _ = (serial, revoked_at);
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Load the authorizations cached as `name`
+    ///
+    /// Used by [`AuthorizationCache::load()`](crate::AuthorizationCache::load()). The default
+    /// implementation stores no authorizations.
+    fn load_authorizations<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> StoreFuture<'a, Vec<CachedAuthorization>> {
+        let _ = name;
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    /// Store the cached `authorizations` as `name`, replacing those stored before
+    fn save_authorizations<'a>(
+        &'a self,
+        name: &'a str,
+        authorizations: &'a [CachedAuthorization],
+    ) -> StoreFuture<'a, ()> {
+        let _ = (name, authorizations);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Whether a certificate needs to be ordered, as determined by [`check_coverage()`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Coverage {
+    /// An existing certificate covers all identifiers and does not expire soon
+    AlreadyCovered {
+        /// The expiry of the existing certificate
+        not_after: OffsetDateTime,
+    },
+    /// No certificate covers all identifiers, or the one that does expires soon
+    Needed,
+}
+
+impl Coverage {
+    /// Check whether `chain` covers `identifiers` for longer than `renew_before` from the
+    /// current time of `clock`
+    ///
+    /// Use this for a certificate that is deployed outside of a [`CertStore`].
+    pub fn of(
+        chain: &CertificateChain,
+        identifiers: &[Identifier],
+        renew_before: Duration,
+        clock: &dyn Clock,
+    ) -> Result<Self, Error> {
+        let renew_before = time::Duration::try_from(renew_before)
+            .map_err(|_| Error::Config("renewal window is too large"))?;
+        let not_after = chain.not_after()?;
+        match not_after - clock.now_utc() > renew_before && chain.covers(identifiers)? {
+            true => Ok(Self::AlreadyCovered { not_after }),
+            false => Ok(Self::Needed),
+        }
+    }
+}
+
+/// Check whether the certificate stored as `name` covers `identifiers` for longer than
+/// `renew_before` from the current time of `clock`
+///
+/// Call this before ordering a certificate to avoid ordering one again while the existing
+/// one is still good, which would count against the CA's duplicate certificate limits. The
+/// certificate itself is checked, so a stored certificate for more identifiers than those
+/// requested also covers them.
+pub async fn check_coverage(
+    store: &dyn CertStore,
+    name: &str,
+    identifiers: &[Identifier],
+    renew_before: Duration,
+    clock: &dyn Clock,
+) -> Result<Coverage, Error> {
+    match store.load_certificate(name).await? {
+        Some(stored) => Coverage::of(&stored.chain, identifiers, renew_before, clock),
+        None => Ok(Coverage::Needed),
+    }
+}
+
+/// The key under which the order for `identifiers` is kept in a [`CertStore`]
+///
+/// The hex-encoded SHA-256 hash of the identifiers, sorted and without duplicates, so that
+/// the same set of identifiers maps to the same key whatever their order and case.
+pub fn order_key(identifiers: &[Identifier]) -> String {
+    let mut names = identifiers
+        .iter()
+        .map(Identifier::canonical)
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+    hex(digest(&SHA256, names.join("\n").as_bytes()).as_ref())
+}
+
+/// The CA to record as [`StoredCertificate::ca`] for certificates issued through `account`
+///
+/// This is the [`Account::ca_label()`] of the account, or else the URL of its CA's directory.
+pub fn issuing_ca(account: &Account) -> Option<String> {
+    account
+        .ca_label()
+        .or_else(|| account.directory_url())
+        .map(str::to_owned)
+}
+
+pub(crate) fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// An issued certificate with its private key and the identifiers it was ordered for
+///
+/// The [`Debug`] output omits the private key.
+#[derive(Clone)]
+pub struct StoredCertificate {
+    /// The identifiers the certificate was ordered for, used to renew it
+    pub identifiers: Vec<Identifier>,
+    /// The certificate chain
+    pub chain: CertificateChain,
+    /// The PEM-encoded private key of the certificate
+    pub private_key_pem: String,
+    /// The CA that issued the certificate, if known
+    ///
+    /// The [`Account::ca_label()`](crate::Account::ca_label()) of the account it was issued
+    /// through, or else the URL of the CA's directory.
+    pub ca: Option<String>,
+}
+
+impl fmt::Debug for StoredCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoredCertificate")
+            .field("identifiers", &self.identifiers)
+            .field("chain", &self.chain)
+            .field("ca", &self.ca)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StoredCertificate {
+    /// Encode the certificate and its private key in `format`, naming the files after `name`
+    ///
+    /// `password` protects PKCS #12 and JKS exports and is ignored for the other formats.
+    /// Use [`ExportedFile::write_to()`] to save the files.
+    pub fn export(
+        &self,
+        name: &str,
+        format: ExportFormat,
+        password: &str,
+    ) -> Result<Vec<ExportedFile>, Error> {
+        let name = valid(name)?;
+        let (label, key) = der::pem_decode(self.private_key_pem.as_bytes())?;
+        if label != "PRIVATE KEY" {
+            return Err(Error::Malformed("private key is not in PKCS #8 format"));
+        }
+        let chain = self.chain.der()?;
+        let leaf = chain
+            .first()
+            .ok_or(Error::Str("certificate chain is empty"))?;
+
+        let pem = |certificates: &[Vec<u8>]| {
+            certificates
+                .iter()
+                .map(|der| der::pem_encode("CERTIFICATE", der))
+                .collect::<String>()
+                .into_bytes()
+        };
+        let salt = |len| {
+            let mut salt = vec![0; len];
+            SystemRandom::new().fill(&mut salt)?;
+            Ok::<_, Error>(salt)
+        };
+        let iv = || {
+            let mut iv = [0; 16];
+            SystemRandom::new().fill(&mut iv)?;
+            Ok::<_, Error>(iv)
+        };
+
+        Ok(match format {
+            ExportFormat::CombinedPem => vec![ExportedFile::new(
+                format!("{name}.pem"),
+                [self.private_key_pem.as_bytes(), self.chain.pem().as_bytes()].concat(),
+                true,
+            )],
+            ExportFormat::Certbot => vec![
+                ExportedFile::new(format!("{name}/cert.pem"), pem(&chain[..1]), false),
+                ExportedFile::new(format!("{name}/chain.pem"), pem(&chain[1..]), false),
+                ExportedFile::new(
+                    format!("{name}/{CHAIN}"),
+                    self.chain.pem().as_bytes().to_vec(),
+                    false,
+                ),
+                ExportedFile::new(
+                    format!("{name}/{KEY}"),
+                    self.private_key_pem.as_bytes().to_vec(),
+                    true,
+                ),
+            ],
+            ExportFormat::Der => vec![
+                ExportedFile::new(format!("{name}/cert.der"), leaf.clone(), false),
+                ExportedFile::new(format!("{name}/privkey.der"), key, true),
+            ],
+            ExportFormat::Pkcs12 => vec![ExportedFile::new(
+                format!("{name}.p12"),
+                der::pkcs12(&key, &chain, name, password, &salt(16)?, &salt(16)?, &iv()?),
+                true,
+            )],
+            ExportFormat::Jks => {
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_millis() as u64);
+                // JKS aliases are case-insensitive and stored in lowercase
+                let alias = name.to_lowercase();
+                vec![ExportedFile::new(
+                    format!("{name}.jks"),
+                    der::jks(&key, &chain, &alias, password, &salt(20)?, timestamp),
+                    true,
+                )]
+            }
+        })
+    }
+}
+
+/// A format for exporting a [`StoredCertificate`] to servers that don't read separate PEM files
+///
+/// The file names below are for a certificate exported as `<name>`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ExportFormat {
+    /// The private key followed by the chain in a single `<name>.pem`, as used by HAProxy
+    CombinedPem,
+    /// Separate files in a `<name>/` directory, like those of certbot
+    ///
+    /// `cert.pem` holds the end-entity certificate, `chain.pem` the intermediates,
+    /// `fullchain.pem` both and `privkey.pem` the private key.
+    Certbot,
+    /// The end-entity certificate in `<name>/cert.der` and the PKCS #8 private key in
+    /// `<name>/privkey.der`
+    Der,
+    /// A PKCS #12 archive `<name>.p12` with the private key and the chain
+    ///
+    /// The private key is encrypted with the password using PBES2 with PBKDF2-HMAC-SHA256
+    /// and AES-256-CBC, and the archive is integrity-protected with it, like archives created
+    /// by OpenSSL 3. Both OpenSSL and Java's KeyStore can read it. The certificates are not
+    /// encrypted.
+    Pkcs12,
+    /// A Java KeyStore `<name>.jks` with the private key and the chain
+    ///
+    /// The entry is named after the certificate in lowercase, and the key and keystore are
+    /// both protected with the password.
+    Jks,
+}
+
+/// A file produced by [`StoredCertificate::export()`]
+///
+/// The [`Debug`] output omits the contents.
+pub struct ExportedFile {
+    /// The name of the file
+    pub file_name: String,
+    /// The contents of the file
+    pub contents: Vec<u8>,
+    /// Whether the file contains the private key
+    pub private: bool,
+}
+
+impl ExportedFile {
+    fn new(file_name: impl Into<String>, contents: Vec<u8>, private: bool) -> Self {
+        Self {
+            file_name: file_name.into(),
+            contents,
+            private,
+        }
+    }
+
+    /// Write the file to `dir`, which is created if needed, returning the path of the file
+    ///
+    /// Files are replaced atomically, and files containing the private key are only readable
+    /// by the owner on Unix.
+    pub fn write_to(&self, dir: &Path) -> Result<PathBuf, Error> {
+        let path = dir.join(&self.file_name);
+        write_file(&path, &self.contents, self.private)?;
+        Ok(path)
+    }
+}
+
+impl fmt::Debug for ExportedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExportedFile")
+            .field("file_name", &self.file_name)
+            .field("private", &self.private)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [`CertStore`] that keeps its contents in a directory
+///
+/// Credentials are stored in `accounts/<name>.json`. Certificates are stored in
+/// `certificates/<name>/` as `fullchain.pem`, `privkey.pem` and `identifiers.json`, orders
+/// in progress in `orders/<key>.json`, cached authorizations in `authorizations/<name>.json`
+/// and recorded revocations in `revocations/<serial>`, holding the time of the revocation.
+/// Files are replaced atomically, and files containing private keys are only readable by the
+/// owner on Unix. Names may not be empty, contain path separators or start with a dot.
+///
+/// Each saved certificate is written to a new directory in `certificates/.versions/<name>/`,
+/// and `certificates/<name>` is a symbolic link to the current one, so that readers never see
+/// the key of one certificate with the chain of another. On Unix the link is replaced with a
+/// single atomic rename; elsewhere, `certificates/<name>` is a directory that is replaced
+/// with two renames, and a save interrupted in between leaves no certificate for `name`.
+/// Versions left behind by an interrupted save are removed by the next save.
+pub struct FileStore {
+    root: PathBuf,
+}
+
+impl FileStore {
+    /// Keep the contents of the store in `root`, which is created if needed
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn account_path(&self, name: &str) -> Result<PathBuf, Error> {
+        Ok(self
+            .root
+            .join("accounts")
+            .join(format!("{}.json", valid(name)?)))
+    }
+
+    fn order_path(&self, key: &str) -> Result<PathBuf, Error> {
+        Ok(self
+            .root
+            .join("orders")
+            .join(format!("{}.json", valid(key)?)))
+    }
+
+    fn authorizations_path(&self, name: &str) -> Result<PathBuf, Error> {
+        Ok(self
+            .root
+            .join("authorizations")
+            .join(format!("{}.json", valid(name)?)))
+    }
+
+    fn revocation_path(&self, serial: &str) -> Result<PathBuf, Error> {
+        Ok(self.root.join("revocations").join(valid(serial)?))
+    }
+
+    /// The directory holding the files of the certificate stored as `name`
+    pub fn certificate_dir(&self, name: &str) -> Result<PathBuf, Error> {
+        Ok(self.root.join("certificates").join(valid(name)?))
+    }
+}
+
+impl CertStore for FileStore {
+    fn load_account<'a>(&'a self, name: &'a str) -> StoreFuture<'a, Option<AccountCredentials>> {
+        Box::pin(async move {
+            match read(self.account_path(name)?).await? {
+                Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn save_account<'a>(
+        &'a self,
+        name: &'a str,
+        credentials: &'a AccountCredentials,
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let data = serde_json::to_vec_pretty(credentials)?;
+            write(self.account_path(name)?, data, true).await
+        })
+    }
+
+    fn load_certificate<'a>(&'a self, name: &'a str) -> StoreFuture<'a, Option<StoredCertificate>> {
+        Box::pin(async move {
+            let dir = self.certificate_dir(name)?;
+            let files = blocking(move || {
+                // Resolve the link once, so that all files come from the same version
+                let dir = match fs::canonicalize(&dir) {
+                    Ok(dir) => dir,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                let metadata = match read_file(&dir.join(IDENTIFIERS))? {
+                    Some(data) => data,
+                    None => return Ok(None),
+                };
+                match (read_file(&dir.join(CHAIN))?, read_file(&dir.join(KEY))?) {
+                    (Some(chain), Some(key)) => Ok(Some((metadata, chain, key))),
+                    _ => Err(Error::Str("incomplete certificate in store")),
+                }
+            });
+            let (metadata, chain, key) = match files.await? {
+                Some(files) => files,
+                None => return Ok(None),
+            };
+            let metadata = serde_json::from_slice::<Identifiers>(&metadata)?;
+
+            let utf8 = |data| {
+                String::from_utf8(data).map_err(|_| Error::Malformed("PEM data is not UTF-8"))
+            };
+            Ok(Some(StoredCertificate {
+                identifiers: metadata.identifiers,
+                chain: CertificateChain::from_pem(utf8(chain)?)?,
+                private_key_pem: utf8(key)?,
+                ca: metadata.ca,
+            }))
+        })
+    }
+
+    fn save_certificate<'a>(
+        &'a self,
+        name: &'a str,
+        certificate: &'a StoredCertificate,
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let dir = self.certificate_dir(name)?;
+            let versions = self.root.join("certificates").join(VERSIONS).join(name);
+            let identifiers = serde_json::to_vec_pretty(&Identifiers {
+                identifiers: certificate.identifiers.clone(),
+                ca: certificate.ca.clone(),
+            })?;
+            let key = certificate.private_key_pem.clone().into_bytes();
+            let chain = certificate.chain.pem().as_bytes().to_vec();
+
+            let mut version = [0; 8];
+            SystemRandom::new().fill(&mut version)?;
+            let version = versions.join(hex(&version));
+            blocking(move || {
+                fs::create_dir_all(&version)?;
+                write_file(&version.join(KEY), &key, true)?;
+                write_file(&version.join(CHAIN), &chain, false)?;
+                write_file(&version.join(IDENTIFIERS), &identifiers, false)?;
+                sync_dir(&version)?;
+                switch(&dir, &version)?;
+
+                // Remove earlier versions, including those of interrupted saves
+                for entry in fs::read_dir(&versions)? {
+                    let path = entry?.path();
+                    if path != version {
+                        fs::remove_dir_all(path)?;
+                    }
+                }
+                Ok(())
+            })
+            .await
+        })
+    }
+
+    fn load_order<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<ResumableOrder>> {
+        Box::pin(async move {
+            match read(self.order_path(key)?).await? {
+                Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn save_order<'a>(&'a self, key: &'a str, order: &'a ResumableOrder) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let data = serde_json::to_vec_pretty(order)?;
+            write(self.order_path(key)?, data, false).await
+        })
+    }
+
+    fn remove_order<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.order_path(key)?;
+            blocking(move || match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            })
+            .await
+        })
+    }
+
+    fn is_revoked<'a>(&'a self, serial: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(async move { Ok(read(self.revocation_path(serial)?).await?.is_some()) })
+    }
+
+    fn save_revocation<'a>(
+        &'a self,
+        serial: &'a str,
+        revoked_at: OffsetDateTime,
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let time = revoked_at
+                .format(&Rfc3339)
+                .map_err(|_| Error::Malformed("revocation time"))?;
+            write(self.revocation_path(serial)?, time.into_bytes(), false).await
+        })
+    }
+
+    fn load_authorizations<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> StoreFuture<'a, Vec<CachedAuthorization>> {
+        Box::pin(async move {
+            match read(self.authorizations_path(name)?).await? {
+                Some(data) => Ok(serde_json::from_slice(&data)?),
+                None => Ok(Vec::new()),
+            }
+        })
+    }
+
+    fn save_authorizations<'a>(
+        &'a self,
+        name: &'a str,
+        authorizations: &'a [CachedAuthorization],
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let data = serde_json::to_vec_pretty(authorizations)?;
+            write(self.authorizations_path(name)?, data, false).await
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Identifiers {
+    identifiers: Vec<Identifier>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ca: Option<String>,
+}
+
+fn valid(name: &str) -> Result<&str, Error> {
+    match name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        true => Err(Error::Config("invalid name for store entry")),
+        false => Ok(name),
+    }
+}
+
+/// Run file system operations on a thread where blocking is acceptable
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(Error::Cancelled("file store operation")),
+    }
+}
+
+async fn read(path: PathBuf) -> Result<Option<Vec<u8>>, Error> {
+    blocking(move || read_file(&path)).await
+}
+
+async fn write(path: PathBuf, data: Vec<u8>, private: bool) -> Result<(), Error> {
+    blocking(move || write_file(&path, &data, private)).await
+}
+
+fn read_file(path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Point `link` at the directory `target` with an atomic rename of a new symbolic link
+#[cfg(unix)]
+fn switch(link: &Path, target: &Path) -> Result<(), Error> {
+    let parent = link.parent().ok_or(Error::Str("certificate directory"))?;
+    let relative = target.strip_prefix(parent).unwrap_or(target);
+
+    // A directory from before certificates were versioned can't be replaced by a rename
+    // of the link, so it is moved to the versions first (and removed with them)
+    if fs::symlink_metadata(link).map_or(false, |metadata| metadata.is_dir()) {
+        fs::rename(link, old_version(target))?;
+    }
+
+    let mut tmp = link.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let _ = fs::remove_file(&tmp);
+    std::os::unix::fs::symlink(relative, &tmp)?;
+    fs::rename(&tmp, link)?;
+    sync_dir(parent)
+}
+
+/// Move the directory `link` to the versions and `target` in its place
+#[cfg(not(unix))]
+fn switch(link: &Path, target: &Path) -> Result<(), Error> {
+    match fs::rename(link, old_version(target)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    fs::rename(target, link)?;
+    Ok(())
+}
+
+/// A path next to the version `target` under which to keep the version it replaces
+fn old_version(target: &Path) -> PathBuf {
+    let mut old = target.as_os_str().to_owned();
+    old.push(".old");
+    PathBuf::from(old)
+}
+
+/// Make the entries of the directory at `path` durable
+fn sync_dir(path: &Path) -> Result<(), Error> {
+    #[cfg(unix)]
+    fs::File::open(path)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Replace the file at `path` with `data` by writing a temporary file and renaming it
+fn write_file(path: &Path, data: &[u8], private: bool) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    // Remove leftovers from an interrupted write, which may have looser permissions
+    let _ = fs::remove_file(&tmp);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+
+    let mut file = options.open(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+const CHAIN: &str = "fullchain.pem";
+const KEY: &str = "privkey.pem";
+const IDENTIFIERS: &str = "identifiers.json";
+/// The directory in `certificates/` with the versions of each certificate
+const VERSIONS: &str = ".versions";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn certificate_versions() {
+        let root = TempDir::new("certificate-versions");
+        let store = FileStore::new(&root.0);
+        assert!(store.load_certificate("web").await.unwrap().is_none());
+
+        let first = certificate("a.example.com");
+        store.save_certificate("web", &first).await.unwrap();
+        let loaded = store.load_certificate("web").await.unwrap().unwrap();
+        assert_eq!(loaded.chain.pem(), first.chain.pem());
+        assert_eq!(loaded.private_key_pem, first.private_key_pem);
+        assert_eq!(loaded.identifiers, first.identifiers);
+
+        // A save interrupted before the switch leaves the previous certificate in place
+        let versions = root.0.join("certificates").join(VERSIONS).join("web");
+        fs::create_dir_all(versions.join("interrupted")).unwrap();
+        fs::write(versions.join("interrupted").join(KEY), "partial").unwrap();
+        let loaded = store.load_certificate("web").await.unwrap().unwrap();
+        assert_eq!(loaded.private_key_pem, first.private_key_pem);
+
+        let second = certificate("b.example.com");
+        store.save_certificate("web", &second).await.unwrap();
+        let loaded = store.load_certificate("web").await.unwrap().unwrap();
+        assert_eq!(loaded.chain.pem(), second.chain.pem());
+        assert_eq!(loaded.private_key_pem, second.private_key_pem);
+
+        // Only the current version is kept, and its files can be read through the link
+        assert_eq!(fs::read_dir(&versions).unwrap().count(), 1);
+        let dir = store.certificate_dir("web").unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join(CHAIN)).unwrap(),
+            second.chain.pem()
+        );
+    }
+
+    #[tokio::test]
+    async fn unversioned_certificate() {
+        let root = TempDir::new("unversioned-certificate");
+        let store = FileStore::new(&root.0);
+        let first = certificate("a.example.com");
+        let dir = store.certificate_dir("web").unwrap();
+        write_file(&dir.join(KEY), first.private_key_pem.as_bytes(), true).unwrap();
+        write_file(&dir.join(CHAIN), first.chain.pem().as_bytes(), false).unwrap();
+        let identifiers = serde_json::to_vec(&Identifiers {
+            identifiers: first.identifiers.clone(),
+            ca: None,
+        })
+        .unwrap();
+        write_file(&dir.join(IDENTIFIERS), &identifiers, false).unwrap();
+        assert!(store.load_certificate("web").await.unwrap().is_some());
+
+        let second = certificate("b.example.com");
+        store.save_certificate("web", &second).await.unwrap();
+        let loaded = store.load_certificate("web").await.unwrap().unwrap();
+        assert_eq!(loaded.private_key_pem, second.private_key_pem);
+    }
+
+    #[tokio::test]
+    async fn orders_and_revocations() {
+        let root = TempDir::new("orders-and-revocations");
+        let store = FileStore::new(&root.0);
+        assert!(!store.is_revoked("01ab").await.unwrap());
+        let revoked_at = OffsetDateTime::from_unix_timestamp(1_800_000_000).unwrap();
+        store.save_revocation("01ab", revoked_at).await.unwrap();
+        assert!(store.is_revoked("01ab").await.unwrap());
+
+        assert!(store.load_order("a.example.com").await.unwrap().is_none());
+        store.remove_order("a.example.com").await.unwrap();
+        assert!(store.save_revocation("../01ab", revoked_at).await.is_err());
+    }
+
+    fn certificate(name: &str) -> StoredCertificate {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_owned()]).unwrap();
+        StoredCertificate {
+            identifiers: vec![Identifier::Dns(name.to_owned())],
+            chain: CertificateChain::from_pem(cert.serialize_pem().unwrap()).unwrap(),
+            private_key_pem: cert.serialize_private_key_pem(),
+            ca: None,
+        }
+    }
+
+    /// A directory that is removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("instant-acme-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+}
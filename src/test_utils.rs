@@ -0,0 +1,331 @@
+//! Helpers for running end-to-end tests against a Pebble ACME server
+//!
+//! [Pebble](https://github.com/letsencrypt/pebble) is a small ACME test server maintained by
+//! Let's Encrypt. It is usually run together with `pebble-challtestsrv`, which serves
+//! challenge responses and DNS records on behalf of the client under test.
+//!
+//! The helpers in this module are configured through the environment:
+//!
+//! * `PEBBLE_DIRECTORY`: the directory URL (default: `https://localhost:14000/dir`)
+//! * `PEBBLE_CA_CERT`: path to the PEM-encoded CA certificate used for Pebble's HTTPS
+//!   listener (default: `pebble.minica.pem`, as found in Pebble's `test/certs` directory)
+//! * `PEBBLE_CHALLTESTSRV`: the management URL of `pebble-challtestsrv`
+//!   (default: `http://localhost:8055`)
+//! * `PEBBLE_BIN` and `PEBBLE_CONFIG`: if set, [`Pebble::start()`] launches Pebble with the
+//!   given configuration file instead of connecting to a running instance
+//! * `PEBBLE_CHALLTESTSRV_BIN`: if set, [`Pebble::start()`] also launches
+//!   `pebble-challtestsrv`
+
+use std::env;
+use std::time::Duration;
+
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request};
+use hyper_rustls::HttpsConnector;
+use rcgen::{Certificate, CertificateParams, DistinguishedName};
+use serde::Serialize;
+use tokio::process::{Child, Command};
+use tokio::time::sleep;
+
+use crate::{
+    der, Account, AuthorizationStatus, CertStatus, ChallengeType, Error, HttpClient, Identifier,
+    NewAccount, NewOrder, OrderStatus,
+};
+
+/// A Pebble instance to run tests against
+pub struct Pebble {
+    directory_url: String,
+    http: hyper::Client<HttpsConnector<HttpConnector>>,
+    challtestsrv: ChallTestSrv,
+    _processes: Vec<Child>,
+}
+
+impl Pebble {
+    /// Connect to a running Pebble instance, as configured through the environment
+    pub fn from_env() -> Result<Self, Error> {
+        let ca_path = env::var("PEBBLE_CA_CERT").unwrap_or_else(|_| "pebble.minica.pem".into());
+        let ca_pem = std::fs::read(ca_path)?;
+
+        let mut roots = rustls::RootCertStore::empty();
+        for (label, der) in der::pem_decode_all(&ca_pem)? {
+            if label == "CERTIFICATE" {
+                roots
+                    .add(&rustls::Certificate(der))
+                    .map_err(|_| Error::Malformed("Pebble CA certificate"))?;
+            }
+        }
+
+        let tls = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls)
+            .https_only()
+            .enable_http1()
+            .build();
+
+        Ok(Self {
+            directory_url: env::var("PEBBLE_DIRECTORY")
+                .unwrap_or_else(|_| "https://localhost:14000/dir".into()),
+            http: hyper::Client::builder().build(connector),
+            challtestsrv: ChallTestSrv::new(
+                env::var("PEBBLE_CHALLTESTSRV").unwrap_or_else(|_| "http://localhost:8055".into()),
+            ),
+            _processes: Vec::new(),
+        })
+    }
+
+    /// Start Pebble (and optionally `pebble-challtestsrv`) if configured, then connect to it
+    ///
+    /// Processes started by this method are killed when the `Pebble` value is dropped. If
+    /// `PEBBLE_BIN` is not set, this behaves like [`Pebble::from_env()`], except that it still
+    /// waits for the directory to become available.
+    pub async fn start() -> Result<Self, Error> {
+        let mut processes = Vec::new();
+        if let Ok(bin) = env::var("PEBBLE_CHALLTESTSRV_BIN") {
+            processes.push(spawn(Command::new(bin).args(["-defaultIPv6", ""]))?);
+        }
+
+        if let Ok(bin) = env::var("PEBBLE_BIN") {
+            let mut cmd = Command::new(bin);
+            if let Ok(config) = env::var("PEBBLE_CONFIG") {
+                cmd.args(["-config", &config]);
+            }
+            if processes.len() == 1 {
+                cmd.args(["-dnsserver", "127.0.0.1:8053"]);
+            }
+            processes.push(spawn(&mut cmd)?);
+        }
+
+        let mut pebble = Self::from_env()?;
+        pebble._processes = processes;
+
+        for _ in 0..50 {
+            let req = Request::builder()
+                .uri(&pebble.directory_url)
+                .body(Body::empty())
+                .map_err(|_| Error::Config("invalid Pebble directory URL"))?;
+            match pebble.http.request(req).await {
+                Ok(rsp) if rsp.status().is_success() => return Ok(pebble),
+                _ => sleep(Duration::from_millis(100)).await,
+            }
+        }
+
+        Err(Error::Timeout("Pebble to become available"))
+    }
+
+    /// Get a HTTP client that trusts Pebble's CA certificate
+    pub fn http_client(&self) -> Box<dyn HttpClient> {
+        Box::new(self.http.clone())
+    }
+
+    /// The directory URL of the Pebble instance
+    pub fn directory_url(&self) -> &str {
+        &self.directory_url
+    }
+
+    /// The challenge test server used to provision challenge responses
+    pub fn challtestsrv(&self) -> &ChallTestSrv {
+        &self.challtestsrv
+    }
+
+    /// Create a new account on the Pebble instance
+    pub async fn account(&self) -> Result<Account, Error> {
+        Account::create_with_http(
+            &NewAccount::builder()
+                .contact("mailto:test@example.com")
+                .terms_of_service_agreed(true)
+                .build()?,
+            &self.directory_url,
+            None,
+            self.http_client(),
+        )
+        .await
+    }
+
+    /// Run a complete issuance for the given DNS names, returning the PEM certificate chain
+    ///
+    /// Challenge responses are provisioned through `pebble-challtestsrv` and removed again
+    /// once the order is no longer pending. `challenge_type` must be
+    /// [`ChallengeType::Http01`] or [`ChallengeType::Dns01`].
+    pub async fn issue(
+        &self,
+        account: &Account,
+        names: &[&str],
+        challenge_type: ChallengeType,
+    ) -> Result<String, Error> {
+        let order = NewOrder::builder()
+            .identifiers(names.iter().map(|&name| Identifier::Dns(name.to_owned())))
+            .build()?;
+        let mut order = account.new_order(&order).await?;
+
+        let mut cleanup = Vec::new();
+        let result = async {
+            for authz in order.authorizations().await? {
+                if authz.status != AuthorizationStatus::Pending {
+                    continue;
+                }
+
+                let challenge = authz
+                    .find_challenge(challenge_type)
+                    .ok_or(Error::Unsupported("requested challenge type not offered"))?;
+                let key_auth = order.key_authorization(challenge);
+                let name = authz.identifier.value();
+                let response = match challenge_type {
+                    ChallengeType::Http01 => {
+                        self.challtestsrv
+                            .add_http01(&challenge.token, key_auth.as_str())
+                            .await?;
+                        Provisioned::Http01(challenge.token.clone())
+                    }
+                    ChallengeType::Dns01 => {
+                        let host = format!("_acme-challenge.{}.", name.trim_start_matches("*."));
+                        self.challtestsrv
+                            .set_txt(&host, &key_auth.dns_value())
+                            .await?;
+                        Provisioned::Dns01(host)
+                    }
+                    _ => return Err(Error::Unsupported("challenge type for Pebble")),
+                };
+
+                cleanup.push(response);
+                order.set_challenge_ready(&challenge.url).await?;
+            }
+
+            let mut delay = Duration::from_millis(100);
+            for _ in 0..10 {
+                match order.refresh().await?.status {
+                    OrderStatus::Pending => {
+                        sleep(delay).await;
+                        delay *= 2;
+                    }
+                    _ => break,
+                }
+            }
+            Ok::<_, Error>(())
+        }
+        .await;
+
+        for provisioned in cleanup {
+            let _ = match provisioned {
+                Provisioned::Http01(token) => self.challtestsrv.del_http01(&token).await,
+                Provisioned::Dns01(host) => self.challtestsrv.clear_txt(&host).await,
+            };
+        }
+        result?;
+
+        if order.state().status != OrderStatus::Ready {
+            return Err(Error::Timeout("Pebble order to become ready"));
+        }
+
+        let mut params = CertificateParams::new(
+            names
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect::<Vec<_>>(),
+        );
+        params.distinguished_name = DistinguishedName::new();
+        let csr = Certificate::from_params(params)
+            .and_then(|cert| cert.serialize_request_der())
+            .map_err(|_| Error::Config("invalid CSR parameters"))?;
+
+        order.finalize(&csr).await?;
+        for _ in 0..10 {
+            match order.certificate().await? {
+                CertStatus::Ready(chain) => return Ok(chain),
+                CertStatus::Processing { retry_after } => {
+                    sleep(retry_after.unwrap_or(Duration::from_millis(250))).await
+                }
+            }
+        }
+
+        Err(Error::Timeout("Pebble to issue the certificate"))
+    }
+}
+
+enum Provisioned {
+    Http01(String),
+    Dns01(String),
+}
+
+fn spawn(cmd: &mut Command) -> Result<Child, Error> {
+    Ok(cmd.kill_on_drop(true).spawn()?)
+}
+
+/// Client for the management API of `pebble-challtestsrv`
+///
+/// <https://github.com/letsencrypt/pebble/tree/main/cmd/pebble-challtestsrv>
+pub struct ChallTestSrv {
+    url: String,
+    http: hyper::Client<HttpConnector>,
+}
+
+impl ChallTestSrv {
+    /// Create a client for the management API at `url`
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            http: hyper::Client::new(),
+        }
+    }
+
+    /// Serve `content` for the HTTP-01 challenge with the given `token`
+    pub async fn add_http01(&self, token: &str, content: &str) -> Result<(), Error> {
+        self.post("add-http01", &Http01 { token, content }).await
+    }
+
+    /// Stop serving the HTTP-01 challenge with the given `token`
+    pub async fn del_http01(&self, token: &str) -> Result<(), Error> {
+        self.post("del-http01", &Http01 { token, content: "" })
+            .await
+    }
+
+    /// Serve a TXT record with `value` for `host` (which should end in a dot)
+    pub async fn set_txt(&self, host: &str, value: &str) -> Result<(), Error> {
+        self.post("set-txt", &Txt { host, value }).await
+    }
+
+    /// Remove the TXT records for `host`
+    pub async fn clear_txt(&self, host: &str) -> Result<(), Error> {
+        self.post("clear-txt", &Txt { host, value: "" }).await
+    }
+
+    /// Serve A records for `host`, overriding the default IPv4 address
+    pub async fn add_a(&self, host: &str, addresses: &[&str]) -> Result<(), Error> {
+        #[derive(Serialize)]
+        struct A<'a> {
+            host: &'a str,
+            addresses: &'a [&'a str],
+        }
+
+        self.post("add-a", &A { host, addresses }).await
+    }
+
+    async fn post(&self, path: &str, body: &impl Serialize) -> Result<(), Error> {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{}/{path}", self.url.trim_end_matches('/')))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_vec(body)?))
+            .map_err(|_| Error::Config("invalid challenge test server URL"))?;
+
+        match self.http.request(req).await?.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(Error::backend_status("challenge test server", status)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Http01<'a> {
+    token: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct Txt<'a> {
+    host: &'a str,
+    value: &'a str,
+}
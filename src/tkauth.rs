@@ -0,0 +1,239 @@
+//! STIR/SHAKEN identifiers and `tkauth-01` challenges (RFC 9447, RFC 9448)
+//!
+//! Certificates for telephone numbers are ordered for a [`Identifier::TnAuthList`], built
+//! from [`TnEntry`] values with [`Identifier::tn_auth_list()`]. The CA then offers a
+//! [`ChallengeType::TkAuth01`] challenge, which is completed by fetching an authority token
+//! (ATC) from the token authority named in the challenge, using the request from
+//! [`Account::authority_token_request()`], and passing it to
+//! [`Order::submit_authority_token()`].
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use serde::Serialize;
+
+use crate::{der, Error, Identifier};
+#[cfg(doc)]
+use crate::{Account, ChallengeType, Order};
+
+/// An entry of a TNAuthList (RFC 8226 section 9)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TnEntry {
+    /// A service provider code (SPC), such as an OCN
+    ServiceProviderCode(String),
+    /// `count` consecutive telephone numbers, starting at `start`
+    Range {
+        /// The first telephone number of the range
+        start: String,
+        /// The number of telephone numbers in the range, at least 2
+        count: u32,
+    },
+    /// A single telephone number
+    Number(String),
+}
+
+impl Identifier {
+    /// Build a TNAuthList identifier from `entries`
+    ///
+    /// Telephone numbers consist of 1 to 15 digits, `#` or `*`.
+    pub fn tn_auth_list(entries: &[TnEntry]) -> Result<Self, Error> {
+        if entries.is_empty() {
+            return Err(Error::Config("TNAuthList must contain at least one entry"));
+        }
+
+        for entry in entries {
+            let (number, reason) = match entry {
+                TnEntry::ServiceProviderCode(spc) if spc.is_empty() || !spc.is_ascii() => {
+                    (spc, "service provider code must be non-empty ASCII")
+                }
+                TnEntry::ServiceProviderCode(_) => continue,
+                TnEntry::Range { start, count } if *count < 2 => (
+                    start,
+                    "telephone number range must contain at least 2 numbers",
+                ),
+                TnEntry::Range { start: number, .. } | TnEntry::Number(number) => {
+                    match valid_number(number) {
+                        true => continue,
+                        false => (number, "invalid telephone number"),
+                    }
+                }
+            };
+
+            return Err(Error::InvalidIdentifier {
+                identifier: number.clone(),
+                reason,
+            });
+        }
+
+        Ok(Self::TnAuthList(
+            BASE64_URL_SAFE_NO_PAD.encode(der::tn_auth_list(entries)),
+        ))
+    }
+}
+
+fn valid_number(number: &str) -> bool {
+    (1..=15).contains(&number.len())
+        && number
+            .bytes()
+            .all(|b| b.is_ascii_digit() || b == b'#' || b == b'*')
+}
+
+/// The request body for an authority token from a token authority (RFC 9448 section 5)
+///
+/// Created by [`Account::authority_token_request()`]. The token authority responds with a
+/// JSON object whose `token` field is passed to [`Order::submit_authority_token()`].
+#[derive(Clone, Debug, Serialize)]
+pub struct AuthorityTokenRequest {
+    /// The requested token
+    pub atc: AuthorityTokenClaims,
+}
+
+/// The contents of an [`AuthorityTokenRequest`]
+#[derive(Clone, Debug, Serialize)]
+pub struct AuthorityTokenClaims {
+    /// The identifier type, `TNAuthList`
+    pub tktype: String,
+    /// The base64url-encoded TNAuthList
+    pub tkvalue: String,
+    /// Whether the certificate is for a CA that issues certificates to others
+    pub ca: bool,
+    /// The fingerprint of the ACME account key, such as `SHA256 56:3E:...`
+    pub fingerprint: String,
+}
+
+impl AuthorityTokenRequest {
+    pub(crate) fn new(identifier: &Identifier, ca: bool, thumbprint: &[u8]) -> Result<Self, Error> {
+        let tkvalue = match identifier {
+            Identifier::TnAuthList(value) => value.clone(),
+            _ => {
+                return Err(Error::Unsupported(
+                    "authority tokens require a TNAuthList identifier",
+                ))
+            }
+        };
+
+        let hex = thumbprint
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(":");
+        Ok(Self {
+            atc: AuthorityTokenClaims {
+                tktype: "TNAuthList".to_owned(),
+                tkvalue,
+                ca,
+                fingerprint: format!("SHA256 {hex}"),
+            },
+        })
+    }
+}
+
+/// The response to a `tkauth-01` challenge (RFC 9447 section 5)
+#[derive(Serialize)]
+pub(crate) struct AuthorityTokenResponse<'a> {
+    pub(crate) atc: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<TnEntry> {
+        vec![
+            TnEntry::ServiceProviderCode("1234".to_owned()),
+            TnEntry::Range {
+                start: "15551230000".to_owned(),
+                count: 10,
+            },
+            TnEntry::Number("15551234567".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn tn_auth_lists() {
+        let identifier = Identifier::tn_auth_list(&entries()).unwrap();
+        let der = BASE64_URL_SAFE_NO_PAD.decode(identifier.value()).unwrap();
+        let expected = [
+            &[0x30, 0x2b, 0xa0, 0x06, 0x16, 0x04][..],
+            b"1234",
+            &[0xa1, 0x12, 0x30, 0x10, 0x16, 0x0b],
+            b"15551230000",
+            &[0x02, 0x01, 0x0a, 0xa2, 0x0d, 0x16, 0x0b],
+            b"15551234567",
+        ]
+        .concat();
+        assert_eq!(der, expected);
+
+        let invalid = [
+            TnEntry::ServiceProviderCode(String::new()),
+            TnEntry::Range {
+                start: "15551230000".to_owned(),
+                count: 1,
+            },
+            TnEntry::Number("1555123456a".to_owned()),
+            TnEntry::Number("1".repeat(16)),
+        ];
+        for entry in invalid {
+            let result = Identifier::tn_auth_list(std::slice::from_ref(&entry));
+            assert!(
+                matches!(result, Err(Error::InvalidIdentifier { .. })),
+                "{entry:?}"
+            );
+        }
+        assert!(matches!(
+            Identifier::tn_auth_list(&[]),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn tkauth_challenges() {
+        use std::time::Duration;
+
+        use crate::mock::testing::account;
+        use crate::mock::MockServer;
+        use crate::{AccountConfig, ChallengeType, NewOrder, OrderStatus, RetryPolicy};
+
+        let account = account(MockServer::new(), AccountConfig::new()).await;
+        let identifier = Identifier::tn_auth_list(&entries()).unwrap();
+        let new_order = NewOrder::builder()
+            .identifier(identifier.clone())
+            .build()
+            .unwrap();
+        let mut order = account.new_order(&new_order).await.unwrap();
+        let authz = order.authorizations().await.unwrap().remove(0);
+        assert_eq!(authz.identifier, identifier);
+        let challenge = &authz.challenges[0];
+        assert_eq!(challenge.r#type, ChallengeType::TkAuth01);
+        assert_eq!(challenge.tkauth_type.as_deref(), Some("atc"));
+        assert!(challenge.token_authority.is_some());
+
+        // The token request names the TNAuthList and the fingerprint of the account key
+        let request = account.authority_token_request(&identifier, false).unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["atc"]["tktype"], "TNAuthList");
+        assert_eq!(json["atc"]["tkvalue"], identifier.value());
+        assert_eq!(json["atc"]["ca"], false);
+        let key_authorization = order.key_authorization(challenge);
+        let (_, thumbprint) = key_authorization.as_str().split_once('.').unwrap();
+        let thumbprint = BASE64_URL_SAFE_NO_PAD.decode(thumbprint).unwrap();
+        let hex = thumbprint
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            json["atc"]["fingerprint"],
+            format!("SHA256 {}", hex.join(":"))
+        );
+        let dns = Identifier::Dns("example.com".to_owned());
+        let result = account.authority_token_request(&dns, false);
+        assert!(matches!(result, Err(Error::Unsupported(_))));
+
+        order
+            .submit_authority_token(&challenge.url, "token")
+            .await
+            .unwrap();
+        let policy = RetryPolicy::new().delay(Duration::from_millis(1));
+        order.wait_validated(policy).await.unwrap();
+        assert_eq!(order.state().status, OrderStatus::Ready);
+    }
+}
@@ -0,0 +1,193 @@
+//! Obtain external account binding credentials from ZeroSSL
+
+use std::fmt;
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request};
+use serde::Deserialize;
+
+use crate::{form_encode, Error, ExternalAccountKey, HttpClient};
+
+/// Credentials used to request EAB credentials from ZeroSSL
+///
+/// See <https://zerossl.com/documentation/acme/generate-eab-credentials/>.
+///
+/// The [`Debug`] output omits the API key.
+#[derive(Clone, Copy)]
+pub enum ZeroSslAuth<'a> {
+    /// An API access key from the ZeroSSL developer dashboard
+    ApiKey(&'a str),
+    /// The email address of a (possibly new) ZeroSSL account
+    Email(&'a str),
+}
+
+impl fmt::Debug for ZeroSslAuth<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ApiKey(_) => f.write_str("ApiKey(..)"),
+            Self::Email(email) => f.debug_tuple("Email").field(email).finish(),
+        }
+    }
+}
+
+impl ExternalAccountKey {
+    /// Request a new external account key from ZeroSSL
+    ///
+    /// The resulting key can be passed to [`Account::create()`](crate::Account::create())
+    /// together with [`KnownCa::ZeroSsl`](crate::KnownCa::ZeroSsl).
+    #[cfg(feature = "hyper-rustls")]
+    pub async fn from_zerossl(auth: ZeroSslAuth<'_>) -> Result<Self, Error> {
+        Self::from_zerossl_with_http(auth, &crate::DefaultClient::default()).await
+    }
+
+    /// Request a new external account key from ZeroSSL with a custom HTTP client
+    pub async fn from_zerossl_with_http(
+        auth: ZeroSslAuth<'_>,
+        http: &dyn HttpClient,
+    ) -> Result<Self, Error> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+        let request = match auth {
+            ZeroSslAuth::ApiKey(key) => request
+                .uri(format!("{EAB_URL}?access_key={}", form_encode(key)))
+                .body(Body::empty()),
+            ZeroSslAuth::Email(email) => request
+                .uri(format!("{EAB_URL}-email"))
+                .body(Body::from(format!("email={}", form_encode(email)))),
+        };
+
+        let rsp = http
+            .request(request.map_err(|_| Error::Config("invalid ZeroSSL request"))?)
+            .await?;
+        let body = hyper::body::to_bytes(rsp.into_body()).await?;
+        match serde_json::from_slice::<EabResponse>(&body)? {
+            EabResponse {
+                success: true,
+                eab_kid: Some(kid),
+                eab_hmac_key: Some(key),
+            } => Ok(Self::new(kid, &BASE64_URL_SAFE_NO_PAD.decode(key)?)),
+            _ => Err(Error::Str("ZeroSSL did not return EAB credentials")),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EabResponse {
+    #[serde(default)]
+    success: bool,
+    eab_kid: Option<String>,
+    eab_hmac_key: Option<String>,
+}
+
+const EAB_URL: &str = "https://api.zerossl.com/acme/eab-credentials";
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+
+    use hyper::Response;
+    use ring::hmac;
+
+    use super::*;
+
+    /// Answers every request with `body`, recording the URI and body of the last request
+    #[derive(Clone)]
+    struct FakeZeroSsl {
+        body: &'static str,
+        last: Arc<Mutex<(String, String)>>,
+    }
+
+    impl FakeZeroSsl {
+        fn new(body: &'static str) -> Self {
+            Self {
+                body,
+                last: Arc::default(),
+            }
+        }
+
+        fn last(&self) -> (String, String) {
+            self.last.lock().unwrap().clone()
+        }
+    }
+
+    impl HttpClient for FakeZeroSsl {
+        fn request(
+            &self,
+            req: Request<Body>,
+        ) -> Pin<Box<dyn Future<Output = hyper::Result<Response<Body>>>>> {
+            assert_eq!(req.method(), Method::POST);
+            let fake = self.clone();
+            Box::pin(async move {
+                let uri = req.uri().to_string();
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                *fake.last.lock().unwrap() = (uri, body);
+                Ok(Response::new(Body::from(fake.body)))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn eab_credentials() {
+        let fake = FakeZeroSsl::new(
+            r#"{"success":true,"eab_kid":"kid-1","eab_hmac_key":"c2VjcmV0LWhtYWMta2V5"}"#,
+        );
+        let key = ExternalAccountKey::from_zerossl_with_http(ZeroSslAuth::ApiKey("k&y"), &fake)
+            .await
+            .unwrap();
+        assert_eq!(key.id, "kid-1");
+        let expected = hmac::Key::new(hmac::HMAC_SHA256, b"secret-hmac-key");
+        assert_eq!(
+            hmac::sign(&key.key, b"data").as_ref(),
+            hmac::sign(&expected, b"data").as_ref()
+        );
+        assert_eq!(
+            fake.last(),
+            (format!("{EAB_URL}?access_key=k%26y"), String::new())
+        );
+
+        let email = ZeroSslAuth::Email("admin+certs@example.com");
+        ExternalAccountKey::from_zerossl_with_http(email, &fake)
+            .await
+            .unwrap();
+        let (uri, body) = fake.last();
+        assert_eq!(uri, format!("{EAB_URL}-email"));
+        assert_eq!(body, "email=admin%2Bcerts%40example.com");
+
+        let fake = FakeZeroSsl::new(r#"{"success":false,"error":{"code":101}}"#);
+        let result = ExternalAccountKey::from_zerossl_with_http(email, &fake).await;
+        assert!(matches!(result, Err(Error::Str(_))));
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn eab_accounts() {
+        use crate::mock::MockServer;
+        use crate::{Account, NewAccount};
+
+        let fake = FakeZeroSsl::new(
+            r#"{"success":true,"eab_kid":"kid-1","eab_hmac_key":"c2VjcmV0LWhtYWMta2V5"}"#,
+        );
+        let key = ExternalAccountKey::from_zerossl_with_http(ZeroSslAuth::ApiKey("key"), &fake)
+            .await
+            .unwrap();
+        let new_account = NewAccount::builder()
+            .terms_of_service_agreed(true)
+            .build()
+            .unwrap();
+        let create = |eab| {
+            let server = MockServer::new().with_external_account_required();
+            Account::builder().http(Box::new(server)).create(
+                &new_account,
+                MockServer::DIRECTORY_URL,
+                eab,
+            )
+        };
+        assert!(create(Some(&key)).await.is_ok());
+        assert!(matches!(create(None).await, Err(Error::Config(_))));
+    }
+}